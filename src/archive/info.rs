@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Tag added to checkpoint archives, which tells them apart from the archives of finished backups.
+pub const CHECKPOINT_TAG: &str = "checkpoint";
+
+/// Tag added to the archive of an interrupted backup, which only contains the files that were
+/// uploaded before the interruption.
+pub const INCOMPLETE_TAG: &str = "incomplete";
+
+/// Tags of archives that only hold part of a backup, which filters skip unless they ask for the tag.
+const PARTIAL_TAGS: [&str; 2] = [CHECKPOINT_TAG, INCOMPLETE_TAG];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveInfo {
+    pub created: DateTime<Utc>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub hostname: String,
+    pub username: String,
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveFilter {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub hostname: Option<String>,
+    pub username: Option<String>,
+    pub path: Option<PathBuf>,
+}
+
+impl ArchiveFilter {
+    /// Returns whether `info` matches every criterion of the filter. An archive must have all of
+    /// the filter's tags, and its description only needs to contain the filter's description.
+    pub fn matches(&self, info: &ArchiveInfo) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| info.name.as_ref() == Some(name))
+            && self
+                .description
+                .as_ref()
+                .is_none_or(|text| info.description.as_ref().is_some_and(|d| d.contains(text)))
+            && self.tags.iter().all(|tag| info.tags.contains(tag))
+            && self
+                .hostname
+                .as_ref()
+                .is_none_or(|hostname| &info.hostname == hostname)
+            && self
+                .username
+                .as_ref()
+                .is_none_or(|username| &info.username == username)
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|path| info.paths.contains(path))
+    }
+
+    /// Returns whether `info` matches the filter like `matches`, but skips archives that only hold
+    /// part of a backup unless the filter has their tag. This is how archives are picked for
+    /// references such as `latest` and for retention policies.
+    pub fn selects(&self, info: &ArchiveInfo) -> bool {
+        self.matches(info)
+            && PARTIAL_TAGS.iter().all(|&tag| {
+                !info.tags.iter().any(|t| t == tag) || self.tags.iter().any(|t| t == tag)
+            })
+    }
+}
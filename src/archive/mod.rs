@@ -1,34 +1,30 @@
-mod records;
+#[cfg(test)]
+mod tests;
 
-use std::ops::{Deref, DerefMut};
+mod info;
+mod records;
+mod reference;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{
-    block::{Block, BlockRefs},
-    entity::Entity,
-    file::FileTree,
-    hash::Hash,
-};
+use crate::{entity::Entity, hash::Hash, tree::Tree};
 
-pub use self::records::{ArchiveRecord, ArchiveRecords};
+pub use self::{
+    info::{ArchiveFilter, ArchiveInfo, CHECKPOINT_TAG, INCOMPLETE_TAG},
+    records::{ArchiveRecord, ArchiveRecords},
+    reference::ArchiveRef,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Archive {
-    files: FileTree,
-    pub block_refs: BlockRefs,
+    pub info: ArchiveInfo,
+    /// Root tree containing the files at the top level of the archive.
+    pub tree: Hash<Tree>,
 }
 
 impl Archive {
-    pub fn new() -> Self {
-        Archive {
-            files: FileTree::new(),
-            block_refs: BlockRefs::new(),
-        }
-    }
-
-    pub fn add_ref(&mut self, hash: &Hash<Block>) {
-        self.block_refs.add_count(hash, 1);
+    pub fn new(info: ArchiveInfo, tree: Hash<Tree>) -> Self {
+        Archive { info, tree }
     }
 }
 
@@ -36,17 +32,3 @@ impl Entity for Archive {
     const NAME: &'static str = "archive";
     const KEY_PREFIX: &'static str = "archives/";
 }
-
-impl Deref for Archive {
-    type Target = FileTree;
-
-    fn deref(&self) -> &Self::Target {
-        &self.files
-    }
-}
-
-impl DerefMut for Archive {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.files
-    }
-}
@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeSet, HashMap};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -9,11 +9,11 @@ use crate::{
     hash::Hash,
 };
 
-use super::Archive;
+use super::{Archive, ArchiveInfo};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ArchiveRecord {
-    pub created: DateTime<Utc>,
+    pub info: ArchiveInfo,
     pub size: u64,
 }
 
@@ -26,22 +26,22 @@ impl EntityRecord<Archive> for ArchiveRecord {
 #[derive(Debug)]
 pub struct ArchiveRecords {
     records: HashMap<Hash<Archive>, ArchiveRecord>,
-    by_created: BTreeMap<DateTime<Utc>, Hash<Archive>>,
+    by_created: BTreeSet<(DateTime<Utc>, Hash<Archive>)>,
 }
 
 impl ArchiveRecords {
     pub fn new() -> Self {
         ArchiveRecords {
             records: HashMap::new(),
-            by_created: BTreeMap::new(),
+            by_created: BTreeSet::new(),
         }
     }
 
     pub fn from_records(records: HashMap<Hash<Archive>, ArchiveRecord>) -> Self {
-        let mut by_created = BTreeMap::new();
+        let mut by_created = BTreeSet::new();
 
         for (hash, record) in &records {
-            by_created.insert(record.created, *hash);
+            by_created.insert((record.info.created, *hash));
         }
 
         ArchiveRecords {
@@ -50,8 +50,10 @@ impl ArchiveRecords {
         }
     }
 
-    pub fn iter_by_created(&self) -> impl Iterator<Item = (&Hash<Archive>, &ArchiveRecord)> {
-        self.by_created.values().map(|hash| {
+    pub fn iter_by_created(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (&Hash<Archive>, &ArchiveRecord)> {
+        self.by_created.iter().map(|(_, hash)| {
             let record = self.records.get(hash).unwrap();
             (hash, record)
         })
@@ -86,7 +88,7 @@ impl EntityIndex<Archive> for ArchiveRecords {
     }
 
     fn insert(&mut self, hash: Hash<Archive>, record: ArchiveRecord) {
-        self.by_created.insert(record.created, hash);
+        self.by_created.insert((record.info.created, hash));
         self.records.insert(hash, record);
     }
 
@@ -95,7 +97,7 @@ impl EntityIndex<Archive> for ArchiveRecords {
             .records
             .remove(hash)
             .ok_or_else(|| Error::ArchiveRecordNotFound(*hash))?;
-        self.by_created.remove(&record.created);
+        self.by_created.remove(&(record.info.created, *hash));
         Ok(record)
     }
 }
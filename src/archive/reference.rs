@@ -0,0 +1,134 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc};
+
+use crate::{
+    error::{Error, Result},
+    hash::{Hash, ShortHash},
+};
+
+use super::{Archive, ArchiveFilter, ArchiveRecords};
+
+const LATEST: &str = "latest";
+const NAME_PREFIX: &str = "name:";
+const TAG_PREFIX: &str = "tag:";
+
+#[derive(Debug, Clone)]
+pub enum ArchiveRef {
+    Hash(ShortHash<Archive>),
+    Selector(ArchiveSelector),
+}
+
+impl FromStr for ArchiveRef {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // a reference made only of hex digits can't be a selector, so it's parsed as a hash
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit()) {
+            ShortHash::new(s.to_owned()).map(ArchiveRef::Hash)
+        } else {
+            s.parse().map(ArchiveRef::Selector)
+        }
+    }
+}
+
+impl fmt::Display for ArchiveRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveRef::Hash(hash) => write!(f, "{hash}"),
+            ArchiveRef::Selector(selector) => write!(f, "{selector}"),
+        }
+    }
+}
+
+/// Selects the archive that is `offset` archives older than the newest archive matching `filter`
+/// and created before `until`, using the syntax `[latest|name:NAME|tag:TAG][@DATE][~N]`.
+#[derive(Debug, Clone)]
+pub struct ArchiveSelector {
+    text: String,
+    pub filter: ArchiveFilter,
+    pub until: Option<DateTime<Utc>>,
+    pub offset: usize,
+}
+
+impl ArchiveSelector {
+    pub fn resolve(&self, archive_records: &ArchiveRecords) -> Result<Hash<Archive>> {
+        archive_records
+            .iter_by_created()
+            .rev()
+            .filter(|(_, record)| {
+                self.filter.selects(&record.info)
+                    && self.until.is_none_or(|until| record.info.created < until)
+            })
+            .nth(self.offset)
+            .map(|(hash, _)| *hash)
+            .ok_or_else(|| Error::NoArchiveForRef(self.text.clone()))
+    }
+}
+
+impl FromStr for ArchiveSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidArchiveRef(s.to_owned());
+
+        // suffixes are only split off if they're valid, so that a name or tag containing `~` or `@`
+        // is still taken literally
+        let (rest, offset) = match s.rsplit_once('~') {
+            Some((rest, offset))
+                if !offset.is_empty() && offset.bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                (rest, offset.parse().map_err(|_| invalid())?)
+            }
+            _ => (s, 0),
+        };
+
+        let maybe_until = rest
+            .rsplit_once('@')
+            .and_then(|(base, date)| Some((base, parse_until(date)?)));
+        let (base, until) = match maybe_until {
+            Some((base, until)) => (base, Some(until)),
+            None => (rest, None),
+        };
+
+        let mut filter = ArchiveFilter::default();
+        if let Some(name) = base.strip_prefix(NAME_PREFIX)
+            && !name.is_empty()
+        {
+            filter.name = Some(name.to_owned());
+        } else if let Some(tag) = base.strip_prefix(TAG_PREFIX)
+            && !tag.is_empty()
+        {
+            filter.tags.push(tag.to_owned());
+        } else if base != LATEST && !(base.is_empty() && until.is_some()) {
+            return Err(invalid());
+        }
+
+        Ok(ArchiveSelector {
+            text: s.to_owned(),
+            filter,
+            until,
+            offset,
+        })
+    }
+}
+
+impl fmt::Display for ArchiveSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Parses a local date or time into an exclusive upper bound, so that a date includes every
+/// archive created on that day.
+fn parse_until(s: &str) -> Option<DateTime<Utc>> {
+    let naive = if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        date.checked_add_days(Days::new(1))?.and_hms_opt(0, 0, 0)?
+    } else {
+        let time = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").ok()?;
+        time.checked_add_signed(TimeDelta::seconds(1))?
+    };
+
+    let local = Local.from_local_datetime(&naive).earliest()?;
+    Some(local.to_utc())
+}
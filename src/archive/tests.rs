@@ -0,0 +1,308 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{
+    archive::{
+        Archive, ArchiveFilter, ArchiveInfo, ArchiveRecord, ArchiveRecords, ArchiveRef,
+        CHECKPOINT_TAG, INCOMPLETE_TAG, reference::ArchiveSelector,
+    },
+    entity::EntityIndex,
+    error::Error,
+    file::FileData,
+    hash::Hash,
+};
+
+fn time(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 10, day, 12, 0, 0).unwrap()
+}
+
+fn info() -> ArchiveInfo {
+    ArchiveInfo {
+        created: time(18),
+        name: Some("nightly".to_owned()),
+        description: Some("home directory backup".to_owned()),
+        tags: vec!["home".to_owned(), "daily".to_owned()],
+        hostname: "laptop".to_owned(),
+        username: "alice".to_owned(),
+        paths: vec![PathBuf::from("/home/alice")],
+    }
+}
+
+#[test]
+fn filter_empty() {
+    assert!(ArchiveFilter::default().matches(&info()));
+}
+
+#[test]
+fn filter_fields() {
+    let filter = ArchiveFilter {
+        name: Some("nightly".to_owned()),
+        description: Some("home".to_owned()),
+        hostname: Some("laptop".to_owned()),
+        username: Some("alice".to_owned()),
+        path: Some(PathBuf::from("/home/alice")),
+        ..ArchiveFilter::default()
+    };
+    assert!(filter.matches(&info()));
+
+    let filter = ArchiveFilter {
+        hostname: Some("desktop".to_owned()),
+        ..ArchiveFilter::default()
+    };
+    assert!(!filter.matches(&info()));
+
+    let filter = ArchiveFilter {
+        path: Some(PathBuf::from("/home")),
+        ..ArchiveFilter::default()
+    };
+    assert!(!filter.matches(&info()));
+}
+
+#[test]
+fn filter_missing_fields() {
+    let info = ArchiveInfo {
+        name: None,
+        description: None,
+        ..info()
+    };
+
+    let filter = ArchiveFilter {
+        name: Some("nightly".to_owned()),
+        ..ArchiveFilter::default()
+    };
+    assert!(!filter.matches(&info));
+
+    let filter = ArchiveFilter {
+        description: Some(String::new()),
+        ..ArchiveFilter::default()
+    };
+    assert!(!filter.matches(&info));
+}
+
+#[test]
+fn filter_tags() {
+    let filter = ArchiveFilter {
+        tags: vec!["daily".to_owned(), "home".to_owned()],
+        ..ArchiveFilter::default()
+    };
+    assert!(filter.matches(&info()));
+
+    let filter = ArchiveFilter {
+        tags: vec!["daily".to_owned(), "work".to_owned()],
+        ..ArchiveFilter::default()
+    };
+    assert!(!filter.matches(&info()));
+}
+
+fn selector(s: &str) -> ArchiveSelector {
+    match s.parse::<ArchiveRef>().unwrap() {
+        ArchiveRef::Selector(selector) => selector,
+        ArchiveRef::Hash(hash) => panic!("parsed `{s}` as hash {hash}"),
+    }
+}
+
+fn records() -> (ArchiveRecords, Vec<Hash<Archive>>) {
+    let mut records = ArchiveRecords::new();
+    let mut hashes = vec![];
+    let archives = [
+        (15, Some("nightly"), vec!["prod"]),
+        (16, Some("weekly"), vec![]),
+        (17, Some("nightly"), vec![]),
+        (18, None, vec!["prod"]),
+    ];
+
+    for (day, name, tags) in archives {
+        let info = ArchiveInfo {
+            created: time(day),
+            name: name.map(ToOwned::to_owned),
+            tags: tags.into_iter().map(ToOwned::to_owned).collect(),
+            ..info()
+        };
+        let record = ArchiveRecord { info, size: 0 };
+        let hash = Hash::archive(&day.to_le_bytes());
+        records.insert(hash, record);
+        hashes.push(hash);
+    }
+
+    (records, hashes)
+}
+
+#[test]
+fn records_with_same_created_time() {
+    let mut records = ArchiveRecords::new();
+    let hashes = [Hash::archive(b"first"), Hash::archive(b"second")];
+
+    for hash in hashes {
+        let record = ArchiveRecord {
+            info: info(),
+            size: 0,
+        };
+        records.insert(hash, record);
+    }
+    assert_eq!(records.iter_by_created().count(), 2);
+
+    records.remove(&hashes[0]).unwrap();
+    let remaining = records
+        .iter_by_created()
+        .map(|(hash, _)| *hash)
+        .collect::<Vec<_>>();
+    assert_eq!(remaining, vec![hashes[1]]);
+}
+
+#[test]
+fn parse_ref_hash() {
+    let reference = "0123abcd".parse::<ArchiveRef>().unwrap();
+    assert!(matches!(reference, ArchiveRef::Hash(_)));
+}
+
+#[test]
+fn parse_ref_selector() {
+    let latest = selector("latest");
+    assert!(latest.filter.name.is_none() && latest.filter.tags.is_empty());
+    assert!(latest.until.is_none());
+    assert_eq!(latest.offset, 0);
+
+    assert_eq!(selector("latest~3").offset, 3);
+    assert_eq!(
+        selector("name:nightly").filter.name.as_deref(),
+        Some("nightly")
+    );
+    assert_eq!(selector("tag:prod~1").filter.tags, vec!["prod"]);
+    assert!(selector("@2026-10-01").until.is_some());
+    assert!(selector("tag:prod@2026-10-01T12:00:00~2").until.is_some());
+}
+
+#[test]
+fn parse_ref_literal_suffixes() {
+    // suffixes that aren't valid are part of the name or tag
+    for (s, name) in [
+        ("name:a~b", "a~b"),
+        ("name:v1~", "v1~"),
+        ("name:ops@host", "ops@host"),
+        ("name:a@b~c", "a@b~c"),
+    ] {
+        let selector = selector(s);
+        assert_eq!(selector.filter.name.as_deref(), Some(name));
+        assert!(selector.until.is_none());
+        assert_eq!(selector.offset, 0);
+    }
+
+    let selector = selector("tag:ops@host@2026-10-01~2");
+    assert_eq!(selector.filter.tags, vec!["ops@host"]);
+    assert!(selector.until.is_some());
+    assert_eq!(selector.offset, 2);
+}
+
+#[test]
+fn parse_ref_invalid_hash() {
+    for s in [
+        "abc",
+        "0123abcd0123abcd0123abcd0123abcd0123abcd0123abcd0123abcd0123abcd0",
+    ] {
+        let err = s.parse::<ArchiveRef>().unwrap_err();
+        assert_eq!(err, Error::InvalidHash(s.to_owned()));
+    }
+}
+
+#[test]
+fn parse_ref_invalid() {
+    for s in [
+        "",
+        "latest~",
+        "latest~x",
+        "newest",
+        "name:",
+        "tag:~1",
+        "@yesterday",
+    ] {
+        let err = s.parse::<ArchiveRef>().unwrap_err();
+        assert_eq!(err, Error::InvalidArchiveRef(s.to_owned()));
+    }
+}
+
+#[test]
+fn resolve_selector() {
+    let (records, hashes) = records();
+
+    assert_eq!(selector("latest").resolve(&records), Ok(hashes[3]));
+    assert_eq!(selector("latest~1").resolve(&records), Ok(hashes[2]));
+    assert_eq!(selector("name:nightly").resolve(&records), Ok(hashes[2]));
+    assert_eq!(selector("name:nightly~1").resolve(&records), Ok(hashes[0]));
+    assert_eq!(selector("tag:prod~1").resolve(&records), Ok(hashes[0]));
+
+    let mut until = selector("latest");
+    until.until = Some(time(17));
+    assert_eq!(until.resolve(&records), Ok(hashes[1]));
+}
+
+#[test]
+fn resolve_selector_missing() {
+    let (records, _) = records();
+
+    for s in ["latest~4", "name:monthly", "tag:prod~2"] {
+        let err = selector(s).resolve(&records).unwrap_err();
+        assert_eq!(err, Error::NoArchiveForRef(s.to_owned()));
+    }
+}
+
+#[test]
+fn file_data_empty() {
+    assert_eq!(FileData::inline(vec![]), FileData::Empty);
+    assert_eq!(FileData::inline(vec![1]), FileData::Inline(vec![1]));
+    assert_eq!(FileData::blocks(None), FileData::Empty);
+}
+
+#[test]
+fn filter_skips_checkpoints() {
+    let checkpoint = ArchiveInfo {
+        tags: vec![CHECKPOINT_TAG.to_owned()],
+        ..info()
+    };
+    assert!(ArchiveFilter::default().matches(&checkpoint));
+    assert!(!ArchiveFilter::default().selects(&checkpoint));
+    assert!(ArchiveFilter::default().selects(&info()));
+
+    let filter = ArchiveFilter {
+        tags: vec![CHECKPOINT_TAG.to_owned()],
+        ..ArchiveFilter::default()
+    };
+    assert!(filter.selects(&checkpoint));
+}
+
+#[test]
+fn filter_skips_incomplete_archives() {
+    let incomplete = ArchiveInfo {
+        tags: vec!["home".to_owned(), INCOMPLETE_TAG.to_owned()],
+        ..info()
+    };
+    let filter = ArchiveFilter {
+        tags: vec!["home".to_owned()],
+        ..ArchiveFilter::default()
+    };
+    assert!(!filter.selects(&incomplete));
+
+    let filter = ArchiveFilter {
+        tags: vec![INCOMPLETE_TAG.to_owned()],
+        ..ArchiveFilter::default()
+    };
+    assert!(filter.selects(&incomplete));
+}
+
+#[test]
+fn resolve_selector_skips_checkpoints() {
+    let (mut records, hashes) = records();
+    let checkpoint_hash = Hash::archive(b"checkpoint");
+    let info = ArchiveInfo {
+        created: time(19),
+        tags: vec![CHECKPOINT_TAG.to_owned()],
+        ..info()
+    };
+    records.insert(checkpoint_hash, ArchiveRecord { info, size: 0 });
+
+    assert_eq!(selector("latest").resolve(&records), Ok(hashes[3]));
+    assert_eq!(
+        selector("tag:checkpoint").resolve(&records),
+        Ok(checkpoint_hash)
+    );
+}
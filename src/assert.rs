@@ -1,7 +1,9 @@
 use crate::{
-    block::Block,
+    archive::Archive,
+    block::{self, Block},
     error::{Error, Result},
-    hash::{self, Hash},
+    hash::Hash,
+    tree::Tree,
 };
 
 pub fn assert_block_level_eq(hash: &Hash<Block>, actual: u8, expected: Option<u8>) -> Result<()> {
@@ -18,6 +20,18 @@ pub fn assert_block_level_eq(hash: &Hash<Block>, actual: u8, expected: Option<u8
     Ok(())
 }
 
+pub fn assert_block_size_eq(hash: &Hash<Block>, actual: u64, expected: u64) -> Result<()> {
+    if expected != actual {
+        return Err(Error::WrongBlockSize {
+            hash: *hash,
+            actual,
+            expected,
+        });
+    }
+
+    Ok(())
+}
+
 pub fn assert_hash_eq(actual: &Hash<Block>, expected: &Hash<Block>) -> Result<()> {
     if expected != actual {
         return Err(Error::WrongBlockHash {
@@ -29,8 +43,30 @@ pub fn assert_hash_eq(actual: &Hash<Block>, expected: &Hash<Block>) -> Result<()
     Ok(())
 }
 
-pub fn assert_size_multiple_of_hash(size: u64) -> Result<()> {
-    if !size.is_multiple_of(hash::SIZE as u64) {
+pub fn assert_archive_hash_eq(actual: &Hash<Archive>, expected: &Hash<Archive>) -> Result<()> {
+    if expected != actual {
+        return Err(Error::WrongArchiveHash {
+            actual: *actual,
+            expected: *expected,
+        });
+    }
+
+    Ok(())
+}
+
+pub fn assert_tree_hash_eq(actual: &Hash<Tree>, expected: &Hash<Tree>) -> Result<()> {
+    if expected != actual {
+        return Err(Error::WrongTreeHash {
+            actual: *actual,
+            expected: *expected,
+        });
+    }
+
+    Ok(())
+}
+
+pub fn assert_size_multiple_of_child(size: u64) -> Result<()> {
+    if !size.is_multiple_of(block::CHILD_SIZE as u64) {
         return Err(Error::InvalidBlockSize(size));
     }
 
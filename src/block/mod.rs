@@ -8,14 +8,16 @@ use fastcdc::v2020::AsyncStreamCDC;
 use tokio::io::AsyncRead;
 
 use crate::{
-    assert::{assert_block_level_eq, assert_hash_eq, assert_size_multiple_of_hash},
+    assert::{assert_block_level_eq, assert_hash_eq, assert_size_multiple_of_child},
     compress::{compress, decompress},
     entity::Entity,
     error::{Error, Result},
     hash::{self, Hash},
 };
 
-pub use self::records::{BlockRecord, BlockRecords, BlockRefs};
+pub use self::records::{BlockManifest, BlockRecord, BlockRecords, BlockRefs, BlockShard, ShardId};
+
+pub const CHILD_SIZE: usize = hash::SIZE + size_of::<u64>();
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Block {
@@ -26,10 +28,16 @@ pub enum Block {
     Branch {
         hash: Hash<Block>,
         level: u8,
-        children: Vec<Hash<Block>>,
+        children: Vec<ChildBlock>,
     },
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ChildBlock {
+    pub hash: Hash<Block>,
+    pub size: u64,
+}
+
 impl Entity for Block {
     const NAME: &'static str = "block";
     const KEY_PREFIX: &'static str = "blocks/";
@@ -45,7 +53,7 @@ impl Block {
         Ok(Block::Leaf { hash, data })
     }
 
-    pub fn branch(level: u8, children: Vec<Hash<Block>>) -> Result<Self> {
+    pub fn branch(level: u8, children: Vec<ChildBlock>) -> Result<Self> {
         if level == 0 {
             return Err(Error::BranchLevelZero);
         }
@@ -62,7 +70,6 @@ impl Block {
         })
     }
 
-    #[allow(dead_code)]
     pub fn level(&self) -> u8 {
         match self {
             Block::Leaf { .. } => 0,
@@ -76,6 +83,20 @@ impl Block {
         }
     }
 
+    pub fn size(&self) -> u64 {
+        match self {
+            Block::Leaf { data, .. } => data.len() as u64,
+            Block::Branch { children, .. } => children.iter().map(|child| child.size).sum(),
+        }
+    }
+
+    pub fn as_child(&self) -> ChildBlock {
+        ChildBlock {
+            hash: *self.hash(),
+            size: self.size(),
+        }
+    }
+
     pub fn encode(self, compression_level: u8) -> Result<Vec<u8>> {
         let (level, bytes) = self.into_raw(compression_level)?;
         let mut buf = vec![];
@@ -130,7 +151,7 @@ impl Block {
 
     fn branch_from_raw(level: u8, bytes: &[u8]) -> Result<Self> {
         let size = bytes.len() as u64;
-        assert_size_multiple_of_hash(size)?;
+        assert_size_multiple_of_child(size)?;
 
         let children = split(bytes).collect::<Vec<_>>();
         let hash = Hash::branch_block(&children);
@@ -148,22 +169,27 @@ pub fn chunker<R: AsyncRead + Unpin>(reader: R, target_size: u32) -> AsyncStream
     AsyncStreamCDC::new(reader, min_size, target_size, max_size)
 }
 
-fn concat<H, I>(hashes: I) -> Vec<u8>
+fn concat<C, I>(children: I) -> Vec<u8>
 where
-    H: Borrow<Hash<Block>>,
-    I: IntoIterator<Item = H>,
+    C: Borrow<ChildBlock>,
+    I: IntoIterator<Item = C>,
 {
     let mut bytes = vec![];
 
-    for hash in hashes {
-        bytes.extend(hash.borrow().as_bytes());
+    for child in children {
+        let child = child.borrow();
+        bytes.extend(child.hash.as_bytes());
+        bytes.extend(child.size.to_le_bytes());
     }
 
     bytes
 }
 
-fn split(bytes: &[u8]) -> impl Iterator<Item = Hash<Block>> + '_ {
-    bytes
-        .chunks_exact(hash::SIZE)
-        .map(|bytes| Hash::from_bytes(bytes.try_into().unwrap()))
+fn split(bytes: &[u8]) -> impl Iterator<Item = ChildBlock> + '_ {
+    bytes.chunks_exact(CHILD_SIZE).map(|bytes| {
+        let (hash_bytes, size_bytes) = bytes.split_at(hash::SIZE);
+        let hash = Hash::from_bytes(hash_bytes.try_into().unwrap());
+        let size = u64::from_le_bytes(size_bytes.try_into().unwrap());
+        ChildBlock { hash, size }
+    })
 }
@@ -1,27 +1,31 @@
 use std::{
     cmp::Ordering,
-    collections::{HashMap, hash_map},
+    collections::{BTreeMap, BTreeSet, HashMap, btree_map},
+    mem,
+    ops::Range,
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    entity::{EntityIndex, EntityRecord},
+    entity::EntityRecord,
     error::{Error, Result},
     hash::Hash,
+    pack::Pack,
 };
 
 use super::Block;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Reference counts of blocks, ordered by hash so that their serialization is deterministic.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BlockRefs {
-    inner: HashMap<Hash<Block>, u64>,
+    inner: BTreeMap<Hash<Block>, u64>,
 }
 
 impl BlockRefs {
     pub fn new() -> Self {
         BlockRefs {
-            inner: HashMap::new(),
+            inner: BTreeMap::new(),
         }
     }
 
@@ -31,78 +35,311 @@ impl BlockRefs {
             .and_modify(|lhs_count| *lhs_count += count)
             .or_insert(count);
     }
+
+    pub fn add_refs(&mut self, refs: BlockRefs) {
+        for (hash, count) in refs.inner {
+            self.add_count(&hash, count);
+        }
+    }
+
+    pub fn count(&self, hash: &Hash<Block>) -> u64 {
+        self.inner.get(hash).copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash<Block>, &u64)> {
+        self.inner.iter()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BlockRecord {
     pub ref_count: u64,
+    pub pack: Hash<Pack>,
+    pub offset: u64,
     pub size: u64,
 }
 
+impl BlockRecord {
+    pub fn range(&self) -> Range<u64> {
+        self.offset..self.offset + self.size
+    }
+}
+
 impl EntityRecord<Block> for BlockRecord {
     fn size(&self) -> u64 {
         self.size
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct BlockRecords {
+/// Shards are numbered by the leading bits of the hashes of their blocks.
+pub type ShardId = u32;
+
+/// Number of records that shards hold on average before they're split.
+const MAX_SHARD_LEN: u64 = 1 << 12;
+
+/// Most leading bits of a hash that shards are split by.
+const MAX_SHARD_BITS: u8 = 24;
+
+/// How the block records are split into shards, stored as its own object so that a shard can be
+/// found without listing them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockManifest {
+    /// Number of leading hash bits that shards are split by, so there are `2^shard_bits` shards
+    pub shard_bits: u8,
+    /// Number of records across all shards
+    pub len: u64,
+}
+
+/// Records of the blocks whose hashes start with the same bits, stored as a single object.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BlockShard {
     records: HashMap<Hash<Block>, BlockRecord>,
 }
 
+/// Block records split into shards, which are only downloaded when a block in them is needed and
+/// only uploaded when they've been modified.
+///
+/// The number of shards doubles whenever they hold more than `MAX_SHARD_LEN` records on average,
+/// so that the shards a command touches stay small as the repository grows. Shards are never
+/// merged again when blocks are removed.
+#[derive(Debug)]
+pub struct BlockRecords {
+    shard_bits: u8,
+    len: u64,
+    shards: BTreeMap<ShardId, BlockShard>,
+    dirty_ids: BTreeSet<ShardId>,
+    /// Shard bits of the stored shards, which isn't known when the records replace them
+    stored_shard_bits: Option<u8>,
+}
+
 impl BlockRecords {
+    pub const KEY: &'static str = "metadata/blocks";
+    /// Shards are kept apart from the manifest, so that no key is a prefix of another and the
+    /// objects can also be stored as files
+    pub const SHARD_KEY_PREFIX: &'static str = "metadata/block-shards";
+
+    /// Creates empty records with every shard loaded and marked as modified, so that uploading
+    /// them replaces any existing shards.
     pub fn new() -> Self {
         BlockRecords {
-            records: HashMap::new(),
+            shard_bits: 0,
+            len: 0,
+            shards: BTreeMap::from([(0, BlockShard::default())]),
+            dirty_ids: BTreeSet::from([0]),
+            stored_shard_bits: None,
+        }
+    }
+
+    /// Creates records split as given by `manifest` without any shards loaded, which need to be
+    /// loaded before use.
+    pub fn unloaded(manifest: BlockManifest) -> Self {
+        BlockRecords {
+            shard_bits: manifest.shard_bits,
+            len: manifest.len,
+            shards: BTreeMap::new(),
+            dirty_ids: BTreeSet::new(),
+            stored_shard_bits: Some(manifest.shard_bits),
+        }
+    }
+
+    pub fn manifest(&self) -> BlockManifest {
+        BlockManifest {
+            shard_bits: self.shard_bits,
+            len: self.len,
+        }
+    }
+
+    pub fn stored_shard_bits(&self) -> Option<u8> {
+        self.stored_shard_bits
+    }
+
+    pub fn shard_id(&self, hash: &Hash<Block>) -> ShardId {
+        shard_id(self.shard_bits, hash)
+    }
+
+    pub fn shard_key(shard_bits: u8, id: ShardId) -> String {
+        let width = usize::from(shard_bits).div_ceil(4).max(1);
+        format!("{}/{shard_bits}/{id:0width$x}", Self::SHARD_KEY_PREFIX)
+    }
+
+    pub fn shard_ids(shard_bits: u8) -> impl Iterator<Item = ShardId> {
+        0..1 << shard_bits
+    }
+
+    /// Returns the IDs of the shards containing `hashes` that haven't been loaded yet.
+    pub fn missing_shard_ids<'a, I>(&self, hashes: I) -> BTreeSet<ShardId>
+    where
+        I: IntoIterator<Item = &'a Hash<Block>>,
+    {
+        hashes
+            .into_iter()
+            .map(|hash| self.shard_id(hash))
+            .filter(|id| !self.shards.contains_key(id))
+            .collect()
+    }
+
+    /// Returns the IDs of all shards that haven't been loaded yet.
+    pub fn unloaded_shard_ids(&self) -> BTreeSet<ShardId> {
+        Self::shard_ids(self.shard_bits)
+            .filter(|id| !self.shards.contains_key(id))
+            .collect()
+    }
+
+    /// Adds a downloaded shard, unless it has already been loaded by someone else.
+    pub fn load_shard(&mut self, id: ShardId, shard: BlockShard) {
+        self.shards.entry(id).or_insert(shard);
+    }
+
+    pub fn dirty_shards(&self) -> impl Iterator<Item = (ShardId, &BlockShard)> {
+        self.dirty_ids.iter().map(|id| (*id, &self.shards[id]))
+    }
+
+    /// Returns the shard bits that the records should be split by if the shards have grown past
+    /// `MAX_SHARD_LEN` records on average.
+    pub fn split_shard_bits(&self) -> Option<u8> {
+        let shard_bits = (self.shard_bits..MAX_SHARD_BITS)
+            .find(|bits| self.len <= MAX_SHARD_LEN << bits)
+            .unwrap_or(MAX_SHARD_BITS);
+        (shard_bits > self.shard_bits).then_some(shard_bits)
+    }
+
+    /// Splits the records into `2^shard_bits` shards, which are all marked as modified. Every shard
+    /// must be loaded first.
+    pub fn split(&mut self, shard_bits: u8) -> Result<()> {
+        if let Some(id) = self.unloaded_shard_ids().first() {
+            return Err(Error::ShardNotLoaded(*id));
+        }
+
+        let mut shards = BTreeMap::new();
+        for (hash, record) in mem::take(&mut self.shards)
+            .into_values()
+            .flat_map(|shard| shard.records)
+        {
+            let shard: &mut BlockShard = shards.entry(shard_id(shard_bits, &hash)).or_default();
+            shard.records.insert(hash, record);
+        }
+
+        self.shard_bits = shard_bits;
+        self.shards = Self::shard_ids(shard_bits)
+            .map(|id| (id, shards.remove(&id).unwrap_or_default()))
+            .collect();
+        self.dirty_ids = Self::shard_ids(shard_bits).collect();
+        Ok(())
+    }
+
+    /// Adds references to blocks that already have records.
+    pub fn add_refs(&mut self, refs: &BlockRefs) -> Result<()> {
+        for (hash, count) in refs.iter() {
+            let record = self
+                .get_mut(hash)?
+                .ok_or_else(|| Error::BlockRecordNotFound(*hash))?;
+            record.ref_count += count;
         }
+
+        Ok(())
     }
 
     pub fn remove_refs(&mut self, refs: BlockRefs) -> RemoveRefs<'_> {
         RemoveRefs::new(self, refs)
     }
-}
 
-impl Default for BlockRecords {
-    fn default() -> Self {
-        Self::new()
+    /// Iterates over the records in all loaded shards.
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash<Block>, &BlockRecord)> {
+        self.shards.values().flat_map(|shard| shard.records.iter())
     }
-}
 
-impl EntityIndex<Block> for BlockRecords {
-    type Record = BlockRecord;
+    /// Returns the number of bytes in each pack that belong to blocks with records in the loaded
+    /// shards.
+    pub fn pack_usage(&self) -> HashMap<Hash<Pack>, u64> {
+        let mut usage = HashMap::new();
+
+        for (_, record) in self.iter() {
+            *usage.entry(record.pack).or_insert(0) += record.size;
+        }
 
-    const KEY: &'static str = "metadata/blocks";
+        usage
+    }
 
-    fn len(&self) -> usize {
-        self.records.len()
+    /// Returns the number of records across all shards, including those that aren't loaded.
+    pub fn len(&self) -> u64 {
+        self.len
     }
 
-    fn contains(&self, hash: &Hash<Block>) -> bool {
-        self.records.contains_key(hash)
+    /// Returns whether a block has a record, failing if its shard hasn't been loaded rather than
+    /// treating the block as missing.
+    pub fn contains(&self, hash: &Hash<Block>) -> Result<bool> {
+        Ok(self.get(hash)?.is_some())
     }
 
-    fn get(&self, hash: &Hash<Block>) -> Option<&BlockRecord> {
-        self.records.get(hash)
+    /// Returns the record of a block, failing if its shard hasn't been loaded rather than treating
+    /// the block as missing.
+    pub fn get(&self, hash: &Hash<Block>) -> Result<Option<&BlockRecord>> {
+        let id = self.shard_id(hash);
+        let shard = self.shards.get(&id).ok_or(Error::ShardNotLoaded(id))?;
+        Ok(shard.records.get(hash))
     }
 
-    fn get_mut(&mut self, hash: &Hash<Block>) -> Option<&mut BlockRecord> {
-        self.records.get_mut(hash)
+    /// Returns the record of a block like `get`, marking its shard as modified if found.
+    pub fn get_mut(&mut self, hash: &Hash<Block>) -> Result<Option<&mut BlockRecord>> {
+        let id = self.shard_id(hash);
+        let shard = self.shards.get_mut(&id).ok_or(Error::ShardNotLoaded(id))?;
+        let Some(record) = shard.records.get_mut(hash) else {
+            return Ok(None);
+        };
+        self.dirty_ids.insert(id);
+        Ok(Some(record))
     }
 
-    fn insert(&mut self, hash: Hash<Block>, record: BlockRecord) {
-        self.records.insert(hash, record);
+    /// Adds a record, failing if its shard hasn't been loaded, since uploading a shard that only
+    /// holds new records would drop the existing ones.
+    pub fn insert(&mut self, hash: Hash<Block>, record: BlockRecord) -> Result<()> {
+        if self
+            .shard_mut(&hash)?
+            .records
+            .insert(hash, record)
+            .is_none()
+        {
+            self.len += 1;
+        }
+
+        Ok(())
     }
 
-    fn remove(&mut self, hash: &Hash<Block>) -> Result<BlockRecord> {
-        self.records
+    pub fn remove(&mut self, hash: &Hash<Block>) -> Result<BlockRecord> {
+        let record = self
+            .shard_mut(hash)?
+            .records
             .remove(hash)
-            .ok_or_else(|| Error::BlockRecordNotFound(*hash))
+            .ok_or_else(|| Error::BlockRecordNotFound(*hash))?;
+        self.len -= 1;
+        Ok(record)
+    }
+
+    fn shard_mut(&mut self, hash: &Hash<Block>) -> Result<&mut BlockShard> {
+        let id = self.shard_id(hash);
+        let shard = self.shards.get_mut(&id).ok_or(Error::ShardNotLoaded(id))?;
+        self.dirty_ids.insert(id);
+        Ok(shard)
     }
 }
 
+impl Default for BlockRecords {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn shard_id(shard_bits: u8, hash: &Hash<Block>) -> ShardId {
+    let bytes = hash.as_bytes();
+    let prefix = ShardId::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    prefix
+        .checked_shr(ShardId::BITS - u32::from(shard_bits))
+        .unwrap_or(0)
+}
+
 pub struct RemoveRefs<'a> {
     records: &'a mut BlockRecords,
-    refs_iter: hash_map::IntoIter<Hash<Block>, u64>,
+    refs_iter: btree_map::IntoIter<Hash<Block>, u64>,
 }
 
 impl<'a> RemoveRefs<'a> {
@@ -119,14 +356,18 @@ impl Iterator for RemoveRefs<'_> {
 
     fn next(&mut self) -> Option<Self::Item> {
         for (hash, ref_count) in self.refs_iter.by_ref() {
-            if let Some(record) = self.records.get_mut(&hash) {
+            let maybe_record = match self.records.get_mut(&hash) {
+                Ok(maybe_record) => maybe_record,
+                Err(err) => return Some(Err(err)),
+            };
+
+            if let Some(record) = maybe_record {
                 match record.ref_count.cmp(&ref_count) {
                     Ordering::Greater => {
                         record.ref_count -= ref_count;
                     }
                     Ordering::Equal => {
-                        let record = self.records.remove(&hash).unwrap();
-                        return Some(Ok((hash, record)));
+                        return Some(self.records.remove(&hash).map(|record| (hash, record)));
                     }
                     Ordering::Less => {
                         return Some(Err(Error::WrongRefCount {
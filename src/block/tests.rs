@@ -1,11 +1,17 @@
+use itertools::Itertools;
+
 use crate::{
-    block::Block,
+    block::{Block, BlockManifest, BlockRecord, BlockRecords, BlockRefs, BlockShard, ChildBlock},
     error::Error,
     hash::{self, Hash},
 };
 
 pub const COMPRESSION_LEVEL: u8 = 3;
 pub const NULL_HASH: Hash<Block> = Hash::from_bytes([0; hash::SIZE]);
+pub const NULL_CHILD: ChildBlock = ChildBlock {
+    hash: NULL_HASH,
+    size: 1,
+};
 
 fn roundtrip_block(block: &Block) -> Block {
     let bytes = block.clone().encode(COMPRESSION_LEVEL).unwrap();
@@ -26,14 +32,14 @@ fn block_leaf_empty_error() {
 #[test]
 fn block_branch_0_error() {
     assert_eq!(
-        Block::branch(0, vec![NULL_HASH]),
+        Block::branch(0, vec![NULL_CHILD]),
         Err(Error::BranchLevelZero)
     );
 }
 
 #[test]
 fn block_branch_1_roundtrip() {
-    let block = Block::branch(1, vec![NULL_HASH]).unwrap();
+    let block = Block::branch(1, vec![NULL_CHILD]).unwrap();
     assert_eq!(block, roundtrip_block(&block));
 }
 
@@ -44,12 +50,211 @@ fn block_branch_1_empty_error() {
 
 #[test]
 fn block_branch_2_roundtrip() {
-    let block = Block::branch(2, vec![NULL_HASH]).unwrap();
+    let block = Block::branch(2, vec![NULL_CHILD]).unwrap();
     assert_eq!(block, roundtrip_block(&block));
 }
 
 #[test]
 fn block_branch_255_roundtrip() {
-    let block = Block::branch(255, vec![NULL_HASH]).unwrap();
+    let block = Block::branch(255, vec![NULL_CHILD]).unwrap();
+    assert_eq!(block, roundtrip_block(&block));
+}
+
+#[test]
+fn block_branch_size() {
+    let children = vec![
+        ChildBlock {
+            hash: NULL_HASH,
+            size: 3,
+        },
+        ChildBlock {
+            hash: NULL_HASH,
+            size: 5,
+        },
+    ];
+    let block = Block::branch(1, children).unwrap();
+    assert_eq!(block.size(), 8);
     assert_eq!(block, roundtrip_block(&block));
 }
+
+fn block_record(ref_count: u64) -> BlockRecord {
+    BlockRecord {
+        ref_count,
+        pack: Hash::pack(&[]),
+        offset: 0,
+        size: 1,
+    }
+}
+
+/// Returns records split by the first byte of each hash, without any shards loaded.
+fn unloaded_records() -> BlockRecords {
+    BlockRecords::unloaded(BlockManifest {
+        shard_bits: 8,
+        len: 0,
+    })
+}
+
+/// Returns a hash whose leading bits are the bits of `n` reversed, so that consecutive numbers are
+/// spread across shards.
+fn spread_hash(n: u32) -> Hash<Block> {
+    let mut bytes = [0; hash::SIZE];
+    bytes[..4].copy_from_slice(&n.reverse_bits().to_be_bytes());
+    Hash::from_bytes(bytes)
+}
+
+#[test]
+fn block_records_missing_shards() {
+    let first_hash = Hash::from_bytes([0; hash::SIZE]);
+    let second_hash = Hash::from_bytes([1; hash::SIZE]);
+    let mut records = unloaded_records();
+    let missing_ids = records.missing_shard_ids([&first_hash, &second_hash]);
+    assert_eq!(missing_ids.into_iter().collect::<Vec<_>>(), vec![0, 1]);
+
+    records.load_shard(0, BlockShard::default());
+    let missing_ids = records.missing_shard_ids([&first_hash, &second_hash]);
+    assert_eq!(missing_ids.into_iter().collect::<Vec<_>>(), vec![1]);
+    assert!(!records.contains(&first_hash).unwrap());
+    assert_eq!(records.unloaded_shard_ids().len(), 255);
+}
+
+#[test]
+fn block_records_dirty_shards() {
+    let first_hash = Hash::from_bytes([0; hash::SIZE]);
+    let second_hash = Hash::from_bytes([1; hash::SIZE]);
+    let mut records = unloaded_records();
+    records.load_shard(0, BlockShard::default());
+    records.load_shard(1, BlockShard::default());
+    assert_eq!(records.dirty_shards().count(), 0);
+
+    assert!(records.get_mut(&second_hash).unwrap().is_none());
+    assert_eq!(records.dirty_shards().count(), 0);
+
+    records.insert(first_hash, block_record(1)).unwrap();
+    let dirty_ids = records.dirty_shards().map(|(id, _)| id).collect::<Vec<_>>();
+    assert_eq!(dirty_ids, vec![0]);
+}
+
+#[test]
+fn block_records_unloaded_shard() {
+    let first_hash = Hash::from_bytes([0; hash::SIZE]);
+    let second_hash = Hash::from_bytes([1; hash::SIZE]);
+    let mut records = unloaded_records();
+    records.load_shard(0, BlockShard::default());
+    records.insert(first_hash, block_record(1)).unwrap();
+
+    let err = Some(Error::ShardNotLoaded(1));
+    assert_eq!(records.get(&second_hash).err(), err);
+    assert_eq!(records.get_mut(&second_hash).err(), err);
+    assert_eq!(records.contains(&second_hash).err(), err);
+    assert_eq!(records.insert(second_hash, block_record(1)).err(), err);
+    assert_eq!(records.remove(&second_hash).err(), err);
+    assert!(records.get(&first_hash).unwrap().is_some());
+
+    let dirty_ids = records.dirty_shards().map(|(id, _)| id).collect::<Vec<_>>();
+    assert_eq!(dirty_ids, vec![0]);
+}
+
+#[test]
+fn block_records_new_replaces_all_shards() {
+    let records = BlockRecords::new();
+    assert_eq!(records.dirty_shards().count(), 1);
+    assert_eq!(records.len(), 0);
+    assert_eq!(records.stored_shard_bits(), None);
+}
+
+#[test]
+fn block_records_len() {
+    let mut records = unloaded_records();
+    records.load_shard(0, BlockShard::default());
+    let hash = Hash::from_bytes([0; hash::SIZE]);
+
+    records.insert(hash, block_record(1)).unwrap();
+    records.insert(hash, block_record(2)).unwrap();
+    assert_eq!(records.len(), 1);
+
+    records.remove(&hash).unwrap();
+    assert_eq!(records.len(), 0);
+    assert_eq!(
+        records.remove(&hash).err(),
+        Some(Error::BlockRecordNotFound(hash))
+    );
+    assert_eq!(records.len(), 0);
+}
+
+#[test]
+fn block_records_split() {
+    let mut records = BlockRecords::new();
+    for n in 0..4096 {
+        records.insert(spread_hash(n), block_record(1)).unwrap();
+    }
+    assert_eq!(records.split_shard_bits(), None);
+
+    for n in 4096..10000 {
+        records.insert(spread_hash(n), block_record(1)).unwrap();
+    }
+    assert_eq!(records.split_shard_bits(), Some(2));
+
+    records.split(2).unwrap();
+    assert_eq!(records.manifest().shard_bits, 2);
+    assert_eq!(records.len(), 10000);
+    assert_eq!(records.split_shard_bits(), None);
+    assert_eq!(records.dirty_shards().count(), 4);
+    let shard_ids = records.iter().map(|(hash, _)| records.shard_id(hash));
+    assert!(shard_ids.counts().values().all(|count| *count == 2500));
+    for n in 0..10000 {
+        assert!(records.contains(&spread_hash(n)).unwrap());
+    }
+}
+
+#[test]
+fn block_records_split_unloaded() {
+    let mut records = unloaded_records();
+    records.load_shard(0, BlockShard::default());
+    assert_eq!(records.split(9), Err(Error::ShardNotLoaded(1)));
+}
+
+#[test]
+fn block_records_add_refs() {
+    let first_hash = Hash::from_bytes([0; hash::SIZE]);
+    let second_hash = Hash::from_bytes([1; hash::SIZE]);
+    let mut records = BlockRecords::new();
+    records.insert(first_hash, block_record(1)).unwrap();
+
+    let mut refs = BlockRefs::new();
+    refs.add_count(&first_hash, 2);
+    records.add_refs(&refs).unwrap();
+    assert_eq!(records.get(&first_hash).unwrap().unwrap().ref_count, 3);
+
+    refs.add_count(&second_hash, 1);
+    assert_eq!(
+        records.add_refs(&refs),
+        Err(Error::BlockRecordNotFound(second_hash))
+    );
+}
+
+#[test]
+fn block_shard_key() {
+    assert_eq!(BlockRecords::shard_key(0, 0), "metadata/block-shards/0/0");
+    assert_eq!(BlockRecords::shard_key(8, 0), "metadata/block-shards/8/00");
+    assert_eq!(
+        BlockRecords::shard_key(8, 171),
+        "metadata/block-shards/8/ab"
+    );
+    assert_eq!(
+        BlockRecords::shard_key(10, 0x2bc),
+        "metadata/block-shards/10/2bc"
+    );
+}
+
+#[test]
+fn block_shard_id() {
+    let hash = Hash::from_bytes([171; hash::SIZE]);
+    assert_eq!(BlockRecords::new().shard_id(&hash), 0);
+    assert_eq!(unloaded_records().shard_id(&hash), 171);
+
+    let records = BlockRecords::unloaded(BlockManifest {
+        shard_bits: 10,
+        len: 0,
+    });
+    assert_eq!(records.shard_id(&hash), 0x2ae);
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use log::warn;
+use tokio::{fs, task::spawn_blocking};
+
+use crate::{block::Block, entity::Entity, error::Result, hash::Hash};
+
+use super::write_atomic;
+
+/// Fraction of the capacity that can be cached before the directory is scanned again, so that
+/// blocks cached by other processes sharing it count towards the limit too.
+const RESCAN_FRACTION: u64 = 16;
+
+/// Encoded blocks kept on the local disk by hash, limited to a total size by evicting the least
+/// recently used blocks.
+///
+/// Blocks are verified against their hash whenever they're read, so a cache shared between machines
+/// or left behind by an interrupted restore can't introduce corrupted data. Other processes can
+/// share the directory, in which case each one picks up the others' blocks as it reads them and
+/// whenever it scans the directory again.
+#[derive(Debug)]
+pub struct DiskBlockCache {
+    dir: PathBuf,
+    capacity: u64,
+    index: Mutex<LruIndex>,
+    /// Total size of the blocks cached since the directory was last scanned
+    unscanned_size: AtomicU64,
+}
+
+impl DiskBlockCache {
+    /// Opens the cache in `dir`, ordering any blocks that are already there by when they were last
+    /// used and evicting blocks until they fit in `capacity`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory can't be created or listed.
+    pub async fn open(dir: &Path, capacity: u64) -> Result<Self> {
+        let blocks_dir = dir.join(Block::KEY_PREFIX);
+        fs::create_dir_all(&blocks_dir).await?;
+
+        let mut index = scan_index(blocks_dir).await?;
+        let evicted = index.evict(capacity);
+        let cache = DiskBlockCache {
+            dir: dir.to_owned(),
+            capacity,
+            index: Mutex::new(index),
+            unscanned_size: AtomicU64::new(0),
+        };
+
+        cache.remove_files(evicted).await;
+        Ok(cache)
+    }
+
+    /// Returns the cached block with the given hash, if any. Blocks that fail verification are
+    /// removed from the cache and treated as missing.
+    pub async fn get(&self, hash: &Hash<Block>) -> Option<Block> {
+        self.read(hash).await.map(|(block, _)| block)
+    }
+
+    /// Returns the cached block with the given hash as it's encoded, if any, after checking that
+    /// it matches its hash.
+    pub async fn get_encoded(&self, hash: &Hash<Block>) -> Option<Vec<u8>> {
+        self.read(hash).await.map(|(_, bytes)| bytes)
+    }
+
+    async fn read(&self, hash: &Hash<Block>) -> Option<(Block, Vec<u8>)> {
+        // blocks missing from the index are still read, since another process sharing the cache
+        // may have added them
+        let path = self.path(hash);
+        let bytes = match fs::read(&path).await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                // another process sharing the cache may have evicted the block
+                if err.kind() != ErrorKind::NotFound {
+                    warn!("failed to read cached block {hash} ({err})");
+                }
+
+                self.index().remove(hash);
+                return None;
+            }
+        };
+
+        let hash = *hash;
+        let result = spawn_blocking(move || {
+            touch(&path);
+            Block::decode(&hash, None, &bytes).map(|block| (block, bytes))
+        })
+        .await;
+
+        match result {
+            Ok(Ok((block, bytes))) => {
+                self.insert(hash, bytes.len() as u64).await;
+                Some((block, bytes))
+            }
+            Ok(Err(err)) => {
+                warn!("discarding invalid cached block {hash} ({err})");
+                self.index().remove(&hash);
+                self.remove_files([hash]).await;
+                None
+            }
+            Err(err) => {
+                warn!("failed to read cached block {hash} ({err})");
+                None
+            }
+        }
+    }
+
+    /// Stores an encoded block, evicting the least recently used blocks if the cache is full.
+    /// Failing to do so only means the block will be downloaded again, so errors are logged instead
+    /// of returned.
+    pub async fn put(&self, hash: &Hash<Block>, bytes: &[u8]) {
+        let size = bytes.len() as u64;
+        if size > self.capacity {
+            return;
+        }
+
+        if let Err(err) = write_atomic(&self.path(hash), bytes).await {
+            warn!("failed to cache block {hash} ({err})");
+            return;
+        }
+
+        self.insert(*hash, size).await;
+
+        // only one of the tasks that cross the threshold at the same time scans the directory
+        let threshold = self.capacity / RESCAN_FRACTION;
+        if self.unscanned_size.fetch_add(size, Ordering::Relaxed) + size >= threshold
+            && self.unscanned_size.swap(0, Ordering::Relaxed) >= threshold
+        {
+            self.rescan().await;
+        }
+    }
+
+    /// Adds a block as the most recently used and evicts blocks until the rest fit.
+    async fn insert(&self, hash: Hash<Block>, size: u64) {
+        let evicted = {
+            let mut index = self.index();
+            index.insert(hash, size);
+            index.evict(self.capacity)
+        };
+
+        self.remove_files(evicted).await;
+    }
+
+    /// Replaces the index with the blocks in the directory, which picks up blocks that other
+    /// processes sharing it have cached or evicted, and evicts blocks until they fit again.
+    async fn rescan(&self) {
+        let mut index = match scan_index(self.dir.join(Block::KEY_PREFIX)).await {
+            Ok(index) => index,
+            Err(err) => {
+                warn!("failed to scan block cache ({err})");
+                return;
+            }
+        };
+
+        let evicted = index.evict(self.capacity);
+        *self.index() = index;
+        self.remove_files(evicted).await;
+    }
+
+    async fn remove_files<I: IntoIterator<Item = Hash<Block>>>(&self, hashes: I) {
+        for hash in hashes {
+            if let Err(err) = fs::remove_file(self.path(&hash)).await
+                && err.kind() != ErrorKind::NotFound
+            {
+                warn!("failed to remove cached block {hash} ({err})");
+            }
+        }
+    }
+
+    /// Returns the index of the cached blocks, which each operation leaves consistent, so it's
+    /// still usable after a panic while it was locked.
+    fn index(&self) -> MutexGuard<'_, LruIndex> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn path(&self, hash: &Hash<Block>) -> PathBuf {
+        self.dir.join(hash.key())
+    }
+}
+
+/// Blocks ordered by when they were last used, along with their total size.
+#[derive(Debug)]
+pub struct LruIndex {
+    entries: HashMap<Hash<Block>, LruEntry>,
+    order: BTreeMap<u64, Hash<Block>>,
+    next_tick: u64,
+    size: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LruEntry {
+    tick: u64,
+    size: u64,
+}
+
+impl LruIndex {
+    pub fn new() -> Self {
+        LruIndex {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_tick: 0,
+            size: 0,
+        }
+    }
+
+    /// Adds a block as the most recently used, replacing any existing entry for it.
+    pub fn insert(&mut self, hash: Hash<Block>, size: u64) {
+        self.remove(&hash);
+
+        let tick = self.next_tick;
+        self.entries.insert(hash, LruEntry { tick, size });
+        self.order.insert(tick, hash);
+        self.next_tick += 1;
+        self.size += size;
+    }
+
+    /// Removes a block, returning whether it was found.
+    pub fn remove(&mut self, hash: &Hash<Block>) -> bool {
+        let Some(entry) = self.entries.remove(hash) else {
+            return false;
+        };
+
+        self.order.remove(&entry.tick);
+        self.size -= entry.size;
+        true
+    }
+
+    /// Removes the least recently used blocks until the total size is within `capacity`, returning
+    /// their hashes.
+    pub fn evict(&mut self, capacity: u64) -> Vec<Hash<Block>> {
+        let mut evicted = vec![];
+
+        while self.size > capacity
+            && let Some((_, hash)) = self.order.pop_first()
+        {
+            let entry = self.entries.remove(&hash).unwrap();
+            self.size -= entry.size;
+            evicted.push(hash);
+        }
+
+        evicted
+    }
+}
+
+/// Returns an index of the blocks in a directory, ordered by when their files were last modified.
+async fn scan_index(blocks_dir: PathBuf) -> Result<LruIndex> {
+    let mut entries = spawn_blocking(move || scan_entries(&blocks_dir)).await??;
+    entries.sort_unstable();
+
+    let mut index = LruIndex::new();
+    for (_, hash, size) in entries {
+        index.insert(hash, size);
+    }
+
+    Ok(index)
+}
+
+fn scan_entries(blocks_dir: &Path) -> io::Result<Vec<(SystemTime, Hash<Block>, u64)>> {
+    let mut entries = vec![];
+
+    for dir_entry in std::fs::read_dir(blocks_dir)? {
+        let dir_entry = dir_entry?;
+        let key = format!(
+            "{}{}",
+            Block::KEY_PREFIX,
+            dir_entry.file_name().to_string_lossy()
+        );
+
+        // skips temporary files from interrupted writes
+        let Ok(hash) = Hash::from_key(&key) else {
+            continue;
+        };
+
+        // another process sharing the cache may have evicted the block since it was listed
+        let metadata = match dir_entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        entries.push((metadata.modified()?, hash, metadata.len()));
+    }
+
+    Ok(entries)
+}
+
+/// Updates a block's modification time, which orders blocks for eviction when the directory is
+/// scanned again. This is only a hint, so failing to do so is ignored.
+fn touch(path: &Path) {
+    if let Ok(file) = File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
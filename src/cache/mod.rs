@@ -0,0 +1,136 @@
+#[cfg(test)]
+mod tests;
+
+mod blocks;
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use log::warn;
+use tokio::fs;
+
+pub use self::blocks::DiskBlockCache;
+
+/// Distinguishes the temporary files of concurrent writes within a process.
+static NEXT_TEMP_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Local copies of metadata objects along with their `ETag` headers, which allow unchanged objects
+/// to be skipped with conditional requests.
+#[derive(Debug)]
+pub struct MetadataCache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CachedObject {
+    pub etag: String,
+    pub bytes: Vec<u8>,
+}
+
+impl MetadataCache {
+    pub fn new(dir: PathBuf) -> Self {
+        MetadataCache { dir }
+    }
+
+    /// Returns the cached copy of an object, if any. Unreadable entries are treated as missing,
+    /// since they'll be replaced by the next download, and corrupted ones are removed.
+    pub async fn get(&self, key: &str) -> Option<CachedObject> {
+        let path = self.path(key);
+        let entry = match fs::read(&path).await {
+            Ok(entry) => entry,
+            Err(err) if err.kind() == ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("failed to read cached {key} ({err})");
+                return None;
+            }
+        };
+
+        let maybe_cached = decode_entry(entry);
+        if maybe_cached.is_none() {
+            warn!("discarding invalid cached {key}");
+            self.remove(key).await;
+        }
+
+        maybe_cached
+    }
+
+    /// Stores a copy of an object. Failing to do so only means it will be downloaded again, so
+    /// errors are logged instead of returned.
+    pub async fn put(&self, key: &str, etag: &str, bytes: &[u8]) {
+        if let Err(err) = write_atomic(&self.path(key), &encode_entry(etag, bytes)).await {
+            warn!("failed to cache {key} ({err})");
+        }
+    }
+
+    pub async fn remove(&self, key: &str) {
+        if let Err(err) = fs::remove_file(self.path(key)).await
+            && err.kind() != ErrorKind::NotFound
+        {
+            warn!("failed to remove cached {key} ({err})");
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+}
+
+/// Writes `contents` to the file at `path`, creating its directory if needed, so that the file is
+/// either left as it was or has all of the new contents.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    // written to a temporary file first so that an interrupted write can't leave a torn entry,
+    // named so that concurrent writes of the same entry, even from other processes, don't mix
+    let temp_id = NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed);
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".{}.{temp_id}.tmp", process::id()));
+
+    fs::write(&temp_path, contents).await?;
+    if let Err(err) = fs::rename(&temp_path, path).await {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Encodes a cache entry as the `ETag` and a checksum of the object's contents, each followed by
+/// a newline, and then the contents.
+pub fn encode_entry(etag: &str, bytes: &[u8]) -> Vec<u8> {
+    let checksum = blake3::hash(bytes).to_hex();
+    let mut entry = Vec::with_capacity(etag.len() + checksum.len() + 2 + bytes.len());
+    entry.extend(etag.as_bytes());
+    entry.push(b'\n');
+    entry.extend(checksum.as_bytes());
+    entry.push(b'\n');
+    entry.extend(bytes);
+    entry
+}
+
+/// Decodes a cache entry, returning `None` if it's malformed or its contents don't match their
+/// checksum, since its `ETag` can't be trusted either then.
+pub fn decode_entry(mut entry: Vec<u8>) -> Option<CachedObject> {
+    let etag_end = entry.iter().position(|&byte| byte == b'\n')?;
+    let checksum_end = etag_end
+        + 1
+        + entry[etag_end + 1..]
+            .iter()
+            .position(|&byte| byte == b'\n')?;
+    let bytes = entry.split_off(checksum_end + 1);
+
+    let checksum = str::from_utf8(&entry[etag_end + 1..checksum_end]).ok()?;
+    if blake3::Hash::from_hex(checksum).ok()? != blake3::hash(&bytes) {
+        return None;
+    }
+
+    entry.truncate(etag_end);
+    let etag = String::from_utf8(entry).ok()?;
+    Some(CachedObject { etag, bytes })
+}
@@ -0,0 +1,217 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    block::Block,
+    cache::{
+        CachedObject, DiskBlockCache, MetadataCache, blocks::LruIndex, decode_entry, encode_entry,
+    },
+    entity::Entity,
+    hash::Hash,
+};
+
+fn block_hash(n: u8) -> Hash<Block> {
+    Hash::leaf_block(&[n])
+}
+
+/// Returns an empty directory for a test to cache blocks in.
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cubist-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Returns a block's hash along with its encoding, which is the same size for every `n`.
+fn encoded_block(n: u8) -> (Hash<Block>, Vec<u8>) {
+    let block = Block::leaf(vec![n; 100]).unwrap();
+    let hash = *block.hash();
+    (hash, block.encode(3).unwrap())
+}
+
+fn cached_block_count(dir: &Path) -> usize {
+    fs::read_dir(dir.join(Block::KEY_PREFIX)).unwrap().count()
+}
+
+#[test]
+fn cache_entry_roundtrip() {
+    let entry = encode_entry("\"abc\"", b"line 1\nline 2");
+    assert_eq!(
+        decode_entry(entry),
+        Some(CachedObject {
+            etag: "\"abc\"".to_owned(),
+            bytes: b"line 1\nline 2".to_vec(),
+        })
+    );
+}
+
+#[test]
+fn cache_entry_empty_object() {
+    let entry = encode_entry("\"abc\"", &[]);
+    let cached_object = decode_entry(entry).unwrap();
+    assert!(cached_object.bytes.is_empty());
+}
+
+#[test]
+fn cache_entry_invalid() {
+    assert_eq!(decode_entry(b"no newline".to_vec()), None);
+    assert_eq!(decode_entry(b"\"abc\"\nno checksum".to_vec()), None);
+
+    let mut entry = encode_entry("\"abc\"", b"contents");
+    entry[0] = 0xff;
+    assert_eq!(decode_entry(entry), None);
+}
+
+#[test]
+fn cache_entry_corrupted() {
+    let mut entry = encode_entry("\"abc\"", b"contents");
+    *entry.last_mut().unwrap() = b'S';
+    assert_eq!(decode_entry(entry.clone()), None);
+
+    // including entries cut short by an interrupted write
+    entry.pop();
+    assert_eq!(decode_entry(entry), None);
+}
+
+#[tokio::test]
+async fn metadata_cache_removes_corrupted_entry() {
+    let dir = cache_dir("corrupted-entry");
+    let cache = MetadataCache::new(dir.clone());
+
+    cache.put("metadata/archives", "\"abc\"", b"contents").await;
+    assert_eq!(
+        cache.get("metadata/archives").await.unwrap().bytes,
+        b"contents"
+    );
+
+    let path = dir.join("metadata/archives");
+    let mut entry = fs::read(&path).unwrap();
+    *entry.last_mut().unwrap() = b'S';
+    fs::write(&path, entry).unwrap();
+
+    assert_eq!(cache.get("metadata/archives").await, None);
+    assert!(!path.exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn metadata_cache_replaces_entry() {
+    let dir = cache_dir("replaces-entry");
+    let cache = MetadataCache::new(dir.clone());
+
+    cache.put("metadata/archives", "\"abc\"", b"old").await;
+    cache.put("metadata/archives", "\"def\"", b"new").await;
+    let cached = cache.get("metadata/archives").await.unwrap();
+    assert_eq!(cached.etag, "\"def\"");
+    assert_eq!(cached.bytes, b"new");
+
+    // without leaving temporary files behind
+    let file_count = fs::read_dir(dir.join("metadata")).unwrap().count();
+    assert_eq!(file_count, 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn lru_evicts_least_recently_used() {
+    let mut index = LruIndex::new();
+    index.insert(block_hash(0), 1);
+    index.insert(block_hash(1), 1);
+    index.insert(block_hash(2), 1);
+    index.insert(block_hash(0), 1);
+
+    assert_eq!(index.evict(3), vec![]);
+    assert_eq!(index.evict(2), vec![block_hash(1)]);
+    assert_eq!(index.evict(0), vec![block_hash(2), block_hash(0)]);
+}
+
+#[test]
+fn lru_insert_replaces_size() {
+    let mut index = LruIndex::new();
+    index.insert(block_hash(0), 5);
+    index.insert(block_hash(0), 3);
+
+    assert_eq!(index.evict(3), vec![]);
+    assert_eq!(index.evict(2), vec![block_hash(0)]);
+}
+
+#[test]
+fn lru_remove() {
+    let mut index = LruIndex::new();
+    index.insert(block_hash(0), 1);
+
+    assert!(index.remove(&block_hash(0)));
+    assert!(!index.remove(&block_hash(0)));
+    assert_eq!(index.evict(0), vec![]);
+}
+
+#[tokio::test]
+async fn block_cache_get_and_put() {
+    let dir = cache_dir("get-and-put");
+    let (hash, bytes) = encoded_block(0);
+    let cache = DiskBlockCache::open(&dir, 1 << 20).await.unwrap();
+
+    assert!(cache.get(&hash).await.is_none());
+    cache.put(&hash, &bytes).await;
+    assert_eq!(cache.get(&hash).await.unwrap().hash(), &hash);
+    assert_eq!(cache.get_encoded(&hash).await.unwrap(), bytes);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn block_cache_discards_invalid_block() {
+    let dir = cache_dir("invalid");
+    let (hash, _) = encoded_block(0);
+    let (_, other_bytes) = encoded_block(1);
+    let cache = DiskBlockCache::open(&dir, 1 << 20).await.unwrap();
+
+    cache.put(&hash, &other_bytes).await;
+    assert!(cache.get(&hash).await.is_none());
+    assert_eq!(cached_block_count(&dir), 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn block_cache_evicts_on_open() {
+    let dir = cache_dir("evicts-on-open");
+    let blocks: Vec<_> = (0..3).map(encoded_block).collect();
+    let size = blocks[0].1.len() as u64;
+
+    let cache = DiskBlockCache::open(&dir, 3 * size).await.unwrap();
+    for (hash, bytes) in &blocks {
+        cache.put(hash, bytes).await;
+    }
+    assert_eq!(cached_block_count(&dir), 3);
+
+    DiskBlockCache::open(&dir, 2 * size).await.unwrap();
+    assert_eq!(cached_block_count(&dir), 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn block_cache_shared_between_processes() {
+    let dir = cache_dir("shared");
+    let blocks: Vec<_> = (0..4).map(encoded_block).collect();
+    let size = blocks[0].1.len() as u64;
+
+    // both caches are opened before either has any blocks
+    let first = DiskBlockCache::open(&dir, 3 * size).await.unwrap();
+    let second = DiskBlockCache::open(&dir, 3 * size).await.unwrap();
+
+    // blocks cached by one are found by the other
+    first.put(&blocks[0].0, &blocks[0].1).await;
+    first.put(&blocks[1].0, &blocks[1].1).await;
+    assert!(second.get(&blocks[0].0).await.is_some());
+
+    // and count towards its limit
+    second.put(&blocks[2].0, &blocks[2].1).await;
+    second.put(&blocks[3].0, &blocks[3].1).await;
+    assert_eq!(cached_block_count(&dir), 3);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
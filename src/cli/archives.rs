@@ -2,7 +2,8 @@ use std::sync::Arc;
 
 use clap::builder::styling::AnsiColor;
 use humantime::format_duration;
-use log::info;
+use itertools::Itertools;
+use log::{debug, info};
 
 use crate::{
     arc::unarc,
@@ -24,10 +25,42 @@ pub async fn main(cli: ArchivesArgs) -> Result<()> {
 
     let archive_records = download_archive_records(storage.clone()).await?;
 
+    let filter = cli.filter.filter()?;
+
     for (hash, archive_record) in archive_records.iter_by_created() {
-        let formatted_time = format_time(&archive_record.created);
+        let archive_info = &archive_record.info;
+        if !filter.matches(archive_info) {
+            continue;
+        }
+
+        let formatted_time = format_time(&archive_info.created);
         let time_style = AnsiColor::Blue.on_default();
-        info!("{time_style}{formatted_time}{time_style:#} {hash}");
+        let name_style = AnsiColor::Green.on_default();
+        let host_style = AnsiColor::BrightBlack.on_default();
+        let formatted_name = archive_info
+            .name
+            .as_ref()
+            .map(|name| format!(" {name_style}{name}{name_style:#}"))
+            .unwrap_or_default();
+        let formatted_host = format!("{}@{}", archive_info.username, archive_info.hostname);
+        let formatted_tags = if archive_info.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", archive_info.tags.join(", "))
+        };
+        info!(
+            "{time_style}{formatted_time}{time_style:#} {hash}{formatted_name} {host_style}{formatted_host}{host_style:#}{formatted_tags}"
+        );
+
+        if let Some(description) = &archive_info.description {
+            debug!("    {description}");
+        }
+        let formatted_paths = archive_info
+            .paths
+            .iter()
+            .map(|path| path.display())
+            .join(", ");
+        debug!("    paths: {formatted_paths}");
     }
 
     let storage = unarc(storage);
@@ -1,11 +1,24 @@
-use std::{fmt, ops::RangeInclusive, path::PathBuf};
+use std::{fmt, ops::RangeInclusive, path::PathBuf, time::Duration};
 
+use aws_sdk_s3::types::StorageClass;
 use clap::{ArgAction, Args, ValueEnum};
 use concolor_clap::ColorChoice;
 
-use crate::{archive::Archive, file::WalkOrder, hash::ShortHash};
-
-use super::parse::{parse_range_inclusive, parse_short_hash};
+use crate::{
+    archive::{ArchiveFilter, ArchiveRef},
+    cache::DiskBlockCache,
+    error::Error,
+    file::{ExcludePattern, WalkOrder},
+    ops::FailureMode,
+    progress::ProgressType,
+    retention::{GroupBy, RetentionPolicy},
+    storage::{
+        ClientOptions, ObjectOptions, RateLimits, RepoLocation, RepoUrl, RetryPolicy,
+        RetryableError,
+    },
+};
+
+use super::parse::{parse_bytes, parse_range_inclusive, parse_short_hash, parse_storage_class};
 
 const COMPRESSION_LEVEL_RANGE: RangeInclusive<u8> = 1..=19;
 const DEFAULT_COMPRESSION_LEVEL: u8 = 3;
@@ -13,10 +26,25 @@ const DEFAULT_COMPRESSION_LEVEL: u8 = 3;
 const BLOCK_SIZE_RANGE: RangeInclusive<u32> = 1..=u32::MAX;
 const DEFAULT_TARGET_BLOCK_SIZE: u32 = 1 << 20;
 
+/// Inlined files are part of their directory's tree, which is read into memory whole, so they're
+/// limited to what fits comfortably in one.
+pub const MAX_INLINE_THRESHOLD: u64 = 1 << 16;
+
 const TASK_COUNT_RANGE: RangeInclusive<usize> = 1..=1024;
 const DEFAULT_TASK_COUNT: usize = 8;
 
-fn parse_compression_level(s: &str) -> Result<u8, String> {
+const PERCENTAGE_RANGE: RangeInclusive<u8> = 1..=100;
+
+const REQUEST_RATE_RANGE: RangeInclusive<u64> = 1..=u64::MAX;
+
+/// Limits on the length of object tag keys and values, as set by S3.
+const MAX_TAG_KEY_LENGTH: usize = 128;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+const MAX_ATTEMPTS_RANGE: RangeInclusive<u32> = 1..=100;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+pub fn parse_compression_level(s: &str) -> Result<u8, String> {
     parse_range_inclusive(s, COMPRESSION_LEVEL_RANGE)
 }
 
@@ -24,12 +52,73 @@ fn parse_block_size(s: &str) -> Result<u32, String> {
     parse_range_inclusive(s, BLOCK_SIZE_RANGE)
 }
 
-fn parse_task_count(s: &str) -> Result<usize, String> {
+pub fn parse_inline_threshold(s: &str) -> Result<u64, String> {
+    match parse_bytes(s)? {
+        threshold if threshold > MAX_INLINE_THRESHOLD => Err(format!(
+            "{threshold} is larger than the maximum of {MAX_INLINE_THRESHOLD}"
+        )),
+        threshold => Ok(threshold),
+    }
+}
+
+pub fn parse_task_count(s: &str) -> Result<usize, String> {
     parse_range_inclusive(s, TASK_COUNT_RANGE)
 }
 
-pub fn parse_archive_hash(s: &str) -> Result<ShortHash<Archive>, String> {
-    parse_short_hash(s)
+fn parse_percentage(s: &str) -> Result<u8, String> {
+    parse_range_inclusive(s, PERCENTAGE_RANGE)
+}
+
+fn parse_max_attempts(s: &str) -> Result<u32, String> {
+    parse_range_inclusive(s, MAX_ATTEMPTS_RANGE)
+}
+
+fn parse_byte_rate(s: &str) -> Result<u64, String> {
+    match parse_bytes(s)? {
+        0 => Err("rate must be greater than 0".to_owned()),
+        rate => Ok(rate),
+    }
+}
+
+fn parse_request_rate(s: &str) -> Result<u64, String> {
+    parse_range_inclusive(s, REQUEST_RATE_RANGE)
+}
+
+fn parse_object_tag(s: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = s.split_once('=') else {
+        return Err("expected KEY=VALUE".to_owned());
+    };
+
+    if key.is_empty() || key.chars().count() > MAX_TAG_KEY_LENGTH {
+        Err(format!("key must be 1-{MAX_TAG_KEY_LENGTH} characters"))
+    } else if value.chars().count() > MAX_TAG_VALUE_LENGTH {
+        Err(format!(
+            "value must be at most {MAX_TAG_VALUE_LENGTH} characters"
+        ))
+    } else {
+        Ok((key.to_owned(), value.to_owned()))
+    }
+}
+
+fn parse_archive_ref(s: &str) -> Result<ArchiveRef, String> {
+    match s.parse() {
+        Ok(reference) => Ok(reference),
+        // parsed again only to explain what's wrong with the hash
+        Err(Error::InvalidHash(_)) => parse_short_hash(s).map(ArchiveRef::Hash),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+pub fn parse_exclude_pattern(s: &str) -> Result<ExcludePattern, String> {
+    s.parse().map_err(|err: Error| err.to_string())
+}
+
+pub fn parse_repo_url(s: &str) -> Result<RepoUrl, String> {
+    s.parse().map_err(|err: Error| err.to_string())
+}
+
+fn parse_repo_location(s: &str) -> Result<RepoLocation, String> {
+    s.parse().map_err(|err: Error| err.to_string())
 }
 
 #[derive(Args, Debug)]
@@ -38,6 +127,22 @@ pub struct BackupArgs {
     #[arg(required = true)]
     pub paths: Vec<PathBuf>,
 
+    /// Leave out files matching a glob pattern (can be repeated)
+    #[arg(long = "exclude", value_name = "PATTERN", value_parser = parse_exclude_pattern)]
+    pub excludes: Vec<ExcludePattern>,
+
+    /// Name of the archive
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Description of the archive
+    #[arg(long)]
+    pub description: Option<String>,
+
+    /// Tag to add to the archive (can be repeated)
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
     /// Compression level (1-19)
     #[arg(
         short = 'l',
@@ -58,6 +163,11 @@ pub struct BackupArgs {
     )]
     pub target_block_size: u32,
 
+    /// Maximum size of files to store inside the archive instead of as blocks (e.g. 4Ki, up to
+    /// 64Ki)
+    #[arg(long, value_name = "SIZE", default_value_t = 0, value_parser = parse_inline_threshold)]
+    pub inline_threshold: u64,
+
     /// Number of background tasks to use
     #[arg(
         short = 'j',
@@ -68,6 +178,22 @@ pub struct BackupArgs {
     )]
     pub tasks: usize,
 
+    /// How often to save the progress of the backup (0 to disable)
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "15m",
+        value_parser = humantime::parse_duration,
+    )]
+    pub checkpoint_interval: Duration,
+
+    #[command(flatten)]
+    pub failure: FailureArgs,
+
+    /// How to show progress
+    #[arg(long, default_value_t = ProgressType::Auto)]
+    pub progress: ProgressType,
+
     /// Undo all changes when finished
     #[arg(short = 't', long, default_value_t = false)]
     pub transient: bool,
@@ -82,9 +208,9 @@ pub struct BackupArgs {
 
 #[derive(Args, Debug)]
 pub struct RestoreArgs {
-    /// Archive to restore from
-    #[arg(value_parser = parse_archive_hash)]
-    pub archive: ShortHash<Archive>,
+    /// Archive to restore from (hash or reference such as `latest`)
+    #[arg(value_parser = parse_archive_ref)]
+    pub archive: ArchiveRef,
 
     /// Files to restore (or all files if empty)
     pub paths: Vec<PathBuf>,
@@ -93,6 +219,9 @@ pub struct RestoreArgs {
     #[arg(long, default_value_t = WalkOrder::DepthFirst)]
     pub order: WalkOrder,
 
+    #[command(flatten)]
+    pub block_cache: BlockCacheArgs,
+
     /// Number of background tasks to use
     #[arg(
         short = 'j',
@@ -103,6 +232,13 @@ pub struct RestoreArgs {
     )]
     pub tasks: usize,
 
+    #[command(flatten)]
+    pub failure: FailureArgs,
+
+    /// How to show progress
+    #[arg(long, default_value_t = ProgressType::Auto)]
+    pub progress: ProgressType,
+
     /// Show operations that would be performed without actually doing them
     #[arg(short = 'n', long, default_value_t = false)]
     pub dry_run: bool,
@@ -111,11 +247,172 @@ pub struct RestoreArgs {
     pub global: GlobalArgs,
 }
 
+#[derive(Args, Debug)]
+pub struct CopyArgs {
+    /// Archive(s) to copy (hashes or references such as `latest`)
+    #[arg(required = true, value_parser = parse_archive_ref)]
+    pub archives: Vec<ArchiveRef>,
+
+    /// Repository URL or local directory to copy the archives to (e.g. `s3://other-bucket/team-a/`
+    /// or `/mnt/backup`)
+    #[arg(long, value_name = "URL", value_parser = parse_repo_location)]
+    pub to: RepoLocation,
+
+    /// S3 endpoint URL of the destination (or the same as the source)
+    #[arg(long, value_name = "URL")]
+    pub to_endpoint: Option<String>,
+
+    /// AWS region of the destination (or the same as the source)
+    #[arg(long)]
+    pub to_region: Option<String>,
+
+    /// Profile in the AWS config files to load destination credentials from (or the same as the
+    /// source)
+    #[arg(long, value_name = "NAME")]
+    pub to_aws_profile: Option<String>,
+
+    /// Storage class for packs of blocks in the destination
+    #[arg(long, value_name = "CLASS", value_parser = parse_storage_class)]
+    pub to_block_storage_class: Option<StorageClass>,
+
+    /// Storage class for archives and trees in the destination
+    #[arg(long, value_name = "CLASS", value_parser = parse_storage_class)]
+    pub to_archive_storage_class: Option<StorageClass>,
+
+    /// Storage class for metadata in the destination
+    #[arg(long, value_name = "CLASS", value_parser = parse_storage_class)]
+    pub to_metadata_storage_class: Option<StorageClass>,
+
+    /// KMS key to encrypt new objects in the destination with (SSE-KMS)
+    #[arg(long, value_name = "KEY_ID")]
+    pub to_sse_kms_key: Option<String>,
+
+    /// Tag to add to every new object in the destination (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_object_tag)]
+    pub to_object_tag: Vec<(String, String)>,
+
+    /// Compression level (1-19) to re-encode copied blocks with (or copy them as they're stored)
+    #[arg(long, value_name = "NUM", value_parser = parse_compression_level)]
+    pub to_compression_level: Option<u8>,
+
+    #[command(flatten)]
+    pub block_cache: BlockCacheArgs,
+
+    /// Number of background tasks to use
+    #[arg(
+        short = 'j',
+        long,
+        value_name = "NUM",
+        default_value_t = DEFAULT_TASK_COUNT,
+        value_parser = parse_task_count,
+    )]
+    pub tasks: usize,
+
+    /// Show operations that would be performed without actually doing them
+    #[arg(short = 'n', long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+impl CopyArgs {
+    /// Returns the client options for the destination, taking any that aren't given from the
+    /// source.
+    pub fn to_client_options(&self) -> ClientOptions {
+        let source = self.global.client.options();
+        ClientOptions {
+            endpoint_url: self.to_endpoint.clone().or(source.endpoint_url),
+            region: self.to_region.clone().or(source.region),
+            profile_name: self.to_aws_profile.clone().or(source.profile_name),
+        }
+    }
+
+    /// Returns whether packs can be copied on the server, which needs the destination to be in S3
+    /// on the same endpoint as the source. Re-encoded blocks can't be, since packs are copied as
+    /// they're stored.
+    pub fn server_side_copy(&self) -> bool {
+        matches!(self.to, RepoLocation::S3(_))
+            && self.to_compression_level.is_none()
+            && self
+                .to_client_options()
+                .same_endpoint(&self.global.client.options())
+    }
+
+    /// Returns the options for new objects in the destination. Unlike the client options, these
+    /// aren't taken from the source, whose storage classes and KMS key may not suit or exist in
+    /// the destination.
+    pub fn to_object_options(&self) -> ObjectOptions {
+        ObjectOptions {
+            block_storage_class: self.to_block_storage_class.clone(),
+            archive_storage_class: self.to_archive_storage_class.clone(),
+            metadata_storage_class: self.to_metadata_storage_class.clone(),
+            sse_kms_key_id: self.to_sse_kms_key.clone(),
+            tags: self.to_object_tag.clone(),
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct DeleteArgs {
-    /// Archive(s) to delete
-    #[arg(required = true, value_parser = parse_archive_hash)]
-    pub archives: Vec<ShortHash<Archive>>,
+    /// Archive(s) to delete (hashes or references such as `latest`)
+    #[arg(required = true, value_parser = parse_archive_ref)]
+    pub archives: Vec<ArchiveRef>,
+
+    /// Number of background tasks to use
+    #[arg(
+        short = 'j',
+        long,
+        value_name = "NUM",
+        default_value_t = DEFAULT_TASK_COUNT,
+        value_parser = parse_task_count,
+    )]
+    pub tasks: usize,
+
+    /// Show operations that would be performed without actually doing them
+    #[arg(short = 'n', long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct PruneArgs {
+    /// Number of most recent archives to keep
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    pub keep_last: usize,
+
+    /// Number of hourly archives to keep
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    pub keep_hourly: usize,
+
+    /// Number of daily archives to keep
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    pub keep_daily: usize,
+
+    /// Number of weekly archives to keep
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    pub keep_weekly: usize,
+
+    /// Number of monthly archives to keep
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    pub keep_monthly: usize,
+
+    /// Number of yearly archives to keep
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    pub keep_yearly: usize,
+
+    /// Keep all archives created within this duration (e.g. "30days")
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    pub keep_within: Option<Duration>,
+
+    /// Apply the policy separately to each group of archives with the same values
+    #[arg(long, value_name = "FIELD", value_delimiter = ',')]
+    pub group_by: Vec<GroupBy>,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
 
     /// Number of background tasks to use
     #[arg(
@@ -135,8 +432,25 @@ pub struct DeleteArgs {
     pub global: GlobalArgs,
 }
 
+impl PruneArgs {
+    pub fn policy(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_last: self.keep_last,
+            keep_hourly: self.keep_hourly,
+            keep_daily: self.keep_daily,
+            keep_weekly: self.keep_weekly,
+            keep_monthly: self.keep_monthly,
+            keep_yearly: self.keep_yearly,
+            keep_within: self.keep_within,
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct ArchivesArgs {
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
     #[command(flatten)]
     pub global: GlobalArgs,
 }
@@ -160,20 +474,324 @@ pub struct CleanupArgs {
     pub global: GlobalArgs,
 }
 
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// Download all blocks and verify their contents
+    #[arg(long, default_value_t = false)]
+    pub read_data: bool,
+
+    /// Download a percentage of blocks and verify their contents (1-100)
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = parse_percentage,
+        conflicts_with = "read_data",
+    )]
+    pub read_data_subset: Option<u8>,
+
+    /// Number that picks the blocks read by --read-data-subset, to read the same ones again (random
+    /// by default)
+    #[arg(long, value_name = "NUM", requires = "read_data_subset")]
+    pub seed: Option<u64>,
+
+    /// Number of background tasks to use
+    #[arg(
+        short = 'j',
+        long,
+        value_name = "NUM",
+        default_value_t = DEFAULT_TASK_COUNT,
+        value_parser = parse_task_count,
+    )]
+    pub tasks: usize,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RebuildIndexArgs {
+    /// Number of background tasks to use
+    #[arg(
+        short = 'j',
+        long,
+        value_name = "NUM",
+        default_value_t = DEFAULT_TASK_COUNT,
+        value_parser = parse_task_count,
+    )]
+    pub tasks: usize,
+
+    /// Show operations that would be performed without actually doing them
+    #[arg(short = 'n', long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct FilterArgs {
+    /// Only include archives with this name
+    #[arg(long, value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// Only include archives whose description contains this text
+    #[arg(long, value_name = "TEXT")]
+    pub description: Option<String>,
+
+    /// Only include archives with this tag (can be repeated)
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
+    /// Only include archives created on this host
+    #[arg(long = "host", value_name = "HOSTNAME")]
+    pub hostname: Option<String>,
+
+    /// Only include archives created by this user
+    #[arg(long = "user", value_name = "USERNAME")]
+    pub username: Option<String>,
+
+    /// Only include archives that backed up this path
+    #[arg(long, value_name = "PATH")]
+    pub path: Option<PathBuf>,
+}
+
+impl FilterArgs {
+    pub fn filter(&self) -> crate::error::Result<ArchiveFilter> {
+        let path = self.path.as_deref().map(std::path::absolute).transpose()?;
+        Ok(ArchiveFilter {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            tags: self.tags.clone(),
+            hostname: self.hostname.clone(),
+            username: self.username.clone(),
+            path,
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct BlockCacheArgs {
+    /// Directory to cache downloaded blocks in
+    #[arg(long, value_name = "DIR")]
+    pub block_cache: Option<PathBuf>,
+
+    /// Maximum total size of cached blocks (e.g. 500M or 1Gi)
+    #[arg(long, value_name = "SIZE", default_value = "1Gi", value_parser = parse_bytes)]
+    pub block_cache_size: u64,
+}
+
+impl BlockCacheArgs {
+    pub async fn open(&self) -> crate::error::Result<Option<DiskBlockCache>> {
+        match &self.block_cache {
+            Some(dir) => Ok(Some(
+                DiskBlockCache::open(dir, self.block_cache_size).await?,
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct FailureArgs {
+    /// Succeed even if some files fail
+    #[arg(long, default_value_t = false, conflicts_with = "fail_fast")]
+    pub keep_going: bool,
+
+    /// Stop at the first file that fails
+    #[arg(long, default_value_t = false)]
+    pub fail_fast: bool,
+}
+
+impl FailureArgs {
+    pub fn mode(&self) -> FailureMode {
+        if self.fail_fast {
+            FailureMode::FailFast
+        } else if self.keep_going {
+            FailureMode::KeepGoing
+        } else {
+            FailureMode::Fail
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct GlobalArgs {
     /// S3 bucket
     #[arg(short = 'b', long)]
     pub bucket: Option<String>,
 
+    /// Repository URL, for a repository under a prefix of a bucket (e.g. `s3://bucket/team-a/`)
+    #[arg(long, value_name = "URL", conflicts_with = "bucket", value_parser = parse_repo_url)]
+    pub repo: Option<RepoUrl>,
+
+    /// Profile in the config file to take defaults from (or `default`, if it exists)
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
     /// Format to use for stats
     #[arg(long)]
     pub stats: Option<StatsType>,
 
+    /// Don't cache metadata locally
+    #[arg(long, default_value_t = false)]
+    pub no_cache: bool,
+
+    #[command(flatten)]
+    pub client: ClientArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+
+    #[command(flatten)]
+    pub limits: LimitArgs,
+
+    #[command(flatten)]
+    pub objects: ObjectArgs,
+
     #[command(flatten)]
     pub logger: LoggerArgs,
 }
 
+#[derive(Args, Debug)]
+pub struct ClientArgs {
+    /// S3 endpoint URL, for S3-compatible services
+    #[arg(long, value_name = "URL")]
+    pub endpoint: Option<String>,
+
+    /// AWS region
+    #[arg(long)]
+    pub region: Option<String>,
+
+    /// Profile in the AWS config files to load credentials from
+    #[arg(long, value_name = "NAME")]
+    pub aws_profile: Option<String>,
+}
+
+impl ClientArgs {
+    pub fn options(&self) -> ClientOptions {
+        ClientOptions {
+            endpoint_url: self.endpoint.clone(),
+            region: self.region.clone(),
+            profile_name: self.aws_profile.clone(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct RetryArgs {
+    /// Maximum number of times to send each request (1-100)
+    #[arg(
+        long,
+        value_name = "NUM",
+        default_value_t = DEFAULT_MAX_ATTEMPTS,
+        value_parser = parse_max_attempts,
+    )]
+    pub max_attempts: u32,
+
+    /// Delay before retrying a failed request, which doubles with each retry
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "100ms",
+        value_parser = humantime::parse_duration,
+    )]
+    pub retry_delay: Duration,
+
+    /// Maximum delay before retrying a failed request
+    #[arg(
+        long,
+        value_name = "DURATION",
+        default_value = "20s",
+        value_parser = humantime::parse_duration,
+    )]
+    pub max_retry_delay: Duration,
+
+    /// Don't randomize delays before retrying failed requests
+    #[arg(long, default_value_t = false)]
+    pub no_retry_jitter: bool,
+
+    /// Errors to retry requests after (comma-separated)
+    #[arg(
+        long = "retry-on",
+        value_name = "ERRORS",
+        value_delimiter = ',',
+        default_values_t = RetryableError::ALL,
+    )]
+    pub retryable_errors: Vec<RetryableError>,
+}
+
+impl RetryArgs {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: self.retry_delay,
+            max_delay: self.max_retry_delay,
+            jitter: !self.no_retry_jitter,
+            retryable_errors: self.retryable_errors.clone(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct LimitArgs {
+    /// Maximum bytes per second to upload (e.g. 10M)
+    #[arg(long, value_name = "RATE", value_parser = parse_byte_rate)]
+    pub limit_upload: Option<u64>,
+
+    /// Maximum bytes per second to download (e.g. 10M)
+    #[arg(long, value_name = "RATE", value_parser = parse_byte_rate)]
+    pub limit_download: Option<u64>,
+
+    /// Maximum number of requests to send per second
+    #[arg(long, value_name = "NUM", value_parser = parse_request_rate)]
+    pub max_request_rate: Option<u64>,
+}
+
+impl LimitArgs {
+    pub fn limits(&self) -> RateLimits {
+        RateLimits {
+            upload_bytes_per_second: self.limit_upload,
+            download_bytes_per_second: self.limit_download,
+            requests_per_second: self.max_request_rate,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ObjectArgs {
+    /// Storage class for packs of blocks
+    #[arg(long, value_name = "CLASS", value_parser = parse_storage_class)]
+    pub block_storage_class: Option<StorageClass>,
+
+    /// Storage class for archives and trees
+    #[arg(long, value_name = "CLASS", value_parser = parse_storage_class)]
+    pub archive_storage_class: Option<StorageClass>,
+
+    /// Storage class for metadata
+    #[arg(long, value_name = "CLASS", value_parser = parse_storage_class)]
+    pub metadata_storage_class: Option<StorageClass>,
+
+    /// KMS key to encrypt new objects with (SSE-KMS)
+    #[arg(long, value_name = "KEY_ID")]
+    pub sse_kms_key: Option<String>,
+
+    /// Tag to add to every new object (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_object_tag)]
+    pub object_tag: Vec<(String, String)>,
+}
+
+impl ObjectArgs {
+    pub fn options(&self) -> ObjectOptions {
+        ObjectOptions {
+            block_storage_class: self.block_storage_class.clone(),
+            archive_storage_class: self.archive_storage_class.clone(),
+            metadata_storage_class: self.metadata_storage_class.clone(),
+            sse_kms_key_id: self.sse_kms_key.clone(),
+            tags: self.object_tag.clone(),
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct LoggerArgs {
     /// When to use color in output
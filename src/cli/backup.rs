@@ -1,23 +1,38 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    io,
+    path::{self, PathBuf},
+    sync::Arc,
+};
 
+use chrono::{DateTime, Utc};
 use clap::builder::styling::AnsiColor;
 use humantime::format_duration;
-use log::info;
-use tokio::try_join;
+use log::{info, warn};
+use tokio::{sync::Mutex, task::JoinHandle, try_join};
 
 use crate::{
     arc::{rwarc, unarc, unrwarc},
-    archive::Archive,
+    archive::{Archive, ArchiveInfo, INCOMPLETE_TAG},
+    block::BlockRecords,
     entity::EntityIndex,
-    error::Result,
+    env,
+    error::{Error, Result},
+    file::{ExcludeSet, FileTree},
     format::{format_size, format_speed},
     locks::BlockLocks,
     ops::{
-        BackupState, backup_all, download_archive_records, download_block_records,
-        try_delete_blocks, upload_archive, upload_archive_records, upload_block_records,
-        upload_pending_files,
+        BackupState, FileFailures, backup_all, delete_checkpoint, delete_transient_packs,
+        download_archive_records, download_block_manifest, download_pack_records,
+        download_tree_records, find_stale_checkpoints, remove_stale_checkpoints,
+        replace_checkpoint, scan_totals, upload_archive, upload_backup_records,
+        upload_pending_files, upload_pending_pack, upload_trees, write_format_version,
     },
-    stats::CommandStats,
+    pack::PackBuilder,
+    progress::{Direction, ProgressReporter},
+    signal::Cancellation,
+    stats::{CommandStats, FinalizedCommandStats},
+    tree::DirRefs,
 };
 
 use super::{
@@ -27,71 +42,174 @@ use super::{
 };
 
 pub async fn main(cli: BackupArgs) -> Result<()> {
-    let stats = rwarc(CommandStats::new());
+    let stats = CommandStats::new();
+    let info = archive_info(&cli, stats.start_time)?;
+    let stats = rwarc(stats);
     let storage = Arc::new(create_storage(&cli.global).await?);
-    let archive = rwarc(Archive::new());
+    if !cli.dry_run {
+        write_format_version(&storage).await?;
+    }
+    let files = rwarc(FileTree::new());
+    let dir_refs = rwarc(DirRefs::new());
     let block_locks = rwarc(BlockLocks::new());
+    let cancellation = Cancellation::listen();
 
-    let (mut archive_records, block_records) = try_join!(
+    let (archive_records, block_manifest, pack_records, tree_records) = try_join!(
         download_archive_records(storage.clone()),
-        download_block_records(storage.clone()),
+        download_block_manifest(storage.clone()),
+        download_pack_records(storage.clone()),
+        download_tree_records(storage.clone()),
     )?;
 
-    let block_records = rwarc(block_records);
+    // checkpoints only make sense for backups that are kept
+    let checkpoint_interval = Some(cli.checkpoint_interval)
+        .filter(|interval| !cli.transient && !cli.dry_run && !interval.is_zero());
+
+    // packs that a transient backup must not delete, even if they end up unused
+    let existing_packs = if cli.transient {
+        pack_records.iter().map(|(hash, _)| *hash).collect()
+    } else {
+        HashSet::new()
+    };
+
+    // block shards are loaded as blocks are looked up
+    let archive_records = rwarc(archive_records);
+    let block_records = rwarc(BlockRecords::unloaded(block_manifest));
+    let pack_records = rwarc(pack_records);
+    let tree_records = rwarc(tree_records);
     let state = Arc::new(BackupState {
+        info,
         compression_level: cli.compression_level,
         target_block_size: cli.target_block_size,
+        inline_threshold: cli.inline_threshold,
         task_count: cli.tasks,
+        checkpoint_interval,
         dry_run: cli.dry_run,
+        excludes: ExcludeSet::new(cli.excludes),
+        cancellation,
+        failures: FileFailures::new(cli.failure.mode(), "back up"),
         stats,
         storage,
-        archive,
+        files,
+        dir_refs,
+        archive_records,
         block_records,
+        pack_records,
+        tree_records,
+        pack_builder: Mutex::new(PackBuilder::new()),
         block_locks,
+        checkpoint: Mutex::new(None),
+        stale_checkpoints: Mutex::new(vec![]),
     });
+
+    // only the final archive of a backup that is kept replaces the checkpoints of earlier runs
+    if !cli.transient && !cli.dry_run {
+        let stale_checkpoints = find_stale_checkpoints(&state).await?;
+        if !stale_checkpoints.is_empty() {
+            info!("found {} stale checkpoint(s)", stale_checkpoints.len());
+        }
+        *state.stale_checkpoints.lock().await = stale_checkpoints;
+    }
+
     let (sender, receiver) = async_channel::bounded(state.task_count);
 
+    let progress = ProgressReporter::start(cli.progress, Direction::Upload, state.stats.clone());
+    let maybe_scan = start_scan(&progress, &state, &cli.paths);
+
     try_join!(
         backup_all(state.clone(), sender, &cli.paths),
         upload_pending_files(state.clone(), receiver),
     )?;
+    upload_pending_pack(state.clone()).await?;
+
+    if let Some(scan) = maybe_scan {
+        scan.abort();
+    }
+    progress.finish().await;
+
+    let cancelled = state.cancellation.is_cancelled();
+
+    if cli.transient {
+        delete_transient_packs(&state, &existing_packs).await?;
+    } else {
+        create_archive(&state, cancelled).await?;
+    }
 
     let BackupState {
+        failures,
         stats,
         storage,
-        archive,
-        block_records,
         ..
     } = unarc(state);
     let stats = unrwarc(stats);
+    let storage = unarc(storage);
+    let full_stats = stats.finalize(storage.stats());
 
-    if cli.transient {
-        let archive = unrwarc(archive);
-        let mut block_records = unrwarc(block_records);
-        let removed_blocks = block_records.remove_refs(archive.block_refs);
-        let removed_hashes = removed_blocks.map(|result| result.map(|(hash, _)| hash));
-        try_delete_blocks(storage.clone(), removed_hashes, cli.tasks).await?;
-    } else {
-        let (hash, record) = upload_archive(storage.clone(), archive, stats.start_time).await?;
-        archive_records.insert(hash, record);
-
-        if !cli.dry_run {
-            try_join!(
-                upload_block_records(storage.clone(), block_records.clone()),
-                upload_archive_records(storage.clone(), rwarc(archive_records)),
-            )?;
-        }
+    print_stats(cli.global.stats, &full_stats)?;
+
+    let failures_result = failures.finish();
+    if cancelled {
+        return Err(Error::Interrupted);
+    }
+
+    failures_result
+}
+
+/// Starts counting the files to back up in the background, if progress is shown.
+fn start_scan(
+    progress: &ProgressReporter,
+    state: &BackupState,
+    paths: &[PathBuf],
+) -> Option<JoinHandle<()>> {
+    progress.is_enabled().then(|| {
+        let paths = paths.to_vec();
+        let excludes = state.excludes.clone();
+        let totals = progress.totals();
+        tokio::spawn(async move {
+            let _ = totals.set(scan_totals(&paths, excludes).await);
+        })
+    })
+}
+
+/// Uploads the archive of a backup that is kept, which replaces the backup's checkpoint.
+async fn create_archive(state: &Arc<BackupState>, cancelled: bool) -> Result<()> {
+    let mut info = state.info.clone();
+    if cancelled {
+        info.tags.push(INCOMPLETE_TAG.to_owned());
+    }
+
+    let tree = upload_trees(state.clone()).await?;
+    let archive = Archive::new(info, tree);
+    let (hash, record) = upload_archive(state.storage.clone(), archive, state.dry_run).await?;
+    state.archive_records.write().await.insert(hash, record);
+
+    // the final archive covers every file in the checkpoint, but an incomplete one may not cover
+    // every file in the checkpoints of earlier runs
+    let mut removed = Vec::from_iter(replace_checkpoint(state, None).await?);
+    if !cancelled {
+        removed.extend(remove_stale_checkpoints(state).await?);
+    }
+    if !state.dry_run {
+        upload_backup_records(state).await?;
+    }
+    for removed in removed {
+        delete_checkpoint(state, removed).await?;
+    }
 
-        let block_count = block_records.read().await.len();
-        let short_hash = hash.format_short(block_count);
+    let archive_count = state.archive_records.read().await.len();
+    let short_hash = hash.format_short(archive_count);
+    if cancelled {
+        warn!("created incomplete archive {short_hash}");
+    } else {
         let style = AnsiColor::Green.on_default();
         info!("{style}created archive{style:#} {short_hash}");
     }
 
-    let storage = unarc(storage);
-    let full_stats = stats.finalize(storage.stats());
+    Ok(())
+}
 
-    match cli.global.stats {
+fn print_stats(stats_type: Option<StatsType>, full_stats: &FinalizedCommandStats) -> Result<()> {
+    match stats_type {
         Some(StatsType::Basic) => {
             print_stat(
                 "metadata downloaded",
@@ -107,16 +225,38 @@ pub async fn main(cli: BackupArgs) -> Result<()> {
             );
             print_stat("bytes read", format_size(full_stats.bytes_read));
             print_stat("files read", full_stats.files_read);
+            print_stat("files inlined", full_stats.files_inlined);
             print_stat("blocks uploaded", full_stats.blocks_uploaded);
+            print_stat("packs uploaded", full_stats.packs_uploaded);
+            print_stat("trees uploaded", full_stats.trees_uploaded);
             print_stat("blocks referenced", full_stats.blocks_referenced);
+            print_stat("requests retried", full_stats.storage.retries);
             print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
             print_stat("upload speed", format_speed(full_stats.upload_speed()));
         }
         Some(StatsType::Json) => {
-            print_stats_json(&full_stats)?;
+            print_stats_json(full_stats)?;
         }
         None => {}
     }
 
     Ok(())
 }
+
+fn archive_info(cli: &BackupArgs, created: DateTime<Utc>) -> Result<ArchiveInfo> {
+    let paths = cli
+        .paths
+        .iter()
+        .map(path::absolute)
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(ArchiveInfo {
+        created,
+        name: cli.name.clone(),
+        description: cli.description.clone(),
+        tags: cli.tags.clone(),
+        hostname: env::hostname()?,
+        username: env::username()?,
+        paths,
+    })
+}
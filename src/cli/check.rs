@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use clap::builder::styling::AnsiColor;
+use humantime::format_duration;
+use log::info;
+use tokio::try_join;
+
+use crate::{
+    arc::{rwarc, unarc, unrwarc},
+    archive::Archive,
+    error::{Error, Result},
+    format::format_size,
+    ops::{
+        CheckState, check_archive_objects, check_archives, check_block_data, check_block_trees,
+        check_pack_objects, check_tree_objects, download_archive_records, download_block_records,
+        download_pack_records, download_tree_records, list_hashes,
+    },
+    pack::Pack,
+    stats::CommandStats,
+    tree::Tree,
+};
+
+use super::{
+    args::{CheckArgs, StatsType},
+    print_stat, print_stats_json,
+    storage::create_storage,
+};
+
+const ALL_BLOCKS_PERCENTAGE: u8 = 100;
+
+pub async fn main(cli: CheckArgs) -> Result<()> {
+    let stats = rwarc(CommandStats::new());
+    let storage = Arc::new(create_storage(&cli.global).await?);
+
+    let (archive_records, block_records, pack_records, tree_records) = try_join!(
+        download_archive_records(storage.clone()),
+        download_block_records(storage.clone(), cli.tasks),
+        download_pack_records(storage.clone()),
+        download_tree_records(storage.clone()),
+    )?;
+    let (archive_hashes, pack_hashes, tree_hashes) = try_join!(
+        list_hashes::<Archive>(storage.clone()),
+        list_hashes::<Pack>(storage.clone()),
+        list_hashes::<Tree>(storage.clone()),
+    )?;
+
+    let state = Arc::new(CheckState {
+        task_count: cli.tasks,
+        stats,
+        storage,
+        archive_records,
+        block_records,
+        pack_records,
+        tree_records,
+        archive_hashes,
+        pack_hashes,
+        tree_hashes,
+    });
+
+    check_pack_objects(state.clone()).await;
+    check_archive_objects(state.clone()).await;
+    check_tree_objects(state.clone()).await;
+    let roots = check_archives(state.clone()).await?;
+    check_block_trees(state.clone(), roots).await?;
+
+    let read_data_percentage = if cli.read_data {
+        Some(ALL_BLOCKS_PERCENTAGE)
+    } else {
+        cli.read_data_subset
+    };
+
+    if let Some(percentage) = read_data_percentage {
+        let seed = cli
+            .seed
+            .unwrap_or_else(|| Utc::now().timestamp().unsigned_abs());
+        if percentage < ALL_BLOCKS_PERCENTAGE {
+            info!("reading blocks selected with seed {seed}");
+        }
+        check_block_data(state.clone(), percentage, seed).await?;
+    }
+
+    let CheckState { stats, storage, .. } = unarc(state);
+    let stats = unrwarc(stats);
+    let problems_found = stats.problems_found;
+
+    if problems_found == 0 {
+        let style = AnsiColor::Green.on_default();
+        info!("{style}no problems found{style:#}");
+    }
+
+    let storage = unarc(storage);
+    let full_stats = stats.finalize(storage.stats());
+
+    match cli.global.stats {
+        Some(StatsType::Basic) => {
+            print_stat(
+                "content downloaded",
+                format_size(full_stats.content_bytes_downloaded),
+            );
+            print_stat(
+                "metadata downloaded",
+                format_size(full_stats.metadata_bytes_downloaded()),
+            );
+            print_stat("archives checked", full_stats.archives_checked);
+            print_stat("blocks checked", full_stats.blocks_checked);
+            print_stat("problems found", full_stats.problems_found);
+            print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
+        }
+        Some(StatsType::Json) => {
+            print_stats_json(&full_stats)?;
+        }
+        None => {}
+    }
+
+    if problems_found > 0 {
+        return Err(Error::CheckFailed(problems_found));
+    }
+
+    Ok(())
+}
@@ -5,11 +5,14 @@ use tokio::try_join;
 
 use crate::{
     arc::{rwarc, unarc, unrwarc},
+    block::BlockRecords,
     error::Result,
     format::format_size,
     ops::{
-        CleanupState, cleanup_archives, cleanup_blocks, download_archive_records,
-        download_block_records, upload_archive_records, upload_block_records,
+        CleanupState, cleanup_archives, cleanup_packs, cleanup_trees, delete_packs,
+        download_archive_records, download_block_manifest, download_pack_records,
+        download_tree_records, repack_packs, upload_archive_records, upload_block_records,
+        upload_pack_records, upload_tree_records, write_format_version,
     },
     stats::CommandStats,
 };
@@ -23,14 +26,22 @@ use super::{
 pub async fn main(cli: CleanupArgs) -> Result<()> {
     let stats = rwarc(CommandStats::new());
     let storage = Arc::new(create_storage(&cli.global).await?);
+    if !cli.dry_run {
+        write_format_version(&storage).await?;
+    }
 
-    let (archive_records, block_records) = try_join!(
+    let (archive_records, block_manifest, pack_records, tree_records) = try_join!(
         download_archive_records(storage.clone()),
-        download_block_records(storage.clone()),
+        download_block_manifest(storage.clone()),
+        download_pack_records(storage.clone()),
+        download_tree_records(storage.clone()),
     )?;
 
     let archive_records = rwarc(archive_records);
-    let block_records = rwarc(block_records);
+    // block shards are loaded as packs are repacked
+    let block_records = rwarc(BlockRecords::unloaded(block_manifest));
+    let pack_records = rwarc(pack_records);
+    let tree_records = rwarc(tree_records);
     let state = Arc::new(CleanupState {
         task_count: cli.tasks,
         dry_run: cli.dry_run,
@@ -38,27 +49,34 @@ pub async fn main(cli: CleanupArgs) -> Result<()> {
         storage,
         archive_records,
         block_records,
+        pack_records,
+        tree_records,
     });
 
     cleanup_archives(state.clone()).await?;
-    cleanup_blocks(state.clone()).await?;
-
-    let CleanupState {
-        stats,
-        storage,
-        archive_records,
-        block_records,
-        ..
-    } = unarc(state);
-    let stats = unrwarc(stats);
+    cleanup_trees(state.clone()).await?;
+    cleanup_packs(state.clone()).await?;
+    let repacked_packs = repack_packs(state.clone()).await?;
 
     if !cli.dry_run {
         try_join!(
-            upload_archive_records(storage.clone(), archive_records),
-            upload_block_records(storage.clone(), block_records),
+            upload_archive_records(state.storage.clone(), state.archive_records.clone()),
+            upload_block_records(
+                state.storage.clone(),
+                state.block_records.clone(),
+                cli.tasks
+            ),
+            upload_pack_records(state.storage.clone(), state.pack_records.clone()),
+            upload_tree_records(state.storage.clone(), state.tree_records.clone()),
         )?;
     }
 
+    // the old packs are referenced by the previous records until the new ones are uploaded
+    delete_packs(state.clone(), repacked_packs).await?;
+
+    let CleanupState { stats, storage, .. } = unarc(state);
+    let stats = unrwarc(stats);
+
     let storage = unarc(storage);
     let full_stats = stats.finalize(storage.stats());
 
@@ -73,7 +91,11 @@ pub async fn main(cli: CleanupArgs) -> Result<()> {
                 format_size(full_stats.metadata_bytes_uploaded()),
             );
             print_stat("archives deleted", full_stats.archives_deleted);
+            print_stat("trees deleted", full_stats.trees_deleted);
             print_stat("blocks deleted", full_stats.blocks_deleted);
+            print_stat("blocks repacked", full_stats.blocks_repacked);
+            print_stat("packs uploaded", full_stats.packs_uploaded);
+            print_stat("packs deleted", full_stats.packs_deleted);
             print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
         }
         Some(StatsType::Json) => {
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgMatches, parser::ValueSource};
+use serde::Deserialize;
+
+use crate::{
+    env,
+    error::{Error, Result},
+};
+
+use super::{
+    Command,
+    args::{
+        PruneArgs, parse_compression_level, parse_exclude_pattern, parse_repo_url, parse_task_count,
+    },
+    storage::{ENV_VAR_REPO, ENV_VAR_STORAGE},
+};
+
+const CONFIG_DIR_NAME: &str = "cubist";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Profile that's used when `--profile` isn't given, if the config file has it.
+const DEFAULT_PROFILE: &str = "default";
+
+/// Environment variables that override the profile's options, as they would if they were given on
+/// the command line. The AWS SDK reads its own variables when the client options aren't given.
+const REPO_ENV_VARS: [&str; 2] = [ENV_VAR_REPO, ENV_VAR_STORAGE];
+const ENDPOINT_ENV_VARS: [&str; 1] = ["AWS_ENDPOINT_URL"];
+const REGION_ENV_VARS: [&str; 2] = ["AWS_REGION", "AWS_DEFAULT_REGION"];
+const AWS_PROFILE_ENV_VARS: [&str; 1] = ["AWS_PROFILE"];
+
+/// Options that make up a retention policy. If any of them are given, the whole policy is taken
+/// from the command line instead of the profile.
+const RETENTION_ARGS: [&str; 7] = [
+    "keep_last",
+    "keep_hourly",
+    "keep_daily",
+    "keep_weekly",
+    "keep_monthly",
+    "keep_yearly",
+    "keep_within",
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    profiles: HashMap<String, Profile>,
+}
+
+/// Defaults for options that aren't given on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(clippy::struct_field_names)]
+pub struct Profile {
+    repo: Option<String>,
+    endpoint: Option<String>,
+    region: Option<String>,
+    aws_profile: Option<String>,
+    tasks: Option<usize>,
+    compression_level: Option<u8>,
+    exclude: Option<Vec<String>>,
+    retention: Option<RetentionConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(clippy::struct_field_names)]
+struct RetentionConfig {
+    keep_last: usize,
+    keep_hourly: usize,
+    keep_daily: usize,
+    keep_weekly: usize,
+    keep_monthly: usize,
+    keep_yearly: usize,
+    keep_within: Option<String>,
+}
+
+/// Returns the path of the config file, `$XDG_CONFIG_HOME/cubist/config.toml`.
+pub fn config_path() -> Option<PathBuf> {
+    env::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME))
+}
+
+impl Config {
+    /// Loads the config file at `path`, or an empty config if it doesn't exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text, path),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parses the text of the config file at `path`.
+    pub fn parse(text: &str, path: &Path) -> Result<Self> {
+        toml::from_str(text).map_err(|err| Error::InvalidConfig {
+            path: path.to_owned(),
+            message: err.message().to_owned(),
+        })
+    }
+
+    /// Returns the profile named `maybe_name`, or the default profile if no name is given.
+    pub fn profile(&self, maybe_name: Option<&str>) -> Result<Option<(&str, &Profile)>> {
+        let name = maybe_name.unwrap_or(DEFAULT_PROFILE);
+        match self.profiles.get_key_value(name) {
+            Some((name, profile)) => Ok(Some((name.as_str(), profile))),
+            None if maybe_name.is_some() => Err(Error::ProfileNotFound(name.to_owned())),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Profile {
+    /// Fills in the options of `command` that weren't given on the command line, as told by
+    /// `matches`, or by an environment variable that `is_env_set` reports as set.
+    pub fn apply<F>(
+        &self,
+        name: &str,
+        command: &mut Command,
+        matches: &ArgMatches,
+        is_env_set: F,
+    ) -> Result<()>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let any_env_set = |vars: &[&str]| vars.iter().any(|var| is_env_set(var));
+        let invalid = |field, message| Error::InvalidProfile {
+            profile: name.to_owned(),
+            field,
+            message,
+        };
+
+        let global = command.global_mut();
+        if global.repo.is_none()
+            && global.bucket.is_none()
+            && !any_env_set(&REPO_ENV_VARS)
+            && let Some(repo) = &self.repo
+        {
+            global.repo = Some(parse_repo_url(repo).map_err(|message| invalid("repo", message))?);
+        }
+
+        let client = &mut global.client;
+        if !any_env_set(&ENDPOINT_ENV_VARS) {
+            fill(&mut client.endpoint, self.endpoint.as_deref());
+        }
+        if !any_env_set(&REGION_ENV_VARS) {
+            fill(&mut client.region, self.region.as_deref());
+        }
+        if !any_env_set(&AWS_PROFILE_ENV_VARS) {
+            fill(&mut client.aws_profile, self.aws_profile.as_deref());
+        }
+
+        // only look up arguments that the command has, since clap rejects unknown ones
+        if let Some(tasks) = self.tasks
+            && let Some(command_tasks) = command.tasks_mut()
+            && !is_given(matches, "tasks")
+        {
+            *command_tasks = parse_task_count(&tasks.to_string())
+                .map_err(|message| invalid("tasks", message))?;
+        }
+
+        if let Command::Backup(args) = command
+            && let Some(level) = self.compression_level
+            && !is_given(matches, "compression_level")
+        {
+            args.compression_level = parse_compression_level(&level.to_string())
+                .map_err(|message| invalid("compression_level", message))?;
+        }
+
+        if let Command::Backup(args) = command
+            && let Some(patterns) = &self.exclude
+            && !is_given(matches, "excludes")
+        {
+            args.excludes = patterns
+                .iter()
+                .map(|pattern| parse_exclude_pattern(pattern))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|message| invalid("exclude", message))?;
+        }
+
+        if let Command::Prune(args) = command
+            && let Some(retention) = &self.retention
+            && !RETENTION_ARGS.iter().any(|id| is_given(matches, id))
+        {
+            retention
+                .apply(args)
+                .map_err(|message| invalid("retention", message))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RetentionConfig {
+    fn apply(&self, args: &mut PruneArgs) -> std::result::Result<(), String> {
+        args.keep_last = self.keep_last;
+        args.keep_hourly = self.keep_hourly;
+        args.keep_daily = self.keep_daily;
+        args.keep_weekly = self.keep_weekly;
+        args.keep_monthly = self.keep_monthly;
+        args.keep_yearly = self.keep_yearly;
+        args.keep_within = self
+            .keep_within
+            .as_deref()
+            .map(humantime::parse_duration)
+            .transpose()
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+}
+
+fn is_given(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+fn fill(arg: &mut Option<String>, value: Option<&str>) {
+    if arg.is_none() {
+        *arg = value.map(ToOwned::to_owned);
+    }
+}
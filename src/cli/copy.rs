@@ -0,0 +1,188 @@
+use std::sync::{Arc, atomic::AtomicBool};
+
+use clap::builder::styling::AnsiColor;
+use humantime::format_duration;
+use itertools::Itertools;
+use log::info;
+use tokio::{
+    sync::{Mutex, RwLock},
+    try_join,
+};
+
+use crate::{
+    arc::{rwarc, unarc, unrwarc},
+    block::{BlockRecords, BlockRefs},
+    entity::EntityIndex,
+    error::Result,
+    format::format_size,
+    ops::{
+        CopyState, copy_archive_trees, copy_missing_blocks, download_archive_records,
+        download_block_manifest, download_pack_records, download_tree_records,
+        resolve_archive_refs, upload_archive_records, upload_block_records, upload_copied_archive,
+        upload_pack_records, upload_tree_records, write_format_version,
+    },
+    pack::PackBuilder,
+    stats::{CommandStats, FinalizedCommandStats},
+};
+
+use super::{
+    args::{CopyArgs, StatsType},
+    print_stat, print_stats_json,
+    storage::{create_storage, open_storage},
+};
+
+pub async fn main(cli: CopyArgs) -> Result<()> {
+    let stats = rwarc(CommandStats::new());
+    let source_storage = Arc::new(create_storage(&cli.global).await?);
+    let client_options = cli.to_client_options();
+    let server_side_copy = cli.server_side_copy();
+    let object_options = cli.to_object_options();
+    let storage = open_storage(&cli.global, cli.to.clone(), client_options, object_options);
+    let storage = Arc::new(storage.await?);
+    if !cli.dry_run {
+        write_format_version(&storage).await?;
+    }
+
+    let (
+        source_archive_records,
+        source_block_manifest,
+        archive_records,
+        block_manifest,
+        pack_records,
+        tree_records,
+    ) = try_join!(
+        download_archive_records(source_storage.clone()),
+        download_block_manifest(source_storage.clone()),
+        download_archive_records(storage.clone()),
+        download_block_manifest(storage.clone()),
+        download_pack_records(storage.clone()),
+        download_tree_records(storage.clone()),
+    )?;
+
+    let archive_hashes = resolve_archive_refs(
+        source_storage.clone(),
+        &source_archive_records,
+        &cli.archives,
+    )
+    .await?;
+
+    // packs can only be copied on the server if the destination's client can read the source
+    let source_pack_records = if server_side_copy {
+        Some(download_pack_records(source_storage.clone()).await?)
+    } else {
+        None
+    };
+
+    let source_block_cache = cli.block_cache.open().await?;
+
+    let archive_records = rwarc(archive_records);
+    // block shards of both repositories are loaded as blocks are copied
+    let block_records = rwarc(BlockRecords::unloaded(block_manifest));
+    let pack_records = rwarc(pack_records);
+    let tree_records = rwarc(tree_records);
+    let state = Arc::new(CopyState {
+        task_count: cli.tasks,
+        dry_run: cli.dry_run,
+        compression_level: cli.to_compression_level,
+        stats,
+        source_storage,
+        source_block_records: RwLock::new(BlockRecords::unloaded(source_block_manifest)),
+        source_pack_records,
+        source_block_cache,
+        storage,
+        archive_records,
+        block_records,
+        pack_records,
+        tree_records,
+        pack_builder: Mutex::new(PackBuilder::new()),
+        missing_blocks: Mutex::new(BlockRefs::new()),
+        copy_denied: AtomicBool::new(false),
+    });
+
+    let mut archives = vec![];
+    for hash in archive_hashes.into_iter().unique() {
+        let short_hash = hash.format_short(source_archive_records.len());
+        match copy_archive_trees(state.clone(), hash).await? {
+            Some(archive) => archives.push((hash, archive)),
+            None => info!("archive {short_hash} already exists in {}", cli.to),
+        }
+    }
+
+    // archives are only uploaded once all of the blocks they reference have been copied
+    copy_missing_blocks(state.clone()).await?;
+    for (hash, archive) in archives {
+        upload_copied_archive(state.clone(), hash, archive).await?;
+
+        let short_hash = hash.format_short(source_archive_records.len());
+        let style = AnsiColor::Green.on_default();
+        info!("{style}copied archive{style:#} {short_hash}");
+    }
+
+    let CopyState {
+        stats,
+        source_storage,
+        storage,
+        archive_records,
+        block_records,
+        pack_records,
+        tree_records,
+        ..
+    } = unarc(state);
+    let stats = unrwarc(stats);
+
+    if !cli.dry_run {
+        try_join!(
+            upload_archive_records(storage.clone(), archive_records),
+            upload_block_records(storage.clone(), block_records, cli.tasks),
+            upload_pack_records(storage.clone(), pack_records),
+            upload_tree_records(storage.clone(), tree_records),
+        )?;
+    }
+
+    let mut storage_stats = unarc(storage).stats();
+    storage_stats.merge(unarc(source_storage).stats());
+    let full_stats = stats.finalize(storage_stats);
+
+    print_stats(cli.global.stats, &full_stats)
+}
+
+fn print_stats(stats_type: Option<StatsType>, full_stats: &FinalizedCommandStats) -> Result<()> {
+    match stats_type {
+        Some(StatsType::Basic) => {
+            print_stat(
+                "content downloaded",
+                format_size(full_stats.content_bytes_downloaded),
+            );
+            print_stat(
+                "content uploaded",
+                format_size(full_stats.content_bytes_uploaded),
+            );
+            print_stat(
+                "content copied",
+                format_size(full_stats.storage.bytes_copied),
+            );
+            print_stat(
+                "metadata downloaded",
+                format_size(full_stats.metadata_bytes_downloaded()),
+            );
+            print_stat(
+                "metadata uploaded",
+                format_size(full_stats.metadata_bytes_uploaded()),
+            );
+            print_stat("archives copied", full_stats.archives_copied);
+            print_stat("trees uploaded", full_stats.trees_uploaded);
+            print_stat("blocks uploaded", full_stats.blocks_uploaded);
+            print_stat("blocks referenced", full_stats.blocks_referenced);
+            print_stat("packs uploaded", full_stats.packs_uploaded);
+            print_stat("packs copied", full_stats.packs_copied);
+            print_stat("requests retried", full_stats.storage.retries);
+            print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
+        }
+        Some(StatsType::Json) => {
+            print_stats_json(full_stats)?;
+        }
+        None => {}
+    }
+
+    Ok(())
+}
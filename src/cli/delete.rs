@@ -5,11 +5,14 @@ use tokio::try_join;
 
 use crate::{
     arc::{rwarc, unarc, unrwarc},
+    block::BlockRecords,
     error::Result,
     format::format_size,
     ops::{
         CleanupState, delete_archives_and_garbage_blocks, download_archive_records,
-        download_block_records, expand_hashes, upload_archive_records, upload_block_records,
+        download_block_manifest, download_pack_records, download_tree_records,
+        resolve_archive_refs, upload_archive_records, upload_block_records, upload_pack_records,
+        upload_tree_records,
     },
     stats::CommandStats,
 };
@@ -24,15 +27,21 @@ pub async fn main(cli: DeleteArgs) -> Result<()> {
     let stats = rwarc(CommandStats::new());
     let storage = Arc::new(create_storage(&cli.global).await?);
 
-    let archive_hashes = expand_hashes(storage.clone(), &cli.archives).await?;
-
-    let (archive_records, block_records) = try_join!(
+    let (archive_records, block_manifest, pack_records, tree_records) = try_join!(
         download_archive_records(storage.clone()),
-        download_block_records(storage.clone()),
+        download_block_manifest(storage.clone()),
+        download_pack_records(storage.clone()),
+        download_tree_records(storage.clone()),
     )?;
 
+    let archive_hashes =
+        resolve_archive_refs(storage.clone(), &archive_records, &cli.archives).await?;
+
     let archive_records = rwarc(archive_records);
-    let block_records = rwarc(block_records);
+    // block shards are loaded as blocks are removed
+    let block_records = rwarc(BlockRecords::unloaded(block_manifest));
+    let pack_records = rwarc(pack_records);
+    let tree_records = rwarc(tree_records);
     let state = Arc::new(CleanupState {
         task_count: cli.tasks,
         dry_run: cli.dry_run,
@@ -40,6 +49,8 @@ pub async fn main(cli: DeleteArgs) -> Result<()> {
         storage,
         archive_records,
         block_records,
+        pack_records,
+        tree_records,
     });
 
     delete_archives_and_garbage_blocks(state.clone(), &archive_hashes).await?;
@@ -49,6 +60,8 @@ pub async fn main(cli: DeleteArgs) -> Result<()> {
         storage,
         archive_records,
         block_records,
+        pack_records,
+        tree_records,
         ..
     } = unarc(state);
     let stats = unrwarc(stats);
@@ -56,7 +69,9 @@ pub async fn main(cli: DeleteArgs) -> Result<()> {
     if !cli.dry_run {
         try_join!(
             upload_archive_records(storage.clone(), archive_records),
-            upload_block_records(storage.clone(), block_records),
+            upload_block_records(storage.clone(), block_records, cli.tasks),
+            upload_pack_records(storage.clone(), pack_records),
+            upload_tree_records(storage.clone(), tree_records),
         )?;
     }
 
@@ -75,7 +90,9 @@ pub async fn main(cli: DeleteArgs) -> Result<()> {
             );
             print_stat("bytes deleted", format_size(full_stats.bytes_deleted));
             print_stat("archives deleted", full_stats.archives_deleted);
+            print_stat("trees deleted", full_stats.trees_deleted);
             print_stat("blocks deleted", full_stats.blocks_deleted);
+            print_stat("packs deleted", full_stats.packs_deleted);
             print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
         }
         Some(StatsType::Json) => {
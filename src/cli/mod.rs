@@ -1,10 +1,18 @@
+#[cfg(test)]
+mod tests;
+
 mod archives;
 mod backup;
+mod check;
 mod cleanup;
+mod copy;
 mod delete;
+mod prune;
+mod rebuild_index;
 mod restore;
 
 mod args;
+mod config;
 mod parse;
 mod storage;
 
@@ -15,7 +23,7 @@ use std::{
 };
 
 use clap::{
-    Parser, Subcommand,
+    ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand,
     builder::{Styles, styling::AnsiColor},
 };
 use concolor_clap::{ColorChoice, color_choice};
@@ -23,13 +31,18 @@ use env_logger::WriteStyle;
 use log::LevelFilter;
 
 use crate::{
+    env,
     error::{Result, handle_error},
     logger,
     stats::FinalizedCommandStats,
 };
 
-use self::args::{
-    ArchivesArgs, BackupArgs, CleanupArgs, DeleteArgs, GlobalArgs, LoggerArgs, RestoreArgs,
+use self::{
+    args::{
+        ArchivesArgs, BackupArgs, CheckArgs, CleanupArgs, CopyArgs, DeleteArgs, GlobalArgs,
+        LoggerArgs, PruneArgs, RebuildIndexArgs, RestoreArgs,
+    },
+    config::{Config, config_path},
 };
 
 /// Fast deduplicated backups on top of S3
@@ -55,14 +68,29 @@ enum Command {
     /// Restore files from an archive
     Restore(RestoreArgs),
 
+    /// Copy archives to another repository
+    Copy(CopyArgs),
+
     /// Delete one or more archives
     Delete(DeleteArgs),
 
+    /// Delete archives according to a retention policy
+    ///
+    /// Checkpoints and incomplete archives are left out unless they're selected with `--tag
+    /// checkpoint` or `--tag incomplete`, so the policy only counts finished backups.
+    Prune(PruneArgs),
+
     /// List archives
     Archives(ArchivesArgs),
 
     /// Clean up orphaned blocks and archives
     Cleanup(CleanupArgs),
+
+    /// Check repository integrity
+    Check(CheckArgs),
+
+    /// Rebuild archive and block metadata from stored archives
+    RebuildIndex(RebuildIndexArgs),
 }
 
 impl Command {
@@ -70,30 +98,111 @@ impl Command {
         match self {
             Command::Backup(args) => &args.global,
             Command::Restore(args) => &args.global,
+            Command::Copy(args) => &args.global,
             Command::Delete(args) => &args.global,
+            Command::Prune(args) => &args.global,
             Command::Archives(args) => &args.global,
             Command::Cleanup(args) => &args.global,
+            Command::Check(args) => &args.global,
+            Command::RebuildIndex(args) => &args.global,
         }
     }
+
+    fn global_mut(&mut self) -> &mut GlobalArgs {
+        match self {
+            Command::Backup(args) => &mut args.global,
+            Command::Restore(args) => &mut args.global,
+            Command::Copy(args) => &mut args.global,
+            Command::Delete(args) => &mut args.global,
+            Command::Prune(args) => &mut args.global,
+            Command::Archives(args) => &mut args.global,
+            Command::Cleanup(args) => &mut args.global,
+            Command::Check(args) => &mut args.global,
+            Command::RebuildIndex(args) => &mut args.global,
+        }
+    }
+
+    fn tasks_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Command::Backup(args) => Some(&mut args.tasks),
+            Command::Restore(args) => Some(&mut args.tasks),
+            Command::Copy(args) => Some(&mut args.tasks),
+            Command::Delete(args) => Some(&mut args.tasks),
+            Command::Prune(args) => Some(&mut args.tasks),
+            Command::Archives(_) => None,
+            Command::Cleanup(args) => Some(&mut args.tasks),
+            Command::Check(args) => Some(&mut args.tasks),
+            Command::RebuildIndex(args) => Some(&mut args.tasks),
+        }
+    }
+
+    /// Fills in options that weren't given on the command line from the selected profile in the
+    /// config file, if there is one.
+    fn apply_config(&mut self, matches: &ArgMatches) -> Result<()> {
+        let config = match config_path() {
+            Some(path) => Config::load(&path)?,
+            None => Config::default(),
+        };
+
+        self.apply_profile(&config, matches, env::is_set)
+    }
+
+    /// Fills in options from the selected profile in `config`, except those that are given on the
+    /// command line or by an environment variable that `is_env_set` reports as set.
+    fn apply_profile<F>(
+        &mut self,
+        config: &Config,
+        matches: &ArgMatches,
+        is_env_set: F,
+    ) -> Result<()>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let maybe_name = self.global().profile.clone();
+        if let Some((name, profile)) = config.profile(maybe_name.as_deref())? {
+            profile.apply(name, self, matches, is_env_set)?;
+        }
+
+        Ok(())
+    }
 }
 
+/// Runs the command given on the command line, returning the exit code once it has finished.
+///
+/// # Panics
+///
+/// Panics if the arguments are parsed without a subcommand, which clap already rejects.
 pub async fn main() -> ExitCode {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
     let global = cli.command.global();
 
     let level = log_level_from_args(&global.logger);
     let style = write_style_from_color_choice(global.logger.color);
     logger::init(level, style);
 
-    let result = match cli.command {
+    let (_, command_matches) = matches.subcommand().unwrap();
+    let result = match cli.command.apply_config(command_matches) {
+        // boxed to keep the commands' large futures off the stack
+        Ok(()) => Box::pin(run(cli.command)).await,
+        Err(err) => Err(err),
+    };
+
+    handle_error(result)
+}
+
+async fn run(command: Command) -> Result<()> {
+    match command {
         Command::Backup(args) => backup::main(args).await,
         Command::Restore(args) => restore::main(args).await,
+        Command::Copy(args) => copy::main(args).await,
         Command::Delete(args) => delete::main(args).await,
+        Command::Prune(args) => prune::main(args).await,
         Command::Archives(args) => archives::main(args).await,
         Command::Cleanup(args) => cleanup::main(args).await,
-    };
-
-    handle_error(result)
+        Command::Check(args) => check::main(args).await,
+        Command::RebuildIndex(args) => rebuild_index::main(args).await,
+    }
 }
 
 fn log_level_from_args(args: &LoggerArgs) -> LevelFilter {
@@ -1,5 +1,7 @@
 use std::{fmt::Display, ops::RangeInclusive, str::FromStr};
 
+use aws_sdk_s3::types::StorageClass;
+
 use crate::hash::{self, ShortHash};
 
 pub fn parse_range_inclusive<N: PartialEq + PartialOrd + FromStr + Display>(
@@ -33,3 +35,51 @@ pub fn parse_short_hash<T>(s: &str) -> Result<ShortHash<T>, String> {
         ))
     }
 }
+
+/// Parses a number of bytes, optionally followed by a decimal (K, M, G, T) or binary (Ki, Mi, Gi,
+/// Ti) unit.
+pub fn parse_bytes(s: &str) -> Result<u64, String> {
+    let split_at = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split_at);
+    let value: u64 = number.parse().map_err(|_| "invalid numeric value")?;
+
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1_000,
+        "M" => 1_000_000,
+        "G" => 1_000_000_000,
+        "T" => 1_000_000_000_000,
+        "Ki" => 1 << 10,
+        "Mi" => 1 << 20,
+        "Gi" => 1 << 30,
+        "Ti" => 1 << 40,
+        _ => return Err(format!("invalid unit `{unit}`")),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("{s} is too large"))
+}
+
+/// Storage classes whose objects can be read right away. Others, such as `GLACIER` and
+/// `DEEP_ARCHIVE`, need objects to be restored first, which every command would fail on.
+const INSTANT_STORAGE_CLASSES: [&str; 7] = [
+    "STANDARD",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER_IR",
+    "REDUCED_REDUNDANCY",
+    "EXPRESS_ONEZONE",
+];
+
+pub fn parse_storage_class(s: &str) -> Result<StorageClass, String> {
+    if INSTANT_STORAGE_CLASSES.contains(&s) {
+        Ok(StorageClass::from(s))
+    } else {
+        Err(format!(
+            "expected one of {}",
+            INSTANT_STORAGE_CLASSES.join(", ")
+        ))
+    }
+}
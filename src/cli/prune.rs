@@ -0,0 +1,170 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::Local;
+use clap::builder::styling::AnsiColor;
+use humantime::format_duration;
+use itertools::Itertools;
+use log::{Level, log};
+use tokio::try_join;
+
+use crate::{
+    arc::{rwarc, unarc, unrwarc},
+    block::BlockRecords,
+    error::{Error, Result},
+    format::{format_size, format_time},
+    ops::{
+        CleanupState, delete_archives_and_garbage_blocks, download_archive_records,
+        download_block_manifest, download_pack_records, download_tree_records,
+        upload_archive_records, upload_block_records, upload_pack_records, upload_tree_records,
+    },
+    retention::{apply_policy, group_key},
+    stats::{CommandStats, FinalizedCommandStats},
+};
+
+use super::{
+    args::{PruneArgs, StatsType},
+    print_stat, print_stats_json,
+    storage::create_storage,
+};
+
+pub async fn main(cli: PruneArgs) -> Result<()> {
+    let policy = cli.policy();
+    if policy.is_empty() {
+        return Err(Error::EmptyRetentionPolicy);
+    }
+
+    let stats = rwarc(CommandStats::new());
+    let storage = Arc::new(create_storage(&cli.global).await?);
+
+    let (archive_records, block_manifest, pack_records, tree_records) = try_join!(
+        download_archive_records(storage.clone()),
+        download_block_manifest(storage.clone()),
+        download_pack_records(storage.clone()),
+        download_tree_records(storage.clone()),
+    )?;
+
+    let filter = cli.filter.filter()?;
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+
+    for (hash, record) in archive_records.iter_by_created().rev() {
+        if filter.selects(&record.info) {
+            let key = group_key(&record.info, &cli.group_by);
+            groups.entry(key).or_default().push((hash, record));
+        }
+    }
+
+    let now = Local::now();
+    let mut removed_hashes = vec![];
+    // each archive is only listed by default when nothing is removed, since there can be many
+    let level = if cli.dry_run {
+        Level::Info
+    } else {
+        Level::Debug
+    };
+
+    for (key, archives) in &groups {
+        if !key.is_empty() {
+            let style = AnsiColor::Magenta.on_default();
+            log!(level, "{style}group{style:#} {key}");
+        }
+
+        let times = archives
+            .iter()
+            .map(|(_, record)| record.info.created.with_timezone(&Local))
+            .collect::<Vec<_>>();
+        let all_reasons = apply_policy(&times, &policy, &now);
+
+        for ((hash, record), reasons) in archives.iter().zip(&all_reasons) {
+            let formatted_time = format_time(&record.info.created);
+            let time_style = AnsiColor::Blue.on_default();
+            if reasons.is_empty() {
+                let style = AnsiColor::Yellow.on_default();
+                log!(
+                    level,
+                    "{style}remove{style:#} {time_style}{formatted_time}{time_style:#} {hash}"
+                );
+                removed_hashes.push(**hash);
+            } else {
+                let style = AnsiColor::Green.on_default();
+                let reasons_style = AnsiColor::BrightBlack.on_default();
+                let formatted_reasons = reasons.iter().join(", ");
+                log!(
+                    level,
+                    "{style}keep  {style:#} {time_style}{formatted_time}{time_style:#} {hash} {reasons_style}({formatted_reasons}){reasons_style:#}"
+                );
+            }
+        }
+    }
+
+    let archive_records = rwarc(archive_records);
+    // block shards are loaded as blocks are removed
+    let block_records = rwarc(BlockRecords::unloaded(block_manifest));
+    let pack_records = rwarc(pack_records);
+    let tree_records = rwarc(tree_records);
+    let state = Arc::new(CleanupState {
+        task_count: cli.tasks,
+        dry_run: cli.dry_run,
+        stats,
+        storage,
+        archive_records,
+        block_records,
+        pack_records,
+        tree_records,
+    });
+
+    if !removed_hashes.is_empty() {
+        delete_archives_and_garbage_blocks(state.clone(), &removed_hashes).await?;
+    }
+
+    let CleanupState {
+        stats,
+        storage,
+        archive_records,
+        block_records,
+        pack_records,
+        tree_records,
+        ..
+    } = unarc(state);
+    let stats = unrwarc(stats);
+
+    if !cli.dry_run && !removed_hashes.is_empty() {
+        try_join!(
+            upload_archive_records(storage.clone(), archive_records),
+            upload_block_records(storage.clone(), block_records, cli.tasks),
+            upload_pack_records(storage.clone(), pack_records),
+            upload_tree_records(storage.clone(), tree_records),
+        )?;
+    }
+
+    let storage = unarc(storage);
+    let full_stats = stats.finalize(storage.stats());
+
+    print_stats(cli.global.stats, &full_stats)
+}
+
+fn print_stats(stats_type: Option<StatsType>, full_stats: &FinalizedCommandStats) -> Result<()> {
+    match stats_type {
+        Some(StatsType::Basic) => {
+            print_stat(
+                "metadata downloaded",
+                format_size(full_stats.metadata_bytes_downloaded()),
+            );
+            print_stat(
+                "metadata uploaded",
+                format_size(full_stats.metadata_bytes_uploaded()),
+            );
+            print_stat("bytes deleted", format_size(full_stats.bytes_deleted));
+            print_stat("archives deleted", full_stats.archives_deleted);
+            print_stat("trees deleted", full_stats.trees_deleted);
+            print_stat("blocks deleted", full_stats.blocks_deleted);
+            print_stat("packs deleted", full_stats.packs_deleted);
+            print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
+        }
+        Some(StatsType::Json) => {
+            print_stats_json(full_stats)?;
+        }
+        None => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use clap::builder::styling::AnsiColor;
+use humantime::format_duration;
+use log::info;
+use tokio::try_join;
+
+use crate::{
+    arc::{rwarc, unarc},
+    entity::EntityIndex,
+    error::{Error, Result},
+    format::format_size,
+    ops::{
+        SkippedObjects, rebuild_archive_records, rebuild_block_records, rebuild_tree_records,
+        upload_archive_records, upload_block_records, upload_pack_records, upload_tree_records,
+        write_format_version,
+    },
+    stats::CommandStats,
+};
+
+use super::{
+    args::{RebuildIndexArgs, StatsType},
+    print_stat, print_stats_json,
+    storage::create_storage,
+};
+
+pub async fn main(cli: RebuildIndexArgs) -> Result<()> {
+    let stats = CommandStats::new();
+    let storage = Arc::new(create_storage(&cli.global).await?);
+    if !cli.dry_run {
+        write_format_version(&storage).await?;
+    }
+
+    let mut skipped = SkippedObjects::default();
+    let (archive_records, roots) =
+        rebuild_archive_records(storage.clone(), cli.tasks, &mut skipped).await?;
+    let (tree_records, block_refs) =
+        rebuild_tree_records(storage.clone(), &roots, cli.tasks, &mut skipped).await?;
+    let (block_records, pack_records) =
+        rebuild_block_records(storage.clone(), &block_refs, cli.tasks, &mut skipped).await?;
+
+    // records missing what the skipped objects reference would let `cleanup` delete it
+    if skipped.total() > 0 {
+        return Err(Error::IncompleteRebuild(skipped.total()));
+    }
+
+    let archive_count = archive_records.len();
+    let block_count = block_records.len();
+    let pack_count = pack_records.len();
+    let tree_count = tree_records.len();
+
+    if !cli.dry_run {
+        try_join!(
+            upload_archive_records(storage.clone(), rwarc(archive_records)),
+            upload_block_records(storage.clone(), rwarc(block_records), cli.tasks),
+            upload_pack_records(storage.clone(), rwarc(pack_records)),
+            upload_tree_records(storage.clone(), rwarc(tree_records)),
+        )?;
+    }
+
+    let style = AnsiColor::Green.on_default();
+    info!(
+        "{style}rebuilt index{style:#} with {archive_count} archives, {tree_count} trees, {block_count} blocks, and {pack_count} packs"
+    );
+
+    let storage = unarc(storage);
+    let full_stats = stats.finalize(storage.stats());
+
+    match cli.global.stats {
+        Some(StatsType::Basic) => {
+            print_stat(
+                "metadata downloaded",
+                format_size(full_stats.metadata_bytes_downloaded()),
+            );
+            print_stat(
+                "metadata uploaded",
+                format_size(full_stats.metadata_bytes_uploaded()),
+            );
+            print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
+        }
+        Some(StatsType::Json) => {
+            print_stats_json(&full_stats)?;
+        }
+        None => {}
+    }
+
+    Ok(())
+}
@@ -1,14 +1,20 @@
 use std::{collections::HashMap, sync::Arc};
 
 use humantime::format_duration;
-use tokio::try_join;
+use tokio::{sync::RwLock, try_join};
 
 use crate::{
     arc::{rwarc, unarc, unrwarc},
+    block::BlockRecords,
     error::Result,
     format::{format_size, format_speed},
     locks::BlockLocks,
-    ops::{RestoreState, download_archive, download_pending_files, expand_hash, restore_all},
+    ops::{
+        FileFailures, RestoreState, count_totals, download_archive, download_archive_records,
+        download_block_manifest, download_file_tree, download_pending_files, load_block_shards,
+        resolve_archive_ref, restore_all,
+    },
+    progress::{Direction, ProgressReporter},
     stats::CommandStats,
 };
 
@@ -24,14 +30,31 @@ pub async fn main(cli: RestoreArgs) -> Result<()> {
     let local_blocks = rwarc(HashMap::new());
     let block_locks = rwarc(BlockLocks::new());
 
-    let archive_hash = expand_hash(storage.clone(), &cli.archive).await?;
+    let (archive_records, block_manifest) = try_join!(
+        download_archive_records(storage.clone()),
+        download_block_manifest(storage.clone()),
+    )?;
+
+    let block_cache = cli.block_cache.open().await?;
+
+    let archive_hash = resolve_archive_ref(storage.clone(), &archive_records, &cli.archive).await?;
     let archive = download_archive(storage.clone(), &archive_hash).await?;
+    let (files, block_hashes) =
+        download_file_tree(storage.clone(), archive.tree, &cli.paths, cli.tasks).await?;
+
+    // only the block shards of the restored files are needed
+    let block_records = RwLock::new(BlockRecords::unloaded(block_manifest));
+    load_block_shards(storage.clone(), &block_records, &block_hashes, cli.tasks).await?;
+    let block_records = block_records.into_inner();
 
     let state = Arc::new(RestoreState {
         order: cli.order,
         task_count: cli.tasks,
         dry_run: cli.dry_run,
-        archive,
+        failures: FileFailures::new(cli.failure.mode(), "restore"),
+        files,
+        block_records,
+        block_cache,
         stats,
         storage,
         local_blocks,
@@ -39,12 +62,23 @@ pub async fn main(cli: RestoreArgs) -> Result<()> {
     });
     let (sender, receiver) = async_channel::bounded(state.task_count);
 
+    let progress = ProgressReporter::start(cli.progress, Direction::Download, state.stats.clone());
+    if progress.is_enabled() {
+        progress.set_totals(count_totals(&state, &cli.paths)?);
+    }
+
     try_join!(
         restore_all(state.clone(), sender, &cli.paths),
         download_pending_files(state.clone(), receiver)
     )?;
+    progress.finish().await;
 
-    let RestoreState { stats, storage, .. } = unarc(state);
+    let RestoreState {
+        failures,
+        stats,
+        storage,
+        ..
+    } = unarc(state);
     let stats = unrwarc(stats);
     let storage = unarc(storage);
     let full_stats = stats.finalize(storage.stats());
@@ -62,7 +96,9 @@ pub async fn main(cli: RestoreArgs) -> Result<()> {
             print_stat("bytes written", format_size(full_stats.bytes_written));
             print_stat("files created", full_stats.files_created);
             print_stat("blocks downloaded", full_stats.blocks_downloaded);
+            print_stat("blocks cached", full_stats.blocks_cached);
             print_stat("blocks referenced", full_stats.blocks_referenced);
+            print_stat("requests retried", full_stats.storage.retries);
             print_stat("elapsed time", format_duration(full_stats.elapsed_time()));
             print_stat("download speed", format_speed(full_stats.download_speed()));
         }
@@ -72,5 +108,5 @@ pub async fn main(cli: RestoreArgs) -> Result<()> {
         None => {}
     }
 
-    Ok(())
+    failures.finish()
 }
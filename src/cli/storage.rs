@@ -1,15 +1,76 @@
-use crate::{env, error::Result, storage::Storage};
+use crate::{
+    cache::MetadataCache,
+    env,
+    error::{Error, Result},
+    ops::check_format_version,
+    storage::{ClientOptions, ObjectOptions, RepoLocation, RepoUrl, Storage},
+};
 
 use super::GlobalArgs;
 
-const ENV_VAR_STORAGE: &str = "CUBIST_BUCKET";
+pub const ENV_VAR_STORAGE: &str = "CUBIST_BUCKET";
+pub const ENV_VAR_REPO: &str = "CUBIST_REPO";
+const CACHE_DIR_NAME: &str = "cubist";
+
+/// Returns the repository given by `--repo` or `--bucket`, falling back to the environment.
+fn repo_url(args: &GlobalArgs) -> Result<RepoUrl> {
+    if let Some(repo) = &args.repo {
+        return Ok(repo.clone());
+    }
+
+    if let Some(bucket) = &args.bucket {
+        return Ok(RepoUrl::from_bucket(bucket.clone()));
+    }
+
+    match env::var(ENV_VAR_REPO) {
+        Ok(url) => url.parse(),
+        Err(Error::MissingEnvVar(_)) => env::var(ENV_VAR_STORAGE).map(RepoUrl::from_bucket),
+        Err(err) => Err(err),
+    }
+}
 
 pub async fn create_storage(args: &GlobalArgs) -> Result<Storage> {
-    let bucket = if let Some(bucket) = &args.bucket {
-        bucket.clone()
-    } else {
-        env::var(ENV_VAR_STORAGE)?
+    let repo = repo_url(args)?;
+    let location = RepoLocation::S3(repo);
+    open_storage(
+        args,
+        location,
+        args.client.options(),
+        args.objects.options(),
+    )
+    .await
+}
+
+/// Opens the repository at `location` with the rest of the settings taken from `args`, for
+/// commands that use a repository other than the one given by `--repo` or `--bucket`. Fails if
+/// the repository was written in a format that this version can't read.
+pub async fn open_storage(
+    args: &GlobalArgs,
+    location: RepoLocation,
+    client: ClientOptions,
+    objects: ObjectOptions,
+) -> Result<Storage> {
+    let cache = match &location {
+        RepoLocation::S3(repo) if !args.no_cache => env::cache_dir().map(|dir| {
+            let repo_dir = dir
+                .join(CACHE_DIR_NAME)
+                .join(&repo.bucket)
+                .join(&repo.prefix);
+            MetadataCache::new(repo_dir)
+        }),
+        _ => None,
     };
 
-    Ok(Storage::new(bucket).await)
+    let storage = Storage::new(
+        location,
+        client,
+        cache,
+        args.retry.policy(),
+        args.limits.limits(),
+        objects,
+    )
+    .await;
+
+    check_format_version(&storage).await?;
+    Ok(storage)
 }
@@ -0,0 +1,339 @@
+use std::{iter, path::Path, time::Duration};
+
+use aws_sdk_s3::types::StorageClass;
+use clap::{CommandFactory, FromArgMatches};
+
+use crate::{
+    archive::Archive,
+    error::{Error, Result},
+};
+
+use super::{
+    Cli, Command,
+    args::{BackupArgs, CopyArgs, MAX_INLINE_THRESHOLD, parse_inline_threshold},
+    config::Config,
+    parse::{parse_bytes, parse_short_hash, parse_storage_class},
+};
+
+const CONFIG: &str = r#"
+[profiles.default]
+repo = "s3://backups/team-a/"
+region = "eu-west-1"
+aws_profile = "backups"
+tasks = 16
+compression_level = 5
+exclude = ["*.tmp", "/cache"]
+
+[profiles.default.retention]
+keep_daily = 7
+keep_within = "2days"
+
+[profiles.minio]
+repo = "s3://local"
+endpoint = "http://localhost:9000"
+"#;
+
+/// Parses the command line `args` and fills in options from `config`, as if only the
+/// environment variables in `env_vars` were set.
+fn command_with_config(args: &[&str], config: &str, env_vars: &[&str]) -> Result<Command> {
+    let args = iter::once("cubist").chain(args.iter().copied());
+    let matches = Cli::command().try_get_matches_from(args).unwrap();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap();
+    let (_, command_matches) = matches.subcommand().unwrap();
+
+    let config = Config::parse(config, Path::new("config.toml"))?;
+    cli.command
+        .apply_profile(&config, command_matches, |var| env_vars.contains(&var))?;
+    Ok(cli.command)
+}
+
+fn repo_of(command: &Command) -> Option<String> {
+    let repo = command.global().repo.as_ref()?;
+    Some(format!("{}/{}", repo.bucket, repo.prefix))
+}
+
+#[test]
+fn short_hash_length() {
+    assert!(parse_short_hash::<Archive>("abcdef").is_ok());
+    assert_eq!(
+        parse_short_hash::<Archive>("abc").unwrap_err(),
+        "hash has 3 characters, expected 6-64"
+    );
+    assert_eq!(
+        parse_short_hash::<Archive>("abcdeg").unwrap_err(),
+        "invalid characters in hash"
+    );
+}
+
+#[test]
+fn storage_class_instant_access() {
+    assert_eq!(
+        parse_storage_class("STANDARD_IA").unwrap(),
+        StorageClass::StandardIa
+    );
+    assert_eq!(
+        parse_storage_class("GLACIER_IR").unwrap(),
+        StorageClass::GlacierIr
+    );
+}
+
+#[test]
+fn storage_class_needs_restore() {
+    for class in ["GLACIER", "DEEP_ARCHIVE", "standard", ""] {
+        let err = parse_storage_class(class).unwrap_err();
+        assert!(err.starts_with("expected one of STANDARD, "));
+    }
+}
+
+#[test]
+fn bytes_with_units() {
+    assert_eq!(parse_bytes("0"), Ok(0));
+    assert_eq!(parse_bytes("512"), Ok(512));
+    assert_eq!(parse_bytes("5K"), Ok(5_000));
+    assert_eq!(parse_bytes("5M"), Ok(5_000_000));
+    assert_eq!(parse_bytes("2G"), Ok(2_000_000_000));
+    assert_eq!(parse_bytes("1T"), Ok(1_000_000_000_000));
+    assert_eq!(parse_bytes("5Ki"), Ok(5 * 1024));
+    assert_eq!(parse_bytes("5Mi"), Ok(5 * 1024 * 1024));
+    assert_eq!(parse_bytes("2Gi"), Ok(2 << 30));
+    assert_eq!(parse_bytes("1Ti"), Ok(1 << 40));
+}
+
+#[test]
+fn bytes_invalid_unit() {
+    assert_eq!(parse_bytes("5k"), Err("invalid unit `k`".to_owned()));
+    assert_eq!(parse_bytes("5 M"), Err("invalid unit ` M`".to_owned()));
+    assert_eq!(parse_bytes("5MB"), Err("invalid unit `MB`".to_owned()));
+}
+
+#[test]
+fn bytes_overflow() {
+    assert_eq!(parse_bytes(&u64::MAX.to_string()), Ok(u64::MAX));
+    assert_eq!(
+        parse_bytes("18446744073709552K"),
+        Err("18446744073709552K is too large".to_owned())
+    );
+    assert_eq!(
+        parse_bytes("18446744073709551616"),
+        Err("invalid numeric value".to_owned())
+    );
+}
+
+#[test]
+fn bytes_empty() {
+    assert_eq!(parse_bytes(""), Err("invalid numeric value".to_owned()));
+    assert_eq!(parse_bytes("M"), Err("invalid numeric value".to_owned()));
+    assert_eq!(parse_bytes("-1"), Err("invalid numeric value".to_owned()));
+}
+
+#[test]
+fn inline_threshold_limit() {
+    assert_eq!(parse_inline_threshold("0"), Ok(0));
+    assert_eq!(parse_inline_threshold("4Ki"), Ok(4096));
+    assert_eq!(parse_inline_threshold("64Ki"), Ok(MAX_INLINE_THRESHOLD));
+    assert_eq!(
+        parse_inline_threshold("65537"),
+        Err("65537 is larger than the maximum of 65536".to_owned())
+    );
+}
+
+#[test]
+fn block_cache_size_with_units() {
+    let Command::Restore(args) = command_with_config(&["restore", "latest"], "", &[]).unwrap()
+    else {
+        panic!("expected a restore command");
+    };
+    assert_eq!(args.block_cache.block_cache_size, 1 << 30);
+
+    let args = ["restore", "latest", "--block-cache-size", "500M"];
+    let Command::Restore(args) = command_with_config(&args, "", &[]).unwrap() else {
+        panic!("expected a restore command");
+    };
+    assert_eq!(args.block_cache.block_cache_size, 500_000_000);
+}
+
+#[test]
+fn config_invalid_toml() {
+    for text in [
+        "[profiles.default",
+        "[profiles.default]\ninclude = [\"*.tmp\"]",
+    ] {
+        let err = Config::parse(text, Path::new("config.toml")).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidConfig { path, .. } if path == Path::new("config.toml"))
+        );
+    }
+}
+
+#[test]
+fn config_empty() {
+    let command = command_with_config(&["archives"], "", &[]).unwrap();
+    assert_eq!(repo_of(&command), None);
+
+    let err = command_with_config(&["archives", "--profile", "minio"], "", &[]).unwrap_err();
+    assert_eq!(err, Error::ProfileNotFound("minio".to_owned()));
+}
+
+#[test]
+fn profile_default() {
+    let command = command_with_config(&["archives"], CONFIG, &[]).unwrap();
+    let global = command.global();
+    assert_eq!(repo_of(&command).as_deref(), Some("backups/team-a/"));
+    assert_eq!(global.client.region.as_deref(), Some("eu-west-1"));
+    assert_eq!(global.client.aws_profile.as_deref(), Some("backups"));
+    assert_eq!(global.client.endpoint, None);
+}
+
+#[test]
+fn profile_named() {
+    let command = command_with_config(&["archives", "--profile", "minio"], CONFIG, &[]).unwrap();
+    let global = command.global();
+    assert_eq!(repo_of(&command).as_deref(), Some("local/"));
+    assert_eq!(
+        global.client.endpoint.as_deref(),
+        Some("http://localhost:9000")
+    );
+    assert_eq!(global.client.region, None);
+
+    let err = command_with_config(&["archives", "--profile", "other"], CONFIG, &[]).unwrap_err();
+    assert_eq!(err, Error::ProfileNotFound("other".to_owned()));
+}
+
+fn excludes_of(args: &BackupArgs) -> Vec<String> {
+    args.excludes.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn profile_command_options() {
+    let Command::Backup(args) = command_with_config(&["backup", "/home"], CONFIG, &[]).unwrap()
+    else {
+        unreachable!();
+    };
+    assert_eq!(args.tasks, 16);
+    assert_eq!(args.compression_level, 5);
+    assert_eq!(excludes_of(&args), ["*.tmp", "/cache"]);
+
+    let backup = [
+        ["backup", "-j", "4", "--compression-level", "1"].as_slice(),
+        &["--exclude", "*.log", "/home"],
+    ]
+    .concat();
+    let Command::Backup(args) = command_with_config(&backup, CONFIG, &[]).unwrap() else {
+        unreachable!();
+    };
+    assert_eq!(args.tasks, 4);
+    assert_eq!(args.compression_level, 1);
+    assert_eq!(excludes_of(&args), ["*.log"]);
+}
+
+#[test]
+fn flags_override_profile() {
+    let args = ["archives", "--repo", "s3://other/", "--region", "us-east-1"];
+    let command = command_with_config(&args, CONFIG, &[]).unwrap();
+    assert_eq!(repo_of(&command).as_deref(), Some("other/"));
+    assert_eq!(command.global().client.region.as_deref(), Some("us-east-1"));
+
+    // a bucket replaces the profile's repository as well
+    let command = command_with_config(&["archives", "-b", "other"], CONFIG, &[]).unwrap();
+    assert_eq!(repo_of(&command), None);
+    assert_eq!(command.global().bucket.as_deref(), Some("other"));
+}
+
+#[test]
+fn env_vars_override_profile() {
+    for var in ["CUBIST_REPO", "CUBIST_BUCKET"] {
+        let command = command_with_config(&["archives"], CONFIG, &[var]).unwrap();
+        assert_eq!(repo_of(&command), None);
+    }
+
+    let env_vars = ["AWS_DEFAULT_REGION", "AWS_PROFILE"];
+    let command = command_with_config(&["archives"], CONFIG, &env_vars).unwrap();
+    let global = command.global();
+    assert_eq!(repo_of(&command).as_deref(), Some("backups/team-a/"));
+    assert_eq!(global.client.region, None);
+    assert_eq!(global.client.aws_profile, None);
+
+    let args = ["archives", "--profile", "minio"];
+    let command = command_with_config(&args, CONFIG, &["AWS_ENDPOINT_URL"]).unwrap();
+    assert_eq!(command.global().client.endpoint, None);
+}
+
+#[test]
+fn profile_retention() {
+    let Command::Prune(args) = command_with_config(&["prune"], CONFIG, &[]).unwrap() else {
+        unreachable!();
+    };
+    assert_eq!(args.keep_daily, 7);
+    assert_eq!(args.keep_last, 0);
+    assert_eq!(args.keep_within, Some(Duration::from_hours(48)));
+}
+
+#[test]
+fn retention_flags_replace_profile_policy() {
+    // any retention flag replaces the whole policy rather than being combined with it
+    let prune = ["prune", "--keep-last", "3"];
+    let Command::Prune(args) = command_with_config(&prune, CONFIG, &[]).unwrap() else {
+        unreachable!();
+    };
+    assert_eq!(args.keep_last, 3);
+    assert_eq!(args.keep_daily, 0);
+    assert_eq!(args.keep_within, None);
+}
+
+#[test]
+fn profile_invalid_option() {
+    // options are only checked for commands that have them
+    let config = "[profiles.default]\ntasks = 0";
+    assert!(command_with_config(&["archives"], config, &[]).is_ok());
+
+    let err = command_with_config(&["backup", "/home"], config, &[]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InvalidProfile { profile, field: "tasks", .. } if profile == "default"
+    ));
+
+    let config = "[profiles.default]\nexclude = [\"\"]";
+    let err = command_with_config(&["backup", "/home"], config, &[]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::InvalidProfile {
+            field: "exclude",
+            ..
+        }
+    ));
+
+    let config = "[profiles.default]\nrepo = \"backups\"";
+    let err = command_with_config(&["archives"], config, &[]).unwrap_err();
+    assert!(matches!(err, Error::InvalidProfile { field: "repo", .. }));
+}
+
+fn copy_command(args: &[&str]) -> CopyArgs {
+    let args = [["copy", "latest"].as_slice(), args].concat();
+    match command_with_config(&args, "", &[]).unwrap() {
+        Command::Copy(args) => args,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn copy_server_side() {
+    assert!(copy_command(&["--to", "s3://other"]).server_side_copy());
+    assert!(copy_command(&["--region", "eu-west-1", "--to", "s3://other"]).server_side_copy());
+
+    // a different region, a local directory, or re-encoded blocks rule it out
+    let cases: [&[&str]; 3] = [
+        &[
+            "--region",
+            "us-east-1",
+            "--to",
+            "s3://other",
+            "--to-region",
+            "eu-west-1",
+        ],
+        &["--to", "/mnt/backup"],
+        &["--to", "s3://other", "--to-compression-level", "9"],
+    ];
+    for args in cases {
+        assert!(!copy_command(args).server_side_copy());
+    }
+}
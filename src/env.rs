@@ -1,4 +1,7 @@
-use std::env::VarError;
+use std::{
+    env::{VarError, var_os},
+    path::PathBuf,
+};
 
 use crate::error::{Error, Result};
 
@@ -11,3 +14,32 @@ pub fn var(name: &str) -> Result<String> {
         }
     })
 }
+
+/// Returns whether the environment variable `name` is set, even if it isn't valid Unicode.
+pub fn is_set(name: &str) -> bool {
+    var_os(name).is_some()
+}
+
+pub fn hostname() -> Result<String> {
+    Ok(whoami::fallible::hostname()?)
+}
+
+pub fn username() -> Result<String> {
+    Ok(whoami::fallible::username()?)
+}
+
+/// Returns the directory for user-specific cache files, following the XDG base directory spec.
+pub fn cache_dir() -> Option<PathBuf> {
+    var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+}
+
+/// Returns the directory for user-specific config files, following the XDG base directory spec.
+pub fn config_dir() -> Option<PathBuf> {
+    var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
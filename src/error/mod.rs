@@ -5,7 +5,10 @@ use std::{io, path::PathBuf, process::ExitCode};
 use log::error;
 use thiserror::Error;
 
-use crate::{archive::Archive, block::Block, hash::Hash};
+use crate::{
+    archive::Archive, block::Block, hash::Hash, pack::Pack, signal::EXIT_CODE_INTERRUPTED,
+    tree::Tree,
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -32,6 +35,9 @@ pub enum Error {
     #[error("`{0}` does not exist")]
     FileDoesNotExist(PathBuf),
 
+    #[error("`{0}` is not a regular file")]
+    FileIsNotRegular(PathBuf),
+
     #[error("inode {0} does not exist")]
     InodeDoesNotExist(u64),
 
@@ -50,6 +56,21 @@ pub enum Error {
     #[error("no block record found for {0}")]
     BlockRecordNotFound(Hash<Block>),
 
+    #[error("no pack record found for {0}")]
+    PackRecordNotFound(Hash<Pack>),
+
+    #[error("block shard {0:x} hasn't been loaded")]
+    ShardNotLoaded(u32),
+
+    #[error("no tree record found for {0}")]
+    TreeRecordNotFound(Hash<Tree>),
+
+    #[error("block {hash} is stored in missing pack {pack}")]
+    MissingBlockPack { hash: Hash<Block>, pack: Hash<Pack> },
+
+    #[error("pack {0} has an invalid index")]
+    InvalidPackIndex(Hash<Pack>),
+
     #[error("block {hash} has ref count {actual}, expected at least {expected}")]
     WrongRefCount {
         hash: Hash<Block>,
@@ -57,12 +78,51 @@ pub enum Error {
         expected: u64,
     },
 
+    #[error("block {hash} has ref count {actual}, expected {expected}")]
+    RefCountMismatch {
+        hash: Hash<Block>,
+        actual: u64,
+        expected: u64,
+    },
+
+    #[error("tree {hash} has ref count {actual}, expected {expected}")]
+    TreeRefCountMismatch {
+        hash: Hash<Tree>,
+        actual: u64,
+        expected: u64,
+    },
+
+    #[error("pack {hash} has used size {actual}, expected {expected}")]
+    PackUsageMismatch {
+        hash: Hash<Pack>,
+        actual: u64,
+        expected: u64,
+    },
+
+    #[error("branch block {hash} references missing block {child}")]
+    MissingChildBlock {
+        hash: Hash<Block>,
+        child: Hash<Block>,
+    },
+
     #[error("block has hash {actual}, expected {expected}")]
     WrongBlockHash {
         actual: Hash<Block>,
         expected: Hash<Block>,
     },
 
+    #[error("archive has hash {actual}, expected {expected}")]
+    WrongArchiveHash {
+        actual: Hash<Archive>,
+        expected: Hash<Archive>,
+    },
+
+    #[error("tree has hash {actual}, expected {expected}")]
+    WrongTreeHash {
+        actual: Hash<Tree>,
+        expected: Hash<Tree>,
+    },
+
     #[error("block has level {actual}, expected {expected}")]
     WrongBlockLevel {
         hash: Hash<Block>,
@@ -70,6 +130,13 @@ pub enum Error {
         expected: u8,
     },
 
+    #[error("block has size {actual}, expected {expected}")]
+    WrongBlockSize {
+        hash: Hash<Block>,
+        actual: u64,
+        expected: u64,
+    },
+
     #[error("block has invalid size {0}")]
     InvalidBlockSize(u64),
 
@@ -82,9 +149,67 @@ pub enum Error {
     #[error("block is empty")]
     EmptyBlock,
 
+    #[error("found {0} problem(s) in repository")]
+    CheckFailed(u64),
+
+    #[error("no upload ID returned for `{0}`")]
+    MissingUploadId(String),
+
+    #[error("interrupted before finishing")]
+    Interrupted,
+
+    #[error("{0} file(s) failed")]
+    FilesFailed(u64),
+
+    #[error("no retention policy specified")]
+    EmptyRetentionPolicy,
+
+    #[error("archive reference `{0}` is invalid")]
+    InvalidArchiveRef(String),
+
+    #[error("no archive found for `{0}`")]
+    NoArchiveForRef(String),
+
     #[error("`{0}` must be set")]
     MissingEnvVar(String),
 
+    #[error("repository URL `{0}` is invalid, expected `s3://BUCKET[/PREFIX]`")]
+    InvalidRepoUrl(String),
+
+    #[error("exclude pattern `{0}` is invalid")]
+    InvalidExcludePattern(String),
+
+    #[error("config file `{path}` is invalid ({message})")]
+    InvalidConfig { path: PathBuf, message: String },
+
+    #[error("profile `{0}` not found in config file")]
+    ProfileNotFound(String),
+
+    #[error("profile `{profile}` has invalid `{field}` ({message})")]
+    InvalidProfile {
+        profile: String,
+        field: &'static str,
+        message: String,
+    },
+
+    #[error("skipped {0} objects that couldn't be read, so the rebuilt index wasn't uploaded")]
+    IncompleteRebuild(u64),
+
+    #[error("format version `{0}` is invalid")]
+    InvalidFormatVersion(String),
+
+    #[error(
+        "repository has format version {found}, but this version of cubist only supports version \
+         {supported}"
+    )]
+    UnsupportedFormatVersion { found: u32, supported: u32 },
+
+    #[error("repository has no format version, so it was written by an older version of cubist")]
+    MissingFormatVersion,
+
+    #[error("not allowed to copy `{0}` on the server")]
+    CopyDenied(String),
+
     #[error(transparent)]
     Io(#[from] io::Error),
 
@@ -110,7 +235,7 @@ impl From<anyhow::Error> for Error {
     }
 }
 
-#[allow(clippy::match_same_arms)]
+#[allow(clippy::match_same_arms, clippy::too_many_lines)]
 impl PartialEq for Error {
     fn eq(&self, other: &Self) -> bool {
         #[allow(clippy::enum_glob_use)]
@@ -126,12 +251,27 @@ impl PartialEq for Error {
             }
             (FileIsNotDirectory(path_l), FileIsNotDirectory(path_r)) => path_l == path_r,
             (FileDoesNotExist(path_l), FileDoesNotExist(path_r)) => path_l == path_r,
+            (FileIsNotRegular(path_l), FileIsNotRegular(path_r)) => path_l == path_r,
             (InodeDoesNotExist(inode_l), InodeDoesNotExist(inode_r)) => inode_l == inode_r,
             (EmptyPath, EmptyPath) => true,
             (PathAlreadyArchived(path_l), PathAlreadyArchived(path_r)) => path_l == path_r,
             (FileAlreadyExists(path_l), FileAlreadyExists(path_r)) => path_l == path_r,
             (ArchiveRecordNotFound(hash_l), ArchiveRecordNotFound(hash_r)) => hash_l == hash_r,
             (BlockRecordNotFound(hash_l), BlockRecordNotFound(hash_r)) => hash_l == hash_r,
+            (PackRecordNotFound(hash_l), PackRecordNotFound(hash_r)) => hash_l == hash_r,
+            (ShardNotLoaded(id_l), ShardNotLoaded(id_r)) => id_l == id_r,
+            (TreeRecordNotFound(hash_l), TreeRecordNotFound(hash_r)) => hash_l == hash_r,
+            (
+                MissingBlockPack {
+                    hash: hash_l,
+                    pack: pack_l,
+                },
+                MissingBlockPack {
+                    hash: hash_r,
+                    pack: pack_r,
+                },
+            ) => hash_l == hash_r && pack_l == pack_r,
+            (InvalidPackIndex(hash_l), InvalidPackIndex(hash_r)) => hash_l == hash_r,
             (
                 WrongRefCount {
                     hash: hash_l,
@@ -144,6 +284,52 @@ impl PartialEq for Error {
                     expected: expected_r,
                 },
             ) => hash_l == hash_r && actual_l == actual_r && expected_l == expected_r,
+            (
+                RefCountMismatch {
+                    hash: hash_l,
+                    actual: actual_l,
+                    expected: expected_l,
+                },
+                RefCountMismatch {
+                    hash: hash_r,
+                    actual: actual_r,
+                    expected: expected_r,
+                },
+            ) => hash_l == hash_r && actual_l == actual_r && expected_l == expected_r,
+            (
+                TreeRefCountMismatch {
+                    hash: hash_l,
+                    actual: actual_l,
+                    expected: expected_l,
+                },
+                TreeRefCountMismatch {
+                    hash: hash_r,
+                    actual: actual_r,
+                    expected: expected_r,
+                },
+            ) => hash_l == hash_r && actual_l == actual_r && expected_l == expected_r,
+            (
+                PackUsageMismatch {
+                    hash: hash_l,
+                    actual: actual_l,
+                    expected: expected_l,
+                },
+                PackUsageMismatch {
+                    hash: hash_r,
+                    actual: actual_r,
+                    expected: expected_r,
+                },
+            ) => hash_l == hash_r && actual_l == actual_r && expected_l == expected_r,
+            (
+                MissingChildBlock {
+                    hash: hash_l,
+                    child: child_l,
+                },
+                MissingChildBlock {
+                    hash: hash_r,
+                    child: child_r,
+                },
+            ) => hash_l == hash_r && child_l == child_r,
             (
                 WrongBlockHash {
                     actual: actual_l,
@@ -154,6 +340,26 @@ impl PartialEq for Error {
                     expected: expected_r,
                 },
             ) => actual_l == actual_r && expected_l == expected_r,
+            (
+                WrongArchiveHash {
+                    actual: actual_l,
+                    expected: expected_l,
+                },
+                WrongArchiveHash {
+                    actual: actual_r,
+                    expected: expected_r,
+                },
+            ) => actual_l == actual_r && expected_l == expected_r,
+            (
+                WrongTreeHash {
+                    actual: actual_l,
+                    expected: expected_l,
+                },
+                WrongTreeHash {
+                    actual: actual_r,
+                    expected: expected_r,
+                },
+            ) => actual_l == actual_r && expected_l == expected_r,
             (
                 WrongBlockLevel {
                     hash: hash_l,
@@ -166,21 +372,87 @@ impl PartialEq for Error {
                     expected: expected_r,
                 },
             ) => hash_l == hash_r && actual_l == actual_r && expected_l == expected_r,
+            (
+                WrongBlockSize {
+                    hash: hash_l,
+                    actual: actual_l,
+                    expected: expected_l,
+                },
+                WrongBlockSize {
+                    hash: hash_r,
+                    actual: actual_r,
+                    expected: expected_r,
+                },
+            ) => hash_l == hash_r && actual_l == actual_r && expected_l == expected_r,
             (InvalidBlockSize(size_l), InvalidBlockSize(size_r)) => size_l == size_r,
             (BranchLevelZero, BranchLevelZero) => true,
             (TooManyBlockLevels, TooManyBlockLevels) => true,
             (EmptyBlock, EmptyBlock) => true,
+            (CheckFailed(count_l), CheckFailed(count_r)) => count_l == count_r,
+            (MissingUploadId(key_l), MissingUploadId(key_r)) => key_l == key_r,
+            (Interrupted, Interrupted) => true,
+            (FilesFailed(count_l), FilesFailed(count_r)) => count_l == count_r,
+            (EmptyRetentionPolicy, EmptyRetentionPolicy) => true,
+            (InvalidArchiveRef(ref_l), InvalidArchiveRef(ref_r)) => ref_l == ref_r,
+            (NoArchiveForRef(ref_l), NoArchiveForRef(ref_r)) => ref_l == ref_r,
             (MissingEnvVar(var_l), MissingEnvVar(var_r)) => var_l == var_r,
+            (InvalidRepoUrl(url_l), InvalidRepoUrl(url_r)) => url_l == url_r,
+            (InvalidExcludePattern(pattern_l), InvalidExcludePattern(pattern_r)) => {
+                pattern_l == pattern_r
+            }
+            (
+                InvalidConfig {
+                    path: path_l,
+                    message: message_l,
+                },
+                InvalidConfig {
+                    path: path_r,
+                    message: message_r,
+                },
+            ) => path_l == path_r && message_l == message_r,
+            (ProfileNotFound(name_l), ProfileNotFound(name_r)) => name_l == name_r,
+            (IncompleteRebuild(count_l), IncompleteRebuild(count_r)) => count_l == count_r,
+            (InvalidFormatVersion(text_l), InvalidFormatVersion(text_r)) => text_l == text_r,
+            (
+                UnsupportedFormatVersion {
+                    found: found_l,
+                    supported: supported_l,
+                },
+                UnsupportedFormatVersion {
+                    found: found_r,
+                    supported: supported_r,
+                },
+            ) => found_l == found_r && supported_l == supported_r,
+            (MissingFormatVersion, MissingFormatVersion) => true,
+            (CopyDenied(key_l), CopyDenied(key_r)) => key_l == key_r,
+            (
+                InvalidProfile {
+                    profile: profile_l,
+                    field: field_l,
+                    message: message_l,
+                },
+                InvalidProfile {
+                    profile: profile_r,
+                    field: field_r,
+                    message: message_r,
+                },
+            ) => profile_l == profile_r && field_l == field_r && message_l == message_r,
             _ => false,
         }
     }
 }
 
 pub fn handle_error<T>(result: Result<T>) -> ExitCode {
-    if let Err(err) = result {
-        error!("{err:#?}");
-        ExitCode::FAILURE
-    } else {
-        ExitCode::SUCCESS
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        // exits like a process killed by the signal, so that scripts can tell it was interrupted
+        Err(Error::Interrupted) => {
+            error!("{:#?}", Error::Interrupted);
+            ExitCode::from(EXIT_CODE_INTERRUPTED)
+        }
+        Err(err) => {
+            error!("{err:#?}");
+            ExitCode::FAILURE
+        }
     }
 }
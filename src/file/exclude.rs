@@ -0,0 +1,89 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use regex::Regex;
+
+use crate::error::{Error, Result};
+
+/// Glob pattern for paths to leave out of a backup. `*` matches any part of a name, `**` any number
+/// of directories, and `?` any single character. A pattern without a `/` matches names at any depth,
+/// while one with a `/` matches paths relative to the directory being backed up.
+#[derive(Debug, Clone)]
+pub struct ExcludePattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl ExcludePattern {
+    pub fn matches(&self, path: &Path) -> bool {
+        self.regex.is_match(&path.to_string_lossy())
+    }
+}
+
+impl FromStr for ExcludePattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let glob = s.trim_start_matches('/').trim_end_matches('/');
+        if glob.is_empty() {
+            return Err(Error::InvalidExcludePattern(s.to_owned()));
+        }
+
+        // a leading `/` anchors the pattern to the directory being backed up, like any other `/`
+        let anchored = glob.contains('/') || s.starts_with('/');
+        let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+        let regex = format!("{prefix}{}$", glob_to_regex(glob));
+        let regex = Regex::new(&regex).map_err(|_| Error::InvalidExcludePattern(s.to_owned()))?;
+
+        Ok(ExcludePattern {
+            pattern: s.to_owned(),
+            regex,
+        })
+    }
+}
+
+impl fmt::Display for ExcludePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+/// Patterns that together decide which paths are left out of a backup.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludeSet {
+    patterns: Vec<ExcludePattern>,
+}
+
+impl ExcludeSet {
+    pub fn new(patterns: Vec<ExcludePattern>) -> Self {
+        ExcludeSet { patterns }
+    }
+
+    /// Returns whether `path`, relative to the directory being backed up, is excluded. The
+    /// directory itself never is.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        !path.as_os_str().is_empty() && self.patterns.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.next_if_eq(&'*').is_some() => {
+                // `**/` also matches no directories at all
+                if chars.next_if_eq(&'/').is_some() {
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            _ => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+
+    regex
+}
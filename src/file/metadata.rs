@@ -9,6 +9,8 @@ pub struct Metadata {
     pub mode: u32,
     pub group: u32,
     pub owner: u32,
+    /// Size of the file's contents, which lets a restore know how much it has left to write.
+    pub size: u64,
     #[serde(with = "ts_milliseconds_option")]
     pub accessed: Option<DateTime<Utc>>,
     #[serde(with = "ts_milliseconds_option")]
@@ -24,6 +26,7 @@ impl Metadata {
             mode: native.mode(),
             group: native.gid(),
             owner: native.uid(),
+            size: native.len(),
             accessed: native.accessed().ok().map(Into::into),
             created: native.created().ok().map(Into::into),
             modified: native.modified().ok().map(Into::into),
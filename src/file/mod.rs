@@ -1,3 +1,7 @@
+#[cfg(test)]
+mod tests;
+
+mod exclude;
 mod metadata;
 mod node;
 mod tree;
@@ -14,8 +18,9 @@ use tokio::fs;
 use crate::error::Result;
 
 pub use self::{
+    exclude::{ExcludePattern, ExcludeSet},
     metadata::Metadata,
-    node::{FileType, Node, NodeChildren},
+    node::{FileData, FileType, Node, NodeChildren},
     tree::FileTree,
     walk::WalkOrder,
 };
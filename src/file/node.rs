@@ -8,11 +8,11 @@ use super::metadata::Metadata;
 
 pub type NodeChildren = BTreeMap<OsString, Node>;
 
-#[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Node {
     File {
         metadata: Metadata,
-        hash: Option<Hash<Block>>,
+        data: FileData,
     },
     Symlink {
         metadata: Metadata,
@@ -42,6 +42,30 @@ impl Node {
     }
 }
 
+/// Where the contents of a file are stored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FileData {
+    Empty,
+    /// Contents of a small file, stored in the archive itself.
+    Inline(Vec<u8>),
+    /// Hash of the root of the block tree containing the file's contents.
+    Blocks(Hash<Block>),
+}
+
+impl FileData {
+    pub fn inline(bytes: Vec<u8>) -> Self {
+        if bytes.is_empty() {
+            FileData::Empty
+        } else {
+            FileData::Inline(bytes)
+        }
+    }
+
+    pub fn blocks(maybe_hash: Option<Hash<Block>>) -> Self {
+        maybe_hash.map_or(FileData::Empty, FileData::Blocks)
+    }
+}
+
 #[derive(PartialEq, Eq)]
 pub enum FileType {
     File,
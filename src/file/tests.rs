@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use crate::{
+    error::Error,
+    file::{ExcludePattern, ExcludeSet},
+};
+
+fn excludes(patterns: &[&str]) -> ExcludeSet {
+    ExcludeSet::new(patterns.iter().map(|s| s.parse().unwrap()).collect())
+}
+
+#[test]
+fn exclude_names_at_any_depth() {
+    let excludes = excludes(&["*.tmp", "node_modules", "cache?"]);
+    for path in [
+        "a.tmp",
+        "src/a.tmp",
+        "web/node_modules",
+        "node_modules",
+        "x/cache1",
+    ] {
+        assert!(excludes.is_excluded(Path::new(path)), "{path}");
+    }
+    for path in [
+        "a.tmp.txt",
+        "node_modules2/a",
+        "x/cache",
+        "x/cache12",
+        "tmp",
+    ] {
+        assert!(!excludes.is_excluded(Path::new(path)), "{path}");
+    }
+}
+
+#[test]
+fn exclude_paths_from_root() {
+    let excludes = excludes(&["build/*", "/target", "logs/**/*.gz"]);
+    for path in ["build/out", "target", "logs/a.gz", "logs/2024/01/a.gz"] {
+        assert!(excludes.is_excluded(Path::new(path)), "{path}");
+    }
+    for path in [
+        "build",
+        "build/out/a",
+        "src/target",
+        "src/logs/a.gz",
+        "logs/a.gzip",
+    ] {
+        assert!(!excludes.is_excluded(Path::new(path)), "{path}");
+    }
+}
+
+#[test]
+fn exclude_literal_characters() {
+    let excludes = excludes(&["a+b.(1)"]);
+    assert!(excludes.is_excluded(Path::new("a+b.(1)")));
+    assert!(!excludes.is_excluded(Path::new("aab.(1)")));
+}
+
+#[test]
+fn exclude_never_matches_root() {
+    assert!(!excludes(&["**"]).is_excluded(Path::new("")));
+}
+
+#[test]
+fn parse_invalid_exclude_patterns() {
+    for pattern in ["", "/", "//"] {
+        assert_eq!(
+            pattern.parse::<ExcludePattern>().unwrap_err(),
+            Error::InvalidExcludePattern(pattern.to_owned())
+        );
+    }
+}
@@ -4,8 +4,6 @@ use std::{
     path::{Component, Path, PathBuf},
 };
 
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-
 use crate::{
     error::{Error, Result},
     file::{Node, NodeChildren},
@@ -13,7 +11,7 @@ use crate::{
 
 use super::walk::{WalkNode, WalkOrder};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileTree {
     children: NodeChildren,
     paths: HashMap<u64, PathBuf>,
@@ -27,7 +25,7 @@ impl FileTree {
         }
     }
 
-    fn from_children(children: NodeChildren) -> Self {
+    pub fn from_children(children: NodeChildren) -> Self {
         let mut paths = HashMap::new();
         let walker = WalkNode::from_children(&children, WalkOrder::DepthFirst);
 
@@ -38,6 +36,10 @@ impl FileTree {
         FileTree { children, paths }
     }
 
+    pub fn into_children(self) -> NodeChildren {
+        self.children
+    }
+
     pub fn walk(&self, maybe_path: Option<&Path>, order: WalkOrder) -> Result<WalkNode<'_>> {
         let walker = if let Some(path) = maybe_path {
             let node = self
@@ -104,20 +106,6 @@ impl FileTree {
     }
 }
 
-impl Serialize for FileTree {
-    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
-        Serialize::serialize(&self.children, serializer)
-    }
-}
-
-impl<'de> Deserialize<'de> for FileTree {
-    fn deserialize<D: Deserializer<'de>>(
-        deserializer: D,
-    ) -> std::result::Result<FileTree, D::Error> {
-        Deserialize::deserialize(deserializer).map(FileTree::from_children)
-    }
-}
-
 fn path_keys(path: &Path) -> Result<(Vec<&OsStr>, &OsStr)> {
     let mut keys = path
         .components()
@@ -51,7 +51,7 @@ impl<'a> Iterator for WalkNode<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            WalkNode::Single(maybe_node) => maybe_node.map(|node| (PathBuf::new(), node)),
+            WalkNode::Single(maybe_node) => maybe_node.take().map(|node| (PathBuf::new(), node)),
             WalkNode::DepthFirst(iter) => iter.next(),
             WalkNode::BreadthFirst(iter) => iter.next(),
         }
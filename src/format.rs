@@ -3,9 +3,11 @@ use std::{borrow::Cow, path::Path};
 use chrono::{DateTime, Local, Utc};
 use humansize::{BaseUnit, DECIMAL, FormatSizeOptions, ToF64, Unsigned};
 
-const BITS_PER_SECOND: FormatSizeOptions = FormatSizeOptions::from(DECIMAL)
-    .base_unit(BaseUnit::Bit)
-    .suffix("/s");
+fn bits_per_second() -> FormatSizeOptions {
+    FormatSizeOptions::from(DECIMAL)
+        .base_unit(BaseUnit::Bit)
+        .suffix("/s")
+}
 
 pub fn format_path(path: &Path) -> String {
     let path_str = path.to_string_lossy();
@@ -22,7 +24,7 @@ pub fn format_size<T: ToF64 + Unsigned>(input: T) -> String {
 }
 
 pub fn format_speed<T: ToF64 + Unsigned>(input: T) -> String {
-    humansize::format_size(input, BITS_PER_SECOND)
+    humansize::format_size(input, bits_per_second())
 }
 
 pub fn format_time(time: &DateTime<Utc>) -> String {
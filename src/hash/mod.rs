@@ -2,15 +2,17 @@ mod short;
 
 pub use self::short::{PREFIX_LENGTH_RANGE, ShortHash};
 
-use std::{fmt, hash, marker::PhantomData, ops::Deref};
+use std::{cmp::Ordering, fmt, hash, marker::PhantomData, ops::Deref};
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
-    archive::{Archive, ArchiveRecord},
-    block::Block,
+    archive::Archive,
+    block::{Block, ChildBlock},
     entity::Entity,
     error::{Error, Result},
+    pack::Pack,
+    tree::Tree,
 };
 
 pub const SIZE: usize = blake3::OUT_LEN;
@@ -34,8 +36,8 @@ impl<E> Hash<E> {
         Hash::from_inner(inner)
     }
 
-    pub fn format_short(&self, block_count: usize) -> String {
-        ShortHash::from_hash(self, block_count).into()
+    pub fn format_short(&self, hash_count: usize) -> String {
+        ShortHash::from_hash(self, hash_count).into()
     }
 }
 
@@ -50,11 +52,12 @@ impl Hash<Block> {
         blake3::hash(data).into()
     }
 
-    pub fn branch_block(children: &[Self]) -> Self {
+    pub fn branch_block(children: &[ChildBlock]) -> Self {
         let mut hasher = blake3::Hasher::new();
 
-        for hash in children {
-            hasher.update(hash.as_bytes());
+        for child in children {
+            hasher.update(child.hash.as_bytes());
+            hasher.update(&child.size.to_le_bytes());
         }
 
         hasher.finalize().into()
@@ -62,14 +65,20 @@ impl Hash<Block> {
 }
 
 impl Hash<Archive> {
-    pub fn archive(archive: &ArchiveRecord) -> Self {
-        let mut hasher = blake3::Hasher::new();
+    pub fn archive(bytes: &[u8]) -> Self {
+        blake3::hash(bytes).into()
+    }
+}
 
-        let timestamp = archive.created.format("%+").to_string();
-        hasher.update(timestamp.as_bytes());
-        hasher.update(&archive.size.to_le_bytes());
+impl Hash<Pack> {
+    pub fn pack(bytes: &[u8]) -> Self {
+        blake3::hash(bytes).into()
+    }
+}
 
-        hasher.finalize().into()
+impl Hash<Tree> {
+    pub fn tree(bytes: &[u8]) -> Self {
+        blake3::hash(bytes).into()
     }
 }
 
@@ -113,6 +122,18 @@ impl<E> PartialEq for Hash<E> {
 
 impl<E> Eq for Hash<E> {}
 
+impl<E> PartialOrd for Hash<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> Ord for Hash<E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
 impl<E> hash::Hash for Hash<E> {
     fn hash<H: hash::Hasher>(&self, state: &mut H) {
         self.inner.hash(state);
@@ -39,8 +39,8 @@ impl<E> ShortHash<E> {
         }
     }
 
-    pub fn from_hash(hash: &Hash<E>, block_count: usize) -> Self {
-        let len = safe_prefix_length(block_count);
+    pub fn from_hash(hash: &Hash<E>, hash_count: usize) -> Self {
+        let len = safe_prefix_length(hash_count);
         let s = hash.to_hex()[..len].to_string();
         ShortHash::from_string(s)
     }
@@ -94,13 +94,13 @@ impl<E> fmt::Display for ShortHash<E> {
     clippy::cast_precision_loss,
     clippy::cast_sign_loss
 )]
-fn safe_prefix_length(block_count: usize) -> usize {
+fn safe_prefix_length(hash_count: usize) -> usize {
     // see https://github.com/git/git/commit/e6c587c733b4634030b353f4024794b08bc86892
-    // 2^(2N) > block_count
-    //     2N > log2(block_count)
-    //      N > log2(block_count) / 2
-    //      N = log2(block_count) / 2 + 1
-    let bits = (block_count as f64).log2() / 2.0 + 1.0;
+    // 2^(2N) > hash_count
+    //     2N > log2(hash_count)
+    //      N > log2(hash_count) / 2
+    //      N = log2(hash_count) / 2 + 1
+    let bits = (hash_count as f64).log2() / 2.0 + 1.0;
     let chars = bits / 4.0;
     let len = chars.ceil() as usize;
     len.clamp(MIN_PREFIX_LENGTH, MAX_PREFIX_LENGTH)
@@ -8,6 +8,7 @@ mod arc;
 mod archive;
 mod assert;
 mod block;
+mod cache;
 mod compress;
 mod entity;
 mod env;
@@ -18,8 +19,22 @@ mod hash;
 mod locks;
 mod logger;
 mod ops;
+mod pack;
 mod prefix;
+mod progress;
+mod reader;
+mod retention;
 mod serde;
+mod signal;
 mod stats;
 mod storage;
 mod task;
+mod tree;
+
+pub use self::{
+    archive::ArchiveRef,
+    cache::DiskBlockCache,
+    error::{Error, Result},
+    reader::ArchiveFileReader,
+    storage::{RepoUrl, Storage},
+};
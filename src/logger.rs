@@ -3,6 +3,8 @@ use std::io::{self, Write};
 use env_logger::{WriteStyle, fmt::Formatter};
 use log::{Level, LevelFilter, Record};
 
+use crate::progress;
+
 pub fn init(level: LevelFilter, style: WriteStyle) {
     env_logger::Builder::new()
         .format(format)
@@ -14,6 +16,8 @@ pub fn init(level: LevelFilter, style: WriteStyle) {
 fn format(f: &mut Formatter, record: &Record) -> io::Result<()> {
     let args = record.args();
     let level = record.level();
+    progress::clear_line()?;
+
     if let Some(prefix) = level_prefix(level) {
         let style = f.default_level_style(level);
         writeln!(f, "{style}{prefix}{style:#}{args}")
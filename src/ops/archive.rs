@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
-use chrono::{DateTime, Utc};
-use tokio::{sync::RwLock, task::spawn_blocking};
+use tokio::task::spawn_blocking;
 
 use crate::{
-    archive::{Archive, ArchiveRecord},
+    archive::{Archive, ArchiveRecord, ArchiveRecords, ArchiveRef},
+    assert::assert_archive_hash_eq,
     compress::{compress, decompress},
     error::Result,
     hash::Hash,
@@ -12,12 +12,16 @@ use crate::{
     storage::Storage,
 };
 
+use super::{expand_hash, expand_hashes};
+
 const COMPRESSION_LEVEL: u8 = 3;
 
 pub async fn download_archive(storage: Arc<Storage>, hash: &Hash<Archive>) -> Result<Archive> {
     let compressed_bytes = storage.get(&hash.key()).await?;
+    let hash = *hash;
     spawn_blocking(move || {
         let bytes = decompress(&compressed_bytes)?;
+        assert_archive_hash_eq(&Hash::archive(&bytes), &hash)?;
         deserialize(&bytes)
     })
     .await?
@@ -25,19 +29,64 @@ pub async fn download_archive(storage: Arc<Storage>, hash: &Hash<Archive>) -> Re
 
 pub async fn upload_archive(
     storage: Arc<Storage>,
-    archive: Arc<RwLock<Archive>>,
-    created: DateTime<Utc>,
+    archive: Archive,
+    dry_run: bool,
 ) -> Result<(Hash<Archive>, ArchiveRecord)> {
     let (compressed_bytes, hash, record) = spawn_blocking(move || {
-        let bytes = serialize(&*archive.blocking_read())?;
+        let bytes = serialize(&archive)?;
         let compressed_bytes = compress(&bytes, COMPRESSION_LEVEL)?;
         let size = compressed_bytes.len() as u64;
-        let record = ArchiveRecord { created, size };
-        let hash = Hash::archive(&record);
+        let record = ArchiveRecord {
+            info: archive.info,
+            size,
+        };
+        let hash = Hash::archive(&bytes);
         Result::Ok((compressed_bytes, hash, record))
     })
     .await??;
 
-    storage.put(&hash.key(), compressed_bytes).await?;
+    if !dry_run {
+        storage.put(&hash.key(), compressed_bytes).await?;
+    }
+
     Ok((hash, record))
 }
+
+pub async fn resolve_archive_ref(
+    storage: Arc<Storage>,
+    archive_records: &ArchiveRecords,
+    reference: &ArchiveRef,
+) -> Result<Hash<Archive>> {
+    match reference {
+        ArchiveRef::Hash(short_hash) => expand_hash(storage, short_hash).await,
+        ArchiveRef::Selector(selector) => selector.resolve(archive_records),
+    }
+}
+
+pub async fn resolve_archive_refs(
+    storage: Arc<Storage>,
+    archive_records: &ArchiveRecords,
+    references: &[ArchiveRef],
+) -> Result<Vec<Hash<Archive>>> {
+    let short_hashes = references
+        .iter()
+        .filter_map(|reference| match reference {
+            ArchiveRef::Hash(short_hash) => Some(short_hash),
+            ArchiveRef::Selector(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let expanded_hashes = if short_hashes.is_empty() {
+        vec![]
+    } else {
+        expand_hashes(storage, &short_hashes).await?
+    };
+    let mut expanded_hashes = expanded_hashes.into_iter();
+
+    references
+        .iter()
+        .map(|reference| match reference {
+            ArchiveRef::Hash(_) => Ok(expanded_hashes.next().unwrap()),
+            ArchiveRef::Selector(selector) => selector.resolve(archive_records),
+        })
+        .collect()
+}
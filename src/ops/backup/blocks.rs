@@ -1,12 +1,18 @@
-use std::{mem::replace, sync::Arc};
+use std::{
+    collections::HashSet,
+    mem::{replace, take},
+    sync::Arc,
+};
 
 use tokio::task::spawn_blocking;
 
 use crate::{
-    block::{Block, BlockRecord},
+    block::{self, Block, BlockRecords, BlockRefs, ChildBlock},
     entity::EntityIndex,
     error::{Error, Result},
-    hash::{self, Hash},
+    hash::Hash,
+    ops::{load_block_shards, try_delete_packs, upload_pack},
+    pack::{Pack, PackBuilder, PackRecords},
 };
 
 use super::BackupState;
@@ -14,7 +20,8 @@ use super::BackupState;
 #[derive(Debug)]
 pub struct UploadTree {
     state: Arc<BackupState>,
-    layers: Vec<Vec<Hash<Block>>>,
+    layers: Vec<Vec<ChildBlock>>,
+    block_refs: BlockRefs,
 }
 
 impl UploadTree {
@@ -22,31 +29,53 @@ impl UploadTree {
         UploadTree {
             state,
             layers: vec![],
+            block_refs: BlockRefs::new(),
         }
     }
 
     pub async fn add_leaf(&mut self, data: Vec<u8>) -> Result<()> {
         let block = spawn_blocking(move || Block::leaf(data)).await??;
-        let hash = self.upload_block(block).await?;
-        self.add_inner(hash, false).await
+        let child = self.upload_block(block).await?;
+        self.add_inner(child, false).await
     }
 
-    pub async fn finalize(mut self) -> Result<Option<Hash<Block>>> {
+    /// Returns the hash of the root block, if any, along with references to every block in the
+    /// tree. If that fails, the references are released like `release` does.
+    pub async fn finalize(mut self) -> Result<(Option<Hash<Block>>, BlockRefs)> {
         if self.layers.is_empty() {
-            return Ok(None);
+            return Ok((None, self.block_refs));
         }
 
         let bottom_layer = self.layers.first_mut().unwrap();
-        let hash = bottom_layer.pop().unwrap();
-        self.add_inner(hash, true).await?;
+        let child = bottom_layer.pop().unwrap();
+        if let Err(err) = self.add_inner(child, true).await {
+            self.release().await?;
+            return Err(err);
+        }
 
         let top_layer = self.layers.last().unwrap();
-        let hash = *top_layer.first().unwrap();
-        Ok(Some(hash))
+        let hash = top_layer.first().unwrap().hash;
+        Ok((Some(hash), self.block_refs))
+    }
+
+    /// Removes the references taken for the blocks uploaded so far, for a file that failed before
+    /// its tree was finished. Blocks that end up unreferenced no longer count towards the size
+    /// used in their pack.
+    pub async fn release(self) -> Result<()> {
+        // held so that the pack can't be finished in between
+        let mut pack_builder = self.state.pack_builder.lock().await;
+        let mut block_records = self.state.block_records.write().await;
+        let mut pack_records = self.state.pack_records.write().await;
+        release_block_refs(
+            &self.block_refs,
+            &mut pack_builder,
+            &mut block_records,
+            &mut pack_records,
+        )
     }
 
-    async fn add_inner(&mut self, mut hash: Hash<Block>, finalizing: bool) -> Result<()> {
-        let max_layer_size = self.state.target_block_size as usize / hash::SIZE;
+    async fn add_inner(&mut self, mut child: ChildBlock, finalizing: bool) -> Result<()> {
+        let max_layer_size = self.state.target_block_size as usize / block::CHILD_SIZE;
 
         for i in 0.. {
             if i >= self.layers.len() {
@@ -55,7 +84,7 @@ impl UploadTree {
             }
 
             let layer = self.layers.get_mut(i).unwrap();
-            layer.push(hash);
+            layer.push(child);
 
             let len = layer.len();
 
@@ -70,41 +99,171 @@ impl UploadTree {
             let level = (i + 1).try_into().map_err(|_| Error::TooManyBlockLevels)?;
             let children = replace(layer, Vec::with_capacity(max_layer_size));
             let block = spawn_blocking(move || Block::branch(level, children)).await??;
-            hash = self.upload_block(block).await?;
+            child = self.upload_block(block).await?;
         }
 
         Ok(())
     }
 
-    async fn upload_block(&mut self, block: Block) -> Result<Hash<Block>> {
-        let hash = *block.hash();
+    async fn upload_block(&mut self, block: Block) -> Result<ChildBlock> {
+        let child = block.as_child();
+        let hash = child.hash;
         let lock = self.state.block_locks.write().await.lock(&hash);
         let permit = lock.acquire().await?;
 
-        let block_exists = self.state.block_records.read().await.contains(&hash);
-        if block_exists {
-            let mut block_records = self.state.block_records.write().await;
-            let record = block_records.get_mut(&hash).unwrap();
-            record.ref_count += 1;
-        } else {
+        if !add_existing_ref(&self.state, &hash).await? {
             let compression_level = self.state.compression_level;
             let bytes = spawn_blocking(move || block.encode(compression_level)).await??;
-            let size = bytes.len() as u64;
-
-            if !self.state.dry_run {
-                self.state.storage.put(&hash.key(), bytes).await?;
-                self.state.stats.write().await.blocks_uploaded += 1;
-                self.state.stats.write().await.content_bytes_uploaded += size;
-            }
-
-            let record = BlockRecord { ref_count: 1, size };
-            self.state.block_records.write().await.insert(hash, record);
+            add_to_pack(&self.state, hash, &bytes).await?;
         }
 
-        self.state.archive.write().await.add_ref(&hash);
+        self.block_refs.add_count(&hash, 1);
         self.state.stats.write().await.blocks_referenced += 1;
 
         drop(permit);
-        Ok(hash)
+        Ok(child)
+    }
+}
+
+pub async fn upload_pending_pack(state: Arc<BackupState>) -> Result<()> {
+    let mut pack_builder = state.pack_builder.lock().await;
+    if pack_builder.is_empty() {
+        return Ok(());
+    }
+
+    let pack = pack_builder.finish();
+    drop(pack_builder);
+    upload_backup_pack(&state, pack).await
+}
+
+/// Removes references to blocks that are either stored or waiting in the current pack. Stored
+/// blocks that are no longer referenced lose their records, and the size they used in their pack is
+/// released.
+pub fn release_block_refs(
+    block_refs: &BlockRefs,
+    pack_builder: &mut PackBuilder,
+    block_records: &mut BlockRecords,
+    pack_records: &mut PackRecords,
+) -> Result<()> {
+    let mut stored_refs = BlockRefs::new();
+    for (hash, &count) in block_refs.iter() {
+        if !pack_builder.remove_count(hash, count) {
+            stored_refs.add_count(hash, count);
+        }
+    }
+
+    for result in block_records.remove_refs(stored_refs) {
+        let (_, record) = result?;
+        pack_records.release_block(&record);
+    }
+
+    Ok(())
+}
+
+/// Removes the block references held by the files of a transient backup, then deletes the packs
+/// that it uploaded along with their records. `existing_packs` are the packs that the repository
+/// had before the backup, which are kept even if they end up unused.
+pub async fn delete_transient_packs(
+    state: &BackupState,
+    existing_packs: &HashSet<Hash<Pack>>,
+) -> Result<()> {
+    let mut block_records = state.block_records.write().await;
+    let mut pack_records = state.pack_records.write().await;
+
+    for block_refs in take(&mut *state.dir_refs.write().await).into_values() {
+        for result in block_records.remove_refs(block_refs) {
+            let (_, record) = result?;
+            pack_records.release_block(&record);
+        }
+    }
+
+    let unused_hashes = pack_records
+        .iter()
+        .filter(|(hash, record)| record.used_size == 0 && !existing_packs.contains(*hash))
+        .map(|(hash, _)| *hash)
+        .collect::<Vec<_>>();
+
+    for hash in &unused_hashes {
+        pack_records.remove(hash)?;
+    }
+
+    drop(block_records);
+    drop(pack_records);
+
+    if state.dry_run {
+        return Ok(());
+    }
+
+    let storage = state.storage.clone();
+    try_delete_packs(storage, unused_hashes.into_iter().map(Ok), state.task_count).await
+}
+
+/// Adds a reference to a block that is either already stored or waiting in a pack that hasn't been
+/// uploaded yet, returning whether the block was found.
+async fn add_existing_ref(state: &BackupState, hash: &Hash<Block>) -> Result<bool> {
+    let storage = state.storage.clone();
+    load_block_shards(storage, &state.block_records, [hash], state.task_count).await?;
+
+    // held while checking the records so that the pack can't be finished in between
+    let mut pack_builder = state.pack_builder.lock().await;
+
+    if let Some(record) = state.block_records.write().await.get_mut(hash)? {
+        record.ref_count += 1;
+        Ok(true)
+    } else {
+        Ok(pack_builder.add_ref(hash))
+    }
+}
+
+async fn add_to_pack(state: &BackupState, hash: Hash<Block>, bytes: &[u8]) -> Result<()> {
+    let mut pack_builder = state.pack_builder.lock().await;
+    pack_builder.add(hash, bytes, 1);
+
+    if pack_builder.is_full() {
+        let pack = pack_builder.finish();
+        drop(pack_builder);
+        upload_backup_pack(state, pack).await?;
     }
+
+    Ok(())
+}
+
+/// Uploads a finished pack, then adds records for its blocks. Until then, references to its blocks
+/// are counted by the pack builder, so that no record points to a pack that isn't stored.
+async fn upload_backup_pack(state: &BackupState, pack: Pack) -> Result<()> {
+    let pack_hash = pack.hash;
+    upload_pack(
+        &state.storage,
+        &state.stats,
+        &state.pack_records,
+        pack,
+        state.dry_run,
+    )
+    .await?;
+
+    // held while moving the records so that references can't be missed in between
+    let mut pack_builder = state.pack_builder.lock().await;
+    let records = pack_builder.complete(&pack_hash);
+    let block_count = records.len() as u64;
+    let mut block_records = state.block_records.write().await;
+    let mut pack_records = state.pack_records.write().await;
+
+    for (hash, record) in records {
+        // only referenced by files that failed after uploading it
+        if record.ref_count == 0 {
+            pack_records.release_block(&record);
+        } else {
+            block_records.insert(hash, record)?;
+        }
+    }
+
+    drop(pack_records);
+    drop(block_records);
+    drop(pack_builder);
+
+    if !state.dry_run {
+        state.stats.write().await.blocks_uploaded += block_count;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    iter,
+    mem::{replace, take},
+    sync::Arc,
+};
+
+use clap::builder::styling::AnsiColor;
+use log::info;
+use tokio::{task::block_in_place, try_join};
+
+use crate::{
+    archive::{Archive, ArchiveInfo, CHECKPOINT_TAG},
+    block::BlockRecords,
+    entity::EntityIndex,
+    error::Result,
+    hash::Hash,
+    ops::{
+        download_archive, download_trees, load_block_shards, upload_archive,
+        upload_archive_records, upload_block_records, upload_pack_records, upload_tree_records,
+    },
+    pack::PackRecords,
+    tree::{Tree, TreeRecords},
+};
+
+use super::{BackupState, blocks::upload_pending_pack, trees::upload_tree_set};
+
+/// An archive of the files that were backed up before the checkpoint, which is replaced by the next
+/// checkpoint or by the final archive.
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub hash: Hash<Archive>,
+    pub tree: Hash<Tree>,
+    pub trees: HashMap<Hash<Tree>, Tree>,
+}
+
+/// A checkpoint that has been removed from the records, but whose objects haven't been deleted.
+#[derive(Debug)]
+pub struct RemovedCheckpoint {
+    hash: Hash<Archive>,
+    tree_hashes: Vec<Hash<Tree>>,
+}
+
+/// Finds the checkpoints left behind by earlier runs of this backup that didn't finish. They're
+/// only removed once this backup has finished, so their blocks stay referenced and can be reused
+/// until then.
+pub async fn find_stale_checkpoints(state: &BackupState) -> Result<Vec<Checkpoint>> {
+    let hashes = state
+        .archive_records
+        .read()
+        .await
+        .iter_by_created()
+        .filter(|(_, record)| is_stale_checkpoint(&record.info, &state.info))
+        .map(|(hash, _)| *hash)
+        .collect::<Vec<_>>();
+
+    let mut checkpoints = vec![];
+    for hash in hashes {
+        let archive = download_archive(state.storage.clone(), &hash).await?;
+        let trees = download_trees(state.storage.clone(), [archive.tree], state.task_count)
+            .await?
+            .into_iter()
+            .map(|(hash, result)| Ok((hash, result?)))
+            .collect::<Result<_>>()?;
+        checkpoints.push(Checkpoint {
+            hash,
+            tree: archive.tree,
+            trees,
+        });
+    }
+
+    // removing the checkpoints needs the shards of every block they reference
+    let block_hashes = checkpoints
+        .iter()
+        .flat_map(|checkpoint| checkpoint.trees.values())
+        .flat_map(|tree| tree.block_refs.iter().map(|(hash, _)| hash));
+    load_block_shards(
+        state.storage.clone(),
+        &state.block_records,
+        block_hashes,
+        state.task_count,
+    )
+    .await?;
+
+    Ok(checkpoints)
+}
+
+/// Returns whether `info` belongs to a checkpoint of an earlier run of the backup described by
+/// `backup`, which must have been made from the same paths on the same machine.
+pub fn is_stale_checkpoint(info: &ArchiveInfo, backup: &ArchiveInfo) -> bool {
+    info.tags.iter().any(|tag| tag == CHECKPOINT_TAG)
+        && info.name == backup.name
+        && info.hostname == backup.hostname
+        && info.username == backup.username
+        && info.paths == backup.paths
+}
+
+/// Uploads an archive of the files that have been backed up so far along with the records, so that
+/// if the backup is interrupted, running it again reuses the blocks that were already uploaded.
+///
+/// Files that are still being uploaded reference blocks without being part of the archive, so this
+/// must only be called while no files are being uploaded.
+pub async fn write_checkpoint(state: Arc<BackupState>) -> Result<()> {
+    upload_pending_pack(state.clone()).await?;
+
+    let files = state.files.read().await.clone();
+    let dir_refs = state.dir_refs.read().await.clone();
+    let (tree, trees) = upload_tree_set(&state, files, dir_refs.clone()).await?;
+
+    let mut info = state.info.clone();
+    info.tags.push(CHECKPOINT_TAG.to_owned());
+    let archive = Archive::new(info, tree);
+    let (hash, record) = upload_archive(state.storage.clone(), archive, state.dry_run).await?;
+    state.archive_records.write().await.insert(hash, record);
+
+    let checkpoint = Checkpoint { hash, tree, trees };
+    let maybe_removed = replace_checkpoint(&state, Some(checkpoint)).await?;
+    upload_backup_records(&state).await?;
+    if let Some(removed) = maybe_removed {
+        delete_checkpoint(&state, removed).await?;
+    }
+
+    // the blocks of these files were referenced once for the checkpoint's trees, so they're
+    // referenced again for the trees of the final archive
+    let mut block_records = state.block_records.write().await;
+    for block_refs in dir_refs.values() {
+        block_records.add_refs(block_refs)?;
+    }
+    drop(block_records);
+
+    let archive_count = state.archive_records.read().await.len();
+    let short_hash = hash.format_short(archive_count);
+    let style = AnsiColor::Yellow.on_default();
+    info!("{style}wrote checkpoint{style:#} {short_hash}");
+    Ok(())
+}
+
+/// Replaces the current checkpoint, removing the previous one along with any of its trees that are
+/// no longer referenced from the records.
+pub async fn replace_checkpoint(
+    state: &BackupState,
+    maybe_checkpoint: Option<Checkpoint>,
+) -> Result<Option<RemovedCheckpoint>> {
+    let Some(checkpoint) = replace(&mut *state.checkpoint.lock().await, maybe_checkpoint) else {
+        return Ok(None);
+    };
+
+    remove_checkpoint(state, checkpoint).await.map(Some)
+}
+
+/// Removes the checkpoints found by `find_stale_checkpoints`, which the final archive of a
+/// finished backup replaces.
+pub async fn remove_stale_checkpoints(state: &BackupState) -> Result<Vec<RemovedCheckpoint>> {
+    let checkpoints = take(&mut *state.stale_checkpoints.lock().await);
+    let mut removed = vec![];
+    for checkpoint in checkpoints {
+        removed.push(remove_checkpoint(state, checkpoint).await?);
+    }
+
+    Ok(removed)
+}
+
+async fn remove_checkpoint(
+    state: &BackupState,
+    checkpoint: Checkpoint,
+) -> Result<RemovedCheckpoint> {
+    state
+        .archive_records
+        .write()
+        .await
+        .remove(&checkpoint.hash)?;
+    let tree_hashes = block_in_place(|| {
+        remove_checkpoint_trees(
+            &checkpoint,
+            &mut state.block_records.blocking_write(),
+            &mut state.pack_records.blocking_write(),
+            &mut state.tree_records.blocking_write(),
+        )
+    })?;
+
+    Ok(RemovedCheckpoint {
+        hash: checkpoint.hash,
+        tree_hashes,
+    })
+}
+
+/// Deletes the objects of a checkpoint, which must happen after the records that no longer
+/// reference them are uploaded.
+pub async fn delete_checkpoint(state: &BackupState, removed: RemovedCheckpoint) -> Result<()> {
+    let tree_keys = removed.tree_hashes.iter().map(Hash::key);
+    let keys = iter::once(removed.hash.key()).chain(tree_keys);
+    state.storage.delete_many(keys).await?;
+
+    state.stats.write().await.trees_deleted += removed.tree_hashes.len() as u64;
+    Ok(())
+}
+
+pub async fn upload_backup_records(state: &BackupState) -> Result<()> {
+    let storage = &state.storage;
+    try_join!(
+        upload_block_records(
+            storage.clone(),
+            state.block_records.clone(),
+            state.task_count
+        ),
+        upload_pack_records(storage.clone(), state.pack_records.clone()),
+        upload_tree_records(storage.clone(), state.tree_records.clone()),
+        upload_archive_records(storage.clone(), state.archive_records.clone()),
+    )?;
+
+    Ok(())
+}
+
+/// Removes a ref to the checkpoint's root tree, and to the blocks and subtrees of each tree that is
+/// no longer referenced, returning the hashes of those trees.
+pub fn remove_checkpoint_trees(
+    checkpoint: &Checkpoint,
+    block_records: &mut BlockRecords,
+    pack_records: &mut PackRecords,
+    tree_records: &mut TreeRecords,
+) -> Result<Vec<Hash<Tree>>> {
+    let mut removed_hashes = vec![];
+    let mut pending = vec![checkpoint.tree];
+
+    while let Some(hash) = pending.pop() {
+        if tree_records.remove_ref(&hash)?.is_none() {
+            continue;
+        }
+
+        let tree = &checkpoint.trees[&hash];
+        for result in block_records.remove_refs(tree.block_refs.clone()) {
+            let (_, record) = result?;
+            pack_records.release_block(&record);
+        }
+
+        pending.extend(tree.subtrees());
+        removed_hashes.push(hash);
+    }
+
+    Ok(removed_hashes)
+}
@@ -3,28 +3,30 @@ use std::{
     path::{Path, PathBuf},
     pin::pin,
     sync::Arc,
+    time::Instant,
 };
 
 use async_channel::{Receiver, Sender};
-use async_walkdir::{DirEntry, WalkDir};
+use async_walkdir::{DirEntry, Filtering, WalkDir};
 use clap::builder::styling::AnsiColor;
 use log::{debug, warn};
 use tokio::{
     fs::{self, File},
-    io::BufReader,
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, BufReader},
 };
 use tokio_stream::StreamExt;
 
 use crate::{
-    block::{self, Block},
-    error::{Result, handle_error},
-    file::{Node, read_metadata},
+    block::{self, Block, BlockRefs},
+    error::Result,
+    file::{ExcludeSet, FileData, Node, read_metadata},
     format::{format_path, format_size},
     hash::Hash,
+    progress::ProgressTotals,
     task::BoundedJoinSet,
 };
 
-use super::{BackupState, blocks::UploadTree};
+use super::{BackupState, blocks::UploadTree, checkpoint::write_checkpoint};
 
 #[derive(Debug)]
 pub struct PendingUpload {
@@ -49,8 +51,8 @@ pub async fn backup_recursive(
     sender: Sender<PendingUpload>,
     path: &Path,
 ) -> Result<()> {
-    let mut walker = WalkDir::new(path);
-    loop {
+    let mut walker = walk_excluding(path, state.excludes.clone());
+    while !state.cancellation.is_cancelled() {
         match walker.try_next().await {
             Ok(Some(entry)) => {
                 let maybe_file = backup_from_entry(state.clone(), entry, path).await?;
@@ -68,6 +70,52 @@ pub async fn backup_recursive(
     Ok(())
 }
 
+/// Counts the files under `paths` and their total size for estimating how long the backup will
+/// take. Anything that can't be read is skipped here and reported by the backup itself.
+pub async fn scan_totals<P: AsRef<Path>>(paths: &[P], excludes: ExcludeSet) -> ProgressTotals {
+    let mut totals = ProgressTotals::default();
+
+    for path in paths {
+        let mut walker = walk_excluding(path.as_ref(), excludes.clone());
+        while let Some(result) = walker.next().await {
+            let Ok(entry) = result else {
+                continue;
+            };
+
+            if let Ok(metadata) = entry.metadata().await
+                && metadata.is_file()
+            {
+                totals.files += 1;
+                totals.bytes += metadata.len();
+            }
+        }
+    }
+
+    totals
+}
+
+/// Walks the directory at `path`, leaving out excluded entries and everything beneath them.
+fn walk_excluding(path: &Path, excludes: ExcludeSet) -> WalkDir {
+    let base_path = path.to_owned();
+    WalkDir::new(path).filter(move |entry| {
+        let local_path = entry.path();
+        let excluded = local_path
+            .strip_prefix(&base_path)
+            .is_ok_and(|archive_path| excludes.is_excluded(archive_path));
+        if excluded {
+            debug!("excluded {}", format_path(&local_path));
+        }
+
+        async move {
+            if excluded {
+                Filtering::IgnoreDir
+            } else {
+                Filtering::Continue
+            }
+        }
+    })
+}
+
 async fn backup_from_entry(
     state: Arc<BackupState>,
     entry: DirEntry,
@@ -88,8 +136,7 @@ async fn backup_from_entry(
         let metadata = read_metadata(&local_path).await?;
         let path = fs::read_link(&local_path).await?;
         let node = Node::Symlink { metadata, path };
-        let archive = &mut state.archive.write().await;
-        archive.insert(archive_path, node)?;
+        state.files.write().await.insert(archive_path, node)?;
 
         let style = AnsiColor::Cyan.on_default();
         debug!("{style}added symlink{style:#} {formatted_path}");
@@ -97,8 +144,7 @@ async fn backup_from_entry(
         let metadata = read_metadata(&local_path).await?;
         let children = BTreeMap::new();
         let node = Node::Directory { metadata, children };
-        let archive = &mut state.archive.write().await;
-        archive.insert(archive_path, node)?;
+        state.files.write().await.insert(archive_path, node)?;
 
         let style = AnsiColor::Magenta.on_default();
         debug!("{style}added directory{style:#} {formatted_path}");
@@ -114,20 +160,42 @@ pub async fn upload_pending_files(
     receiver: Receiver<PendingUpload>,
 ) -> Result<()> {
     let mut tasks = BoundedJoinSet::new(state.task_count);
+    let mut checkpoint_time = Instant::now();
 
     while let Ok(pending_file) = receiver.recv().await {
+        // files that are already queued are skipped so that the walk can stop
+        if state.cancellation.is_cancelled() {
+            continue;
+        }
+
+        if let Some(interval) = state.checkpoint_interval
+            && checkpoint_time.elapsed() >= interval
+        {
+            // files that are still being uploaded can't be part of the checkpoint
+            while let Some(result) = tasks.join_next().await {
+                result??;
+            }
+
+            write_checkpoint(state.clone()).await?;
+            checkpoint_time = Instant::now();
+        }
+
         let state = state.clone();
         tasks
-            .spawn(Box::pin(upload_pending_file(state, pending_file)))
+            .spawn(Box::pin(async move {
+                let path = pending_file.local_path.clone();
+                let result = upload_pending_file(state.clone(), pending_file).await;
+                state.failures.handle(&path, result)
+            }))
             .await?;
 
         while let Some(result) = tasks.try_join_next() {
-            handle_error(result?);
+            result??;
         }
     }
 
     while let Some(result) = tasks.join_next().await {
-        handle_error(result?);
+        result??;
     }
 
     Ok(())
@@ -139,43 +207,121 @@ async fn upload_pending_file(state: Arc<BackupState>, pending_file: PendingUploa
         archive_path,
     } = pending_file;
 
-    let metadata = read_metadata(&local_path).await?;
+    let mut metadata = read_metadata(&local_path).await?;
     let mut file = File::open(&local_path).await?;
-    let (hash, size) = upload_file(state.clone(), &mut file).await?;
-    let node = Node::File { metadata, hash };
-    let archive = &mut state.archive.write().await;
-    archive.insert(archive_path, node)?;
+
+    let maybe_bytes = read_inline(&state, &mut file).await?;
+    let (data, size, msg) = if let Some(bytes) = maybe_bytes {
+        let size = bytes.len() as u64;
+        (FileData::inline(bytes), size, "inlined file")
+    } else {
+        let (hash, block_refs, size) = upload_file(state.clone(), &mut file).await?;
+        add_dir_refs(&state, &archive_path, block_refs).await;
+        (FileData::blocks(hash), size, "uploaded file")
+    };
+
+    // the file may have changed size since its metadata was read
+    metadata.size = size;
+    let node = Node::File { metadata, data };
+    state.files.write().await.insert(archive_path, node)?;
 
     let formatted_path = format_path(&local_path);
     let formatted_size = format_size(size);
     let msg_style = AnsiColor::Blue.on_default();
     let size_style = AnsiColor::BrightBlack.on_default();
     debug!(
-        "{msg_style}uploaded file{msg_style:#} {formatted_path} {size_style}({formatted_size}){size_style:#}"
+        "{msg_style}{msg}{msg_style:#} {formatted_path} {size_style}({formatted_size}){size_style:#}"
     );
     Ok(())
 }
 
+/// Reads the entire file if it's no larger than the inline threshold, otherwise rewinds it so that
+/// it can be uploaded as blocks.
+async fn read_inline(state: &BackupState, file: &mut File) -> Result<Option<Vec<u8>>> {
+    let Some(bytes) = read_up_to(file, state.inline_threshold).await? else {
+        return Ok(None);
+    };
+
+    let size = bytes.len() as u64;
+    state.stats.write().await.bytes_read += size;
+    state.stats.write().await.files_read += 1;
+    if size > 0 {
+        state.stats.write().await.files_inlined += 1;
+    }
+
+    Ok(Some(bytes))
+}
+
+/// Reads the rest of `reader` if it's no larger than `max_size` (and `max_size` isn't 0),
+/// otherwise rewinds it.
+pub async fn read_up_to<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut R,
+    max_size: u64,
+) -> Result<Option<Vec<u8>>> {
+    if max_size == 0 {
+        return Ok(None);
+    }
+
+    let mut bytes = vec![];
+    (&mut *reader)
+        .take(max_size + 1)
+        .read_to_end(&mut bytes)
+        .await?;
+
+    if bytes.len() as u64 > max_size {
+        reader.rewind().await?;
+        return Ok(None);
+    }
+
+    Ok(Some(bytes))
+}
+
 pub async fn upload_file(
     state: Arc<BackupState>,
     file: &mut File,
-) -> Result<(Option<Hash<Block>>, u64)> {
+) -> Result<(Option<Hash<Block>>, BlockRefs, u64)> {
     let reader = BufReader::new(file);
     let mut chunker = block::chunker(reader, state.target_block_size);
     let mut chunks = pin!(chunker.as_stream());
     let mut tree = UploadTree::new(state.clone());
     let mut size = 0;
 
-    while let Some(chunk) = chunks.try_next().await? {
-        size += chunk.data.len() as u64;
-        tree.add_leaf(chunk.data).await?;
+    loop {
+        let chunk = match chunks.try_next().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => {
+                tree.release().await?;
+                return Err(err.into());
+            }
+        };
+
+        let chunk_size = chunk.data.len() as u64;
+        size += chunk_size;
+        if let Err(err) = tree.add_leaf(chunk.data).await {
+            tree.release().await?;
+            return Err(err);
+        }
+
+        // counted as each chunk is read so that progress moves during large files
+        state.stats.write().await.bytes_read += chunk_size;
     }
 
-    state.stats.write().await.bytes_read += size;
     state.stats.write().await.files_read += 1;
 
-    let hash = tree.finalize().await?;
-    Ok((hash, size))
+    let (hash, block_refs) = tree.finalize().await?;
+    Ok((hash, block_refs, size))
+}
+
+/// Records the block refs of a file under the directory containing it, since each directory's
+/// tree holds the refs of the files directly inside it.
+async fn add_dir_refs(state: &BackupState, archive_path: &Path, block_refs: BlockRefs) {
+    let dir_path = archive_path
+        .parent()
+        .map(Path::to_owned)
+        .unwrap_or_default();
+    let mut dir_refs = state.dir_refs.write().await;
+    dir_refs.entry(dir_path).or_default().add_refs(block_refs);
 }
 
 fn handle_walkdir_error(err: async_walkdir::Error) -> Result<()> {
@@ -1,25 +1,63 @@
+#[cfg(test)]
+mod tests;
+
 mod blocks;
+mod checkpoint;
 mod files;
+mod trees;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
 use crate::{
-    archive::Archive, block::BlockRecords, locks::BlockLocks, stats::CommandStats, storage::Storage,
+    archive::{ArchiveInfo, ArchiveRecords},
+    block::BlockRecords,
+    file::{ExcludeSet, FileTree},
+    locks::BlockLocks,
+    ops::FileFailures,
+    pack::{PackBuilder, PackRecords},
+    signal::Cancellation,
+    stats::CommandStats,
+    storage::Storage,
+    tree::{DirRefs, TreeRecords},
 };
 
-pub use self::files::{backup_all, upload_pending_files};
+use self::checkpoint::Checkpoint;
+
+pub use self::{
+    blocks::{delete_transient_packs, upload_pending_pack},
+    checkpoint::{
+        delete_checkpoint, find_stale_checkpoints, remove_stale_checkpoints, replace_checkpoint,
+        upload_backup_records,
+    },
+    files::{backup_all, scan_totals, upload_pending_files},
+    trees::upload_trees,
+};
 
 #[derive(Debug)]
 pub struct BackupState {
+    pub info: ArchiveInfo,
     pub compression_level: u8,
     pub target_block_size: u32,
+    pub inline_threshold: u64,
     pub task_count: usize,
+    pub checkpoint_interval: Option<Duration>,
     pub dry_run: bool,
+    pub excludes: ExcludeSet,
+    pub cancellation: Cancellation,
+    pub failures: FileFailures,
     pub stats: Arc<RwLock<CommandStats>>,
     pub storage: Arc<Storage>,
-    pub archive: Arc<RwLock<Archive>>,
+    pub files: Arc<RwLock<FileTree>>,
+    pub dir_refs: Arc<RwLock<DirRefs>>,
+    pub archive_records: Arc<RwLock<ArchiveRecords>>,
     pub block_records: Arc<RwLock<BlockRecords>>,
+    pub pack_records: Arc<RwLock<PackRecords>>,
+    pub tree_records: Arc<RwLock<TreeRecords>>,
+    pub pack_builder: Mutex<PackBuilder>,
     pub block_locks: Arc<RwLock<BlockLocks>>,
+    pub checkpoint: Mutex<Option<Checkpoint>>,
+    /// Checkpoints of earlier runs that didn't finish, which are replaced by the final archive.
+    pub stale_checkpoints: Mutex<Vec<Checkpoint>>,
 }
@@ -0,0 +1,274 @@
+use std::{collections::HashMap, ffi::OsString, io::Cursor, path::PathBuf};
+
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    archive::{ArchiveInfo, CHECKPOINT_TAG},
+    block::{Block, BlockRecord, BlockRecords, BlockRefs},
+    entity::EntityIndex,
+    error::Error,
+    file::Metadata,
+    hash::{self, Hash},
+    pack::{Pack, PackBuilder, PackRecord, PackRecords},
+    tree::{Tree, TreeEntry, TreeRecord, TreeRecords},
+};
+
+use super::{
+    blocks::release_block_refs,
+    checkpoint::{Checkpoint, is_stale_checkpoint, remove_checkpoint_trees},
+    files::read_up_to,
+};
+
+fn block_hash(n: u8) -> Hash<Block> {
+    Hash::from_bytes([n; hash::SIZE])
+}
+
+fn pack_hash() -> Hash<Pack> {
+    Hash::pack(&[])
+}
+
+fn info(tags: &[&str]) -> ArchiveInfo {
+    ArchiveInfo {
+        created: Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap(),
+        name: Some("nightly".to_owned()),
+        description: None,
+        tags: tags.iter().map(|&tag| tag.to_owned()).collect(),
+        hostname: "laptop".to_owned(),
+        username: "alice".to_owned(),
+        paths: vec![PathBuf::from("/home/alice")],
+    }
+}
+
+fn block_refs(refs: &[(u8, u64)]) -> BlockRefs {
+    let mut block_refs = BlockRefs::new();
+    for &(n, count) in refs {
+        block_refs.add_count(&block_hash(n), count);
+    }
+    block_refs
+}
+
+fn dir_entry(tree: Hash<Tree>) -> TreeEntry {
+    let metadata = Metadata {
+        inode: 1,
+        mode: 0o755,
+        group: 0,
+        owner: 0,
+        size: 0,
+        accessed: None,
+        created: None,
+        modified: None,
+    };
+    TreeEntry::Directory { metadata, tree }
+}
+
+/// Records for a checkpoint whose root tree holds block 0 and a subtree holding block 1, where
+/// the subtree and block 1 are also referenced by another archive.
+fn records() -> (Checkpoint, BlockRecords, PackRecords, TreeRecords) {
+    let subtree = Tree {
+        entries: [].into(),
+        block_refs: block_refs(&[(1, 1)]),
+    };
+    let subtree_hash = Hash::tree(b"subtree");
+    let root = Tree {
+        entries: [(OsString::from("dir"), dir_entry(subtree_hash))].into(),
+        block_refs: block_refs(&[(0, 1)]),
+    };
+    let root_hash = Hash::tree(b"root");
+
+    let mut block_records = BlockRecords::new();
+    for (n, ref_count) in [(0, 1), (1, 2)] {
+        let record = BlockRecord {
+            ref_count,
+            pack: pack_hash(),
+            offset: u64::from(n) * 10,
+            size: 10,
+        };
+        block_records.insert(block_hash(n), record).unwrap();
+    }
+
+    let mut pack_records = PackRecords::new();
+    pack_records.insert(
+        pack_hash(),
+        PackRecord {
+            size: 20,
+            used_size: 20,
+        },
+    );
+
+    let mut tree_records = TreeRecords::new();
+    for (hash, ref_count) in [(root_hash, 1), (subtree_hash, 2)] {
+        tree_records.insert(hash, TreeRecord { ref_count, size: 1 });
+    }
+
+    let checkpoint = Checkpoint {
+        hash: Hash::archive(b"checkpoint"),
+        tree: root_hash,
+        trees: HashMap::from([(root_hash, root), (subtree_hash, subtree)]),
+    };
+    (checkpoint, block_records, pack_records, tree_records)
+}
+
+#[test]
+fn stale_checkpoint_of_same_backup() {
+    let backup = info(&[]);
+    assert!(is_stale_checkpoint(&info(&[CHECKPOINT_TAG]), &backup));
+    assert!(!is_stale_checkpoint(&info(&[]), &backup));
+
+    let other_host = ArchiveInfo {
+        hostname: "desktop".to_owned(),
+        ..info(&[CHECKPOINT_TAG])
+    };
+    assert!(!is_stale_checkpoint(&other_host, &backup));
+
+    let other_paths = ArchiveInfo {
+        paths: vec![PathBuf::from("/home/bob")],
+        ..info(&[CHECKPOINT_TAG])
+    };
+    assert!(!is_stale_checkpoint(&other_paths, &backup));
+}
+
+#[test]
+fn remove_checkpoint_releases_unshared_refs() {
+    let (checkpoint, mut block_records, mut pack_records, mut tree_records) = records();
+
+    let removed = remove_checkpoint_trees(
+        &checkpoint,
+        &mut block_records,
+        &mut pack_records,
+        &mut tree_records,
+    )
+    .unwrap();
+    assert_eq!(removed, vec![checkpoint.tree]);
+
+    // the shared subtree keeps its blocks
+    assert!(!tree_records.contains(&checkpoint.tree));
+    let subtree_hash = checkpoint.trees[&checkpoint.tree]
+        .subtrees()
+        .next()
+        .unwrap();
+    assert_eq!(tree_records.get(subtree_hash).unwrap().ref_count, 1);
+    assert!(!block_records.contains(&block_hash(0)).unwrap());
+    assert_eq!(
+        block_records
+            .get(&block_hash(1))
+            .unwrap()
+            .unwrap()
+            .ref_count,
+        2
+    );
+    assert_eq!(pack_records.get(&pack_hash()).unwrap().used_size, 10);
+}
+
+#[test]
+fn remove_checkpoint_of_shared_root() {
+    let (checkpoint, mut block_records, mut pack_records, mut tree_records) = records();
+    tree_records.add_ref(&checkpoint.tree);
+
+    let removed = remove_checkpoint_trees(
+        &checkpoint,
+        &mut block_records,
+        &mut pack_records,
+        &mut tree_records,
+    )
+    .unwrap();
+    assert!(removed.is_empty());
+
+    assert_eq!(tree_records.get(&checkpoint.tree).unwrap().ref_count, 1);
+    assert_eq!(
+        block_records
+            .get(&block_hash(0))
+            .unwrap()
+            .unwrap()
+            .ref_count,
+        1
+    );
+    assert_eq!(pack_records.get(&pack_hash()).unwrap().used_size, 20);
+}
+
+#[test]
+fn remove_checkpoints_sharing_root() {
+    // a stale checkpoint and the current one can have the same root tree
+    let (checkpoint, mut block_records, mut pack_records, mut tree_records) = records();
+    tree_records.add_ref(&checkpoint.tree);
+
+    for expected in [vec![], vec![checkpoint.tree]] {
+        let removed = remove_checkpoint_trees(
+            &checkpoint,
+            &mut block_records,
+            &mut pack_records,
+            &mut tree_records,
+        )
+        .unwrap();
+        assert_eq!(removed, expected);
+    }
+
+    assert!(!block_records.contains(&block_hash(0)).unwrap());
+    assert_eq!(pack_records.get(&pack_hash()).unwrap().used_size, 10);
+}
+
+#[test]
+fn release_refs_of_failed_file() {
+    let (_, mut block_records, mut pack_records, _) = records();
+    let mut pack_builder = PackBuilder::new();
+    pack_builder.add(block_hash(2), &[2; 10], 2);
+
+    // the file referenced both stored blocks and a block waiting in the current pack
+    let refs = block_refs(&[(0, 1), (1, 1), (2, 1)]);
+    release_block_refs(
+        &refs,
+        &mut pack_builder,
+        &mut block_records,
+        &mut pack_records,
+    )
+    .unwrap();
+
+    assert!(!block_records.contains(&block_hash(0)).unwrap());
+    assert_eq!(
+        block_records
+            .get(&block_hash(1))
+            .unwrap()
+            .unwrap()
+            .ref_count,
+        1
+    );
+    assert_eq!(pack_records.get(&pack_hash()).unwrap().used_size, 10);
+
+    let pack = pack_builder.finish();
+    let records = pack_builder.complete(&pack.hash);
+    assert_eq!(records[0].1.ref_count, 1);
+}
+
+#[test]
+fn release_refs_not_found() {
+    let (_, mut block_records, mut pack_records, _) = records();
+    let mut pack_builder = PackBuilder::new();
+
+    let err = release_block_refs(
+        &block_refs(&[(3, 1)]),
+        &mut pack_builder,
+        &mut block_records,
+        &mut pack_records,
+    )
+    .unwrap_err();
+    assert_eq!(err, Error::BlockRecordNotFound(block_hash(3)));
+}
+
+#[tokio::test]
+async fn read_files_around_inline_threshold() {
+    for size in [0, 1, 9, 10] {
+        let mut reader = Cursor::new(vec![1; size]);
+        let bytes = read_up_to(&mut reader, 10).await.unwrap();
+        assert_eq!(bytes, Some(vec![1; size]));
+    }
+
+    // larger files are rewound so that they can be read again as blocks
+    let mut reader = Cursor::new(vec![1; 11]);
+    assert_eq!(read_up_to(&mut reader, 10).await.unwrap(), None);
+    assert_eq!(reader.position(), 0);
+}
+
+#[tokio::test]
+async fn read_files_without_inline_threshold() {
+    let mut reader = Cursor::new(vec![]);
+    assert_eq!(read_up_to(&mut reader, 0).await.unwrap(), None);
+}
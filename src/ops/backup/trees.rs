@@ -0,0 +1,125 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem::{replace, take},
+    sync::Arc,
+};
+
+use clap::builder::styling::AnsiColor;
+use log::debug;
+use tokio::task::{block_in_place, spawn_blocking};
+
+use crate::{
+    block::BlockRecords,
+    entity::EntityIndex,
+    error::Result,
+    file::FileTree,
+    hash::Hash,
+    ops::upload_tree,
+    task::BoundedJoinSet,
+    tree::{DirRefs, EncodedTree, Tree, TreeRecord, TreeSet},
+};
+
+use super::BackupState;
+
+/// Builds a tree for every directory that was backed up and uploads the trees that aren't already
+/// stored, returning the hash of the root tree.
+pub async fn upload_trees(state: Arc<BackupState>) -> Result<Hash<Tree>> {
+    let files = replace(&mut *state.files.write().await, FileTree::new());
+    let dir_refs = take(&mut *state.dir_refs.write().await);
+    let (root, _) = upload_tree_set(&state, files, dir_refs).await?;
+    Ok(root)
+}
+
+/// Builds and uploads the trees of `files` like `upload_trees`, also returning every tree by hash.
+pub async fn upload_tree_set(
+    state: &Arc<BackupState>,
+    files: FileTree,
+    mut dir_refs: DirRefs,
+) -> Result<(Hash<Tree>, HashMap<Hash<Tree>, Tree>)> {
+    let tree_set =
+        spawn_blocking(move || TreeSet::build(files.into_children(), &mut dir_refs)).await??;
+
+    let new_hashes = block_in_place(|| register_trees(state, &tree_set))?;
+    let mut tasks = BoundedJoinSet::new(state.task_count);
+    let mut trees = HashMap::new();
+
+    for (hash, encoded_tree) in tree_set.trees {
+        let EncodedTree {
+            tree,
+            compressed_bytes,
+        } = encoded_tree;
+        trees.insert(hash, tree);
+
+        if state.dry_run || !new_hashes.contains(&hash) {
+            continue;
+        }
+
+        let state = state.clone();
+        tasks
+            .spawn(async move {
+                let storage = state.storage.clone();
+                upload_tree(storage, hash, compressed_bytes).await?;
+                state.stats.write().await.trees_uploaded += 1;
+
+                let style = AnsiColor::Magenta.on_default();
+                debug!("{style}uploaded tree{style:#} {hash}");
+                Result::Ok(())
+            })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            result??;
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+
+    Ok((tree_set.root, trees))
+}
+
+/// Adds records for trees that don't exist yet, along with their subtrees, returning their hashes.
+///
+/// Every block of every file was referenced while it was uploaded, but an existing tree already
+/// holds references to the blocks beneath it, so those references are removed again.
+fn register_trees(state: &BackupState, tree_set: &TreeSet) -> Result<HashSet<Hash<Tree>>> {
+    let mut block_records = state.block_records.blocking_write();
+    let mut tree_records = state.tree_records.blocking_write();
+    let mut new_hashes = HashSet::new();
+    let mut pending = vec![tree_set.root];
+
+    while let Some(hash) = pending.pop() {
+        let encoded_tree = &tree_set.trees[&hash];
+
+        if tree_records.add_ref(&hash) {
+            remove_tree_refs(&mut block_records, tree_set, hash)?;
+        } else {
+            let size = encoded_tree.compressed_bytes.len() as u64;
+            tree_records.insert(hash, TreeRecord { ref_count: 1, size });
+            new_hashes.insert(hash);
+            pending.extend(encoded_tree.tree.subtrees());
+        }
+    }
+
+    Ok(new_hashes)
+}
+
+fn remove_tree_refs(
+    block_records: &mut BlockRecords,
+    tree_set: &TreeSet,
+    hash: Hash<Tree>,
+) -> Result<()> {
+    let mut pending = vec![hash];
+
+    while let Some(hash) = pending.pop() {
+        let tree = &tree_set.trees[&hash].tree;
+        for result in block_records.remove_refs(tree.block_refs.clone()) {
+            result?;
+        }
+
+        pending.extend(tree.subtrees());
+    }
+
+    Ok(())
+}
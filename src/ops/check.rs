@@ -0,0 +1,370 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use async_recursion::async_recursion;
+use log::error;
+use tokio::{sync::RwLock, task::spawn_blocking};
+
+use crate::{
+    arc::rwarc,
+    archive::{Archive, ArchiveRecords},
+    block::{Block, BlockRecords, BlockRefs},
+    entity::EntityIndex,
+    error::{Error, Result},
+    file::FileData,
+    hash::Hash,
+    pack::{Pack, PackRecords},
+    stats::CommandStats,
+    storage::Storage,
+    task::BoundedJoinSet,
+    tree::{Tree, TreeEntry, TreeRecords},
+};
+
+use super::{download_archive, download_block_bytes, download_trees};
+
+#[derive(Debug)]
+pub struct CheckState {
+    pub task_count: usize,
+    pub stats: Arc<RwLock<CommandStats>>,
+    pub storage: Arc<Storage>,
+    pub archive_records: ArchiveRecords,
+    pub block_records: BlockRecords,
+    pub pack_records: PackRecords,
+    pub tree_records: TreeRecords,
+    pub archive_hashes: HashSet<Hash<Archive>>,
+    pub pack_hashes: HashSet<Hash<Pack>>,
+    pub tree_hashes: HashSet<Hash<Tree>>,
+}
+
+pub async fn check_pack_objects(state: Arc<CheckState>) {
+    for hash in &state.pack_hashes {
+        if !state.pack_records.contains(hash) {
+            report(&state, Error::PackRecordNotFound(*hash)).await;
+        }
+    }
+
+    let pack_usage = state.block_records.pack_usage();
+    for (hash, record) in state.pack_records.iter() {
+        if !state.pack_hashes.contains(hash) {
+            report(&state, Error::ItemNotFound(hash.key())).await;
+        }
+
+        let expected = pack_usage.get(hash).copied().unwrap_or_default();
+        if record.used_size != expected {
+            let err = Error::PackUsageMismatch {
+                hash: *hash,
+                actual: record.used_size,
+                expected,
+            };
+            report(&state, err).await;
+        }
+    }
+
+    for (hash, record) in state.block_records.iter() {
+        if !state.pack_records.contains(&record.pack) {
+            let err = Error::MissingBlockPack {
+                hash: *hash,
+                pack: record.pack,
+            };
+            report(&state, err).await;
+        }
+    }
+}
+
+pub async fn check_archive_objects(state: Arc<CheckState>) {
+    for (hash, _) in state.archive_records.iter_by_created() {
+        if !state.archive_hashes.contains(hash) {
+            report(&state, Error::ItemNotFound(hash.key())).await;
+        }
+    }
+}
+
+pub async fn check_tree_objects(state: Arc<CheckState>) {
+    for hash in &state.tree_hashes {
+        if !state.tree_records.contains(hash) {
+            report(&state, Error::TreeRecordNotFound(*hash)).await;
+        }
+    }
+
+    for (hash, _) in state.tree_records.iter() {
+        if !state.tree_hashes.contains(hash) {
+            report(&state, Error::ItemNotFound(hash.key())).await;
+        }
+    }
+}
+
+pub async fn check_archives(state: Arc<CheckState>) -> Result<HashSet<Hash<Block>>> {
+    let mut tasks = BoundedJoinSet::new(state.task_count);
+    let mut expected_tree_refs = HashMap::new();
+
+    for (hash, _) in state.archive_records.iter_by_created() {
+        if !state.archive_hashes.contains(hash) {
+            continue;
+        }
+
+        let storage = state.storage.clone();
+        let hash = *hash;
+        tasks
+            .spawn(async move { download_archive(storage, &hash).await })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            add_archive(&state, &mut expected_tree_refs, result?).await;
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        add_archive(&state, &mut expected_tree_refs, result?).await;
+    }
+
+    let tree_hashes = expected_tree_refs.keys().copied().collect::<Vec<_>>();
+    let trees = download_trees(state.storage.clone(), tree_hashes, state.task_count).await?;
+    let mut expected_refs = BlockRefs::new();
+    let mut roots = HashSet::new();
+
+    for (_, result) in trees {
+        let tree = match result {
+            Ok(tree) => tree,
+            Err(err) => {
+                report(&state, err).await;
+                continue;
+            }
+        };
+
+        for entry in tree.entries.values() {
+            match entry {
+                TreeEntry::File {
+                    data: FileData::Blocks(hash),
+                    ..
+                } => {
+                    roots.insert(*hash);
+                }
+                TreeEntry::Directory { tree, .. } => {
+                    *expected_tree_refs.entry(*tree).or_default() += 1;
+                }
+                TreeEntry::File { .. } | TreeEntry::Symlink { .. } => {}
+            }
+        }
+
+        expected_refs.add_refs(tree.block_refs);
+    }
+
+    for (hash, record) in state.tree_records.iter() {
+        let expected = expected_tree_refs.get(hash).copied().unwrap_or_default();
+        if record.ref_count != expected {
+            let err = Error::TreeRefCountMismatch {
+                hash: *hash,
+                actual: record.ref_count,
+                expected,
+            };
+            report(&state, err).await;
+        }
+    }
+
+    for hash in expected_tree_refs.keys() {
+        if !state.tree_records.contains(hash) {
+            report(&state, Error::TreeRecordNotFound(*hash)).await;
+        }
+    }
+
+    for (hash, record) in state.block_records.iter() {
+        let expected = expected_refs.count(hash);
+        if record.ref_count != expected {
+            let err = Error::RefCountMismatch {
+                hash: *hash,
+                actual: record.ref_count,
+                expected,
+            };
+            report(&state, err).await;
+        }
+    }
+
+    for (hash, _) in expected_refs.iter() {
+        if !state.block_records.contains(hash)? {
+            report(&state, Error::BlockRecordNotFound(*hash)).await;
+        }
+    }
+
+    Ok(roots)
+}
+
+pub async fn check_block_trees(state: Arc<CheckState>, roots: HashSet<Hash<Block>>) -> Result<()> {
+    let mut tasks = BoundedJoinSet::new(state.task_count);
+    let visited = rwarc(HashSet::new());
+
+    for hash in roots {
+        let state = state.clone();
+        let visited = visited.clone();
+        tasks
+            .spawn(async move {
+                let result = check_block_tree(state.clone(), visited, hash, None).await;
+                report_result(&state, result).await;
+            })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            result?;
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Downloads and verifies a percentage of blocks, which `seed` picks so that the same ones can be
+/// checked again.
+pub async fn check_block_data(state: Arc<CheckState>, percentage: u8, seed: u64) -> Result<()> {
+    let mut tasks = BoundedJoinSet::new(state.task_count);
+    let selected_hashes = state
+        .block_records
+        .iter()
+        .map(|(hash, _)| *hash)
+        .filter(|hash| is_selected(hash, seed, percentage))
+        .collect::<Vec<_>>();
+
+    for hash in selected_hashes {
+        let state = state.clone();
+        tasks
+            .spawn(async move {
+                let result = check_block(state.clone(), hash).await;
+                report_result(&state, result).await;
+            })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            result?;
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result?;
+    }
+
+    Ok(())
+}
+
+async fn add_archive(
+    state: &CheckState,
+    expected_tree_refs: &mut HashMap<Hash<Tree>, u64>,
+    result: Result<Archive>,
+) {
+    let archive = match result {
+        Ok(archive) => archive,
+        Err(err) => {
+            report(state, err).await;
+            return;
+        }
+    };
+
+    *expected_tree_refs.entry(archive.tree).or_default() += 1;
+    state.stats.write().await.archives_checked += 1;
+}
+
+#[async_recursion]
+async fn check_block_tree(
+    state: Arc<CheckState>,
+    visited: Arc<RwLock<HashSet<Hash<Block>>>>,
+    hash: Hash<Block>,
+    level: Option<u8>,
+) -> Result<()> {
+    if !visited.write().await.insert(hash) {
+        return Ok(());
+    }
+
+    if !state.block_records.contains(&hash)? {
+        report(&state, Error::BlockRecordNotFound(hash)).await;
+        return Ok(());
+    }
+
+    // leaves beneath a branch don't need to be downloaded, but a root block's level is only known
+    // once it has been
+    if level == Some(0) {
+        return Ok(());
+    }
+
+    let block = download_block(&state, hash, level).await?;
+    for err in missing_children(&block, &state.block_records) {
+        report(&state, err).await;
+    }
+
+    for (child, child_level) in child_branches(&block, &state.block_records) {
+        check_block_tree(state.clone(), visited.clone(), child, Some(child_level)).await?;
+    }
+
+    Ok(())
+}
+
+async fn check_block(state: Arc<CheckState>, hash: Hash<Block>) -> Result<()> {
+    let block = download_block(&state, hash, None).await?;
+    for err in missing_children(&block, &state.block_records) {
+        report(&state, err).await;
+    }
+
+    state.stats.write().await.blocks_checked += 1;
+    Ok(())
+}
+
+/// Returns an error for each child of `block` that has no record, or whose record can't be looked
+/// up.
+pub fn missing_children(block: &Block, block_records: &BlockRecords) -> Vec<Error> {
+    let Block::Branch { hash, children, .. } = block else {
+        return vec![];
+    };
+
+    children
+        .iter()
+        .filter_map(|child| match block_records.contains(&child.hash) {
+            Ok(true) => None,
+            Ok(false) => Some(Error::MissingChildBlock {
+                hash: *hash,
+                child: child.hash,
+            }),
+            Err(err) => Some(err),
+        })
+        .collect()
+}
+
+/// Returns the children of `block` that are branches themselves, along with their levels. Only
+/// children with records are returned, since the others are reported by `missing_children`.
+pub fn child_branches(block: &Block, block_records: &BlockRecords) -> Vec<(Hash<Block>, u8)> {
+    match block {
+        Block::Branch {
+            level, children, ..
+        } if *level > 1 => children
+            .iter()
+            .filter(|child| matches!(block_records.contains(&child.hash), Ok(true)))
+            .map(|child| (child.hash, level - 1))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+async fn download_block(state: &CheckState, hash: Hash<Block>, level: Option<u8>) -> Result<Block> {
+    let bytes = download_block_bytes(&state.storage, &state.block_records, &hash).await?;
+    state.stats.write().await.blocks_downloaded += 1;
+    state.stats.write().await.content_bytes_downloaded += bytes.len() as u64;
+
+    spawn_blocking(move || Block::decode(&hash, level, &bytes)).await?
+}
+
+async fn report(state: &CheckState, err: Error) {
+    error!("{err}");
+    state.stats.write().await.problems_found += 1;
+}
+
+async fn report_result(state: &CheckState, result: Result<()>) {
+    if let Err(err) = result {
+        report(state, err).await;
+    }
+}
+
+pub fn is_selected(hash: &Hash<Block>, seed: u64, percentage: u8) -> bool {
+    let (prefix, _) = hash.as_bytes().split_at(size_of::<u64>());
+    let value = u64::from_le_bytes(prefix.try_into().unwrap()).wrapping_add(seed);
+    value % 100 < u64::from(percentage)
+}
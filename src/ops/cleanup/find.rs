@@ -1,6 +1,7 @@
 use std::{pin::pin, sync::Arc};
 
 use async_channel::Sender;
+use log::debug;
 use tokio::{sync::RwLock, task::block_in_place};
 use tokio_stream::StreamExt;
 
@@ -9,11 +10,12 @@ use crate::{
     entity::{Entity, EntityIndex},
     error::{Result, handle_error},
     hash::Hash,
-    ops::download_archive,
+    ops::{download_archive, download_tree, load_block_shards},
     task::BoundedJoinSet,
+    tree::Tree,
 };
 
-use super::{CleanupState, RemovedArchive, RemovedBlock, RemovedEntity};
+use super::{CleanupState, RemovedArchive, RemovedEntity, RemovedPack, RemovedTree};
 
 pub async fn find_garbage_entities<E, I>(
     state: Arc<CleanupState>,
@@ -64,7 +66,7 @@ pub async fn find_archives_and_garbage_blocks<'a, I>(
     state: Arc<CleanupState>,
     hashes: I,
     archive_sender: Sender<RemovedArchive>,
-    block_sender: Sender<RemovedBlock>,
+    tree_sender: Sender<RemovedTree>,
 ) -> Result<()>
 where
     I: IntoIterator<Item = &'a Hash<Archive>>,
@@ -75,7 +77,7 @@ where
         let state = state.clone();
         let hash = *hash;
         let archive_sender = archive_sender.clone();
-        let block_sender = block_sender.clone();
+        let tree_sender = tree_sender.clone();
 
         tasks
             .spawn(async move {
@@ -87,20 +89,7 @@ where
                 };
                 archive_sender.send(removed_archive).await?;
 
-                block_in_place(move || {
-                    let mut block_records = state.block_records.blocking_write();
-                    let removed_records = block_records.remove_refs(archive.block_refs);
-                    for result in removed_records {
-                        let (hash, record) = result?;
-                        let removed_block = RemovedBlock {
-                            hash,
-                            record: Some(record),
-                        };
-                        block_sender.send_blocking(removed_block)?;
-                    }
-
-                    Result::Ok(())
-                })
+                Box::pin(remove_tree_ref(&state, archive.tree, &tree_sender)).await
             })
             .await?;
 
@@ -115,3 +104,83 @@ where
 
     Ok(())
 }
+
+pub async fn find_unused_packs(
+    state: Arc<CleanupState>,
+    sender: Sender<RemovedPack>,
+) -> Result<()> {
+    let unused_hashes = state
+        .pack_records
+        .read()
+        .await
+        .iter()
+        .filter(|(_, record)| record.used_size == 0)
+        .map(|(hash, _)| *hash)
+        .collect::<Vec<_>>();
+
+    for hash in unused_hashes {
+        let record = state.pack_records.write().await.remove(&hash)?;
+        let removed_pack = RemovedPack {
+            hash,
+            record: Some(record),
+        };
+        sender.send(removed_pack).await?;
+    }
+
+    Ok(())
+}
+
+/// Removes a reference to a tree. If the tree is no longer referenced, it's removed along with its
+/// references to its blocks and subtrees.
+async fn remove_tree_ref(
+    state: &CleanupState,
+    hash: Hash<Tree>,
+    tree_sender: &Sender<RemovedTree>,
+) -> Result<()> {
+    let mut pending = vec![hash];
+
+    while let Some(hash) = pending.pop() {
+        let maybe_record = state.tree_records.write().await.remove_ref(&hash)?;
+        let Some(record) = maybe_record else {
+            continue;
+        };
+
+        let tree = download_tree(state.storage.clone(), hash).await?;
+        pending.extend(tree.subtrees());
+
+        let block_hashes = tree.block_refs.iter().map(|(hash, _)| hash);
+        let storage = state.storage.clone();
+        load_block_shards(
+            storage,
+            &state.block_records,
+            block_hashes,
+            state.task_count,
+        )
+        .await?;
+
+        let removed_count = block_in_place(|| {
+            let mut block_records = state.block_records.blocking_write();
+            let mut pack_records = state.pack_records.blocking_write();
+            let removed_records = block_records.remove_refs(tree.block_refs);
+            let mut removed_count = 0;
+            for result in removed_records {
+                let (hash, record) = result?;
+                pack_records.release_block(&record);
+                removed_count += 1;
+                debug!("removed block {hash}");
+            }
+
+            Result::Ok(removed_count)
+        })?;
+
+        state.stats.write().await.blocks_deleted += removed_count;
+
+        let removed_tree = RemovedTree {
+            hash,
+            record: Some(record),
+        };
+        tree_sender.send(removed_tree).await?;
+    }
+
+    Ok(())
+}
@@ -4,20 +4,25 @@ use tokio::{sync::RwLock, try_join};
 
 use crate::{
     archive::{Archive, ArchiveRecords},
-    block::{Block, BlockRecords},
+    block::BlockRecords,
     entity::{Entity, EntityIndex},
     error::Result,
     hash::Hash,
     ops::cleanup::{
         delete::delete_entities,
-        find::{find_archives_and_garbage_blocks, find_garbage_entities},
+        find::{find_archives_and_garbage_blocks, find_garbage_entities, find_unused_packs},
     },
+    pack::{Pack, PackRecords},
     stats::CommandStats,
     storage::{MAX_KEYS_PER_REQUEST, Storage},
+    tree::{Tree, TreeRecords},
 };
 
 mod delete;
 mod find;
+mod repack;
+
+pub use self::repack::repack_packs;
 
 #[derive(Debug)]
 pub struct CleanupState {
@@ -27,6 +32,8 @@ pub struct CleanupState {
     pub storage: Arc<Storage>,
     pub archive_records: Arc<RwLock<ArchiveRecords>>,
     pub block_records: Arc<RwLock<BlockRecords>>,
+    pub pack_records: Arc<RwLock<PackRecords>>,
+    pub tree_records: Arc<RwLock<TreeRecords>>,
 }
 
 #[derive(Debug)]
@@ -36,7 +43,8 @@ pub struct RemovedEntity<E: Entity, I: EntityIndex<E>> {
 }
 
 type RemovedArchive = RemovedEntity<Archive, ArchiveRecords>;
-type RemovedBlock = RemovedEntity<Block, BlockRecords>;
+type RemovedPack = RemovedEntity<Pack, PackRecords>;
+type RemovedTree = RemovedEntity<Tree, TreeRecords>;
 
 pub async fn delete_archives_and_garbage_blocks<'a, I>(
     state: Arc<CleanupState>,
@@ -46,18 +54,18 @@ where
     I: IntoIterator<Item = &'a Hash<Archive>>,
 {
     let (archive_sender, archive_receiver) = async_channel::bounded(MAX_KEYS_PER_REQUEST);
-    let (block_sender, block_receiver) = async_channel::bounded(MAX_KEYS_PER_REQUEST);
+    let (tree_sender, tree_receiver) = async_channel::bounded(MAX_KEYS_PER_REQUEST);
     try_join!(
         Box::pin(find_archives_and_garbage_blocks(
             state.clone(),
             hashes,
             archive_sender,
-            block_sender
+            tree_sender
         )),
         delete_entities(state.clone(), archive_receiver),
-        delete_entities(state.clone(), block_receiver),
+        delete_entities(state.clone(), tree_receiver),
     )?;
-    Ok(())
+    delete_unused_packs(state).await
 }
 
 pub async fn cleanup_archives(state: Arc<CleanupState>) -> Result<()> {
@@ -69,10 +77,44 @@ pub async fn cleanup_archives(state: Arc<CleanupState>) -> Result<()> {
     Ok(())
 }
 
-pub async fn cleanup_blocks(state: Arc<CleanupState>) -> Result<()> {
+pub async fn cleanup_trees(state: Arc<CleanupState>) -> Result<()> {
+    let (sender, receiver) = async_channel::bounded(MAX_KEYS_PER_REQUEST);
+    try_join!(
+        find_garbage_entities(state.clone(), state.tree_records.clone(), sender),
+        delete_entities(state.clone(), receiver),
+    )?;
+    Ok(())
+}
+
+pub async fn cleanup_packs(state: Arc<CleanupState>) -> Result<()> {
+    delete_unused_packs(state.clone()).await?;
+
+    let (sender, receiver) = async_channel::bounded(MAX_KEYS_PER_REQUEST);
+    try_join!(
+        find_garbage_entities(state.clone(), state.pack_records.clone(), sender),
+        delete_entities(state.clone(), receiver),
+    )?;
+    Ok(())
+}
+
+pub async fn delete_packs(state: Arc<CleanupState>, removed_packs: Vec<RemovedPack>) -> Result<()> {
+    let (sender, receiver) = async_channel::bounded(MAX_KEYS_PER_REQUEST);
+    try_join!(
+        async move {
+            for removed_pack in removed_packs {
+                sender.send(removed_pack).await?;
+            }
+            Result::Ok(())
+        },
+        delete_entities(state.clone(), receiver),
+    )?;
+    Ok(())
+}
+
+async fn delete_unused_packs(state: Arc<CleanupState>) -> Result<()> {
     let (sender, receiver) = async_channel::bounded(MAX_KEYS_PER_REQUEST);
     try_join!(
-        find_garbage_entities(state.clone(), state.block_records.clone(), sender),
+        find_unused_packs(state.clone(), sender),
         delete_entities(state.clone(), receiver),
     )?;
     Ok(())
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use clap::builder::styling::AnsiColor;
+use log::debug;
+
+use crate::{
+    entity::EntityIndex,
+    error::{Error, Result},
+    ops::{load_block_shards, upload_pack},
+    pack::{Pack, PackBuilder},
+};
+
+use super::{CleanupState, RemovedPack};
+
+/// Moves the blocks of mostly unused packs into new packs and removes the records of the old packs,
+/// which should only be deleted after the updated records have been uploaded.
+pub async fn repack_packs(state: Arc<CleanupState>) -> Result<Vec<RemovedPack>> {
+    let sparse_hashes = state
+        .pack_records
+        .read()
+        .await
+        .iter()
+        .filter(|(_, record)| record.is_sparse())
+        .map(|(hash, _)| *hash)
+        .collect::<Vec<_>>();
+
+    let mut pack_builder = PackBuilder::new();
+    let mut removed_packs = vec![];
+
+    for hash in sparse_hashes {
+        let data = state.storage.get(&hash.key()).await?;
+        state.stats.write().await.content_bytes_downloaded += data.len() as u64;
+
+        let pack = Pack { hash, data };
+        let entries = pack.entries()?;
+        let block_hashes = entries.iter().map(|entry| &entry.hash);
+        let storage = state.storage.clone();
+        load_block_shards(
+            storage,
+            &state.block_records,
+            block_hashes,
+            state.task_count,
+        )
+        .await?;
+
+        // blocks that were removed, or that were stored again in another pack, are left behind
+        let block_records = state.block_records.read().await;
+        let mut blocks = vec![];
+        for entry in &entries {
+            if let Some(record) = block_records.get(&entry.hash)?
+                && record.pack == hash
+                && record.offset == entry.offset
+            {
+                blocks.push((entry.hash, record.ref_count, record.range()));
+            }
+        }
+        drop(block_records);
+
+        for (block_hash, ref_count, range) in blocks {
+            let bytes = usize::try_from(range.start)
+                .ok()
+                .zip(usize::try_from(range.end).ok())
+                .and_then(|(start, end)| pack.data.get(start..end))
+                .ok_or_else(|| Error::InvalidPackIndex(hash))?;
+            pack_builder.add(block_hash, bytes, ref_count);
+            state.stats.write().await.blocks_repacked += 1;
+
+            if pack_builder.is_full() {
+                upload_repacked(&state, &mut pack_builder).await?;
+            }
+        }
+
+        let record = state.pack_records.write().await.remove(&hash)?;
+        removed_packs.push(RemovedPack {
+            hash,
+            record: Some(record),
+        });
+
+        let style = AnsiColor::Cyan.on_default();
+        debug!("{style}repacked pack{style:#} {hash}");
+    }
+
+    if !pack_builder.is_empty() {
+        upload_repacked(&state, &mut pack_builder).await?;
+    }
+
+    Ok(removed_packs)
+}
+
+/// Uploads a pack of repacked blocks, then points their records at it.
+async fn upload_repacked(state: &CleanupState, pack_builder: &mut PackBuilder) -> Result<()> {
+    let pack = pack_builder.finish();
+    let pack_hash = pack.hash;
+    upload_pack(
+        &state.storage,
+        &state.stats,
+        &state.pack_records,
+        pack,
+        state.dry_run,
+    )
+    .await?;
+
+    let mut block_records = state.block_records.write().await;
+    for (hash, record) in pack_builder.complete(&pack_hash) {
+        block_records.insert(hash, record)?;
+    }
+
+    Ok(())
+}
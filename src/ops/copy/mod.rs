@@ -0,0 +1,514 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use clap::builder::styling::AnsiColor;
+use log::{debug, warn};
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::spawn_blocking,
+};
+
+use crate::{
+    archive::{Archive, ArchiveRecords},
+    assert::assert_archive_hash_eq,
+    block::{Block, BlockRecord, BlockRecords, BlockRefs},
+    cache::DiskBlockCache,
+    entity::EntityIndex,
+    error::{Error, Result},
+    hash::Hash,
+    ops::{
+        download_archive, download_encoded_tree, download_shared_block_bytes, load_block_shards,
+        upload_archive, upload_pack, upload_tree,
+    },
+    pack::{Pack, PackBuilder, PackRecord, PackRecords},
+    stats::CommandStats,
+    storage::Storage,
+    task::BoundedJoinSet,
+    tree::{EncodedTree, Tree, TreeRecord, TreeRecords},
+};
+
+/// Blocks to copy from each source pack, with their records in the destination.
+type PackBlocks = HashMap<Hash<Pack>, Vec<(Hash<Block>, BlockRecord)>>;
+
+/// State for copying archives from a source repository into `storage`, whose records are the
+/// ones that are updated.
+#[derive(Debug)]
+pub struct CopyState {
+    pub task_count: usize,
+    pub dry_run: bool,
+    /// Compression level to re-encode blocks with, if they shouldn't be copied as they're stored
+    pub compression_level: Option<u8>,
+    pub stats: Arc<RwLock<CommandStats>>,
+    pub source_storage: Arc<Storage>,
+    pub source_block_records: RwLock<BlockRecords>,
+    /// Records of the source's packs, if both repositories are on the same endpoint so that packs
+    /// can be copied on the server
+    pub source_pack_records: Option<PackRecords>,
+    /// Cache of the source's blocks, which are the same wherever they're copied to
+    pub source_block_cache: Option<DiskBlockCache>,
+    pub storage: Arc<Storage>,
+    pub archive_records: Arc<RwLock<ArchiveRecords>>,
+    pub block_records: Arc<RwLock<BlockRecords>>,
+    pub pack_records: Arc<RwLock<PackRecords>>,
+    pub tree_records: Arc<RwLock<TreeRecords>>,
+    pub pack_builder: Mutex<PackBuilder>,
+    /// Blocks that the copied archives reference but the destination doesn't have yet, with their
+    /// reference counts there
+    pub missing_blocks: Mutex<BlockRefs>,
+    /// Whether the destination's credentials were denied copying a pack on the server, in which
+    /// case the rest of the blocks are copied one at a time
+    pub copy_denied: AtomicBool,
+}
+
+/// Copies the trees of an archive that the destination doesn't have yet and adds the blocks they
+/// reference to the ones to copy, returning the archive to upload with `upload_copied_archive`
+/// once the blocks have been copied with `copy_missing_blocks`. Archives that the destination
+/// already has are skipped.
+pub async fn copy_archive_trees(
+    state: Arc<CopyState>,
+    hash: Hash<Archive>,
+) -> Result<Option<Archive>> {
+    if state.archive_records.read().await.contains(&hash) {
+        return Ok(None);
+    }
+
+    let archive = download_archive(state.source_storage.clone(), &hash).await?;
+    let block_refs = copy_trees(&state, archive.tree).await?;
+
+    let hashes = block_refs.iter().map(|(hash, _)| *hash).collect::<Vec<_>>();
+    let storage = state.storage.clone();
+    load_block_shards(storage, &state.block_records, &hashes, state.task_count).await?;
+
+    let mut block_records = state.block_records.write().await;
+    let mut missing_blocks = state.missing_blocks.lock().await;
+    let referenced = add_block_refs(&block_refs, &mut block_records, &mut missing_blocks)?;
+    drop(missing_blocks);
+    drop(block_records);
+    state.stats.write().await.blocks_referenced += referenced;
+
+    Ok(Some(archive))
+}
+
+/// Copies the blocks that the copied archives reference but the destination doesn't have into new
+/// packs. The blocks of all of the archives are copied together, so that a source pack is only
+/// skipped as sparse if it's sparse for all of them.
+pub async fn copy_missing_blocks(state: Arc<CopyState>) -> Result<()> {
+    let missing_blocks = mem::take(&mut *state.missing_blocks.lock().await);
+    let missing = missing_blocks
+        .iter()
+        .map(|(hash, &ref_count)| (*hash, ref_count))
+        .collect::<Vec<_>>();
+
+    let source_storage = state.source_storage.clone();
+    let missing_hashes = missing.iter().map(|(hash, _)| hash);
+    load_block_shards(
+        source_storage,
+        &state.source_block_records,
+        missing_hashes,
+        state.task_count,
+    )
+    .await?;
+
+    let missing = match &state.source_pack_records {
+        Some(source_pack_records) => copy_packs(&state, source_pack_records, missing).await?,
+        None => missing,
+    };
+    let mut tasks = BoundedJoinSet::new(state.task_count);
+
+    for (hash, ref_count) in missing {
+        let state = state.clone();
+        tasks
+            .spawn(async move { copy_block(state, hash, ref_count).await })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            result??;
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+
+    upload_pending_copy_pack(&state).await
+}
+
+/// Uploads an archive whose trees and blocks have been copied.
+pub async fn upload_copied_archive(
+    state: Arc<CopyState>,
+    hash: Hash<Archive>,
+    archive: Archive,
+) -> Result<()> {
+    let (copied_hash, record) =
+        upload_archive(state.storage.clone(), archive, state.dry_run).await?;
+    assert_archive_hash_eq(&copied_hash, &hash)?;
+    state.archive_records.write().await.insert(hash, record);
+    state.stats.write().await.archives_copied += 1;
+    Ok(())
+}
+
+async fn upload_pending_copy_pack(state: &CopyState) -> Result<()> {
+    let mut pack_builder = state.pack_builder.lock().await;
+    if pack_builder.is_empty() {
+        return Ok(());
+    }
+
+    let pack = pack_builder.finish();
+    drop(pack_builder);
+    upload_copy_pack(state, pack).await
+}
+
+/// Copies the trees beneath `root` that the destination doesn't have, one level at a time, and
+/// returns the block references that the copied trees hold.
+///
+/// Trees that the destination already has only gain a reference, since the blocks beneath them
+/// are already referenced there.
+async fn copy_trees(state: &Arc<CopyState>, root: Hash<Tree>) -> Result<BlockRefs> {
+    let mut block_refs = BlockRefs::new();
+    let mut pending = vec![root];
+
+    while !pending.is_empty() {
+        let mut tree_records = state.tree_records.write().await;
+        let new_ref_counts = add_tree_refs(&mut tree_records, pending);
+        drop(tree_records);
+
+        let mut tasks = BoundedJoinSet::new(state.task_count);
+        let mut next = vec![];
+
+        for (hash, ref_count) in new_ref_counts {
+            let state = state.clone();
+            tasks
+                .spawn(async move { copy_tree(state, hash, ref_count).await })
+                .await?;
+
+            while let Some(result) = tasks.try_join_next() {
+                add_copied_tree(&mut block_refs, &mut next, result??);
+            }
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            add_copied_tree(&mut block_refs, &mut next, result??);
+        }
+
+        pending = next;
+    }
+
+    Ok(block_refs)
+}
+
+async fn copy_tree(state: Arc<CopyState>, hash: Hash<Tree>, ref_count: u64) -> Result<Tree> {
+    let EncodedTree {
+        tree,
+        compressed_bytes,
+    } = download_encoded_tree(state.source_storage.clone(), hash).await?;
+    let size = compressed_bytes.len() as u64;
+
+    if !state.dry_run {
+        upload_tree(state.storage.clone(), hash, compressed_bytes).await?;
+        state.stats.write().await.trees_uploaded += 1;
+
+        let style = AnsiColor::Magenta.on_default();
+        debug!("{style}copied tree{style:#} {hash}");
+    }
+
+    let record = TreeRecord { ref_count, size };
+    state.tree_records.write().await.insert(hash, record);
+    Ok(tree)
+}
+
+/// Adds a reference to each of the `pending` trees that the destination already has, returning
+/// the others along with how many times they're referenced. A new tree can be referenced by more
+/// than one parent on the same level.
+fn add_tree_refs(
+    tree_records: &mut TreeRecords,
+    pending: Vec<Hash<Tree>>,
+) -> HashMap<Hash<Tree>, u64> {
+    let mut new_ref_counts = HashMap::new();
+    for hash in pending {
+        if !tree_records.add_ref(&hash) {
+            *new_ref_counts.entry(hash).or_insert(0) += 1;
+        }
+    }
+
+    new_ref_counts
+}
+
+fn add_copied_tree(block_refs: &mut BlockRefs, next: &mut Vec<Hash<Tree>>, tree: Tree) {
+    next.extend(tree.subtrees());
+    block_refs.add_refs(tree.block_refs);
+}
+
+/// Adds `block_refs` to the blocks that the destination already has, adding the others to
+/// `missing_blocks`, and returns how many blocks didn't have to be copied. Blocks that are already
+/// missing for another archive are only copied once.
+fn add_block_refs(
+    block_refs: &BlockRefs,
+    block_records: &mut BlockRecords,
+    missing_blocks: &mut BlockRefs,
+) -> Result<u64> {
+    let mut referenced = 0;
+    for (hash, &count) in block_refs.iter() {
+        if let Some(record) = block_records.get_mut(hash)? {
+            record.ref_count += count;
+            referenced += 1;
+        } else {
+            if missing_blocks.count(hash) > 0 {
+                referenced += 1;
+            }
+            missing_blocks.add_count(hash, count);
+        }
+    }
+
+    Ok(referenced)
+}
+
+/// Copies the source packs that hold the `missing` blocks on the server, returning the blocks that
+/// still have to be copied one at a time.
+///
+/// A copied pack keeps its other blocks, which take up space in the destination until `cleanup`
+/// repacks it, so packs that would be sparse in the destination aren't copied.
+async fn copy_packs(
+    state: &Arc<CopyState>,
+    source_pack_records: &PackRecords,
+    missing: Vec<(Hash<Block>, u64)>,
+) -> Result<Vec<(Hash<Block>, u64)>> {
+    let source_block_records = state.source_block_records.read().await;
+    let pack_blocks = group_by_pack(&source_block_records, missing)?;
+    drop(source_block_records);
+    let mut remaining = vec![];
+    let mut tasks = BoundedJoinSet::new(state.task_count);
+
+    for (hash, blocks) in pack_blocks {
+        let maybe_record = copied_pack_record(source_pack_records, &hash, &blocks);
+        let Some(record) = maybe_record.filter(|_| !state.copy_denied.load(Ordering::Relaxed))
+        else {
+            remaining.extend(ref_counts(&blocks));
+            continue;
+        };
+
+        let state = state.clone();
+        tasks
+            .spawn(async move { copy_pack(state, hash, record, blocks).await })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            remaining.extend(result??);
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        remaining.extend(result??);
+    }
+
+    Ok(remaining)
+}
+
+/// Returns the destination's record for a source pack that holds the given blocks, or `None` if
+/// the pack would be sparse there (or the source has no record of it), so it shouldn't be copied.
+fn copied_pack_record(
+    source_pack_records: &PackRecords,
+    hash: &Hash<Pack>,
+    blocks: &[(Hash<Block>, BlockRecord)],
+) -> Option<PackRecord> {
+    let used_size = blocks.iter().map(|(_, record)| record.size).sum();
+    source_pack_records
+        .get(hash)
+        .map(|source_record| PackRecord {
+            size: source_record.size,
+            used_size,
+        })
+        .filter(|record| !record.is_sparse())
+}
+
+fn ref_counts(blocks: &[(Hash<Block>, BlockRecord)]) -> impl Iterator<Item = (Hash<Block>, u64)> {
+    blocks
+        .iter()
+        .map(|(hash, record)| (*hash, record.ref_count))
+}
+
+/// Groups the `missing` blocks by the source pack that holds them, giving each one a record for
+/// the destination with its reference count there. Fails if the source has no record of a block.
+fn group_by_pack(
+    source_block_records: &BlockRecords,
+    missing: Vec<(Hash<Block>, u64)>,
+) -> Result<PackBlocks> {
+    let mut pack_blocks = HashMap::<_, Vec<_>>::new();
+    for (hash, ref_count) in missing {
+        let source_record = source_block_records
+            .get(&hash)?
+            .ok_or(Error::BlockRecordNotFound(hash))?;
+        let record = BlockRecord {
+            ref_count,
+            ..*source_record
+        };
+        pack_blocks
+            .entry(record.pack)
+            .or_default()
+            .push((hash, record));
+    }
+
+    Ok(pack_blocks)
+}
+
+/// Copies a pack on the server and adds records for the given blocks in it. A pack that the
+/// destination already has, whose blocks were all removed, is only given its records again.
+///
+/// If the destination's credentials aren't allowed to read the source, the blocks are returned to
+/// be copied one at a time instead.
+async fn copy_pack(
+    state: Arc<CopyState>,
+    hash: Hash<Pack>,
+    record: PackRecord,
+    blocks: Vec<(Hash<Block>, BlockRecord)>,
+) -> Result<Vec<(Hash<Block>, u64)>> {
+    let exists = state.pack_records.read().await.contains(&hash);
+
+    if !state.dry_run && !exists {
+        let source_storage = &state.source_storage;
+        let result = state
+            .storage
+            .copy_from(source_storage, &hash.key(), record.size)
+            .await;
+
+        if let Err(err @ Error::CopyDenied(_)) = result {
+            if !state.copy_denied.swap(true, Ordering::Relaxed) {
+                warn!("{err}, so blocks will be copied one at a time instead");
+            }
+            return Ok(ref_counts(&blocks).collect());
+        }
+
+        result?;
+        state.stats.write().await.packs_copied += 1;
+
+        let style = AnsiColor::Magenta.on_default();
+        debug!("{style}copied pack{style:#} {hash}");
+    }
+
+    add_copied_pack(
+        &mut *state.pack_records.write().await,
+        &mut *state.block_records.write().await,
+        hash,
+        record,
+        blocks,
+    )?;
+    Ok(vec![])
+}
+
+/// Adds the records of a copied pack and of the given blocks in it, merging them into the pack's
+/// record if the destination already has it.
+fn add_copied_pack(
+    pack_records: &mut PackRecords,
+    block_records: &mut BlockRecords,
+    hash: Hash<Pack>,
+    record: PackRecord,
+    blocks: Vec<(Hash<Block>, BlockRecord)>,
+) -> Result<()> {
+    match pack_records.get_mut(&hash) {
+        Some(existing_record) => existing_record.used_size += record.used_size,
+        None => pack_records.insert(hash, record),
+    }
+
+    for (hash, record) in blocks {
+        block_records.insert(hash, record)?;
+    }
+
+    Ok(())
+}
+
+/// Copies a block as it's encoded in the source, after checking that it matches its hash, or
+/// re-encoded at the destination's compression level.
+async fn copy_block(state: Arc<CopyState>, hash: Hash<Block>, ref_count: u64) -> Result<()> {
+    let cached_bytes = match &state.source_block_cache {
+        Some(block_cache) => block_cache.get_encoded(&hash).await,
+        None => None,
+    };
+
+    let bytes = if let Some(bytes) = cached_bytes {
+        bytes
+    } else {
+        let bytes = download_source_block(&state, hash).await?;
+        if let Some(block_cache) = &state.source_block_cache {
+            block_cache.put(&hash, &bytes).await;
+        }
+        bytes
+    };
+
+    let bytes = match state.compression_level {
+        Some(level) => reencode_block(hash, bytes, level).await?,
+        None => bytes,
+    };
+
+    let mut pack_builder = state.pack_builder.lock().await;
+    pack_builder.add(hash, &bytes, ref_count);
+
+    if pack_builder.is_full() {
+        let pack = pack_builder.finish();
+        drop(pack_builder);
+        upload_copy_pack(&state, pack).await?;
+    }
+
+    Ok(())
+}
+
+/// Decodes a block and encodes it again at `compression_level`. Its hash only depends on its
+/// contents, so it stays the same.
+async fn reencode_block(
+    hash: Hash<Block>,
+    bytes: Vec<u8>,
+    compression_level: u8,
+) -> Result<Vec<u8>> {
+    spawn_blocking(move || Block::decode(&hash, None, &bytes)?.encode(compression_level)).await?
+}
+
+async fn download_source_block(state: &CopyState, hash: Hash<Block>) -> Result<Vec<u8>> {
+    let source_storage = &state.source_storage;
+    let bytes =
+        download_shared_block_bytes(source_storage, &state.source_block_records, &hash).await?;
+
+    let size = bytes.len() as u64;
+    let bytes = spawn_blocking(move || Block::decode(&hash, None, &bytes).map(|_| bytes)).await??;
+
+    let mut stats = state.stats.write().await;
+    stats.blocks_downloaded += 1;
+    stats.content_bytes_downloaded += size;
+    drop(stats);
+
+    Ok(bytes)
+}
+
+/// Uploads a pack of copied blocks, then adds records for them, since they only count as uploaded
+/// once the pack is.
+async fn upload_copy_pack(state: &CopyState, pack: Pack) -> Result<()> {
+    let pack_hash = pack.hash;
+    upload_pack(
+        &state.storage,
+        &state.stats,
+        &state.pack_records,
+        pack,
+        state.dry_run,
+    )
+    .await?;
+
+    let records = state.pack_builder.lock().await.complete(&pack_hash);
+    let block_count = records.len() as u64;
+    let mut block_records = state.block_records.write().await;
+    for (hash, record) in records {
+        block_records.insert(hash, record)?;
+    }
+    drop(block_records);
+
+    if !state.dry_run {
+        state.stats.write().await.blocks_uploaded += block_count;
+    }
+
+    Ok(())
+}
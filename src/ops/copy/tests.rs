@@ -0,0 +1,294 @@
+use crate::{
+    block::{Block, BlockManifest, BlockRecord, BlockRecords, BlockRefs},
+    entity::EntityIndex,
+    error::Error,
+    hash::{self, Hash},
+    pack::{Pack, PackRecord, PackRecords},
+    tree::{Tree, TreeRecord, TreeRecords},
+};
+
+use super::{
+    add_block_refs, add_copied_pack, add_tree_refs, copied_pack_record, group_by_pack,
+    reencode_block, ref_counts,
+};
+
+fn block_hash(n: u8) -> Hash<Block> {
+    Hash::from_bytes([n; hash::SIZE])
+}
+
+fn pack_hash(n: u8) -> Hash<Pack> {
+    Hash::pack(&[n])
+}
+
+fn block_record(pack: u8, offset: u64, ref_count: u64) -> BlockRecord {
+    BlockRecord {
+        ref_count,
+        pack: pack_hash(pack),
+        offset,
+        size: 10,
+    }
+}
+
+/// Returns the hash, offset, and ref count of each block, for comparing records.
+fn summarize(blocks: &[(Hash<Block>, BlockRecord)]) -> Vec<(Hash<Block>, u64, u64)> {
+    blocks
+        .iter()
+        .map(|(hash, record)| (*hash, record.offset, record.ref_count))
+        .collect()
+}
+
+fn block_refs(refs: &[(u8, u64)]) -> BlockRefs {
+    let mut block_refs = BlockRefs::new();
+    for &(n, count) in refs {
+        block_refs.add_count(&block_hash(n), count);
+    }
+    block_refs
+}
+
+#[test]
+fn tree_refs_of_existing_and_new_trees() {
+    let existing = Hash::<Tree>::tree(b"existing");
+    let new = Hash::<Tree>::tree(b"new");
+    let mut tree_records = TreeRecords::new();
+    tree_records.insert(
+        existing,
+        TreeRecord {
+            ref_count: 1,
+            size: 1,
+        },
+    );
+
+    // the new tree is referenced by two parents on the same level
+    let new_ref_counts = add_tree_refs(&mut tree_records, vec![existing, new, new]);
+
+    assert_eq!(new_ref_counts.len(), 1);
+    assert_eq!(new_ref_counts[&new], 2);
+    assert_eq!(tree_records.get(&existing).unwrap().ref_count, 2);
+    assert!(!tree_records.contains(&new));
+}
+
+#[test]
+fn block_refs_of_stored_and_missing_blocks() {
+    let mut block_records = BlockRecords::new();
+    block_records
+        .insert(block_hash(0), block_record(0, 0, 1))
+        .unwrap();
+    // block 1 is also referenced by an archive copied before
+    let mut missing_blocks = block_refs(&[(1, 1)]);
+
+    let refs = block_refs(&[(0, 2), (1, 3), (2, 4)]);
+    let referenced = add_block_refs(&refs, &mut block_records, &mut missing_blocks).unwrap();
+
+    assert_eq!(referenced, 2);
+    assert_eq!(missing_blocks, block_refs(&[(1, 4), (2, 4)]));
+    assert_eq!(
+        block_records
+            .get(&block_hash(0))
+            .unwrap()
+            .unwrap()
+            .ref_count,
+        3
+    );
+}
+
+#[test]
+fn block_refs_with_unloaded_shard() {
+    let manifest = BlockManifest {
+        shard_bits: 8,
+        len: 0,
+    };
+    let mut block_records = BlockRecords::unloaded(manifest);
+    let mut missing_blocks = BlockRefs::new();
+
+    let err = add_block_refs(
+        &block_refs(&[(3, 1)]),
+        &mut block_records,
+        &mut missing_blocks,
+    )
+    .unwrap_err();
+    assert_eq!(err, Error::ShardNotLoaded(3));
+}
+
+#[test]
+fn group_missing_blocks_by_pack() {
+    let mut source_block_records = BlockRecords::new();
+    for (n, pack, offset) in [(0, 0, 0), (1, 0, 10), (2, 1, 0)] {
+        source_block_records
+            .insert(block_hash(n), block_record(pack, offset, 5))
+            .unwrap();
+    }
+
+    let missing = vec![(block_hash(0), 1), (block_hash(1), 2), (block_hash(2), 3)];
+    let pack_blocks = group_by_pack(&source_block_records, missing).unwrap();
+
+    assert_eq!(pack_blocks.len(), 2);
+    assert_eq!(
+        summarize(&pack_blocks[&pack_hash(0)]),
+        vec![(block_hash(0), 0, 1), (block_hash(1), 10, 2)]
+    );
+    // the records take the reference counts in the destination, not the source
+    assert_eq!(
+        summarize(&pack_blocks[&pack_hash(1)]),
+        vec![(block_hash(2), 0, 3)]
+    );
+}
+
+#[test]
+fn group_block_missing_from_source() {
+    let source_block_records = BlockRecords::new();
+
+    let err = group_by_pack(&source_block_records, vec![(block_hash(0), 1)]).unwrap_err();
+    assert_eq!(err, Error::BlockRecordNotFound(block_hash(0)));
+}
+
+#[test]
+fn copied_pack_records() {
+    let mut pack_records = PackRecords::new();
+    let mut block_records = BlockRecords::new();
+    let record = PackRecord {
+        size: 30,
+        used_size: 10,
+    };
+    let blocks = vec![(block_hash(0), block_record(0, 0, 1))];
+
+    add_copied_pack(
+        &mut pack_records,
+        &mut block_records,
+        pack_hash(0),
+        record,
+        blocks,
+    )
+    .unwrap();
+
+    assert_eq!(pack_records.get(&pack_hash(0)).unwrap().used_size, 10);
+    let block_record = block_records.get(&block_hash(0)).unwrap().unwrap();
+    assert_eq!(block_record.pack, pack_hash(0));
+    assert_eq!(block_record.ref_count, 1);
+}
+
+#[test]
+fn copied_pack_merges_existing_record() {
+    // a pack that the destination already has, such as one copied for an earlier archive
+    let mut pack_records = PackRecords::new();
+    pack_records.insert(
+        pack_hash(0),
+        PackRecord {
+            size: 30,
+            used_size: 10,
+        },
+    );
+    let mut block_records = BlockRecords::new();
+    let record = PackRecord {
+        size: 30,
+        used_size: 20,
+    };
+    let blocks = vec![
+        (block_hash(1), block_record(0, 10, 1)),
+        (block_hash(2), block_record(0, 20, 1)),
+    ];
+
+    add_copied_pack(
+        &mut pack_records,
+        &mut block_records,
+        pack_hash(0),
+        record,
+        blocks,
+    )
+    .unwrap();
+
+    let merged = pack_records.get(&pack_hash(0)).unwrap();
+    assert_eq!(merged.size, 30);
+    assert_eq!(merged.used_size, 30);
+    assert!(block_records.get(&block_hash(2)).unwrap().is_some());
+}
+
+/// Source records for a pack of four blocks, 0 to 3, of 10 bytes each.
+fn source_records() -> (BlockRecords, PackRecords) {
+    let mut block_records = BlockRecords::new();
+    for n in 0..4 {
+        block_records
+            .insert(block_hash(n), block_record(0, u64::from(n) * 10, 1))
+            .unwrap();
+    }
+
+    let mut pack_records = PackRecords::new();
+    pack_records.insert(
+        pack_hash(0),
+        PackRecord {
+            size: 40,
+            used_size: 40,
+        },
+    );
+    (block_records, pack_records)
+}
+
+#[test]
+fn copy_pack_missing_most_blocks() {
+    let (source_block_records, source_pack_records) = source_records();
+    let missing = vec![(block_hash(0), 1), (block_hash(1), 1), (block_hash(2), 1)];
+    let pack_blocks = group_by_pack(&source_block_records, missing).unwrap();
+
+    let blocks = &pack_blocks[&pack_hash(0)];
+    let record = copied_pack_record(&source_pack_records, &pack_hash(0), blocks).unwrap();
+    assert_eq!(record.size, 40);
+    assert_eq!(record.used_size, 30);
+}
+
+#[test]
+fn skip_sparse_pack() {
+    let (source_block_records, source_pack_records) = source_records();
+    let pack_blocks = group_by_pack(&source_block_records, vec![(block_hash(0), 1)]).unwrap();
+
+    let blocks = &pack_blocks[&pack_hash(0)];
+    assert!(copied_pack_record(&source_pack_records, &pack_hash(0), blocks).is_none());
+    assert_eq!(
+        ref_counts(blocks).collect::<Vec<_>>(),
+        vec![(block_hash(0), 1)]
+    );
+}
+
+#[test]
+fn skip_pack_missing_from_source() {
+    let (source_block_records, _) = source_records();
+    let missing = vec![(block_hash(0), 1), (block_hash(1), 1)];
+    let pack_blocks = group_by_pack(&source_block_records, missing).unwrap();
+
+    let blocks = &pack_blocks[&pack_hash(0)];
+    assert!(copied_pack_record(&PackRecords::new(), &pack_hash(0), blocks).is_none());
+}
+
+#[test]
+fn pack_not_sparse_across_archives() {
+    // each archive alone needs a quarter of the pack, but together they need half of it
+    let (source_block_records, source_pack_records) = source_records();
+    let mut block_records = BlockRecords::new();
+    let mut missing_blocks = BlockRefs::new();
+    for refs in [block_refs(&[(0, 1)]), block_refs(&[(0, 1), (1, 1)])] {
+        add_block_refs(&refs, &mut block_records, &mut missing_blocks).unwrap();
+    }
+
+    let missing = missing_blocks
+        .iter()
+        .map(|(hash, &ref_count)| (*hash, ref_count))
+        .collect();
+    let pack_blocks = group_by_pack(&source_block_records, missing).unwrap();
+
+    let blocks = &pack_blocks[&pack_hash(0)];
+    assert_eq!(
+        summarize(blocks),
+        vec![(block_hash(0), 0, 2), (block_hash(1), 10, 1)]
+    );
+    let record = copied_pack_record(&source_pack_records, &pack_hash(0), blocks).unwrap();
+    assert_eq!(record.used_size, 20);
+}
+
+#[tokio::test]
+async fn reencoded_block_keeps_hash() {
+    let block = Block::leaf(vec![1; 1000]).unwrap();
+    let hash = *block.hash();
+    let bytes = block.clone().encode(1).unwrap();
+
+    let reencoded = reencode_block(hash, bytes.clone(), 19).await.unwrap();
+    assert_ne!(reencoded, bytes);
+    assert_eq!(Block::decode(&hash, None, &reencoded), Ok(block));
+}
@@ -0,0 +1,86 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{debug, error, warn};
+
+use crate::{
+    error::{Error, Result},
+    format::format_path,
+};
+
+/// How a command handles files that fail to be backed up or restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureMode {
+    /// Stop at the first file that fails.
+    FailFast,
+    /// Finish the other files, then report the failures and fail the command.
+    Fail,
+    /// Finish the other files, then report the failures without failing the command.
+    KeepGoing,
+}
+
+/// Files that failed to be backed up or restored, which are reported once the command finishes.
+#[derive(Debug)]
+pub struct FileFailures {
+    mode: FailureMode,
+    action: &'static str,
+    failures: Mutex<Vec<(PathBuf, Error)>>,
+}
+
+impl FileFailures {
+    /// Creates an empty report, where `action` describes what was done to each file (e.g. "back
+    /// up").
+    pub fn new(mode: FailureMode, action: &'static str) -> Self {
+        FileFailures {
+            mode,
+            action,
+            failures: Mutex::new(vec![]),
+        }
+    }
+
+    /// Records the result of a file's task, returning an error if the command should stop.
+    pub fn handle(&self, path: &Path, result: Result<()>) -> Result<()> {
+        let Err(err) = result else {
+            return Ok(());
+        };
+
+        let formatted_path = format_path(path);
+        if self.mode == FailureMode::FailFast {
+            error!("failed to {} {formatted_path} ({err})", self.action);
+            return Err(Error::FilesFailed(1));
+        }
+
+        debug!("failed to {} {formatted_path} ({err})", self.action);
+        self.failures.lock().unwrap().push((path.to_owned(), err));
+        Ok(())
+    }
+
+    /// Logs every failure, returning an error if there were any and the command shouldn't succeed
+    /// anyway.
+    pub fn finish(self) -> Result<()> {
+        let mut failures = self.failures.into_inner().unwrap();
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        failures.sort_unstable_by(|(path_l, _), (path_r, _)| path_l.cmp(path_r));
+        for (path, err) in &failures {
+            let formatted_path = format_path(path);
+            if self.mode == FailureMode::KeepGoing {
+                warn!("failed to {} {formatted_path} ({err})", self.action);
+            } else {
+                error!("failed to {} {formatted_path} ({err})", self.action);
+            }
+        }
+
+        let count = failures.len() as u64;
+        if self.mode == FailureMode::KeepGoing {
+            warn!("failed to {} {count} file(s)", self.action);
+            Ok(())
+        } else {
+            Err(Error::FilesFailed(count))
+        }
+    }
+}
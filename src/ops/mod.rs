@@ -1,40 +1,71 @@
+#[cfg(test)]
+mod tests;
+
 mod archive;
 mod backup;
+mod check;
 mod cleanup;
+mod copy;
+mod failures;
+mod pack;
+mod rebuild;
 mod records;
 mod restore;
+mod tree;
+mod version;
 
-use std::{borrow::Borrow, sync::Arc};
+use std::{borrow::Borrow, collections::HashSet, pin::pin, sync::Arc};
 
 use itertools::Itertools;
+use tokio_stream::StreamExt;
 
 use crate::{
-    block::Block,
     entity::Entity,
     error::{Result, handle_error},
     hash::{Hash, ShortHash},
+    pack::Pack,
     storage::{MAX_KEYS_PER_REQUEST, Storage},
     task::BoundedJoinSet,
 };
 
 pub use self::{
-    archive::{download_archive, upload_archive},
-    backup::{BackupState, backup_all, upload_pending_files},
-    cleanup::{CleanupState, cleanup_archives, cleanup_blocks, delete_archives_and_garbage_blocks},
+    archive::{download_archive, resolve_archive_ref, resolve_archive_refs, upload_archive},
+    backup::{
+        BackupState, backup_all, delete_checkpoint, delete_transient_packs, find_stale_checkpoints,
+        remove_stale_checkpoints, replace_checkpoint, scan_totals, upload_backup_records,
+        upload_pending_files, upload_pending_pack, upload_trees,
+    },
+    check::{
+        CheckState, check_archive_objects, check_archives, check_block_data, check_block_trees,
+        check_pack_objects, check_tree_objects,
+    },
+    cleanup::{
+        CleanupState, cleanup_archives, cleanup_packs, cleanup_trees,
+        delete_archives_and_garbage_blocks, delete_packs, repack_packs,
+    },
+    copy::{CopyState, copy_archive_trees, copy_missing_blocks, upload_copied_archive},
+    failures::{FailureMode, FileFailures},
+    pack::{download_block_bytes, download_pack_index, download_shared_block_bytes, upload_pack},
+    rebuild::{
+        SkippedObjects, rebuild_archive_records, rebuild_block_records, rebuild_tree_records,
+    },
     records::{
-        download_archive_records, download_block_records, upload_archive_records,
-        upload_block_records,
+        download_archive_records, download_block_manifest, download_block_records,
+        download_pack_records, download_tree_records, load_block_shards, upload_archive_records,
+        upload_block_records, upload_pack_records, upload_tree_records,
     },
-    restore::{RestoreState, download_pending_files, restore_all},
+    restore::{RestoreState, count_totals, download_pending_files, restore_all},
+    tree::{download_encoded_tree, download_file_tree, download_tree, download_trees, upload_tree},
+    version::{check_format_version, write_format_version},
 };
 
-pub async fn try_delete_blocks<H, I>(
+pub async fn try_delete_packs<H, I>(
     storage: Arc<Storage>,
     hashes: I,
     task_count: usize,
 ) -> Result<()>
 where
-    H: Borrow<Hash<Block>>,
+    H: Borrow<Hash<Pack>>,
     I: IntoIterator<Item = Result<H>>,
 {
     let mut tasks = BoundedJoinSet::new(task_count);
@@ -82,3 +113,15 @@ where
         .map(|key| Hash::from_key(key.as_str()))
         .collect()
 }
+
+pub async fn list_hashes<E: Entity>(storage: Arc<Storage>) -> Result<HashSet<Hash<E>>> {
+    let mut hashes = HashSet::new();
+    let mut keys = pin!(storage.keys(Some(E::KEY_PREFIX)));
+
+    while let Some(key) = keys.try_next().await? {
+        let hash = Hash::from_key(&key)?;
+        hashes.insert(hash);
+    }
+
+    Ok(hashes)
+}
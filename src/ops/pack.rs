@@ -0,0 +1,83 @@
+use tokio::sync::RwLock;
+
+use crate::{
+    block::{Block, BlockRecords},
+    entity::EntityIndex,
+    error::{Error, Result},
+    hash::Hash,
+    pack::{self, Pack, PackEntry, PackRecord, PackRecords},
+    stats::CommandStats,
+    storage::Storage,
+};
+
+pub async fn upload_pack(
+    storage: &Storage,
+    stats: &RwLock<CommandStats>,
+    pack_records: &RwLock<PackRecords>,
+    pack: Pack,
+    dry_run: bool,
+) -> Result<()> {
+    let size = pack.data.len() as u64;
+    let used_size = pack.blocks_size()?;
+
+    if !dry_run {
+        storage.put(&pack.hash.key(), pack.data).await?;
+        let mut stats = stats.write().await;
+        stats.packs_uploaded += 1;
+        stats.content_bytes_uploaded += size;
+    }
+
+    pack_records
+        .write()
+        .await
+        .insert(pack.hash, PackRecord { size, used_size });
+    Ok(())
+}
+
+pub async fn download_block_bytes(
+    storage: &Storage,
+    block_records: &BlockRecords,
+    hash: &Hash<Block>,
+) -> Result<Vec<u8>> {
+    let record = block_records
+        .get(hash)?
+        .ok_or_else(|| Error::BlockRecordNotFound(*hash))?;
+    storage.get_range(&record.pack.key(), record.range()).await
+}
+
+/// Downloads a block using records shared with other tasks, holding the read lock only while the
+/// block's location is looked up.
+pub async fn download_shared_block_bytes(
+    storage: &Storage,
+    block_records: &RwLock<BlockRecords>,
+    hash: &Hash<Block>,
+) -> Result<Vec<u8>> {
+    let block_records = block_records.read().await;
+    let record = block_records
+        .get(hash)?
+        .ok_or_else(|| Error::BlockRecordNotFound(*hash))?;
+    let (key, range) = (record.pack.key(), record.range());
+    drop(block_records);
+
+    storage.get_range(&key, range).await
+}
+
+pub async fn download_pack_index(
+    storage: &Storage,
+    hash: &Hash<Pack>,
+    size: u64,
+) -> Result<Vec<PackEntry>> {
+    let key = hash.key();
+    let count_start = size
+        .checked_sub(pack::COUNT_SIZE as u64)
+        .ok_or_else(|| Error::InvalidPackIndex(*hash))?;
+    let count_bytes = storage.get_range(&key, count_start..size).await?;
+    let index_range = pack::index_range(hash, size, &count_bytes)?;
+
+    if index_range.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let index_bytes = storage.get_range(&key, index_range).await?;
+    pack::decode_index(hash, &index_bytes)
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, pin::pin, sync::Arc};
+
+use log::{debug, warn};
+use tokio_stream::StreamExt;
+
+use crate::{
+    archive::{Archive, ArchiveRecord, ArchiveRecords},
+    block::{BlockRecord, BlockRecords, BlockRefs},
+    entity::{Entity, EntityIndex},
+    error::Result,
+    hash::Hash,
+    pack::{Pack, PackEntry, PackRecord, PackRecords},
+    storage::{ObjectInfo, Storage},
+    task::BoundedJoinSet,
+    tree::{Tree, TreeRecord, TreeRecords},
+};
+
+use super::{download_archive, download_pack_index, download_trees};
+
+pub type RecoveredArchive = (Hash<Archive>, ArchiveRecord, Hash<Tree>);
+pub type RecoveredPack = (Hash<Pack>, u64, Vec<PackEntry>);
+
+/// Numbers of objects that couldn't be read while rebuilding the records. Records rebuilt without
+/// them are missing whatever those objects reference, which `cleanup` would then delete.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SkippedObjects {
+    pub archives: u64,
+    pub trees: u64,
+    pub packs: u64,
+}
+
+impl SkippedObjects {
+    pub fn total(&self) -> u64 {
+        self.archives + self.trees + self.packs
+    }
+}
+
+/// Blocks found in the packs that the rebuilt records don't account for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExtraBlocks {
+    /// Blocks that no tree references
+    pub unreferenced: u64,
+    /// Copies of blocks that are also stored in another pack, which only have a record there
+    pub duplicate: u64,
+}
+
+/// Rebuilds the archive records, returning them along with the root tree of every archive.
+pub async fn rebuild_archive_records(
+    storage: Arc<Storage>,
+    task_count: usize,
+    skipped: &mut SkippedObjects,
+) -> Result<(ArchiveRecords, Vec<Hash<Tree>>)> {
+    let mut tasks = BoundedJoinSet::new(task_count);
+    let mut archive_records = ArchiveRecords::new();
+    let mut roots = vec![];
+    let mut object_chunks = pin!(storage.objects_paginated(Some(Archive::KEY_PREFIX)));
+
+    while let Some(objects) = object_chunks.try_next().await? {
+        for object in objects {
+            let storage = storage.clone();
+            tasks
+                .spawn(async move { recover_archive(storage, object).await })
+                .await?;
+
+            while let Some(result) = tasks.try_join_next() {
+                add_archive(&mut archive_records, &mut roots, skipped, result?);
+            }
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        add_archive(&mut archive_records, &mut roots, skipped, result?);
+    }
+
+    Ok((archive_records, roots))
+}
+
+/// Rebuilds the tree records from the root trees of the archives, returning them along with the
+/// block refs of every tree.
+pub async fn rebuild_tree_records(
+    storage: Arc<Storage>,
+    roots: &[Hash<Tree>],
+    task_count: usize,
+    skipped: &mut SkippedObjects,
+) -> Result<(TreeRecords, BlockRefs)> {
+    let mut sizes = HashMap::new();
+    let mut object_chunks = pin!(storage.objects_paginated(Some(Tree::KEY_PREFIX)));
+
+    while let Some(objects) = object_chunks.try_next().await? {
+        for object in objects {
+            let hash = Hash::<Tree>::from_key(&object.key)?;
+            sizes.insert(hash, object.size);
+        }
+    }
+
+    let mut ref_counts = HashMap::<_, u64>::new();
+    for root in roots {
+        *ref_counts.entry(*root).or_default() += 1;
+    }
+
+    let trees = download_trees(storage.clone(), roots.iter().copied(), task_count).await?;
+    let mut tree_records = TreeRecords::new();
+    let mut block_refs = BlockRefs::new();
+
+    for (hash, result) in trees {
+        match result {
+            Ok(tree) => {
+                for subtree in tree.subtrees() {
+                    *ref_counts.entry(*subtree).or_default() += 1;
+                }
+                block_refs.add_refs(tree.block_refs);
+            }
+            Err(err) => {
+                warn!("skipped tree {hash} ({err})");
+                ref_counts.remove(&hash);
+                skipped.trees += 1;
+            }
+        }
+    }
+
+    for (hash, ref_count) in ref_counts {
+        if let Some(size) = sizes.remove(&hash) {
+            tree_records.insert(hash, TreeRecord { ref_count, size });
+        }
+    }
+
+    if !sizes.is_empty() {
+        let unreferenced_count = sizes.len();
+        warn!("found {unreferenced_count} unreferenced trees (use `cleanup` to delete them)");
+    }
+
+    Ok((tree_records, block_refs))
+}
+
+pub async fn rebuild_block_records(
+    storage: Arc<Storage>,
+    block_refs: &BlockRefs,
+    task_count: usize,
+    skipped: &mut SkippedObjects,
+) -> Result<(BlockRecords, PackRecords)> {
+    let mut tasks = BoundedJoinSet::new(task_count);
+    let mut block_records = BlockRecords::new();
+    let mut pack_records = PackRecords::new();
+    let mut extra = ExtraBlocks::default();
+    let mut object_chunks = pin!(storage.objects_paginated(Some(Pack::KEY_PREFIX)));
+
+    while let Some(objects) = object_chunks.try_next().await? {
+        for object in objects {
+            let storage = storage.clone();
+            tasks
+                .spawn(async move { recover_pack(storage, object).await })
+                .await?;
+
+            while let Some(result) = tasks.try_join_next() {
+                add_pack(
+                    &mut block_records,
+                    &mut pack_records,
+                    block_refs,
+                    skipped,
+                    &mut extra,
+                    result?,
+                )?;
+            }
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        add_pack(
+            &mut block_records,
+            &mut pack_records,
+            block_refs,
+            skipped,
+            &mut extra,
+            result?,
+        )?;
+    }
+
+    for (hash, _) in block_refs.iter() {
+        if !block_records.contains(hash)? {
+            warn!("block {hash} is referenced by a tree but does not exist");
+        }
+    }
+
+    if extra.unreferenced > 0 {
+        let count = extra.unreferenced;
+        warn!("found {count} unreferenced blocks (use `cleanup` to delete them)");
+    }
+
+    if extra.duplicate > 0 {
+        let count = extra.duplicate;
+        warn!(
+            "found {count} blocks stored in more than one pack (use `cleanup` to reclaim their space)"
+        );
+    }
+
+    Ok((block_records, pack_records))
+}
+
+async fn recover_archive(storage: Arc<Storage>, object: ObjectInfo) -> Result<RecoveredArchive> {
+    let hash = Hash::from_key(&object.key)?;
+    let archive = download_archive(storage, &hash).await?;
+    let (record, tree) = archive_record(archive, object.size);
+    Ok((hash, record, tree))
+}
+
+/// Returns the record of an archive whose object has `size`, along with its root tree. Everything
+/// in the record, including when the archive was created, comes from the archive itself, since the
+/// object's modification time changes whenever it's copied.
+pub fn archive_record(archive: Archive, size: u64) -> (ArchiveRecord, Hash<Tree>) {
+    let record = ArchiveRecord {
+        info: archive.info,
+        size,
+    };
+    (record, archive.tree)
+}
+
+async fn recover_pack(storage: Arc<Storage>, object: ObjectInfo) -> Result<RecoveredPack> {
+    let hash = Hash::from_key(&object.key)?;
+    let entries = download_pack_index(&storage, &hash, object.size).await?;
+    Ok((hash, object.size, entries))
+}
+
+pub fn add_archive(
+    archive_records: &mut ArchiveRecords,
+    roots: &mut Vec<Hash<Tree>>,
+    skipped: &mut SkippedObjects,
+    result: Result<RecoveredArchive>,
+) {
+    match result {
+        Ok((hash, record, tree)) => {
+            archive_records.insert(hash, record);
+            roots.push(tree);
+        }
+        Err(err) => {
+            warn!("skipped archive ({err})");
+            skipped.archives += 1;
+        }
+    }
+}
+
+/// Adds records for a pack and the referenced blocks it contains, counting the blocks that don't
+/// get a record. A block that already has a record in another pack keeps it, and its copy in this
+/// pack doesn't count towards the pack's used size, so that `cleanup` can repack it away.
+pub fn add_pack(
+    block_records: &mut BlockRecords,
+    pack_records: &mut PackRecords,
+    block_refs: &BlockRefs,
+    skipped: &mut SkippedObjects,
+    extra: &mut ExtraBlocks,
+    result: Result<RecoveredPack>,
+) -> Result<()> {
+    let (pack, size, entries) = match result {
+        Ok(recovered_pack) => recovered_pack,
+        Err(err) => {
+            warn!("skipped pack ({err})");
+            skipped.packs += 1;
+            return Ok(());
+        }
+    };
+
+    let mut used_size = 0;
+
+    for entry in entries {
+        let ref_count = block_refs.count(&entry.hash);
+        if ref_count == 0 {
+            extra.unreferenced += 1;
+        } else if let Some(record) = block_records.get(&entry.hash)? {
+            let hash = entry.hash;
+            let other = record.pack;
+            debug!("block {hash} in pack {pack} is also stored in pack {other}");
+            extra.duplicate += 1;
+        } else {
+            let record = BlockRecord {
+                ref_count,
+                pack,
+                offset: entry.offset,
+                size: entry.size,
+            };
+            block_records.insert(entry.hash, record)?;
+            used_size += entry.size;
+        }
+    }
+
+    pack_records.insert(pack, PackRecord { size, used_size });
+    Ok(())
+}
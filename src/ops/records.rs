@@ -1,22 +1,112 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
 use serde::{Serialize, de::DeserializeOwned};
 use tokio::{sync::RwLock, task::spawn_blocking};
 
 use crate::{
     archive::ArchiveRecords,
-    block::BlockRecords,
+    block::{Block, BlockManifest, BlockRecords, BlockShard, ShardId},
     entity::{Entity, EntityIndex},
     error::Result,
+    hash::Hash,
+    pack::PackRecords,
     serde::{deserialize, serialize},
     storage::Storage,
+    task::BoundedJoinSet,
+    tree::TreeRecords,
 };
 
 pub async fn download_archive_records(storage: Arc<Storage>) -> Result<ArchiveRecords> {
     Box::pin(download_records(storage)).await
 }
 
-pub async fn download_block_records(storage: Arc<Storage>) -> Result<BlockRecords> {
+/// Downloads how the block records are split into shards, which is needed to load any of them.
+pub async fn download_block_manifest(storage: Arc<Storage>) -> Result<BlockManifest> {
+    let maybe_bytes = storage.try_get_cached(BlockRecords::KEY).await?;
+    let manifest = if let Some(bytes) = maybe_bytes {
+        deserialize(&bytes)?
+    } else {
+        BlockManifest::default()
+    };
+
+    Ok(manifest)
+}
+
+/// Downloads every shard of the block records.
+pub async fn download_block_records(
+    storage: Arc<Storage>,
+    task_count: usize,
+) -> Result<BlockRecords> {
+    let manifest = download_block_manifest(storage.clone()).await?;
+    let records = RwLock::new(BlockRecords::unloaded(manifest));
+    load_all_block_shards(storage, &records, task_count).await?;
+    Ok(records.into_inner())
+}
+
+/// Downloads the shards of the block records containing `hashes` that haven't been loaded yet.
+pub async fn load_block_shards<'a, I>(
+    storage: Arc<Storage>,
+    records: &RwLock<BlockRecords>,
+    hashes: I,
+    task_count: usize,
+) -> Result<()>
+where
+    I: IntoIterator<Item = &'a Hash<Block>>,
+{
+    let (shard_bits, missing_ids) = {
+        let records = records.read().await;
+        (
+            records.manifest().shard_bits,
+            records.missing_shard_ids(hashes),
+        )
+    };
+    load_shards(storage, records, shard_bits, missing_ids, task_count).await
+}
+
+/// Downloads the shards of the block records that haven't been loaded yet.
+async fn load_all_block_shards(
+    storage: Arc<Storage>,
+    records: &RwLock<BlockRecords>,
+    task_count: usize,
+) -> Result<()> {
+    let (shard_bits, missing_ids) = {
+        let records = records.read().await;
+        (records.manifest().shard_bits, records.unloaded_shard_ids())
+    };
+    load_shards(storage, records, shard_bits, missing_ids, task_count).await
+}
+
+async fn load_shards(
+    storage: Arc<Storage>,
+    records: &RwLock<BlockRecords>,
+    shard_bits: u8,
+    ids: BTreeSet<ShardId>,
+    task_count: usize,
+) -> Result<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let shards = download_block_shards(storage, shard_bits, ids, task_count).await?;
+    let mut records = records.write().await;
+
+    // splitting the shards in the meantime loaded all of them
+    if records.manifest().shard_bits != shard_bits {
+        return Ok(());
+    }
+
+    for (id, shard) in shards {
+        records.load_shard(id, shard);
+    }
+
+    Ok(())
+}
+
+pub async fn download_pack_records(storage: Arc<Storage>) -> Result<PackRecords> {
+    Box::pin(download_records(storage)).await
+}
+
+pub async fn download_tree_records(storage: Arc<Storage>) -> Result<TreeRecords> {
     Box::pin(download_records(storage)).await
 }
 
@@ -27,9 +117,94 @@ pub async fn upload_archive_records(
     Box::pin(upload_records(storage, records)).await
 }
 
+/// Uploads the shards of the block records that have been modified, followed by the manifest.
+/// Shards that have grown too large are split first, which downloads and uploads all of them, and
+/// the shards they replace are deleted once the manifest no longer refers to them.
 pub async fn upload_block_records(
     storage: Arc<Storage>,
     records: Arc<RwLock<BlockRecords>>,
+    task_count: usize,
+) -> Result<()> {
+    let maybe_split_bits = records.read().await.split_shard_bits();
+    if let Some(shard_bits) = maybe_split_bits {
+        load_all_block_shards(storage.clone(), &records, task_count).await?;
+        records.write().await.split(shard_bits)?;
+    }
+
+    let (manifest, stored_shard_bits) = {
+        let records = records.read().await;
+        (records.manifest(), records.stored_shard_bits())
+    };
+    let shards = spawn_blocking(move || {
+        let records = records.blocking_read();
+        records
+            .dirty_shards()
+            .map(|(id, shard)| serialize(shard).map(|bytes| (id, bytes)))
+            .collect::<Result<Vec<_>>>()
+    })
+    .await??;
+
+    if shards.is_empty() {
+        return Ok(());
+    }
+
+    // records that replace the stored ones don't know how those were split
+    let stored_shard_bits = match stored_shard_bits {
+        Some(shard_bits) => shard_bits,
+        None => download_block_manifest(storage.clone()).await?.shard_bits,
+    };
+
+    upload_block_shards(storage.clone(), manifest.shard_bits, shards, task_count).await?;
+    storage
+        .put_cached(BlockRecords::KEY, serialize(&manifest)?)
+        .await?;
+
+    if stored_shard_bits != manifest.shard_bits {
+        let stale_ids = BlockRecords::shard_ids(stored_shard_bits);
+        let stale_keys = stale_ids.map(|id| BlockRecords::shard_key(stored_shard_bits, id));
+        storage.delete_many(stale_keys).await?;
+    }
+
+    Ok(())
+}
+
+async fn upload_block_shards(
+    storage: Arc<Storage>,
+    shard_bits: u8,
+    shards: Vec<(ShardId, Vec<u8>)>,
+    task_count: usize,
+) -> Result<()> {
+    let mut tasks = BoundedJoinSet::new(task_count);
+
+    for (id, bytes) in shards {
+        let storage = storage.clone();
+        let key = BlockRecords::shard_key(shard_bits, id);
+        tasks
+            .spawn(async move { storage.put_cached(&key, bytes).await })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            result??;
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+pub async fn upload_pack_records(
+    storage: Arc<Storage>,
+    records: Arc<RwLock<PackRecords>>,
+) -> Result<()> {
+    Box::pin(upload_records(storage, records)).await
+}
+
+pub async fn upload_tree_records(
+    storage: Arc<Storage>,
+    records: Arc<RwLock<TreeRecords>>,
 ) -> Result<()> {
     Box::pin(upload_records(storage, records)).await
 }
@@ -39,7 +214,7 @@ where
     E: Entity,
     I: EntityIndex<E> + DeserializeOwned + Default + Send + Sync + 'static,
 {
-    let maybe_bytes = storage.try_get(I::KEY).await?;
+    let maybe_bytes = storage.try_get_cached(I::KEY).await?;
     let archive_records = if let Some(bytes) = maybe_bytes {
         spawn_blocking(move || deserialize(&bytes)).await??
     } else {
@@ -49,11 +224,47 @@ where
     Ok(archive_records)
 }
 
+async fn download_block_shards(
+    storage: Arc<Storage>,
+    shard_bits: u8,
+    ids: BTreeSet<ShardId>,
+    task_count: usize,
+) -> Result<Vec<(ShardId, BlockShard)>> {
+    let mut tasks = BoundedJoinSet::new(task_count);
+    let mut shards = vec![];
+
+    for id in ids {
+        let storage = storage.clone();
+        let key = BlockRecords::shard_key(shard_bits, id);
+        tasks
+            .spawn(async move {
+                let maybe_bytes = storage.try_get_cached(&key).await?;
+                let shard = if let Some(bytes) = maybe_bytes {
+                    spawn_blocking(move || deserialize(&bytes)).await??
+                } else {
+                    BlockShard::default()
+                };
+                Result::Ok((id, shard))
+            })
+            .await?;
+
+        while let Some(result) = tasks.try_join_next() {
+            shards.push(result??);
+        }
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        shards.push(result??);
+    }
+
+    Ok(shards)
+}
+
 async fn upload_records<E, I>(storage: Arc<Storage>, records: Arc<RwLock<I>>) -> Result<()>
 where
     E: Entity,
     I: EntityIndex<E> + Serialize + Send + Sync + 'static,
 {
     let bytes = spawn_blocking(move || serialize(&*records.blocking_read())).await??;
-    storage.put(I::KEY, bytes).await
+    storage.put_cached(I::KEY, bytes).await
 }
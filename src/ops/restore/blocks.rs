@@ -16,6 +16,7 @@ use crate::{
     block::Block,
     error::{Error, Result},
     hash::Hash,
+    ops::download_block_bytes,
 };
 
 use super::{RestoreState, files::PendingDownload};
@@ -91,22 +92,18 @@ pub async fn download_block_recursive(
         let data = read_local_block(state.clone(), local_block).await?;
         write_local_block(state.clone(), file, &data).await?;
     } else {
-        let bytes = state.storage.get(&hash.key()).await?;
-        state.stats.write().await.blocks_downloaded += 1;
-        state.stats.write().await.content_bytes_downloaded += bytes.len() as u64;
-
-        let hash = *hash;
-        let block = spawn_blocking(move || Block::decode(&hash, level, &bytes)).await??;
+        let block = get_block(&state, hash, level).await?;
         match block {
             Block::Leaf { data, .. } => {
                 let local_block = write_local_block(state.clone(), file, &data).await?;
-                state.local_blocks.write().await.insert(hash, local_block);
+                state.local_blocks.write().await.insert(*hash, local_block);
             }
             Block::Branch {
                 level, children, ..
             } => {
-                for hash in &children {
-                    download_block_recursive(state.clone(), file, hash, Some(level - 1)).await?;
+                for child in &children {
+                    download_block_recursive(state.clone(), file, &child.hash, Some(level - 1))
+                        .await?;
                 }
             }
         }
@@ -117,6 +114,34 @@ pub async fn download_block_recursive(
     Ok(size)
 }
 
+/// Gets a block from the block cache if it's there, or else downloads it and adds it to the cache.
+async fn get_block(state: &RestoreState, hash: &Hash<Block>, level: Option<u8>) -> Result<Block> {
+    if let Some(block_cache) = &state.block_cache
+        && let Some(block) = block_cache.get(hash).await
+    {
+        assert_block_level_eq(hash, block.level(), level)?;
+        state.stats.write().await.blocks_cached += 1;
+        return Ok(block);
+    }
+
+    let bytes = download_block_bytes(&state.storage, &state.block_records, hash).await?;
+    state.stats.write().await.blocks_downloaded += 1;
+    state.stats.write().await.content_bytes_downloaded += bytes.len() as u64;
+
+    let hash = *hash;
+    let (block, bytes) = spawn_blocking(move || {
+        let block = Block::decode(&hash, level, &bytes)?;
+        Result::Ok((block, bytes))
+    })
+    .await??;
+
+    if let Some(block_cache) = &state.block_cache {
+        block_cache.put(&hash, &bytes).await;
+    }
+
+    Ok(block)
+}
+
 async fn write_local_block(
     state: Arc<RestoreState>,
     file: &mut ActiveDownload,
@@ -135,7 +160,7 @@ async fn write_local_block(
 
 async fn read_local_block(state: Arc<RestoreState>, local_block: LocalBlock) -> Result<Vec<u8>> {
     let path = state
-        .archive
+        .files
         .path(local_block.inode)
         .ok_or_else(|| Error::InodeDoesNotExist(local_block.inode))?;
     let file = File::open(path).await?;
@@ -6,14 +6,16 @@ use std::{
 use async_channel::{Receiver, Sender};
 use clap::builder::styling::AnsiColor;
 use log::debug;
-use tokio::fs;
+use tokio::{fs, io::AsyncWriteExt};
 
 use crate::{
-    block::Block,
-    error::{Error, Result, handle_error},
-    file::{FileType, Metadata, Node, restore_metadata, restore_metadata_from_node, try_exists},
+    error::{Error, Result},
+    file::{
+        FileData, FileType, Metadata, Node, restore_metadata, restore_metadata_from_node,
+        try_exists,
+    },
     format::{format_path, format_size},
-    hash::Hash,
+    progress::ProgressTotals,
     task::BoundedJoinSet,
 };
 
@@ -25,7 +27,7 @@ use super::{
 #[derive(Debug)]
 pub struct PendingDownload {
     pub metadata: Metadata,
-    pub hash: Option<Hash<Block>>,
+    pub data: FileData,
     pub path: PathBuf,
 }
 
@@ -45,12 +47,34 @@ pub async fn restore_all<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Counts the files that will be restored and their total size, for estimating how long the
+/// restore will take.
+pub fn count_totals<P: AsRef<Path>>(state: &RestoreState, paths: &[P]) -> Result<ProgressTotals> {
+    let roots = if paths.is_empty() {
+        vec![None]
+    } else {
+        paths.iter().map(|path| Some(path.as_ref())).collect()
+    };
+
+    let mut totals = ProgressTotals::default();
+    for root in roots {
+        for (_, node) in state.files.walk(root, state.order)? {
+            if let Node::File { metadata, .. } = node {
+                totals.files += 1;
+                totals.bytes += metadata.size;
+            }
+        }
+    }
+
+    Ok(totals)
+}
+
 async fn restore_recursive(
     state: Arc<RestoreState>,
     sender: Sender<PendingDownload>,
     root: Option<&Path>,
 ) -> Result<()> {
-    let walker = state.archive.walk(root, state.order)?;
+    let walker = state.files.walk(root, state.order)?;
     for (child_path, node) in walker {
         let path = if let Some(path) = root {
             path.join(&child_path)
@@ -77,10 +101,10 @@ async fn restore_from_node(
     }
 
     match node {
-        Node::File { metadata, hash } => {
+        Node::File { metadata, data } => {
             let pending_file = PendingDownload {
                 metadata: metadata.clone(),
-                hash: *hash,
+                data: data.clone(),
                 path: path.to_owned(),
             };
             return Ok(Some(pending_file));
@@ -119,16 +143,20 @@ pub async fn download_pending_files(
     while let Ok(pending_file) = receiver.recv().await {
         let state = state.clone();
         tasks
-            .spawn(download_pending_file(state, pending_file))
+            .spawn(async move {
+                let path = pending_file.path.clone();
+                let result = download_pending_file(state.clone(), pending_file).await;
+                state.failures.handle(&path, result)
+            })
             .await?;
 
         while let Some(result) = tasks.try_join_next() {
-            handle_error(result?);
+            result??;
         }
     }
 
     while let Some(result) = tasks.join_next().await {
-        handle_error(result?);
+        result??;
     }
 
     Ok(())
@@ -143,9 +171,18 @@ async fn download_pending_file(
     if !state.dry_run {
         let mut file = ActiveDownload::new(&pending_file).await?;
 
-        if let Some(ref hash) = pending_file.hash {
-            size = download_block_recursive(state.clone(), &mut file, hash, None).await?;
-            file.sync_all().await?;
+        match &pending_file.data {
+            FileData::Empty => {}
+            FileData::Inline(bytes) => {
+                size = bytes.len() as u64;
+                file.write_all(bytes).await?;
+                file.sync_all().await?;
+                state.stats.write().await.bytes_written += size;
+            }
+            FileData::Blocks(hash) => {
+                size = download_block_recursive(state.clone(), &mut file, hash, None).await?;
+                file.sync_all().await?;
+            }
         }
 
         restore_metadata(&pending_file.path, &pending_file.metadata, FileType::File).await?;
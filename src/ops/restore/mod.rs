@@ -6,13 +6,19 @@ use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
 
 use crate::{
-    archive::Archive, block::Block, file::WalkOrder, hash::Hash, locks::BlockLocks,
-    stats::CommandStats, storage::Storage,
+    block::{Block, BlockRecords},
+    cache::DiskBlockCache,
+    file::{FileTree, WalkOrder},
+    hash::Hash,
+    locks::BlockLocks,
+    ops::FileFailures,
+    stats::CommandStats,
+    storage::Storage,
 };
 
 use self::blocks::LocalBlock;
 
-pub use self::files::{download_pending_files, restore_all};
+pub use self::files::{count_totals, download_pending_files, restore_all};
 
 type LocalBlocks = HashMap<Hash<Block>, LocalBlock>;
 
@@ -21,7 +27,10 @@ pub struct RestoreState {
     pub order: WalkOrder,
     pub task_count: usize,
     pub dry_run: bool,
-    pub archive: Archive,
+    pub failures: FileFailures,
+    pub files: FileTree,
+    pub block_records: BlockRecords,
+    pub block_cache: Option<DiskBlockCache>,
     pub stats: Arc<RwLock<CommandStats>>,
     pub storage: Arc<Storage>,
     pub local_blocks: Arc<RwLock<LocalBlocks>>,
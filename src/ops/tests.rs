@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    archive::{Archive, ArchiveInfo, ArchiveRecords},
+    block::{Block, BlockRecord, BlockRecords, BlockRefs},
+    entity::EntityIndex,
+    error::{Error, Result},
+    hash::{self, Hash},
+    pack::{PackEntry, PackRecords},
+};
+
+use super::{
+    FailureMode, FileFailures,
+    check::{child_branches, is_selected, missing_children},
+    rebuild::{ExtraBlocks, SkippedObjects, add_archive, add_pack, archive_record},
+    version::{FORMAT_VERSION, parse_format_version, validate_format_version},
+};
+
+fn failed() -> Result<()> {
+    Err(Error::EmptyBlock)
+}
+
+#[test]
+fn failures_empty() {
+    for mode in [
+        FailureMode::FailFast,
+        FailureMode::Fail,
+        FailureMode::KeepGoing,
+    ] {
+        let failures = FileFailures::new(mode, "back up");
+        assert_eq!(failures.handle(Path::new("a"), Ok(())), Ok(()));
+        assert_eq!(failures.finish(), Ok(()));
+    }
+}
+
+#[test]
+fn failures_fail_fast() {
+    let failures = FileFailures::new(FailureMode::FailFast, "back up");
+    assert_eq!(
+        failures.handle(Path::new("a"), failed()),
+        Err(Error::FilesFailed(1))
+    );
+
+    // the failure was already reported when the command stopped
+    assert_eq!(failures.finish(), Ok(()));
+}
+
+#[test]
+fn failures_fail() {
+    let failures = FileFailures::new(FailureMode::Fail, "back up");
+    assert_eq!(failures.handle(Path::new("b"), failed()), Ok(()));
+    assert_eq!(failures.handle(Path::new("c"), Ok(())), Ok(()));
+    assert_eq!(failures.handle(Path::new("a"), failed()), Ok(()));
+    assert_eq!(failures.finish(), Err(Error::FilesFailed(2)));
+}
+
+#[test]
+fn failures_keep_going() {
+    let failures = FileFailures::new(FailureMode::KeepGoing, "restore");
+    assert_eq!(failures.handle(Path::new("a"), failed()), Ok(()));
+    assert_eq!(failures.handle(Path::new("b"), failed()), Ok(()));
+    assert_eq!(failures.finish(), Ok(()));
+}
+
+#[test]
+fn format_version_parse() {
+    assert_eq!(parse_format_version(b"1"), Ok(1));
+    assert_eq!(parse_format_version(b"12\n"), Ok(12));
+    assert_eq!(
+        parse_format_version(b""),
+        Err(Error::InvalidFormatVersion(String::new()))
+    );
+    assert_eq!(
+        parse_format_version(b"v1"),
+        Err(Error::InvalidFormatVersion("v1".to_owned()))
+    );
+}
+
+#[test]
+fn format_version_validate() {
+    assert_eq!(validate_format_version(Some(FORMAT_VERSION), false), Ok(()));
+    assert_eq!(
+        validate_format_version(Some(FORMAT_VERSION + 1), false),
+        Err(Error::UnsupportedFormatVersion {
+            found: FORMAT_VERSION + 1,
+            supported: FORMAT_VERSION,
+        })
+    );
+
+    // only an empty repository may have no version
+    assert_eq!(validate_format_version(None, true), Ok(()));
+    assert_eq!(
+        validate_format_version(None, false),
+        Err(Error::MissingFormatVersion)
+    );
+}
+
+fn leaf(byte: u8) -> Block {
+    Block::leaf(vec![byte; 10]).unwrap()
+}
+
+fn records_for(blocks: &[&Block]) -> BlockRecords {
+    let mut block_records = BlockRecords::new();
+    for block in blocks {
+        let record = BlockRecord {
+            ref_count: 1,
+            pack: Hash::pack(&[]),
+            offset: 0,
+            size: 10,
+        };
+        block_records.insert(*block.hash(), record).unwrap();
+    }
+    block_records
+}
+
+#[test]
+fn check_children_of_leaf() {
+    let block = leaf(0);
+    let block_records = records_for(&[&block]);
+    assert!(missing_children(&block, &block_records).is_empty());
+    assert!(child_branches(&block, &block_records).is_empty());
+}
+
+#[test]
+fn check_children_of_lowest_branch() {
+    let (first, second) = (leaf(0), leaf(1));
+    let branch = Block::branch(1, vec![first.as_child(), second.as_child()]).unwrap();
+    let block_records = records_for(&[&branch, &first]);
+
+    // the leaves only need records, so they aren't checked further
+    assert!(child_branches(&branch, &block_records).is_empty());
+    assert_eq!(
+        missing_children(&branch, &block_records),
+        vec![Error::MissingChildBlock {
+            hash: *branch.hash(),
+            child: *second.hash(),
+        }]
+    );
+}
+
+#[test]
+fn check_children_of_upper_branch() {
+    let (first, second) = (leaf(0), leaf(1));
+    let lower = Block::branch(1, vec![first.as_child()]).unwrap();
+    let missing = Block::branch(1, vec![second.as_child()]).unwrap();
+    let upper = Block::branch(2, vec![lower.as_child(), missing.as_child()]).unwrap();
+    let block_records = records_for(&[&upper, &lower, &first, &second]);
+
+    assert_eq!(
+        child_branches(&upper, &block_records),
+        vec![(*lower.hash(), 1)]
+    );
+    assert_eq!(missing_children(&upper, &block_records).len(), 1);
+}
+
+#[test]
+fn check_subset_depends_on_seed() {
+    let hashes = (0..=255).map(|byte| *leaf(byte).hash()).collect::<Vec<_>>();
+    let subset = |seed| {
+        hashes
+            .iter()
+            .filter(|hash| is_selected(hash, seed, 10))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(subset(7), subset(7));
+    assert_ne!(subset(7), subset(8));
+    assert!((10..50).contains(&subset(7).len()));
+    assert!(hashes.iter().all(|hash| is_selected(hash, 7, 100)));
+}
+
+fn archive() -> Archive {
+    let info = ArchiveInfo {
+        created: Utc.with_ymd_and_hms(2026, 10, 1, 12, 0, 0).unwrap(),
+        name: Some("nightly".to_owned()),
+        description: None,
+        tags: vec![],
+        hostname: "laptop".to_owned(),
+        username: "alice".to_owned(),
+        paths: vec![PathBuf::from("/home/alice")],
+    };
+    Archive::new(info, Hash::tree(b"root"))
+}
+
+#[test]
+fn rebuilt_archive_record() {
+    let archive = archive();
+    let created = archive.info.created;
+
+    let (record, tree) = archive_record(archive, 123);
+    assert_eq!(record.info.created, created);
+    assert_eq!(record.info.name.as_deref(), Some("nightly"));
+    assert_eq!(record.size, 123);
+    assert_eq!(tree, Hash::tree(b"root"));
+}
+
+#[test]
+fn rebuilt_archives_skip_failures() {
+    let mut archive_records = ArchiveRecords::new();
+    let mut roots = vec![];
+    let mut skipped = SkippedObjects::default();
+    let hash = Hash::archive(b"archive");
+    let (record, tree) = archive_record(archive(), 1);
+
+    let result = Ok((hash, record, tree));
+    add_archive(&mut archive_records, &mut roots, &mut skipped, result);
+    assert_eq!(skipped.total(), 0);
+
+    let result = Err(Error::EmptyBlock);
+    add_archive(&mut archive_records, &mut roots, &mut skipped, result);
+    assert_eq!(skipped.archives, 1);
+
+    assert_eq!(archive_records.len(), 1);
+    assert!(archive_records.contains(&hash));
+    assert_eq!(roots, vec![tree]);
+}
+
+#[test]
+fn rebuilt_pack_records() {
+    let block_hash = |n| Hash::from_bytes([n; hash::SIZE]);
+    let entry = |n, offset| PackEntry {
+        hash: block_hash(n),
+        offset,
+        size: 10,
+    };
+    let pack = Hash::pack(b"pack");
+    let mut block_refs = BlockRefs::new();
+    block_refs.add_count(&block_hash(0), 2);
+    block_refs.add_count(&block_hash(1), 1);
+
+    let mut block_records = BlockRecords::new();
+    let mut pack_records = PackRecords::new();
+    let mut skipped = SkippedObjects::default();
+    let mut extra = ExtraBlocks::default();
+    let entries = vec![entry(0, 0), entry(1, 10), entry(2, 20)];
+    add_pack(
+        &mut block_records,
+        &mut pack_records,
+        &block_refs,
+        &mut skipped,
+        &mut extra,
+        Ok((pack, 50, entries)),
+    )
+    .unwrap();
+
+    assert_eq!(extra.unreferenced, 1);
+    let record = pack_records.get(&pack).unwrap();
+    assert_eq!((record.size, record.used_size), (50, 20));
+    let record = block_records.get(&block_hash(0)).unwrap().unwrap();
+    assert_eq!((record.ref_count, record.offset), (2, 0));
+    assert!(!block_records.contains(&block_hash(2)).unwrap());
+
+    // a block stored in two packs only keeps the record of the first, and its other copy is
+    // counted as unused
+    let other = Hash::pack(b"other");
+    add_pack(
+        &mut block_records,
+        &mut pack_records,
+        &block_refs,
+        &mut skipped,
+        &mut extra,
+        Ok((other, 30, vec![entry(1, 0)])),
+    )
+    .unwrap();
+    assert_eq!(
+        block_records.get(&block_hash(1)).unwrap().unwrap().pack,
+        pack
+    );
+    assert_eq!(pack_records.get(&other).unwrap().used_size, 0);
+    assert_eq!(extra.duplicate, 1);
+
+    // a pack that can't be read is skipped
+    add_pack(
+        &mut block_records,
+        &mut pack_records,
+        &block_refs,
+        &mut skipped,
+        &mut extra,
+        Err(Error::EmptyBlock),
+    )
+    .unwrap();
+    assert_eq!(skipped.packs, 1);
+    assert_eq!(pack_records.len(), 2);
+}
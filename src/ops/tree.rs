@@ -0,0 +1,184 @@
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    path::{Component, Path},
+    sync::Arc,
+};
+
+use tokio::task::spawn_blocking;
+
+use crate::{
+    assert::assert_tree_hash_eq,
+    block::Block,
+    compress::decompress,
+    error::{Error, Result},
+    file::FileTree,
+    hash::Hash,
+    serde::deserialize,
+    storage::Storage,
+    task::BoundedJoinSet,
+    tree::{EncodedTree, Tree, TreeEntry, node_children},
+};
+
+type DownloadedTrees = HashMap<Hash<Tree>, Result<Tree>>;
+
+pub async fn download_tree(storage: Arc<Storage>, hash: Hash<Tree>) -> Result<Tree> {
+    let encoded_tree = download_encoded_tree(storage, hash).await?;
+    Ok(encoded_tree.tree)
+}
+
+/// Downloads a tree like `download_tree`, but also keeps the compressed bytes so that the tree can
+/// be stored elsewhere without compressing it again.
+pub async fn download_encoded_tree(storage: Arc<Storage>, hash: Hash<Tree>) -> Result<EncodedTree> {
+    let compressed_bytes = storage.get(&hash.key()).await?;
+    spawn_blocking(move || {
+        let bytes = decompress(&compressed_bytes)?;
+        assert_tree_hash_eq(&Hash::tree(&bytes), &hash)?;
+        let tree = deserialize(&bytes)?;
+        Ok(EncodedTree {
+            tree,
+            compressed_bytes,
+        })
+    })
+    .await?
+}
+
+pub async fn upload_tree(
+    storage: Arc<Storage>,
+    hash: Hash<Tree>,
+    compressed_bytes: Vec<u8>,
+) -> Result<()> {
+    storage.put(&hash.key(), compressed_bytes).await
+}
+
+/// Downloads the given trees and all of their subtrees, fetching each distinct tree only once.
+/// Trees that can't be downloaded are returned as errors, and their subtrees are skipped.
+pub async fn download_trees<I>(
+    storage: Arc<Storage>,
+    roots: I,
+    task_count: usize,
+) -> Result<DownloadedTrees>
+where
+    I: IntoIterator<Item = Hash<Tree>>,
+{
+    let mut tasks = BoundedJoinSet::new(task_count);
+    let mut trees = HashMap::new();
+    let mut pending = roots.into_iter().collect::<HashSet<_>>();
+
+    while !pending.is_empty() {
+        let mut next = HashSet::new();
+
+        for hash in pending {
+            let storage = storage.clone();
+            tasks
+                .spawn(async move { (hash, download_tree(storage, hash).await) })
+                .await?;
+
+            while let Some(result) = tasks.try_join_next() {
+                add_tree(&mut trees, &mut next, result?);
+            }
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            add_tree(&mut trees, &mut next, result?);
+        }
+
+        pending = next
+            .into_iter()
+            .filter(|hash| !trees.contains_key(hash))
+            .collect();
+    }
+
+    Ok(trees)
+}
+
+/// Downloads the trees containing `paths` (or every path if empty) and converts them into a file
+/// tree, leaving out any directories that aren't needed to reach those paths.
+///
+/// Also returns the hashes of the blocks referenced by those trees, which cover every block of
+/// the files in the file tree.
+pub async fn download_file_tree<P: AsRef<Path>>(
+    storage: Arc<Storage>,
+    root: Hash<Tree>,
+    paths: &[P],
+    task_count: usize,
+) -> Result<(FileTree, HashSet<Hash<Block>>)> {
+    let mut full_roots = vec![];
+    let mut partial_trees = HashMap::<_, Tree>::new();
+    let mut parent_trees = HashMap::new();
+
+    if paths.is_empty() {
+        full_roots.push(root);
+    }
+
+    for path in paths {
+        let path = path.as_ref();
+        let names = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let last_index = names.len().checked_sub(1).ok_or(Error::EmptyPath)?;
+        let mut hash = root;
+
+        for (i, name) in names.into_iter().enumerate() {
+            if let Entry::Vacant(entry) = parent_trees.entry(hash) {
+                entry.insert(download_tree(storage.clone(), hash).await?);
+            }
+
+            let entry = parent_trees[&hash]
+                .entries
+                .get(name)
+                .ok_or_else(|| Error::FileDoesNotExist(path.to_owned()))?;
+            let partial_tree = partial_trees.entry(hash).or_default();
+            partial_tree.entries.insert(name.to_owned(), entry.clone());
+
+            match entry {
+                TreeEntry::Directory { tree, .. } if i == last_index => full_roots.push(*tree),
+                TreeEntry::Directory { tree, .. } => hash = *tree,
+                _ if i == last_index => {}
+                _ => return Err(Error::FileDoesNotExist(path.to_owned())),
+            }
+        }
+    }
+
+    let downloaded_trees = download_trees(storage, full_roots, task_count).await?;
+    let mut trees = downloaded_trees
+        .into_iter()
+        .map(|(hash, result)| result.map(|tree| (hash, tree)))
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let mut block_hashes = HashSet::new();
+    for tree in trees.values() {
+        block_hashes.extend(tree.block_refs.iter().map(|(hash, _)| *hash));
+    }
+
+    // complete trees take precedence, since they're a superset of the partial ones
+    for (hash, partial_tree) in partial_trees {
+        if let Entry::Vacant(entry) = trees.entry(hash) {
+            // a partial tree has no block references of its own, so those of the full tree are used
+            let block_refs = &parent_trees[&hash].block_refs;
+            block_hashes.extend(block_refs.iter().map(|(hash, _)| *hash));
+            entry.insert(partial_tree);
+        }
+    }
+
+    let children = spawn_blocking(move || node_children(&root, &trees)).await??;
+    Ok((FileTree::from_children(children), block_hashes))
+}
+
+fn add_tree(
+    trees: &mut DownloadedTrees,
+    next: &mut HashSet<Hash<Tree>>,
+    (hash, result): (Hash<Tree>, Result<Tree>),
+) {
+    if let Ok(tree) = &result {
+        next.extend(
+            tree.subtrees()
+                .filter(|subtree| !trees.contains_key(subtree)),
+        );
+    }
+
+    trees.insert(hash, result);
+}
@@ -0,0 +1,76 @@
+use std::pin::pin;
+
+use tokio_stream::StreamExt;
+
+use crate::{
+    error::{Error, Result},
+    storage::Storage,
+};
+
+/// Version of the layout and encoding of the objects in a repository, which is bumped whenever a
+/// repository written by one version can't be read by the other. Repositories written before the
+/// format was versioned have no version.
+///
+/// 2. Branch blocks record the size of each child.
+/// 3. Archive hashes are derived from the encoded archive.
+/// 4. Blocks are stored in packs, and block records locate them in their pack.
+/// 5. Small files are stored inline in their node.
+/// 6. Archives store directories as separate trees.
+/// 7. Block records are split into a number of shards that grows with the repository, and a
+///    manifest records how many.
+pub const FORMAT_VERSION: u32 = 7;
+
+const FORMAT_VERSION_KEY: &str = "metadata/version";
+
+/// Checks that the repository was written in the format that this version reads. A repository
+/// without a format version is only accepted if it's empty, since it was otherwise written before
+/// the format was versioned.
+pub async fn check_format_version(storage: &Storage) -> Result<()> {
+    let maybe_version = read_format_version(storage).await?;
+    let is_empty = maybe_version.is_none() && is_empty(storage).await?;
+    validate_format_version(maybe_version, is_empty)
+}
+
+/// Writes the format version of a repository that doesn't have one yet, which must be done before
+/// anything else is written to a new one. A repository that already has a version is left alone,
+/// and fails the check if it has a different one, such as one written by a newer version of cubist
+/// since the repository was opened.
+pub async fn write_format_version(storage: &Storage) -> Result<()> {
+    if let Some(version) = read_format_version(storage).await? {
+        return validate_format_version(Some(version), false);
+    }
+
+    let bytes = FORMAT_VERSION.to_string().into_bytes();
+    storage.put(FORMAT_VERSION_KEY, bytes).await
+}
+
+async fn read_format_version(storage: &Storage) -> Result<Option<u32>> {
+    match storage.try_get(FORMAT_VERSION_KEY).await? {
+        Some(bytes) => parse_format_version(&bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn parse_format_version(bytes: &[u8]) -> Result<u32> {
+    str::from_utf8(bytes)
+        .ok()
+        .and_then(|text| text.trim().parse().ok())
+        .ok_or_else(|| Error::InvalidFormatVersion(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+pub fn validate_format_version(maybe_version: Option<u32>, is_empty: bool) -> Result<()> {
+    match maybe_version {
+        Some(FORMAT_VERSION) => Ok(()),
+        Some(found) => Err(Error::UnsupportedFormatVersion {
+            found,
+            supported: FORMAT_VERSION,
+        }),
+        None if is_empty => Ok(()),
+        None => Err(Error::MissingFormatVersion),
+    }
+}
+
+async fn is_empty(storage: &Storage) -> Result<bool> {
+    let mut keys = pin!(storage.keys(None));
+    Ok(keys.try_next().await?.is_none())
+}
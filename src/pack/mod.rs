@@ -0,0 +1,242 @@
+mod records;
+#[cfg(test)]
+mod tests;
+
+use std::{collections::HashMap, mem::take, ops::Range};
+
+use crate::{
+    block::{Block, BlockRecord},
+    entity::Entity,
+    error::{Error, Result},
+    hash::{self, Hash},
+};
+
+pub use self::records::{PackRecord, PackRecords};
+
+pub const TARGET_PACK_SIZE: usize = 16 << 20;
+pub const ENTRY_SIZE: usize = hash::SIZE + 2 * size_of::<u64>();
+pub const COUNT_SIZE: usize = size_of::<u32>();
+
+/// Many encoded blocks concatenated into one object, followed by an index of the blocks it
+/// contains and the number of entries in the index.
+#[derive(Debug)]
+pub struct Pack {
+    pub hash: Hash<Pack>,
+    pub data: Vec<u8>,
+}
+
+impl Pack {
+    /// Returns the total size of the blocks in this pack, which come before its index.
+    pub fn blocks_size(&self) -> Result<u64> {
+        Ok(self.index_range()?.start)
+    }
+
+    pub fn entries(&self) -> Result<Vec<PackEntry>> {
+        let range = self.index_range()?;
+        let index_bytes = usize::try_from(range.start)
+            .ok()
+            .zip(usize::try_from(range.end).ok())
+            .and_then(|(start, end)| self.data.get(start..end))
+            .ok_or(Error::InvalidPackIndex(self.hash))?;
+        decode_index(&self.hash, index_bytes)
+    }
+
+    fn index_range(&self) -> Result<Range<u64>> {
+        let count_start = self
+            .data
+            .len()
+            .checked_sub(COUNT_SIZE)
+            .ok_or(Error::InvalidPackIndex(self.hash))?;
+        let size = self.data.len() as u64;
+        index_range(&self.hash, size, &self.data[count_start..])
+    }
+}
+
+impl Entity for Pack {
+    const NAME: &'static str = "pack";
+    const KEY_PREFIX: &'static str = "packs/";
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PackEntry {
+    pub hash: Hash<Block>,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl PackEntry {
+    pub fn range(&self) -> Range<u64> {
+        self.offset..self.offset + self.size
+    }
+}
+
+/// Builds packs out of encoded blocks, and keeps the records of the blocks in finished packs until
+/// their upload completes, so that references to them can still be added and removed.
+#[derive(Debug)]
+pub struct PackBuilder {
+    data: Vec<u8>,
+    entries: Vec<PackEntry>,
+    ref_counts: HashMap<Hash<Block>, u64>,
+    uploading: HashMap<Hash<Pack>, HashMap<Hash<Block>, BlockRecord>>,
+}
+
+impl PackBuilder {
+    pub fn new() -> Self {
+        PackBuilder {
+            data: vec![],
+            entries: vec![],
+            ref_counts: HashMap::new(),
+            uploading: HashMap::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.size() >= TARGET_PACK_SIZE
+    }
+
+    /// Adds a reference to a block that has already been added to this pack or to one that is
+    /// being uploaded, returning whether the block was found.
+    pub fn add_ref(&mut self, hash: &Hash<Block>) -> bool {
+        self.add_count(hash, 1)
+    }
+
+    /// Adds `count` references to a block like `add_ref`.
+    pub fn add_count(&mut self, hash: &Hash<Block>, count: u64) -> bool {
+        if let Some(ref_count) = self.ref_count_mut(hash) {
+            *ref_count += count;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes `count` references to a block in this pack or in one that is being uploaded,
+    /// returning whether the block was found. A block left without references is still written,
+    /// but its record has no references.
+    pub fn remove_count(&mut self, hash: &Hash<Block>, count: u64) -> bool {
+        if let Some(ref_count) = self.ref_count_mut(hash) {
+            *ref_count = ref_count.saturating_sub(count);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Adds a block with `ref_count` references. A block that has already been added only gains
+    /// the references, so that it isn't stored twice.
+    pub fn add(&mut self, hash: Hash<Block>, bytes: &[u8], ref_count: u64) {
+        if self.add_count(&hash, ref_count) {
+            return;
+        }
+
+        let entry = PackEntry {
+            hash,
+            offset: self.data.len() as u64,
+            size: bytes.len() as u64,
+        };
+
+        self.data.extend(bytes);
+        self.entries.push(entry);
+        self.ref_counts.insert(hash, ref_count);
+    }
+
+    /// Returns the finished pack, leaving the builder empty. The records of its blocks are kept
+    /// until `complete` is called once the pack has been uploaded.
+    pub fn finish(&mut self) -> Pack {
+        let mut data = take(&mut self.data);
+        let entries = take(&mut self.entries);
+        let mut ref_counts = take(&mut self.ref_counts);
+
+        for entry in &entries {
+            data.extend(entry.hash.as_bytes());
+            data.extend(entry.offset.to_le_bytes());
+            data.extend(entry.size.to_le_bytes());
+        }
+
+        let count = u32::try_from(entries.len()).unwrap();
+        data.extend(count.to_le_bytes());
+
+        let hash = Hash::pack(&data);
+        let records = entries
+            .into_iter()
+            .map(|entry| {
+                let record = BlockRecord {
+                    ref_count: ref_counts.remove(&entry.hash).unwrap_or_default(),
+                    pack: hash,
+                    offset: entry.offset,
+                    size: entry.size,
+                };
+                (entry.hash, record)
+            })
+            .collect();
+
+        self.uploading.insert(hash, records);
+        Pack { hash, data }
+    }
+
+    /// Returns the records of the blocks in a finished pack, ordered by offset, once it has been
+    /// uploaded.
+    pub fn complete(&mut self, hash: &Hash<Pack>) -> Vec<(Hash<Block>, BlockRecord)> {
+        let mut records = self
+            .uploading
+            .remove(hash)
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<Vec<_>>();
+        records.sort_by_key(|(_, record)| record.offset);
+        records
+    }
+
+    fn ref_count_mut(&mut self, hash: &Hash<Block>) -> Option<&mut u64> {
+        if let Some(ref_count) = self.ref_counts.get_mut(hash) {
+            return Some(ref_count);
+        }
+
+        self.uploading
+            .values_mut()
+            .find_map(|records| records.get_mut(hash))
+            .map(|record| &mut record.ref_count)
+    }
+}
+
+/// Returns the range of the index in a pack of `pack_size` bytes, given the entry count stored in
+/// its last bytes.
+pub fn index_range(hash: &Hash<Pack>, pack_size: u64, count_bytes: &[u8]) -> Result<Range<u64>> {
+    let invalid = || Error::InvalidPackIndex(*hash);
+    let count = u32::from_le_bytes(count_bytes.try_into().map_err(|_| invalid())?);
+    let index_size = u64::from(count) * ENTRY_SIZE as u64;
+    let end = pack_size
+        .checked_sub(COUNT_SIZE as u64)
+        .ok_or_else(invalid)?;
+    let start = end.checked_sub(index_size).ok_or_else(invalid)?;
+    Ok(start..end)
+}
+
+pub fn decode_index(hash: &Hash<Pack>, bytes: &[u8]) -> Result<Vec<PackEntry>> {
+    if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+        return Err(Error::InvalidPackIndex(*hash));
+    }
+
+    let entries = bytes
+        .chunks_exact(ENTRY_SIZE)
+        .map(|bytes| {
+            let (hash_bytes, rest) = bytes.split_at(hash::SIZE);
+            let (offset_bytes, size_bytes) = rest.split_at(size_of::<u64>());
+            PackEntry {
+                hash: Hash::from_bytes(hash_bytes.try_into().unwrap()),
+                offset: u64::from_le_bytes(offset_bytes.try_into().unwrap()),
+                size: u64::from_le_bytes(size_bytes.try_into().unwrap()),
+            }
+        })
+        .collect();
+
+    Ok(entries)
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::BlockRecord,
+    entity::{EntityIndex, EntityRecord},
+    error::{Error, Result},
+    hash::Hash,
+};
+
+use super::Pack;
+
+/// Packs whose blocks use less than this percentage of their size are sparse.
+const SPARSE_THRESHOLD: u64 = 50;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PackRecord {
+    pub size: u64,
+    /// Total size of the blocks in this pack that still have records.
+    pub used_size: u64,
+}
+
+impl PackRecord {
+    /// Returns whether most of this pack is taken up by blocks that no longer have records, so
+    /// that it's worth moving its other blocks into a new pack.
+    pub fn is_sparse(&self) -> bool {
+        self.used_size > 0 && self.used_size * 100 < self.size * SPARSE_THRESHOLD
+    }
+}
+
+impl EntityRecord<Pack> for PackRecord {
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PackRecords {
+    records: HashMap<Hash<Pack>, PackRecord>,
+}
+
+impl PackRecords {
+    pub fn new() -> Self {
+        PackRecords {
+            records: HashMap::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash<Pack>, &PackRecord)> {
+        self.records.iter()
+    }
+
+    /// Subtracts the size of a block whose record was removed from the pack containing it.
+    pub fn release_block(&mut self, record: &BlockRecord) {
+        if let Some(pack_record) = self.records.get_mut(&record.pack) {
+            pack_record.used_size = pack_record.used_size.saturating_sub(record.size);
+        }
+    }
+}
+
+impl Default for PackRecords {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EntityIndex<Pack> for PackRecords {
+    type Record = PackRecord;
+
+    const KEY: &'static str = "metadata/packs";
+
+    fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    fn contains(&self, hash: &Hash<Pack>) -> bool {
+        self.records.contains_key(hash)
+    }
+
+    fn get(&self, hash: &Hash<Pack>) -> Option<&PackRecord> {
+        self.records.get(hash)
+    }
+
+    fn get_mut(&mut self, hash: &Hash<Pack>) -> Option<&mut PackRecord> {
+        self.records.get_mut(hash)
+    }
+
+    fn insert(&mut self, hash: Hash<Pack>, record: PackRecord) {
+        self.records.insert(hash, record);
+    }
+
+    fn remove(&mut self, hash: &Hash<Pack>) -> Result<PackRecord> {
+        self.records
+            .remove(hash)
+            .ok_or_else(|| Error::PackRecordNotFound(*hash))
+    }
+}
@@ -0,0 +1,247 @@
+use std::ops::Range;
+
+use crate::{
+    block::{Block, BlockRecord, BlockRecords},
+    entity::EntityIndex,
+    error::Error,
+    hash::Hash,
+    pack::{
+        COUNT_SIZE, Pack, PackBuilder, PackEntry, PackRecord, PackRecords, decode_index,
+        index_range,
+    },
+};
+
+fn block_hash(n: u8) -> Hash<Block> {
+    Hash::leaf_block(&[n])
+}
+
+/// Finishes a pack and completes its upload right away.
+fn finish(builder: &mut PackBuilder) -> (Pack, Vec<(Hash<Block>, BlockRecord)>) {
+    let pack = builder.finish();
+    let records = builder.complete(&pack.hash);
+    (pack, records)
+}
+
+#[test]
+fn pack_index_roundtrip() {
+    let mut builder = PackBuilder::new();
+    builder.add(block_hash(0), &[0; 10], 1);
+    builder.add(block_hash(1), &[1; 20], 2);
+
+    let (pack, records) = finish(&mut builder);
+    assert!(builder.is_empty());
+    assert_eq!(pack.hash, Hash::pack(&pack.data));
+
+    let size = pack.data.len() as u64;
+    let count_bytes = &pack.data[pack.data.len() - COUNT_SIZE..];
+    let range = index_range(&pack.hash, size, count_bytes).unwrap();
+    let index_bytes = slice(&pack.data, range);
+    let entries = decode_index(&pack.hash, index_bytes).unwrap();
+
+    assert_eq!(
+        entries,
+        vec![
+            PackEntry {
+                hash: block_hash(0),
+                offset: 0,
+                size: 10,
+            },
+            PackEntry {
+                hash: block_hash(1),
+                offset: 10,
+                size: 20,
+            },
+        ]
+    );
+
+    for (hash, record) in records {
+        let entry = entries.iter().find(|entry| entry.hash == hash).unwrap();
+        assert_eq!(record.pack, pack.hash);
+        assert_eq!(record.range(), entry.range());
+    }
+}
+
+#[test]
+fn pack_data_matches_blocks() {
+    let mut builder = PackBuilder::new();
+    builder.add(block_hash(0), &[0; 10], 1);
+    builder.add(block_hash(1), &[1; 20], 1);
+
+    let (pack, records) = finish(&mut builder);
+    for (hash, record) in records {
+        let bytes = slice(&pack.data, record.range());
+        let expected = u8::from(hash != block_hash(0));
+        assert!(bytes.iter().all(|&byte| byte == expected));
+    }
+}
+
+#[test]
+fn pack_add_ref() {
+    let mut builder = PackBuilder::new();
+    assert!(!builder.add_ref(&block_hash(0)));
+
+    builder.add(block_hash(0), &[0; 10], 1);
+    assert!(builder.add_ref(&block_hash(0)));
+    assert!(builder.add_ref(&block_hash(0)));
+
+    let (_, records) = finish(&mut builder);
+    assert_eq!(records[0].1.ref_count, 3);
+}
+
+#[test]
+fn pack_add_count() {
+    let mut builder = PackBuilder::new();
+    assert!(!builder.add_count(&block_hash(0), 2));
+
+    builder.add(block_hash(0), &[0; 10], 3);
+    assert!(builder.add_count(&block_hash(0), 4));
+
+    let (_, records) = finish(&mut builder);
+    assert_eq!(records[0].1.ref_count, 7);
+}
+
+#[test]
+fn pack_remove_count() {
+    let mut builder = PackBuilder::new();
+    assert!(!builder.remove_count(&block_hash(0), 1));
+
+    builder.add(block_hash(0), &[0; 10], 3);
+    builder.add(block_hash(1), &[1; 10], 1);
+    assert!(builder.remove_count(&block_hash(0), 2));
+    assert!(builder.remove_count(&block_hash(1), 2));
+
+    // unreferenced blocks are still written
+    let (pack, records) = finish(&mut builder);
+    assert_eq!(records[0].1.ref_count, 1);
+    assert_eq!(records[1].1.ref_count, 0);
+    assert_eq!(pack.entries().unwrap().len(), 2);
+}
+
+#[test]
+fn pack_add_duplicate() {
+    let mut builder = PackBuilder::new();
+    builder.add(block_hash(0), &[0; 10], 1);
+    builder.add(block_hash(0), &[0; 10], 2);
+    assert_eq!(builder.size(), 10);
+
+    let (pack, records) = finish(&mut builder);
+    assert_eq!(pack.entries().unwrap().len(), 1);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].1.ref_count, 3);
+}
+
+#[test]
+fn pack_refs_while_uploading() {
+    let mut builder = PackBuilder::new();
+    builder.add(block_hash(0), &[0; 10], 1);
+    builder.add(block_hash(1), &[1; 10], 1);
+    let pack = builder.finish();
+    assert!(builder.is_empty());
+
+    // blocks of a pack that is being uploaded can still be referenced
+    assert!(builder.add_ref(&block_hash(0)));
+    assert!(builder.remove_count(&block_hash(1), 1));
+    builder.add(block_hash(0), &[0; 10], 1);
+    assert!(builder.is_empty());
+
+    let records = builder.complete(&pack.hash);
+    assert_eq!(records[0].1.ref_count, 3);
+    assert_eq!(records[1].1.ref_count, 0);
+    assert!(!builder.add_ref(&block_hash(0)));
+    assert!(builder.complete(&pack.hash).is_empty());
+}
+
+#[test]
+fn pack_index_truncated_error() {
+    let hash = Hash::pack(&[]);
+    assert_eq!(
+        index_range(&hash, 8, &100_u32.to_le_bytes()),
+        Err(Error::InvalidPackIndex(hash))
+    );
+    assert_eq!(
+        decode_index(&hash, &[0; 47]),
+        Err(Error::InvalidPackIndex(hash))
+    );
+}
+
+#[test]
+fn pack_usage() {
+    let mut builder = PackBuilder::new();
+    builder.add(block_hash(0), &[0; 10], 1);
+    builder.add(block_hash(1), &[1; 20], 1);
+    let (first_pack, first_records) = finish(&mut builder);
+
+    builder.add(block_hash(2), &[2; 30], 1);
+    let (second_pack, second_records) = finish(&mut builder);
+
+    let mut block_records = BlockRecords::new();
+    for (hash, record) in first_records.into_iter().chain(second_records) {
+        block_records.insert(hash, record).unwrap();
+    }
+    block_records.remove(&block_hash(0)).unwrap();
+
+    let usage = block_records.pack_usage();
+    assert_eq!(usage.len(), 2);
+    assert_eq!(usage[&first_pack.hash], 20);
+    assert_eq!(usage[&second_pack.hash], 30);
+}
+
+#[test]
+fn pack_entries_and_blocks_size() {
+    let mut builder = PackBuilder::new();
+    builder.add(block_hash(0), &[0; 10], 1);
+    builder.add(block_hash(1), &[1; 20], 1);
+
+    let (pack, _) = finish(&mut builder);
+    assert_eq!(pack.blocks_size().unwrap(), 30);
+
+    let hashes = pack
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.hash)
+        .collect::<Vec<_>>();
+    assert_eq!(hashes, vec![block_hash(0), block_hash(1)]);
+}
+
+#[test]
+fn pack_release_block() {
+    let mut builder = PackBuilder::new();
+    builder.add(block_hash(0), &[0; 10], 1);
+    builder.add(block_hash(1), &[1; 20], 1);
+    let (pack, records) = finish(&mut builder);
+
+    let mut pack_records = PackRecords::new();
+    pack_records.insert(
+        pack.hash,
+        PackRecord {
+            size: pack.data.len() as u64,
+            used_size: 30,
+        },
+    );
+
+    for (_, record) in &records {
+        pack_records.release_block(record);
+    }
+    assert_eq!(pack_records.get(&pack.hash).unwrap().used_size, 0);
+}
+
+#[test]
+fn pack_record_sparse() {
+    let record = |used_size| PackRecord {
+        size: 100,
+        used_size,
+    };
+
+    assert!(record(49).is_sparse());
+    assert!(!record(50).is_sparse());
+    assert!(!record(100).is_sparse());
+    // packs without any used blocks are deleted instead of repacked
+    assert!(!record(0).is_sparse());
+}
+
+fn slice(data: &[u8], range: Range<u64>) -> &[u8] {
+    let start = usize::try_from(range.start).unwrap();
+    let end = usize::try_from(range.end).unwrap();
+    &data[start..end]
+}
@@ -0,0 +1,335 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fmt,
+    io::{self, IsTerminal, Write},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use humantime::format_duration;
+use log::warn;
+use serde::Serialize;
+use tokio::{sync::RwLock, task::JoinHandle, time::interval};
+
+use crate::{
+    format::{format_size, format_speed},
+    stats::CommandStats,
+};
+
+const LINE_INTERVAL: Duration = Duration::from_millis(250);
+const JSON_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Clears the current line of the terminal and moves the cursor back to its start.
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// Whether a progress line is currently drawn on the terminal, which has to be cleared before
+/// anything else is printed.
+static DRAWING: AtomicBool = AtomicBool::new(false);
+
+fn is_drawing() -> bool {
+    DRAWING.load(Ordering::Relaxed)
+}
+
+/// Clears the progress line, if any, so that a log message can be printed in its place. The line
+/// is drawn again on the next update.
+///
+/// The line is cleared on stdout, the terminal it's drawn on, so that nothing is written to the
+/// log when it goes to a file or a pipe instead.
+pub fn clear_line() -> io::Result<()> {
+    if is_drawing() {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{CLEAR_LINE}")?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProgressType {
+    /// A progress line on stdout, if it's a terminal
+    Auto,
+    /// JSON lines on stderr
+    Json,
+    /// No progress
+    None,
+}
+
+impl fmt::Display for ProgressType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgressType::Auto => write!(f, "auto"),
+            ProgressType::Json => write!(f, "json"),
+            ProgressType::None => write!(f, "none"),
+        }
+    }
+}
+
+/// Which way content is transferred, which decides the stats that progress is measured by.
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    /// Returns the files and bytes processed so far, and the content bytes transferred for them.
+    fn counts(self, stats: &CommandStats) -> (u64, u64, u64) {
+        match self {
+            Direction::Upload => (
+                stats.files_read,
+                stats.bytes_read,
+                stats.content_bytes_uploaded,
+            ),
+            Direction::Download => (
+                stats.files_created,
+                stats.bytes_written,
+                stats.content_bytes_downloaded,
+            ),
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            Direction::Upload => "uploading",
+            Direction::Download => "downloading",
+        }
+    }
+}
+
+/// Number of files and bytes that a command will process in total.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProgressTotals {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Line,
+    Json,
+}
+
+/// Progress of a command at one point in time, which is what's written for each JSON line.
+#[derive(Debug, Serialize)]
+struct ProgressEvent {
+    elapsed_secs: f64,
+    files: u64,
+    files_total: Option<u64>,
+    bytes: u64,
+    bytes_total: Option<u64>,
+    bytes_transferred: u64,
+    bytes_per_second: u64,
+    dedup_ratio: Option<f64>,
+    eta_secs: Option<u64>,
+    finished: bool,
+}
+
+impl ProgressEvent {
+    /// Measures the progress made after `elapsed_secs` from `stats`, estimating the time left from
+    /// how long the bytes processed so far took.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    fn new(
+        direction: Direction,
+        stats: &CommandStats,
+        maybe_totals: Option<&ProgressTotals>,
+        elapsed_secs: f64,
+        finished: bool,
+    ) -> Self {
+        let (files, bytes, bytes_transferred) = direction.counts(stats);
+        let bytes_per_second = (bytes_transferred as f64 / elapsed_secs) as u64;
+        let dedup_ratio = (bytes_transferred > 0).then(|| bytes as f64 / bytes_transferred as f64);
+        let eta_secs = maybe_totals
+            .filter(|_| bytes > 0 && !finished)
+            .map(|totals| {
+                let remaining = totals.bytes.saturating_sub(bytes);
+                (remaining as f64 * elapsed_secs / bytes as f64).ceil() as u64
+            });
+
+        ProgressEvent {
+            elapsed_secs,
+            files,
+            files_total: maybe_totals.map(|totals| totals.files),
+            bytes,
+            bytes_total: maybe_totals.map(|totals| totals.bytes),
+            bytes_transferred,
+            bytes_per_second,
+            dedup_ratio,
+            eta_secs,
+            finished,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Progress {
+    output: Output,
+    direction: Direction,
+    start_time: Instant,
+    stats: Arc<RwLock<CommandStats>>,
+    totals: Arc<OnceLock<ProgressTotals>>,
+}
+
+impl Progress {
+    async fn report(&self, finished: bool) {
+        let event = self.event(finished).await;
+        let result = match self.output {
+            Output::Line => draw_line(self.direction, &event),
+            Output::Json => write_json(&event),
+        };
+
+        if let Err(err) = result {
+            warn!("failed to report progress ({err})");
+        }
+    }
+
+    async fn event(&self, finished: bool) -> ProgressEvent {
+        let stats = self.stats.read().await;
+        let elapsed_secs = self.start_time.elapsed().as_secs_f64();
+        ProgressEvent::new(
+            self.direction,
+            &stats,
+            self.totals.get(),
+            elapsed_secs,
+            finished,
+        )
+    }
+}
+
+/// Shows the progress of a backup or restore while it runs, either as a line redrawn on the
+/// terminal or as JSON lines for other programs to read.
+#[derive(Debug)]
+pub struct ProgressReporter {
+    maybe_progress: Option<(Arc<Progress>, JoinHandle<()>)>,
+    totals: Arc<OnceLock<ProgressTotals>>,
+}
+
+impl ProgressReporter {
+    /// Starts reporting progress in the background, measured by the stats for `direction`.
+    pub fn start(
+        progress_type: ProgressType,
+        direction: Direction,
+        stats: Arc<RwLock<CommandStats>>,
+    ) -> Self {
+        let totals = Arc::new(OnceLock::new());
+        let maybe_output = match progress_type {
+            ProgressType::Auto => io::stdout().is_terminal().then_some(Output::Line),
+            ProgressType::Json => Some(Output::Json),
+            ProgressType::None => None,
+        };
+
+        let maybe_progress = maybe_output.map(|output| {
+            let progress = Arc::new(Progress {
+                output,
+                direction,
+                start_time: Instant::now(),
+                stats,
+                totals: totals.clone(),
+            });
+
+            let period = match output {
+                Output::Line => LINE_INTERVAL,
+                Output::Json => JSON_INTERVAL,
+            };
+
+            let task_progress = progress.clone();
+            let handle = tokio::spawn(async move {
+                let mut ticker = interval(period);
+                loop {
+                    ticker.tick().await;
+                    task_progress.report(false).await;
+                }
+            });
+
+            (progress, handle)
+        });
+
+        ProgressReporter {
+            maybe_progress,
+            totals,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.maybe_progress.is_some()
+    }
+
+    /// Sets the totals that the ETA is estimated from. Only the first call has any effect.
+    pub fn set_totals(&self, totals: ProgressTotals) {
+        let _ = self.totals.set(totals);
+    }
+
+    /// Returns a handle for setting the totals from another task, such as a scan that runs
+    /// alongside the command.
+    pub fn totals(&self) -> Arc<OnceLock<ProgressTotals>> {
+        self.totals.clone()
+    }
+
+    /// Stops reporting, clearing the progress line or writing a final JSON line.
+    pub async fn finish(self) {
+        let Some((progress, handle)) = self.maybe_progress else {
+            return;
+        };
+
+        handle.abort();
+        let _ = handle.await;
+
+        match progress.output {
+            Output::Line => {
+                let _ = clear_line();
+                DRAWING.store(false, Ordering::Relaxed);
+            }
+            Output::Json => progress.report(true).await,
+        }
+    }
+}
+
+fn draw_line(direction: Direction, event: &ProgressEvent) -> io::Result<()> {
+    let line = format_line(direction, event);
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "{CLEAR_LINE}{line}")?;
+    stdout.flush()?;
+    DRAWING.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+fn format_line(direction: Direction, event: &ProgressEvent) -> String {
+    let files = match event.files_total {
+        Some(total) => format!("{}/{total} files", event.files),
+        None => format!("{} files", event.files),
+    };
+    let bytes = match event.bytes_total {
+        Some(total) => format!("{}/{}", format_size(event.bytes), format_size(total)),
+        None => format_size(event.bytes),
+    };
+    let speed = format_speed(event.bytes_per_second * 8);
+    let dedup = match event.dedup_ratio {
+        Some(ratio) => format!("{ratio:.1}x"),
+        None => "-".to_owned(),
+    };
+    let eta = match event.eta_secs {
+        Some(secs) => format_duration(Duration::from_secs(secs)).to_string(),
+        None => "-".to_owned(),
+    };
+
+    format!(
+        "{files}, {bytes}, {} {speed}, dedup {dedup}, ETA {eta}",
+        direction.verb()
+    )
+}
+
+fn write_json(event: &ProgressEvent) -> io::Result<()> {
+    let mut stderr = io::stderr().lock();
+    serde_json::to_writer(&mut stderr, event)?;
+    writeln!(stderr)
+}
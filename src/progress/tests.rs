@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+
+use crate::stats::CommandStats;
+
+use super::{
+    Direction, ProgressEvent, ProgressReporter, ProgressTotals, ProgressType, format_line,
+};
+
+fn stats() -> CommandStats {
+    CommandStats {
+        files_read: 3,
+        bytes_read: 4_000,
+        content_bytes_uploaded: 1_000,
+        files_created: 1,
+        bytes_written: 500,
+        content_bytes_downloaded: 500,
+        ..CommandStats::new()
+    }
+}
+
+const TOTALS: ProgressTotals = ProgressTotals {
+    files: 10,
+    bytes: 10_000,
+};
+
+#[test]
+fn event_counts_by_direction() {
+    let upload = ProgressEvent::new(Direction::Upload, &stats(), None, 2.0, false);
+    assert_eq!(upload.files, 3);
+    assert_eq!(upload.bytes, 4_000);
+    assert_eq!(upload.bytes_transferred, 1_000);
+    assert_eq!(upload.bytes_per_second, 500);
+    assert_eq!(upload.dedup_ratio, Some(4.0));
+
+    let download = ProgressEvent::new(Direction::Download, &stats(), None, 2.0, false);
+    assert_eq!(download.files, 1);
+    assert_eq!(download.bytes, 500);
+    assert_eq!(download.bytes_transferred, 500);
+    assert_eq!(download.dedup_ratio, Some(1.0));
+}
+
+#[test]
+fn event_without_transfers() {
+    let event = ProgressEvent::new(Direction::Upload, &CommandStats::new(), None, 1.0, false);
+    assert_eq!(event.bytes_per_second, 0);
+    assert_eq!(event.dedup_ratio, None);
+}
+
+#[test]
+fn eta_from_totals() {
+    // 4 KB of 10 KB took 2 seconds, so the other 6 KB take 3 more
+    let event = ProgressEvent::new(Direction::Upload, &stats(), Some(&TOTALS), 2.0, false);
+    assert_eq!(event.files_total, Some(10));
+    assert_eq!(event.bytes_total, Some(10_000));
+    assert_eq!(event.eta_secs, Some(3));
+
+    // partial seconds are rounded up
+    let event = ProgressEvent::new(Direction::Upload, &stats(), Some(&TOTALS), 1.5, false);
+    assert_eq!(event.eta_secs, Some(3));
+}
+
+#[test]
+fn eta_unknown() {
+    // without totals, before any bytes are processed, and once finished
+    let event = ProgressEvent::new(Direction::Upload, &stats(), None, 2.0, false);
+    assert_eq!(event.eta_secs, None);
+
+    let empty = CommandStats::new();
+    let event = ProgressEvent::new(Direction::Upload, &empty, Some(&TOTALS), 2.0, false);
+    assert_eq!(event.eta_secs, None);
+
+    let event = ProgressEvent::new(Direction::Upload, &stats(), Some(&TOTALS), 2.0, true);
+    assert_eq!(event.eta_secs, None);
+}
+
+#[test]
+fn eta_past_totals() {
+    // files can grow after they're scanned
+    let totals = ProgressTotals {
+        files: 1,
+        bytes: 1_000,
+    };
+    let event = ProgressEvent::new(Direction::Upload, &stats(), Some(&totals), 2.0, false);
+    assert_eq!(event.eta_secs, Some(0));
+}
+
+#[test]
+fn line_with_totals() {
+    let event = ProgressEvent::new(Direction::Upload, &stats(), Some(&TOTALS), 2.0, false);
+    assert_eq!(
+        format_line(Direction::Upload, &event),
+        "3/10 files, 4 kB/10 kB, uploading 4 kbit/s, dedup 4.0x, ETA 3s"
+    );
+}
+
+#[test]
+fn line_without_totals() {
+    let event = ProgressEvent::new(Direction::Download, &CommandStats::new(), None, 1.0, false);
+    assert_eq!(
+        format_line(Direction::Download, &event),
+        "0 files, 0 B, downloading 0 bits/s, dedup -, ETA -"
+    );
+}
+
+#[test]
+fn reporter_disabled() {
+    let stats = Arc::new(RwLock::new(CommandStats::new()));
+    let reporter = ProgressReporter::start(ProgressType::None, Direction::Upload, stats);
+    assert!(!reporter.is_enabled());
+
+    // only the first totals are kept
+    reporter.set_totals(TOTALS);
+    reporter.set_totals(ProgressTotals::default());
+    assert_eq!(reporter.totals().get().unwrap().bytes, 10_000);
+}
@@ -0,0 +1,354 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    io::{self, SeekFrom},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, ready},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncSeek, ReadBuf},
+    sync::RwLock,
+    task::spawn_blocking,
+    try_join,
+};
+
+use crate::{
+    archive::ArchiveRef,
+    assert::{assert_block_level_eq, assert_block_size_eq},
+    block::{Block, BlockRecords, ChildBlock},
+    cache::DiskBlockCache,
+    error::{Error, Result},
+    file::{FileData, Node},
+    hash::Hash,
+    ops::{
+        check_format_version, download_archive, download_archive_records, download_block_manifest,
+        download_file_tree, download_shared_block_bytes, load_block_shards, resolve_archive_ref,
+    },
+    storage::Storage,
+};
+
+const CACHE_CAPACITY: usize = 16;
+const TASK_COUNT: usize = 8;
+
+type PendingLeaf = Pin<Box<dyn Future<Output = Result<Leaf>> + Send>>;
+
+/// Random-access reader over the contents of a file in an archive.
+///
+/// Branch blocks record the size of each child subtree, so a read at any offset only needs to fetch
+/// the blocks on the path from the root to the leaf covering that offset. Block records are loaded
+/// one shard at a time as blocks are fetched.
+pub struct ArchiveFileReader {
+    storage: Arc<Storage>,
+    block_records: Arc<RwLock<BlockRecords>>,
+    block_cache: Option<Arc<DiskBlockCache>>,
+    /// Root block of the file's contents, which empty files don't have
+    root: Option<Arc<Block>>,
+    size: u64,
+    position: u64,
+    cache: Arc<Mutex<BlockCache>>,
+    current: Option<Leaf>,
+    pending: Option<PendingLeaf>,
+}
+
+impl ArchiveFileReader {
+    /// Opens the file at `path` in the archive given by `archive`, downloading the archive's
+    /// directories along that path. Blocks are read from `block_cache` when it has them and added
+    /// to it when they're downloaded.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the repository has a format that this version can't read, if the archive can't be
+    /// resolved, if `path` doesn't exist in it or isn't a regular file, or if downloading the
+    /// file's root block fails.
+    pub async fn open(
+        storage: Arc<Storage>,
+        archive: &ArchiveRef,
+        path: &Path,
+        block_cache: Option<DiskBlockCache>,
+    ) -> Result<Self> {
+        check_format_version(&storage).await?;
+        let (archive_records, block_manifest) = try_join!(
+            download_archive_records(storage.clone()),
+            download_block_manifest(storage.clone()),
+        )?;
+        let hash = resolve_archive_ref(storage.clone(), &archive_records, archive).await?;
+        let archive = download_archive(storage.clone(), &hash).await?;
+        let (files, _) =
+            download_file_tree(storage.clone(), archive.tree, &[path], TASK_COUNT).await?;
+
+        let Some(Node::File { data, .. }) = files.get(path) else {
+            return Err(Error::FileIsNotRegular(path.to_owned()));
+        };
+
+        let block_records = Arc::new(RwLock::new(BlockRecords::unloaded(block_manifest)));
+        let block_cache = block_cache.map(Arc::new);
+        let root = match data {
+            FileData::Empty => None,
+            FileData::Inline(bytes) => Some(Block::leaf(bytes.clone())?),
+            FileData::Blocks(hash) => {
+                let cache = block_cache.as_deref();
+                Some(download_block(&storage, &block_records, cache, *hash, None).await?)
+            }
+        };
+        let root = root.map(Arc::new);
+        let size = root.as_ref().map_or(0, |root| root.size());
+        let cache = Arc::new(Mutex::new(BlockCache::new(CACHE_CAPACITY)));
+
+        Ok(ArchiveFileReader {
+            storage,
+            block_records,
+            block_cache,
+            root,
+            size,
+            position: 0,
+            cache,
+            current: None,
+            pending: None,
+        })
+    }
+
+    /// Returns the size of the file in bytes.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the offset that the next read starts from.
+    #[must_use]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn pending_leaf(&self, root: Arc<Block>) -> PendingLeaf {
+        Box::pin(find_leaf(
+            self.storage.clone(),
+            self.block_records.clone(),
+            self.block_cache.clone(),
+            self.cache.clone(),
+            root,
+            self.position,
+        ))
+    }
+}
+
+impl fmt::Debug for ArchiveFileReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveFileReader")
+            .field("root", &self.root.as_ref().map(|root| *root.hash()))
+            .field("size", &self.size)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for ArchiveFileReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let reader = &mut *self;
+
+        loop {
+            if reader.position >= reader.size || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            // only empty files have no root, and reads from them already stopped above
+            let Some(root) = reader.root.clone() else {
+                return Poll::Ready(Ok(()));
+            };
+
+            if let Some(leaf) = &reader.current
+                && let Some(data) = leaf.data_from(reader.position)
+            {
+                let len = data.len().min(buf.remaining());
+                buf.put_slice(&data[..len]);
+                reader.position += len as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            if reader.pending.is_none() {
+                reader.pending = Some(reader.pending_leaf(root));
+            }
+
+            let pending = reader.pending.as_mut().unwrap();
+            let result = ready!(pending.as_mut().poll(cx));
+            reader.pending = None;
+            reader.current = Some(result.map_err(io::Error::other)?);
+        }
+    }
+}
+
+impl AsyncSeek for ArchiveFileReader {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        self.position = seek_position(self.size, self.position, position)?;
+        self.pending = None;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+/// Returns the position that a seek moves to. Seeking past the end is allowed, and reads from there
+/// return no data.
+fn seek_position(size: u64, current: u64, position: SeekFrom) -> io::Result<u64> {
+    let maybe_position = match position {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::End(offset) => size.checked_add_signed(offset),
+        SeekFrom::Current(offset) => current.checked_add_signed(offset),
+    };
+
+    maybe_position.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+#[derive(Debug)]
+struct Leaf {
+    offset: u64,
+    block: Arc<Block>,
+}
+
+impl Leaf {
+    fn data_from(&self, position: u64) -> Option<&[u8]> {
+        let Block::Leaf { data, .. } = &*self.block else {
+            return None;
+        };
+
+        let start = usize::try_from(position.checked_sub(self.offset)?).ok()?;
+        data.get(start..).filter(|data| !data.is_empty())
+    }
+}
+
+#[derive(Debug)]
+struct BlockCache {
+    capacity: usize,
+    blocks: VecDeque<Arc<Block>>,
+}
+
+impl BlockCache {
+    fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            blocks: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn get(&mut self, hash: &Hash<Block>) -> Option<Arc<Block>> {
+        let index = self.blocks.iter().position(|block| block.hash() == hash)?;
+        let block = self.blocks.remove(index)?;
+        self.blocks.push_front(block.clone());
+        Some(block)
+    }
+
+    fn insert(&mut self, block: Arc<Block>) {
+        if self.blocks.len() >= self.capacity {
+            self.blocks.pop_back();
+        }
+
+        self.blocks.push_front(block);
+    }
+}
+
+async fn find_leaf(
+    storage: Arc<Storage>,
+    block_records: Arc<RwLock<BlockRecords>>,
+    block_cache: Option<Arc<DiskBlockCache>>,
+    cache: Arc<Mutex<BlockCache>>,
+    root: Arc<Block>,
+    position: u64,
+) -> Result<Leaf> {
+    let mut block = root;
+    let mut offset = 0;
+
+    while let Block::Branch {
+        level, children, ..
+    } = &*block
+    {
+        let (child, child_offset) = find_child(children, position - offset)?;
+        let level = level - 1;
+        offset += child_offset;
+
+        // copied to avoid holding lock
+        let maybe_block = cache.lock().unwrap().get(&child.hash);
+        block = if let Some(block) = maybe_block {
+            block
+        } else {
+            let disk_cache = block_cache.as_deref();
+            let block = download_block(
+                &storage,
+                &block_records,
+                disk_cache,
+                child.hash,
+                Some(level),
+            )
+            .await?;
+            let block = Arc::new(block);
+            cache.lock().unwrap().insert(block.clone());
+            block
+        };
+
+        assert_block_size_eq(&child.hash, block.size(), child.size)?;
+    }
+
+    Ok(Leaf { offset, block })
+}
+
+fn find_child(children: &[ChildBlock], position: u64) -> Result<(ChildBlock, u64)> {
+    let mut offset = 0;
+
+    for child in children {
+        if position < offset + child.size {
+            return Ok((*child, offset));
+        }
+
+        offset += child.size;
+    }
+
+    Err(Error::InvalidBlockSize(offset))
+}
+
+/// Gets a block from the disk cache if it's there, or else downloads it, loading the shard of its
+/// record first, and adds it to the cache.
+async fn download_block(
+    storage: &Arc<Storage>,
+    block_records: &RwLock<BlockRecords>,
+    block_cache: Option<&DiskBlockCache>,
+    hash: Hash<Block>,
+    level: Option<u8>,
+) -> Result<Block> {
+    if let Some(block_cache) = block_cache
+        && let Some(block) = block_cache.get(&hash).await
+    {
+        assert_block_level_eq(&hash, block.level(), level)?;
+        return Ok(block);
+    }
+
+    load_block_shards(storage.clone(), block_records, [&hash], 1).await?;
+    let bytes = download_shared_block_bytes(storage, block_records, &hash).await?;
+
+    let (block, bytes) = spawn_blocking(move || {
+        let block = Block::decode(&hash, level, &bytes)?;
+        Result::Ok((block, bytes))
+    })
+    .await??;
+
+    if let Some(block_cache) = block_cache {
+        block_cache.put(&hash, &bytes).await;
+    }
+
+    Ok(block)
+}
@@ -0,0 +1,313 @@
+use std::{
+    fs,
+    io::{ErrorKind, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{TimeZone, Utc};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    arc::rwarc,
+    archive::{Archive, ArchiveInfo, ArchiveRecords},
+    block::{Block, BlockRecords, ChildBlock},
+    entity::EntityIndex,
+    error::Error,
+    file::{FileData, Metadata, Node, NodeChildren},
+    hash::{self, Hash},
+    ops::{
+        upload_archive, upload_archive_records, upload_block_records, upload_tree,
+        write_format_version,
+    },
+    pack::PackBuilder,
+    storage::{ClientOptions, ObjectOptions, RateLimits, RepoLocation, RetryPolicy, Storage},
+    tree::{DirRefs, TreeSet},
+};
+
+use super::{ArchiveFileReader, BlockCache, Leaf, find_child, seek_position};
+
+fn child(size: u64) -> ChildBlock {
+    ChildBlock {
+        hash: Hash::from_bytes([0; hash::SIZE]),
+        size,
+    }
+}
+
+fn leaf_block(byte: u8, size: usize) -> Arc<Block> {
+    Arc::new(Block::leaf(vec![byte; size]).unwrap())
+}
+
+#[test]
+fn find_child_offsets() {
+    let children = [child(10), child(20), child(30)];
+    assert_eq!(find_child(&children, 0).unwrap().1, 0);
+    assert_eq!(find_child(&children, 9).unwrap().1, 0);
+    assert_eq!(find_child(&children, 10).unwrap().1, 10);
+    assert_eq!(find_child(&children, 29).unwrap().1, 10);
+    assert_eq!(find_child(&children, 30).unwrap().1, 30);
+    assert_eq!(find_child(&children, 59).unwrap().1, 30);
+    assert_eq!(find_child(&children, 59).unwrap().0.size, 30);
+}
+
+#[test]
+fn find_child_past_end() {
+    let children = [child(10), child(20)];
+    assert_eq!(
+        find_child(&children, 30).unwrap_err(),
+        Error::InvalidBlockSize(30)
+    );
+    assert_eq!(find_child(&[], 0).unwrap_err(), Error::InvalidBlockSize(0));
+}
+
+#[test]
+fn leaf_data_range() {
+    let block = Arc::new(Block::leaf((0..10).collect()).unwrap());
+    let leaf = Leaf { offset: 100, block };
+
+    assert_eq!(leaf.data_from(99), None);
+    assert_eq!(
+        leaf.data_from(100),
+        Some(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9][..])
+    );
+    assert_eq!(leaf.data_from(105), Some(&[5, 6, 7, 8, 9][..]));
+    assert_eq!(leaf.data_from(109), Some(&[9][..]));
+    assert_eq!(leaf.data_from(110), None);
+    assert_eq!(leaf.data_from(u64::MAX), None);
+}
+
+#[test]
+fn block_cache_evicts_least_recently_used() {
+    let first = leaf_block(1, 1);
+    let second = leaf_block(2, 1);
+    let third = leaf_block(3, 1);
+
+    let mut cache = BlockCache::new(2);
+    cache.insert(first.clone());
+    cache.insert(second.clone());
+
+    // reading the first block makes the second one the least recently used
+    assert!(cache.get(first.hash()).is_some());
+    cache.insert(third.clone());
+
+    assert!(cache.get(second.hash()).is_none());
+    assert!(cache.get(first.hash()).is_some());
+    assert!(cache.get(third.hash()).is_some());
+}
+
+#[test]
+fn seek_within_file() {
+    assert_eq!(seek_position(100, 50, SeekFrom::Start(10)).unwrap(), 10);
+    assert_eq!(seek_position(100, 50, SeekFrom::Current(-50)).unwrap(), 0);
+    assert_eq!(seek_position(100, 50, SeekFrom::Current(25)).unwrap(), 75);
+    assert_eq!(seek_position(100, 50, SeekFrom::End(-1)).unwrap(), 99);
+}
+
+#[test]
+fn seek_past_end() {
+    assert_eq!(seek_position(100, 0, SeekFrom::Start(200)).unwrap(), 200);
+    assert_eq!(seek_position(100, 0, SeekFrom::End(10)).unwrap(), 110);
+    assert_eq!(seek_position(100, 90, SeekFrom::Current(20)).unwrap(), 110);
+}
+
+#[test]
+fn seek_to_negative_position() {
+    let err = seek_position(100, 50, SeekFrom::Current(-51)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let err = seek_position(100, 0, SeekFrom::End(-101)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let err = seek_position(100, u64::MAX, SeekFrom::Current(1)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+/// Sizes of the leaves of the file read end to end, chosen so that no two are the same.
+const LEAF_SIZES: [usize; 6] = [4, 7, 1, 9, 5, 6];
+
+fn file_bytes() -> Vec<u8> {
+    let size = LEAF_SIZES.iter().sum::<usize>();
+    (1..=size).map(|n| u8::try_from(n).unwrap()).collect()
+}
+
+/// Returns the blocks of `file_bytes` as a tree with three levels, whose branches have different
+/// numbers of children, along with the root.
+fn file_blocks() -> (Vec<Block>, Hash<Block>) {
+    let bytes = file_bytes();
+    let mut leaves = vec![];
+    let mut start = 0;
+    for size in LEAF_SIZES {
+        leaves.push(Block::leaf(bytes[start..start + size].to_vec()).unwrap());
+        start += size;
+    }
+
+    let branches = [&leaves[..3], &leaves[3..5], &leaves[5..]]
+        .map(|children| Block::branch(1, children.iter().map(Block::as_child).collect()).unwrap());
+    let root = Block::branch(2, branches.iter().map(Block::as_child).collect()).unwrap();
+    let root_hash = *root.hash();
+
+    let blocks = leaves.into_iter().chain(branches).chain([root]).collect();
+    (blocks, root_hash)
+}
+
+fn file_node(data: FileData) -> Node {
+    let metadata = Metadata {
+        inode: 1,
+        mode: 0o644,
+        group: 0,
+        owner: 0,
+        size: 0,
+        accessed: None,
+        created: None,
+        modified: None,
+    };
+    Node::File { metadata, data }
+}
+
+/// Writes a repository to an empty local directory, with a single archive of a file stored in
+/// blocks (`blocks`), an inline file (`inline`), and an empty file (`empty`).
+async fn repository(name: &str) -> Arc<Storage> {
+    let dir = std::env::temp_dir().join(format!("cubist-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let storage = Storage::new(
+        RepoLocation::Local(dir),
+        ClientOptions::default(),
+        None,
+        RetryPolicy::default(),
+        RateLimits::default(),
+        ObjectOptions::default(),
+    )
+    .await;
+    let storage = Arc::new(storage);
+    write_format_version(&storage).await.unwrap();
+
+    let (blocks, root) = file_blocks();
+    let mut pack_builder = PackBuilder::new();
+    for block in blocks {
+        let hash = *block.hash();
+        pack_builder.add(hash, &block.encode(3).unwrap(), 1);
+    }
+    let pack = pack_builder.finish();
+    let pack_hash = pack.hash;
+    storage.put(&pack_hash.key(), pack.data).await.unwrap();
+
+    let mut block_records = BlockRecords::new();
+    for (hash, record) in pack_builder.complete(&pack_hash) {
+        block_records.insert(hash, record).unwrap();
+    }
+    upload_block_records(storage.clone(), rwarc(block_records), 1)
+        .await
+        .unwrap();
+
+    let children = NodeChildren::from([
+        ("blocks".into(), file_node(FileData::Blocks(root))),
+        ("inline".into(), file_node(FileData::Inline(vec![1, 2, 3]))),
+        ("empty".into(), file_node(FileData::Empty)),
+    ]);
+    let tree_set = TreeSet::build(children, &mut DirRefs::new()).unwrap();
+    for (hash, tree) in tree_set.trees {
+        upload_tree(storage.clone(), hash, tree.compressed_bytes)
+            .await
+            .unwrap();
+    }
+
+    let info = ArchiveInfo {
+        created: Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap(),
+        name: None,
+        description: None,
+        tags: vec![],
+        hostname: "laptop".to_owned(),
+        username: "alice".to_owned(),
+        paths: vec![PathBuf::from("/home/alice")],
+    };
+    let archive = Archive::new(info, tree_set.root);
+    let (hash, record) = upload_archive(storage.clone(), archive, false)
+        .await
+        .unwrap();
+    let mut archive_records = ArchiveRecords::new();
+    archive_records.insert(hash, record);
+    upload_archive_records(storage.clone(), rwarc(archive_records))
+        .await
+        .unwrap();
+
+    storage
+}
+
+async fn open_file(storage: &Arc<Storage>, path: &str) -> ArchiveFileReader {
+    let archive = "latest".parse().unwrap();
+    ArchiveFileReader::open(storage.clone(), &archive, Path::new(path), None)
+        .await
+        .unwrap()
+}
+
+async fn read_rest(reader: &mut ArchiveFileReader) -> Vec<u8> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes).await.unwrap();
+    bytes
+}
+
+#[tokio::test]
+async fn read_file_in_blocks() {
+    let storage = repository("read-blocks").await;
+    let mut reader = open_file(&storage, "blocks").await;
+    let bytes = file_bytes();
+    assert_eq!(reader.size(), bytes.len() as u64);
+    assert_eq!(read_rest(&mut reader).await, bytes);
+    assert_eq!(reader.position(), bytes.len() as u64);
+
+    // reads stop at the end of each leaf, so this covers every leaf boundary in both directions
+    for position in (0..=bytes.len()).rev() {
+        reader.seek(SeekFrom::Start(position as u64)).await.unwrap();
+        assert_eq!(read_rest(&mut reader).await, &bytes[position..]);
+    }
+
+    reader.seek(SeekFrom::Start(2)).await.unwrap();
+    let mut buf = [0; 10];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, bytes[2..12]);
+
+    reader.seek(SeekFrom::End(-3)).await.unwrap();
+    assert_eq!(read_rest(&mut reader).await, &bytes[bytes.len() - 3..]);
+}
+
+#[tokio::test]
+async fn read_past_end_of_file() {
+    let storage = repository("read-past-end").await;
+    let mut reader = open_file(&storage, "blocks").await;
+
+    let position = reader.seek(SeekFrom::End(10)).await.unwrap();
+    assert_eq!(position, reader.size() + 10);
+    assert_eq!(reader.read(&mut [0; 4]).await.unwrap(), 0);
+
+    // reading again after a seek back works as before
+    reader.seek(SeekFrom::Start(0)).await.unwrap();
+    assert_eq!(read_rest(&mut reader).await, file_bytes());
+}
+
+#[tokio::test]
+async fn read_inline_and_empty_files() {
+    let storage = repository("read-inline").await;
+
+    let mut reader = open_file(&storage, "inline").await;
+    assert_eq!(reader.size(), 3);
+    assert_eq!(read_rest(&mut reader).await, [1, 2, 3]);
+    reader.seek(SeekFrom::Start(1)).await.unwrap();
+    assert_eq!(read_rest(&mut reader).await, [2, 3]);
+
+    let mut reader = open_file(&storage, "empty").await;
+    assert_eq!(reader.size(), 0);
+    assert!(read_rest(&mut reader).await.is_empty());
+    reader.seek(SeekFrom::Start(5)).await.unwrap();
+    assert!(read_rest(&mut reader).await.is_empty());
+}
+
+#[tokio::test]
+async fn open_missing_file() {
+    let storage = repository("open-missing").await;
+    let archive = "latest".parse().unwrap();
+    let path = Path::new("missing");
+    let err = ArchiveFileReader::open(storage, &archive, path, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err, Error::FileDoesNotExist(path.to_owned()));
+}
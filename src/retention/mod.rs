@@ -0,0 +1,168 @@
+#[cfg(test)]
+mod tests;
+
+use std::{fmt, time::Duration};
+
+use chrono::{DateTime, Datelike, TimeZone, Timelike};
+use clap::ValueEnum;
+use itertools::Itertools;
+
+use crate::archive::ArchiveInfo;
+
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_field_names)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_hourly: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+    pub keep_monthly: usize,
+    pub keep_yearly: usize,
+    pub keep_within: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last == 0
+            && self.keep_hourly == 0
+            && self.keep_daily == 0
+            && self.keep_weekly == 0
+            && self.keep_monthly == 0
+            && self.keep_yearly == 0
+            && self.keep_within.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeepReason {
+    Last,
+    Within,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl fmt::Display for KeepReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeepReason::Last => write!(f, "last"),
+            KeepReason::Within => write!(f, "within"),
+            KeepReason::Hourly => write!(f, "hourly"),
+            KeepReason::Daily => write!(f, "daily"),
+            KeepReason::Weekly => write!(f, "weekly"),
+            KeepReason::Monthly => write!(f, "monthly"),
+            KeepReason::Yearly => write!(f, "yearly"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    Host,
+    User,
+    Name,
+    Tags,
+    Paths,
+}
+
+impl fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupBy::Host => write!(f, "host"),
+            GroupBy::User => write!(f, "user"),
+            GroupBy::Name => write!(f, "name"),
+            GroupBy::Tags => write!(f, "tags"),
+            GroupBy::Paths => write!(f, "paths"),
+        }
+    }
+}
+
+/// Returns a key that is equal for two archives if they have the same values for every field in
+/// `group_by`, or an empty key if `group_by` is empty.
+pub fn group_key(info: &ArchiveInfo, group_by: &[GroupBy]) -> String {
+    group_by
+        .iter()
+        .map(|field| {
+            let value = match field {
+                GroupBy::Host => info.hostname.clone(),
+                GroupBy::User => info.username.clone(),
+                GroupBy::Name => info.name.clone().unwrap_or_default(),
+                GroupBy::Tags => info.tags.iter().sorted().dedup().join(","),
+                GroupBy::Paths => info.paths.iter().map(|path| path.display()).join(","),
+            };
+            format!("{field}={value}")
+        })
+        .join(" ")
+}
+
+/// Returns the reasons for keeping each of `times`, which must be sorted from newest to oldest. An
+/// empty list of reasons means that the corresponding archive should be removed.
+pub fn apply_policy<Tz: TimeZone>(
+    times: &[DateTime<Tz>],
+    policy: &RetentionPolicy,
+    now: &DateTime<Tz>,
+) -> Vec<Vec<KeepReason>> {
+    let mut reasons = vec![vec![]; times.len()];
+
+    for (i, time) in times.iter().enumerate() {
+        if i < policy.keep_last {
+            reasons[i].push(KeepReason::Last);
+        }
+
+        if let Some(within) = policy.keep_within
+            && is_within(time, now, within)
+        {
+            reasons[i].push(KeepReason::Within);
+        }
+    }
+
+    let periodic_policies = [
+        (KeepReason::Hourly, policy.keep_hourly),
+        (KeepReason::Daily, policy.keep_daily),
+        (KeepReason::Weekly, policy.keep_weekly),
+        (KeepReason::Monthly, policy.keep_monthly),
+        (KeepReason::Yearly, policy.keep_yearly),
+    ];
+
+    for (reason, count) in periodic_policies {
+        let mut last_period = None;
+        let mut kept = 0;
+
+        for (i, time) in times.iter().enumerate() {
+            if kept == count {
+                break;
+            }
+
+            let period = period(time, reason);
+            if last_period != Some(period) {
+                last_period = Some(period);
+                reasons[i].push(reason);
+                kept += 1;
+            }
+        }
+    }
+
+    reasons
+}
+
+fn is_within<Tz: TimeZone>(time: &DateTime<Tz>, now: &DateTime<Tz>, within: Duration) -> bool {
+    let age = now.clone() - time.clone();
+    // archives from the future are always within the interval
+    age.to_std().ok().is_none_or(|age| age <= within)
+}
+
+fn period<Tz: TimeZone>(time: &DateTime<Tz>, reason: KeepReason) -> (i32, u32, u32, u32) {
+    match reason {
+        KeepReason::Hourly => (time.year(), time.month(), time.day(), time.hour()),
+        KeepReason::Daily => (time.year(), time.month(), time.day(), 0),
+        KeepReason::Weekly => {
+            let week = time.iso_week();
+            (week.year(), week.week(), 0, 0)
+        }
+        KeepReason::Monthly => (time.year(), time.month(), 0, 0),
+        KeepReason::Yearly => (time.year(), 0, 0, 0),
+        KeepReason::Last | KeepReason::Within => unreachable!(),
+    }
+}
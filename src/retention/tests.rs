@@ -0,0 +1,162 @@
+use std::{path::PathBuf, time::Duration};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{
+    archive::ArchiveInfo,
+    retention::{
+        GroupBy,
+        KeepReason::{self, Daily, Last, Monthly, Weekly, Within, Yearly},
+        RetentionPolicy, apply_policy, group_key,
+    },
+};
+
+fn time(year: i32, month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, hour, 0, 0).unwrap()
+}
+
+fn apply(times: &[DateTime<Utc>], policy: &RetentionPolicy) -> Vec<Vec<KeepReason>> {
+    let now = time(2026, 10, 18, 12);
+    apply_policy(times, policy, &now)
+}
+
+#[test]
+fn empty_policy() {
+    let policy = RetentionPolicy::default();
+    assert!(policy.is_empty());
+
+    let times = [time(2026, 10, 18, 0), time(2026, 10, 17, 0)];
+    assert_eq!(apply(&times, &policy), vec![vec![], vec![]]);
+}
+
+#[test]
+fn keep_last() {
+    let policy = RetentionPolicy {
+        keep_last: 2,
+        ..RetentionPolicy::default()
+    };
+    let times = [
+        time(2026, 10, 18, 0),
+        time(2026, 10, 17, 0),
+        time(2026, 10, 16, 0),
+    ];
+    assert_eq!(apply(&times, &policy), vec![vec![Last], vec![Last], vec![]]);
+}
+
+#[test]
+fn keep_daily() {
+    let policy = RetentionPolicy {
+        keep_daily: 2,
+        ..RetentionPolicy::default()
+    };
+    let times = [
+        time(2026, 10, 18, 6),
+        time(2026, 10, 18, 0),
+        time(2026, 10, 17, 6),
+        time(2026, 10, 16, 6),
+    ];
+    assert_eq!(
+        apply(&times, &policy),
+        vec![vec![Daily], vec![], vec![Daily], vec![]]
+    );
+}
+
+#[test]
+fn keep_weekly() {
+    let policy = RetentionPolicy {
+        keep_weekly: 2,
+        ..RetentionPolicy::default()
+    };
+    // 2026-10-12 is a Monday
+    let times = [
+        time(2026, 10, 13, 0),
+        time(2026, 10, 12, 0),
+        time(2026, 10, 11, 0),
+        time(2026, 10, 4, 0),
+    ];
+    assert_eq!(
+        apply(&times, &policy),
+        vec![vec![Weekly], vec![], vec![Weekly], vec![]]
+    );
+}
+
+#[test]
+fn keep_monthly_and_yearly() {
+    let policy = RetentionPolicy {
+        keep_monthly: 2,
+        keep_yearly: 2,
+        ..RetentionPolicy::default()
+    };
+    let times = [
+        time(2026, 10, 18, 0),
+        time(2026, 10, 1, 0),
+        time(2026, 9, 30, 0),
+        time(2025, 12, 31, 0),
+        time(2024, 12, 31, 0),
+    ];
+    assert_eq!(
+        apply(&times, &policy),
+        vec![
+            vec![Monthly, Yearly],
+            vec![],
+            vec![Monthly],
+            vec![Yearly],
+            vec![],
+        ]
+    );
+}
+
+#[test]
+fn keep_within() {
+    let policy = RetentionPolicy {
+        keep_within: Some(Duration::from_hours(24)),
+        ..RetentionPolicy::default()
+    };
+    let times = [
+        time(2026, 10, 19, 0),
+        time(2026, 10, 18, 0),
+        time(2026, 10, 17, 12),
+        time(2026, 10, 17, 0),
+    ];
+    assert_eq!(
+        apply(&times, &policy),
+        vec![vec![Within], vec![Within], vec![Within], vec![]]
+    );
+}
+
+#[test]
+fn keep_combined() {
+    let policy = RetentionPolicy {
+        keep_last: 1,
+        keep_daily: 2,
+        ..RetentionPolicy::default()
+    };
+    let times = [
+        time(2026, 10, 18, 6),
+        time(2026, 10, 18, 0),
+        time(2026, 10, 17, 0),
+    ];
+    assert_eq!(
+        apply(&times, &policy),
+        vec![vec![Last, Daily], vec![], vec![Daily]]
+    );
+}
+
+#[test]
+fn group_keys() {
+    let info = ArchiveInfo {
+        created: time(2026, 10, 18, 0),
+        name: None,
+        description: None,
+        tags: vec!["b".to_owned(), "a".to_owned()],
+        hostname: "laptop".to_owned(),
+        username: "alice".to_owned(),
+        paths: vec![PathBuf::from("/home/alice")],
+    };
+    assert_eq!(group_key(&info, &[]), "");
+    assert_eq!(group_key(&info, &[GroupBy::Host]), "host=laptop");
+    assert_eq!(
+        group_key(&info, &[GroupBy::Host, GroupBy::Tags, GroupBy::Name]),
+        "host=laptop tags=a,b name="
+    );
+}
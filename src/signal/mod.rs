@@ -0,0 +1,91 @@
+#[cfg(test)]
+mod tests;
+
+use std::process;
+
+use log::{error, warn};
+use tokio::sync::watch;
+
+/// Exit code of a process killed by SIGINT, which is used both when an interrupted command finishes
+/// and when a second signal forces an exit.
+pub const EXIT_CODE_INTERRUPTED: u8 = 130;
+
+/// Tracks whether the process has been asked to stop, so that a command can stop starting new work
+/// and finish what it's doing before exiting.
+#[derive(Debug, Clone)]
+pub struct Cancellation {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Cancellation {
+    /// Starts listening for SIGINT and SIGTERM in the background. The first signal cancels, and a
+    /// second one exits immediately.
+    pub fn listen() -> Self {
+        let (cancellation, canceller) = Cancellation::new();
+
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            warn!("interrupted, finishing files in progress (interrupt again to exit immediately)");
+            canceller.cancel();
+
+            wait_for_signal().await;
+            process::exit(i32::from(EXIT_CODE_INTERRUPTED));
+        });
+
+        cancellation
+    }
+
+    /// Returns a cancellation that is only cancelled by the returned `Canceller`.
+    pub fn new() -> (Self, Canceller) {
+        let (sender, receiver) = watch::channel(false);
+        (Cancellation { receiver }, Canceller { sender })
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+    }
+}
+
+/// Cancels the `Cancellation` it was created with, along with all of its clones.
+#[derive(Debug)]
+pub struct Canceller {
+    sender: watch::Sender<bool>,
+}
+
+impl Canceller {
+    pub fn cancel(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                () = wait_for_ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(err) => {
+            error!("failed to listen for SIGTERM ({err})");
+            wait_for_ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    wait_for_ctrl_c().await;
+}
+
+async fn wait_for_ctrl_c() {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        error!("failed to listen for interrupts ({err})");
+
+        // without a handler, interrupts keep their default behavior of exiting immediately
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,50 @@
+use std::process::ExitCode;
+
+use crate::error::{Error, handle_error};
+
+use super::{Cancellation, EXIT_CODE_INTERRUPTED};
+
+#[test]
+fn cancellation_starts_uncancelled() {
+    let (cancellation, _canceller) = Cancellation::new();
+    assert!(!cancellation.is_cancelled());
+}
+
+#[test]
+fn cancel_reaches_clones() {
+    let (cancellation, canceller) = Cancellation::new();
+    let clone = cancellation.clone();
+
+    canceller.cancel();
+    assert!(cancellation.is_cancelled());
+    assert!(clone.is_cancelled());
+
+    // cancelling again keeps it cancelled
+    canceller.cancel();
+    assert!(cancellation.is_cancelled());
+}
+
+#[test]
+fn cancellation_outlives_canceller() {
+    let (cancellation, canceller) = Cancellation::new();
+    canceller.cancel();
+    drop(canceller);
+    assert!(cancellation.is_cancelled());
+
+    let (cancellation, canceller) = Cancellation::new();
+    drop(canceller);
+    assert!(!cancellation.is_cancelled());
+}
+
+#[test]
+fn interrupted_exit_code() {
+    assert_eq!(
+        handle_error::<()>(Err(Error::Interrupted)),
+        ExitCode::from(EXIT_CODE_INTERRUPTED)
+    );
+    assert_eq!(
+        handle_error::<()>(Err(Error::EmptyBlock)),
+        ExitCode::FAILURE
+    );
+    assert_eq!(handle_error(Ok(())), ExitCode::SUCCESS);
+}
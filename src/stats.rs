@@ -3,7 +3,7 @@ use std::{ops::Deref, time::Duration};
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Serializer, ser::SerializeMap};
 
-use crate::{archive::Archive, block::Block, entity::Entity};
+use crate::{archive::Archive, entity::Entity, pack::Pack, tree::Tree};
 
 #[derive(Debug, Clone)]
 pub struct CommandStats {
@@ -14,12 +14,24 @@ pub struct CommandStats {
     pub bytes_written: u64,
     pub bytes_deleted: u64,
     pub files_read: u64,
+    pub files_inlined: u64,
     pub files_created: u64,
     pub archives_deleted: u64,
+    pub archives_copied: u64,
     pub blocks_downloaded: u64,
+    pub blocks_cached: u64,
     pub blocks_uploaded: u64,
     pub blocks_deleted: u64,
     pub blocks_referenced: u64,
+    pub blocks_repacked: u64,
+    pub packs_uploaded: u64,
+    pub packs_copied: u64,
+    pub packs_deleted: u64,
+    pub trees_uploaded: u64,
+    pub trees_deleted: u64,
+    pub archives_checked: u64,
+    pub blocks_checked: u64,
+    pub problems_found: u64,
 }
 
 impl CommandStats {
@@ -32,12 +44,24 @@ impl CommandStats {
             bytes_written: 0,
             bytes_deleted: 0,
             files_read: 0,
+            files_inlined: 0,
             files_created: 0,
             archives_deleted: 0,
+            archives_copied: 0,
             blocks_downloaded: 0,
+            blocks_cached: 0,
             blocks_uploaded: 0,
             blocks_deleted: 0,
             blocks_referenced: 0,
+            blocks_repacked: 0,
+            packs_uploaded: 0,
+            packs_copied: 0,
+            packs_deleted: 0,
+            trees_uploaded: 0,
+            trees_deleted: 0,
+            archives_checked: 0,
+            blocks_checked: 0,
+            problems_found: 0,
         }
     }
 
@@ -60,9 +84,15 @@ impl EntityStats<Archive> for CommandStats {
     }
 }
 
-impl EntityStats<Block> for CommandStats {
+impl EntityStats<Pack> for CommandStats {
     fn add_entities_deleted(&mut self, count: u64) {
-        self.blocks_deleted += count;
+        self.packs_deleted += count;
+    }
+}
+
+impl EntityStats<Tree> for CommandStats {
+    fn add_entities_deleted(&mut self, count: u64) {
+        self.trees_deleted += count;
     }
 }
 
@@ -71,6 +101,7 @@ impl EntityStats<Block> for CommandStats {
 pub enum RequestKind {
     Get,
     Put,
+    Copy,
     Delete,
 }
 
@@ -81,13 +112,18 @@ pub struct RequestInfo {
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub bytes: Option<u32>,
+    pub bytes: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub struct StorageStats {
     pub bytes_downloaded: u64,
     pub bytes_uploaded: u64,
+    /// Bytes copied from one object to another on the server, which are neither downloaded nor
+    /// uploaded.
+    pub bytes_copied: u64,
+    /// Number of failed requests that were sent again.
+    pub retries: u64,
     pub requests: Vec<RequestInfo>,
 }
 
@@ -96,11 +132,13 @@ impl StorageStats {
         StorageStats {
             bytes_downloaded: 0,
             bytes_uploaded: 0,
+            bytes_copied: 0,
+            retries: 0,
             requests: Vec::new(),
         }
     }
 
-    pub fn add_get(&mut self, start_time: DateTime<Utc>, end_time: DateTime<Utc>, bytes: u32) {
+    pub fn add_get(&mut self, start_time: DateTime<Utc>, end_time: DateTime<Utc>, bytes: u64) {
         let stats = RequestInfo {
             kind: RequestKind::Get,
             start_time,
@@ -108,11 +146,11 @@ impl StorageStats {
             bytes: Some(bytes),
         };
 
-        self.bytes_downloaded += u64::from(bytes);
+        self.bytes_downloaded += bytes;
         self.requests.push(stats);
     }
 
-    pub fn add_put(&mut self, start_time: DateTime<Utc>, end_time: DateTime<Utc>, bytes: u32) {
+    pub fn add_put(&mut self, start_time: DateTime<Utc>, end_time: DateTime<Utc>, bytes: u64) {
         let stats = RequestInfo {
             kind: RequestKind::Put,
             start_time,
@@ -120,7 +158,19 @@ impl StorageStats {
             bytes: Some(bytes),
         };
 
-        self.bytes_uploaded += u64::from(bytes);
+        self.bytes_uploaded += bytes;
+        self.requests.push(stats);
+    }
+
+    pub fn add_copy(&mut self, start_time: DateTime<Utc>, end_time: DateTime<Utc>, bytes: u64) {
+        let stats = RequestInfo {
+            kind: RequestKind::Copy,
+            start_time,
+            end_time,
+            bytes: Some(bytes),
+        };
+
+        self.bytes_copied += bytes;
         self.requests.push(stats);
     }
 
@@ -134,6 +184,19 @@ impl StorageStats {
 
         self.requests.push(stats);
     }
+
+    pub fn add_retry(&mut self) {
+        self.retries += 1;
+    }
+
+    /// Adds the stats of another storage, for commands that use more than one.
+    pub fn merge(&mut self, other: StorageStats) {
+        self.bytes_downloaded += other.bytes_downloaded;
+        self.bytes_uploaded += other.bytes_uploaded;
+        self.bytes_copied += other.bytes_copied;
+        self.retries += other.retries;
+        self.requests.extend(other.requests);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -188,16 +251,30 @@ impl Serialize for FinalizedCommandStats {
             &self.metadata_bytes_downloaded(),
         )?;
         map.serialize_entry("metadata_bytes_uploaded", &self.metadata_bytes_uploaded())?;
+        map.serialize_entry("bytes_copied", &self.storage.bytes_copied)?;
         map.serialize_entry("bytes_read", &self.bytes_read)?;
         map.serialize_entry("bytes_written", &self.bytes_written)?;
         map.serialize_entry("bytes_deleted", &self.bytes_deleted)?;
         map.serialize_entry("files_read", &self.files_read)?;
+        map.serialize_entry("files_inlined", &self.files_inlined)?;
         map.serialize_entry("files_created", &self.files_created)?;
         map.serialize_entry("archives_deleted", &self.archives_deleted)?;
+        map.serialize_entry("archives_copied", &self.archives_copied)?;
         map.serialize_entry("blocks_downloaded", &self.blocks_downloaded)?;
+        map.serialize_entry("blocks_cached", &self.blocks_cached)?;
         map.serialize_entry("blocks_uploaded", &self.blocks_uploaded)?;
         map.serialize_entry("blocks_deleted", &self.blocks_deleted)?;
         map.serialize_entry("blocks_referenced", &self.blocks_referenced)?;
+        map.serialize_entry("blocks_repacked", &self.blocks_repacked)?;
+        map.serialize_entry("packs_uploaded", &self.packs_uploaded)?;
+        map.serialize_entry("packs_copied", &self.packs_copied)?;
+        map.serialize_entry("packs_deleted", &self.packs_deleted)?;
+        map.serialize_entry("trees_uploaded", &self.trees_uploaded)?;
+        map.serialize_entry("trees_deleted", &self.trees_deleted)?;
+        map.serialize_entry("archives_checked", &self.archives_checked)?;
+        map.serialize_entry("blocks_checked", &self.blocks_checked)?;
+        map.serialize_entry("problems_found", &self.problems_found)?;
+        map.serialize_entry("retries", &self.storage.retries)?;
         map.serialize_entry("requests", &self.storage.requests)?;
 
         map.end()
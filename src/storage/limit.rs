@@ -0,0 +1,73 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::time::sleep;
+
+/// Limits on how fast data is transferred and requests are sent, in total across every task that
+/// shares the storage.
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(clippy::struct_field_names)]
+pub struct RateLimits {
+    pub upload_bytes_per_second: Option<u64>,
+    pub download_bytes_per_second: Option<u64>,
+    pub requests_per_second: Option<u64>,
+}
+
+/// Tokens that refill at a constant rate, up to one second's worth.
+///
+/// Taking more tokens than are available puts the bucket into debt instead of failing, and the
+/// caller waits until the debt would be paid off. This lets a single object larger than the bucket
+/// through while keeping the average rate at the limit.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket that refills at `rate` tokens per second.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        TokenBucket {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `amount` tokens, waiting if the bucket doesn't have enough.
+    pub async fn acquire(&self, amount: u64) {
+        let wait = self.take(amount, Instant::now());
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+
+    /// Takes `amount` tokens at `now`, returning how long to wait before using them.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn take(&self, amount: u64, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(state.updated);
+        state.tokens = (state.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        state.updated = now;
+        state.tokens -= amount as f64;
+
+        if state.tokens < 0.0 {
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
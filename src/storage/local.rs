@@ -0,0 +1,122 @@
+use std::{
+    fs::{self as std_fs, File},
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use tokio::{fs, task::spawn_blocking};
+
+use crate::{
+    cache::write_atomic,
+    error::{Error, Result},
+};
+
+use super::ObjectInfo;
+
+/// Suffix of the temporary files that objects are written to before being renamed into place.
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// Repository stored in a local directory, with each object in a file at its key.
+#[derive(Debug, Clone)]
+pub struct LocalDir {
+    root: PathBuf,
+}
+
+impl LocalDir {
+    pub fn new(root: PathBuf) -> Self {
+        LocalDir { root }
+    }
+
+    pub async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(fs::try_exists(self.path(key)).await?)
+    }
+
+    /// Lists the objects whose keys start with `prefix`, sorted by key as S3 lists them. Only the
+    /// directory that holds the prefix is walked, and unfinished writes are skipped.
+    pub async fn list(&self, prefix: &str) -> Result<Vec<ObjectInfo>> {
+        let root = self.root.clone();
+        let prefix = prefix.to_owned();
+
+        spawn_blocking(move || {
+            let base = prefix.rfind('/').map_or("", |end| &prefix[..=end]);
+            let mut objects = vec![];
+            walk(&root.join(base), base, &mut objects)?;
+
+            objects.retain(|object| object.key.starts_with(&prefix));
+            objects.sort_by(|a, b| a.key.cmp(&b.key));
+            Ok(objects)
+        })
+        .await?
+    }
+
+    /// Reads the object with `key`, or only the bytes in `range` of it.
+    pub async fn get(&self, key: &str, range: Option<Range<u64>>) -> Result<Vec<u8>> {
+        let path = self.path(key);
+        let result = spawn_blocking(move || read(&path, range)).await?;
+
+        match result {
+            Ok(bytes) => Ok(bytes),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                Err(Error::ItemNotFound(key.to_owned()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        Ok(write_atomic(&self.path(key), bytes).await?)
+    }
+
+    /// Deletes the object with `key`, which like in S3 succeeds if there's no such object.
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.path(key)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+/// Adds the files beneath `dir`, whose keys start with `base`, to `objects`. A missing directory
+/// just has no objects.
+fn walk(dir: &Path, base: &str, objects: &mut Vec<ObjectInfo>) -> io::Result<()> {
+    let entries = match std_fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let key = format!("{base}{name}");
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), &format!("{key}/"), objects)?;
+        } else if file_type.is_file() && !name.ends_with(TEMP_SUFFIX) {
+            let size = entry.metadata()?.len();
+            objects.push(ObjectInfo { key, size });
+        }
+    }
+
+    Ok(())
+}
+
+fn read(path: &Path, range: Option<Range<u64>>) -> io::Result<Vec<u8>> {
+    let Some(range) = range else {
+        return std_fs::read(path);
+    };
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(range.start))?;
+    let mut bytes = vec![];
+    file.take(range.end - range.start).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
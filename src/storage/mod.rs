@@ -0,0 +1,1055 @@
+#[cfg(test)]
+mod tests;
+
+mod limit;
+mod local;
+mod object;
+mod repo;
+mod retry;
+
+use std::{
+    ops::Range,
+    pin::pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use async_stream::try_stream;
+use aws_config::{Region, retry::RetryConfig};
+use aws_sdk_s3::{
+    Client,
+    config::http::HttpResponse,
+    error::{DisplayErrorContext, ProvideErrorMetadata, SdkError},
+    operation::{get_object::GetObjectError, head_object::HeadObjectError},
+    primitives::ByteStream,
+    types::{
+        CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier, ServerSideEncryption,
+        TaggingDirective,
+    },
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
+use bytes::Bytes;
+use chrono::Utc;
+use itertools::Itertools;
+use log::{debug, warn};
+use tokio::{task::spawn_blocking, time::sleep};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{
+    arc::unarc,
+    cache::MetadataCache,
+    error::{Error, Result},
+    prefix::{find_one_by_prefix, longest_common_prefix},
+    stats::StorageStats,
+};
+
+use self::{
+    limit::TokenBucket,
+    local::LocalDir,
+    object::{ObjectKind, copy_source},
+};
+
+pub use self::{
+    limit::RateLimits,
+    object::ObjectOptions,
+    repo::{RepoLocation, RepoUrl},
+    retry::{RetryPolicy, RetryableError},
+};
+
+pub const MAX_KEYS_PER_REQUEST: usize = 1000;
+
+/// Objects larger than this are uploaded in parts, which keeps each request well under S3's limit
+/// for a single upload and only needs one part to be copied at a time for retries.
+const MULTIPART_THRESHOLD: usize = 32 << 20;
+const MULTIPART_PART_SIZE: usize = 16 << 20;
+
+const STATUS_NOT_MODIFIED: u16 = 304;
+const STATUS_FORBIDDEN: u16 = 403;
+
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+}
+
+/// Settings for the S3 client that override the ones from the environment and AWS config files.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    pub endpoint_url: Option<String>,
+    pub region: Option<String>,
+    /// Profile in the AWS config files to load credentials and other settings from
+    pub profile_name: Option<String>,
+}
+
+impl ClientOptions {
+    /// Returns whether clients with these options and `other` send requests to the same endpoint
+    /// and region with the same credentials, so that either one can copy objects between their
+    /// repositories.
+    pub fn same_endpoint(&self, other: &ClientOptions) -> bool {
+        self.endpoint_url == other.endpoint_url
+            && self.region == other.region
+            && self.profile_name == other.profile_name
+    }
+}
+
+/// Repository in S3, along with the client to send requests for it with.
+#[derive(Debug)]
+struct S3Repo {
+    client: Client,
+    repo: RepoUrl,
+}
+
+/// Where the objects of a repository are stored.
+#[derive(Debug)]
+enum Backend {
+    S3(S3Repo),
+    Local(LocalDir),
+}
+
+#[derive(Debug)]
+pub struct Storage {
+    backend: Backend,
+    cache: Option<MetadataCache>,
+    retry_policy: RetryPolicy,
+    upload_limit: Option<TokenBucket>,
+    download_limit: Option<TokenBucket>,
+    request_limit: Option<TokenBucket>,
+    objects: ObjectOptions,
+    tagging: Option<String>,
+    stats: Arc<Mutex<StorageStats>>,
+}
+
+impl Storage {
+    /// Creates the storage for the repository at `location`. The client options only apply to
+    /// repositories in S3, and the cache is only used for them, since local objects are read
+    /// directly.
+    pub async fn new(
+        location: RepoLocation,
+        client_options: ClientOptions,
+        cache: Option<MetadataCache>,
+        retry_policy: RetryPolicy,
+        limits: RateLimits,
+        objects: ObjectOptions,
+    ) -> Self {
+        let backend = match location {
+            RepoLocation::S3(repo) => {
+                let client = create_client(client_options).await;
+                Backend::S3(S3Repo { client, repo })
+            }
+            RepoLocation::Local(dir) => Backend::Local(LocalDir::new(dir)),
+        };
+        let stats = Arc::new(Mutex::new(StorageStats::new()));
+        let tagging = objects.tagging();
+
+        Storage {
+            backend,
+            cache,
+            retry_policy,
+            upload_limit: limits.upload_bytes_per_second.map(TokenBucket::new),
+            download_limit: limits.download_bytes_per_second.map(TokenBucket::new),
+            request_limit: limits.requests_per_second.map(TokenBucket::new),
+            objects,
+            tagging,
+            stats,
+        }
+    }
+
+    /// Opens the repository at `repo` with the client settings from the environment and the
+    /// default retry policy, without a metadata cache or rate limits.
+    pub async fn open(repo: RepoUrl) -> Self {
+        Storage::new(
+            RepoLocation::S3(repo),
+            ClientOptions::default(),
+            None,
+            RetryPolicy::default(),
+            RateLimits::default(),
+            ObjectOptions::default(),
+        )
+        .await
+    }
+}
+
+impl Storage {
+    /// Returns whether an object with `key` exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after any retries.
+    #[allow(dead_code)]
+    pub async fn exists(&self, key: &str) -> Result<bool> {
+        let s3 = match &self.backend {
+            Backend::S3(s3) => s3,
+            Backend::Local(dir) => return dir.exists(key).await,
+        };
+
+        let start_time = Utc::now();
+        let response_result = self
+            .send_with_retry(|| {
+                s3.client
+                    .head_object()
+                    .bucket(&s3.repo.bucket)
+                    .key(s3.repo.object_key(key))
+                    .send()
+            })
+            .await
+            .map_err(SdkError::into_service_error);
+
+        let exists = match response_result {
+            Ok(_) => Ok(true),
+            Err(HeadObjectError::NotFound(_)) => Ok(false),
+            Err(err) => Err(Error::other(err)),
+        }?;
+
+        let end_time = Utc::now();
+        self.record().add_get(start_time, end_time, 0);
+        Ok(exists)
+    }
+
+    pub fn objects_paginated<'a>(
+        &'a self,
+        prefix: Option<&'a str>,
+    ) -> impl Stream<Item = Result<Vec<ObjectInfo>>> + 'a {
+        try_stream! {
+            let s3 = match &self.backend {
+                Backend::S3(s3) => s3,
+                Backend::Local(dir) => {
+                    let start_time = Utc::now();
+                    let objects = dir.list(prefix.unwrap_or_default()).await?;
+                    let end_time = Utc::now();
+                    self.record().add_get(start_time, end_time, 0);
+
+                    // pages are the same size as S3's, so that callers handle them the same way
+                    for page in objects.chunks(MAX_KEYS_PER_REQUEST) {
+                        yield page.to_vec();
+                    }
+                    return;
+                }
+            };
+
+            let mut maybe_token = None;
+
+            loop {
+                let start_time = Utc::now();
+                let page = self
+                    .send_with_retry(|| {
+                        s3.client
+                            .list_objects_v2()
+                            .bucket(&s3.repo.bucket)
+                            .prefix(s3.repo.object_key(prefix.unwrap_or_default()))
+                            .set_continuation_token(maybe_token.clone())
+                            .send()
+                    })
+                    .await?;
+                let end_time = Utc::now();
+
+                let mut objects = vec![];
+                let mut size = 0;
+
+                let contents = page.contents.unwrap_or(vec![]);
+                for object in contents {
+                    let object_key = object.key.ok_or_else(|| Error::InvalidKey(String::new()))?;
+                    let key = s3.repo.repo_key(object_key)?;
+                    let object_size = u64::try_from(object.size.unwrap_or(0)).unwrap_or(0);
+                    size += object_size;
+
+                    let object_info = ObjectInfo {
+                        key,
+                        size: object_size,
+                    };
+                    objects.push(object_info);
+                }
+
+                self.record().add_get(start_time, end_time, size);
+
+                yield objects;
+
+                maybe_token = page.next_continuation_token;
+                if !page.is_truncated.unwrap_or(false) || maybe_token.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn keys_paginated<'a>(
+        &'a self,
+        prefix: Option<&'a str>,
+    ) -> impl Stream<Item = Result<Vec<String>>> + 'a {
+        self.objects_paginated(prefix).map(|result| {
+            result.map(|objects| {
+                objects
+                    .into_iter()
+                    .map(|object| object.key)
+                    .collect::<Vec<_>>()
+            })
+        })
+    }
+
+    pub fn keys<'a>(&'a self, prefix: Option<&'a str>) -> impl Stream<Item = Result<String>> + 'a {
+        try_stream! {
+            let mut pages = pin!(self.keys_paginated(prefix));
+            while let Some(keys) = pages.try_next().await? {
+                for key in keys {
+                    yield key;
+                }
+            }
+        }
+    }
+
+    /// Lists the keys that start with `prefix`, or every key in the repository.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the list requests fails.
+    pub async fn keys_vec(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+        self.keys(prefix).collect().await
+    }
+
+    /// Returns the only key that starts with `prefix`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no key or more than one key starts with `prefix`, or if listing the keys
+    /// fails.
+    pub async fn expand_key(&self, prefix: &str) -> Result<String> {
+        let keys = self.keys_vec(Some(prefix)).await?;
+        match &keys[..] {
+            [key] => Ok(key.clone()),
+            [] => Err(Error::NoItemForPrefix(prefix.to_owned())),
+            _ => Err(Error::MultipleItemsForPrefix(prefix.to_owned())),
+        }
+    }
+
+    /// Returns the only key that starts with each of `prefixes`, listing the keys once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no key or more than one key starts with any of the prefixes, or if
+    /// listing the keys fails.
+    pub async fn expand_keys<S, I>(&self, prefixes: I) -> Result<Vec<String>>
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = S>,
+    {
+        let prefixes = prefixes.into_iter().collect::<Vec<_>>();
+        let common_prefix = longest_common_prefix(&prefixes);
+        let keys = self.keys_vec(common_prefix).await?;
+        let mut matching_keys = vec![];
+
+        for prefix in &prefixes {
+            let matching_key = find_one_by_prefix(&keys, prefix.as_ref())?;
+            matching_keys.push(matching_key.to_owned());
+        }
+
+        Ok(matching_keys)
+    }
+
+    /// Downloads the object with `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ItemNotFound`] if there's no such object, or another error if the request
+    /// fails.
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.get_object(key, None).await
+    }
+
+    /// Downloads the bytes in `range` of the object with `key`. An empty range is returned without
+    /// a request, since it can't be expressed as a `Range` header.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ItemNotFound`] if there's no such object, or another error if the request
+    /// fails.
+    pub async fn get_range(&self, key: &str, range: Range<u64>) -> Result<Vec<u8>> {
+        if range.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.get_object(key, Some(range)).await
+    }
+
+    async fn get_object(&self, key: &str, range: Option<Range<u64>>) -> Result<Vec<u8>> {
+        let start_time = Utc::now();
+        let s3 = match &self.backend {
+            Backend::S3(s3) => s3,
+            Backend::Local(dir) => {
+                let bytes = dir.get(key, range).await?;
+                let end_time = Utc::now();
+                self.record()
+                    .add_get(start_time, end_time, bytes.len() as u64);
+                return Ok(bytes);
+            }
+        };
+
+        let range_header = range.map(|range| format!("bytes={}-{}", range.start, range.end - 1));
+        let response = self
+            .send_with_retry(|| {
+                s3.client
+                    .get_object()
+                    .bucket(&s3.repo.bucket)
+                    .key(s3.repo.object_key(key))
+                    .set_range(range_header.clone())
+                    .send()
+            })
+            .await
+            .map_err(|err| match err.into_service_error() {
+                GetObjectError::NoSuchKey(_) => Error::ItemNotFound(key.to_owned()),
+                err => Error::other(err),
+            })?;
+        let bytes = self.read_body(response.body).await?;
+
+        let end_time = Utc::now();
+        let size = bytes.len() as u64;
+        self.record().add_get(start_time, end_time, size);
+        Ok(bytes)
+    }
+
+    /// Downloads the object with `key`, returning `None` if there's no such object.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after any retries.
+    pub async fn try_get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.get(key).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(Error::ItemNotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Gets an object like `try_get`, but keeps a local copy of it and skips the download if the
+    /// object hasn't changed since the copy was made.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after any retries.
+    pub async fn try_get_cached(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let (Backend::S3(s3), Some(cache)) = (&self.backend, &self.cache) else {
+            return self.try_get(key).await;
+        };
+
+        // corrupted entries are dropped by the cache, so the object is downloaded again in full
+        let maybe_cached = cache.get(key).await;
+        let maybe_etag = maybe_cached.as_ref().map(|cached| cached.etag.clone());
+
+        let start_time = Utc::now();
+        let response_result = self
+            .send_with_retry(|| {
+                s3.client
+                    .get_object()
+                    .bucket(&s3.repo.bucket)
+                    .key(s3.repo.object_key(key))
+                    .set_if_none_match(maybe_etag.clone())
+                    .send()
+            })
+            .await;
+
+        let response = match response_result {
+            Ok(response) => response,
+            Err(err)
+                if err
+                    .raw_response()
+                    .is_some_and(|response| response.status().as_u16() == STATUS_NOT_MODIFIED) =>
+            {
+                let end_time = Utc::now();
+                self.record().add_get(start_time, end_time, 0);
+                return Ok(maybe_cached.map(|cached| cached.bytes));
+            }
+            Err(err) => {
+                return match err.into_service_error() {
+                    GetObjectError::NoSuchKey(_) => {
+                        cache.remove(key).await;
+                        Ok(None)
+                    }
+                    err => Err(Error::other(err)),
+                };
+            }
+        };
+
+        let maybe_etag = response.e_tag.clone();
+        let bytes = self.read_body(response.body).await?;
+
+        let end_time = Utc::now();
+        let size = bytes.len() as u64;
+        self.record().add_get(start_time, end_time, size);
+
+        if let Some(etag) = maybe_etag {
+            cache.put(key, &etag, &bytes).await;
+        }
+
+        Ok(Some(bytes))
+    }
+
+    /// Uploads `bytes` as the object with `key`, in parts if it's large.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any request fails after any retries.
+    pub async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<()> {
+        self.put_object(key, bytes).await?;
+        Ok(())
+    }
+
+    /// Puts an object like `put`, but also keeps a local copy of it for `try_get_cached`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any request fails after any retries.
+    pub async fn put_cached(&self, key: &str, bytes: Vec<u8>) -> Result<()> {
+        let Some(cache) = &self.cache else {
+            return self.put(key, bytes).await;
+        };
+
+        let maybe_etag = self.put_object(key, bytes.clone()).await?;
+        if let Some(etag) = maybe_etag {
+            cache.put(key, &etag, &bytes).await;
+        }
+
+        Ok(())
+    }
+
+    /// Puts an object, returning its `ETag`.
+    async fn put_object(&self, key: &str, bytes: Vec<u8>) -> Result<Option<String>> {
+        let s3 = match &self.backend {
+            Backend::S3(s3) => s3,
+            Backend::Local(dir) => {
+                let start_time = Utc::now();
+                dir.put(key, &bytes).await?;
+                let end_time = Utc::now();
+                self.record()
+                    .add_put(start_time, end_time, bytes.len() as u64);
+                return Ok(None);
+            }
+        };
+
+        if bytes.len() > MULTIPART_THRESHOLD {
+            return self.put_multipart(s3, key, bytes).await;
+        }
+
+        let size = bytes.len() as u64;
+        let (bytes, encoded_digest) = spawn_blocking(move || {
+            let encoded_digest = md5_base64(&bytes);
+            (bytes, encoded_digest)
+        })
+        .await?;
+        // converted once so that retries share the buffer instead of copying it
+        let bytes = Bytes::from(bytes);
+
+        let kind = ObjectKind::from_key(key);
+        let start_time = Utc::now();
+        let response = self
+            .send_with_retry(|| async {
+                self.acquire_upload(size).await;
+                s3.client
+                    .put_object()
+                    .bucket(&s3.repo.bucket)
+                    .key(s3.repo.object_key(key))
+                    .body(bytes.clone().into())
+                    .content_md5(&encoded_digest)
+                    .set_storage_class(self.objects.storage_class(kind))
+                    .set_server_side_encryption(self.server_side_encryption())
+                    .set_ssekms_key_id(self.objects.sse_kms_key_id.clone())
+                    .set_tagging(self.tagging.clone())
+                    .send()
+                    .await
+            })
+            .await?;
+
+        let end_time = Utc::now();
+        self.record().add_put(start_time, end_time, size);
+        Ok(response.e_tag)
+    }
+
+    /// Waits until `size` bytes can be uploaded under the upload limit. This is done for each
+    /// attempt, since a retry sends the whole body again.
+    async fn acquire_upload(&self, size: u64) {
+        if let Some(limit) = &self.upload_limit {
+            limit.acquire(size).await;
+        }
+    }
+
+    /// Returns the encryption to request for new objects, which is only set when objects are
+    /// encrypted with a specific KMS key. Otherwise the bucket's default encryption applies.
+    fn server_side_encryption(&self) -> Option<ServerSideEncryption> {
+        self.objects
+            .sse_kms_key_id
+            .as_ref()
+            .map(|_| ServerSideEncryption::AwsKms)
+    }
+
+    /// Puts an object in parts, each with its own checksum. If any part fails, the upload is
+    /// aborted so that S3 doesn't keep the parts that were already uploaded.
+    async fn put_multipart(
+        &self,
+        s3: &S3Repo,
+        key: &str,
+        bytes: Vec<u8>,
+    ) -> Result<Option<String>> {
+        let (bytes, part_digests) = spawn_blocking(move || {
+            let part_digests = part_ranges(bytes.len() as u64)
+                .map(|range| md5_base64(&bytes[usize_range(range)]))
+                .collect::<Vec<_>>();
+            (Bytes::from(bytes), part_digests)
+        })
+        .await?;
+
+        let upload_id = self.create_multipart(s3, key).await?;
+        let result = self
+            .put_parts(s3, key, &upload_id, &bytes, &part_digests)
+            .await;
+        if result.is_err() {
+            self.abort_multipart(s3, key, &upload_id).await;
+        }
+
+        result
+    }
+
+    async fn put_parts(
+        &self,
+        s3: &S3Repo,
+        key: &str,
+        upload_id: &str,
+        bytes: &Bytes,
+        part_digests: &[String],
+    ) -> Result<Option<String>> {
+        let mut parts = vec![];
+
+        let ranges = part_ranges(bytes.len() as u64).zip(part_digests);
+        for (part_number, (range, encoded_digest)) in (1..).zip(ranges) {
+            let size = range.end - range.start;
+            // slicing shares the buffer, so retries don't copy the part
+            let part = bytes.slice(usize_range(range));
+
+            let start_time = Utc::now();
+            let response = self
+                .send_with_retry(|| async {
+                    self.acquire_upload(size).await;
+                    s3.client
+                        .upload_part()
+                        .bucket(&s3.repo.bucket)
+                        .key(s3.repo.object_key(key))
+                        .upload_id(upload_id)
+                        .part_number(part_number)
+                        .body(part.clone().into())
+                        .content_md5(encoded_digest)
+                        .send()
+                        .await
+                })
+                .await?;
+
+            let end_time = Utc::now();
+            self.record().add_put(start_time, end_time, size);
+
+            let completed_part = CompletedPart::builder()
+                .set_e_tag(response.e_tag)
+                .part_number(part_number)
+                .build();
+            parts.push(completed_part);
+        }
+
+        self.complete_multipart(s3, key, upload_id, parts).await
+    }
+
+    /// Copies the object with `key` from `source` without downloading it, which needs both
+    /// repositories to be in S3 and reachable with this storage's client. The copy gets this
+    /// storage's settings for new objects rather than those of the source object.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CopyDenied`] if either repository isn't in S3 or this storage's
+    /// credentials aren't allowed to read the source, or another error if any request fails after
+    /// any retries.
+    pub async fn copy_from(&self, source: &Storage, key: &str, size: u64) -> Result<()> {
+        let (Backend::S3(s3), Backend::S3(source)) = (&self.backend, &source.backend) else {
+            return Err(Error::CopyDenied(key.to_owned()));
+        };
+
+        let copy_source = copy_source(&source.repo.bucket, &source.repo.object_key(key));
+        if size > MULTIPART_THRESHOLD as u64 {
+            return self.copy_multipart(s3, key, &copy_source, size).await;
+        }
+
+        let kind = ObjectKind::from_key(key);
+        let start_time = Utc::now();
+        self.send_with_retry(|| {
+            s3.client
+                .copy_object()
+                .bucket(&s3.repo.bucket)
+                .key(s3.repo.object_key(key))
+                .copy_source(&copy_source)
+                .set_storage_class(self.objects.storage_class(kind))
+                .set_server_side_encryption(self.server_side_encryption())
+                .set_ssekms_key_id(self.objects.sse_kms_key_id.clone())
+                .tagging_directive(TaggingDirective::Replace)
+                .set_tagging(self.tagging.clone())
+                .send()
+        })
+        .await
+        .map_err(|err| copy_error(err, key))?;
+
+        let end_time = Utc::now();
+        self.record().add_copy(start_time, end_time, size);
+        Ok(())
+    }
+
+    /// Copies an object in parts, like `put_multipart` uploads one.
+    async fn copy_multipart(
+        &self,
+        s3: &S3Repo,
+        key: &str,
+        copy_source: &str,
+        size: u64,
+    ) -> Result<()> {
+        let upload_id = self.create_multipart(s3, key).await?;
+        let result = self
+            .copy_parts(s3, key, &upload_id, copy_source, size)
+            .await;
+        if result.is_err() {
+            self.abort_multipart(s3, key, &upload_id).await;
+        }
+
+        result
+    }
+
+    async fn copy_parts(
+        &self,
+        s3: &S3Repo,
+        key: &str,
+        upload_id: &str,
+        copy_source: &str,
+        size: u64,
+    ) -> Result<()> {
+        let mut parts = vec![];
+
+        for (part_number, range) in (1..).zip(part_ranges(size)) {
+            let part_size = range.end - range.start;
+            let range = format!("bytes={}-{}", range.start, range.end - 1);
+
+            let start_time = Utc::now();
+            let response = self
+                .send_with_retry(|| {
+                    s3.client
+                        .upload_part_copy()
+                        .bucket(&s3.repo.bucket)
+                        .key(s3.repo.object_key(key))
+                        .upload_id(upload_id)
+                        .part_number(part_number)
+                        .copy_source(copy_source)
+                        .copy_source_range(&range)
+                        .send()
+                })
+                .await
+                .map_err(|err| copy_error(err, key))?;
+
+            let end_time = Utc::now();
+            self.record().add_copy(start_time, end_time, part_size);
+
+            let completed_part = CompletedPart::builder()
+                .set_e_tag(response.copy_part_result.and_then(|result| result.e_tag))
+                .part_number(part_number)
+                .build();
+            parts.push(completed_part);
+        }
+
+        self.complete_multipart(s3, key, upload_id, parts).await?;
+        Ok(())
+    }
+
+    /// Starts a multipart upload with the settings for new objects, returning its ID.
+    async fn create_multipart(&self, s3: &S3Repo, key: &str) -> Result<String> {
+        let kind = ObjectKind::from_key(key);
+        let start_time = Utc::now();
+        let response = self
+            .send_with_retry(|| {
+                s3.client
+                    .create_multipart_upload()
+                    .bucket(&s3.repo.bucket)
+                    .key(s3.repo.object_key(key))
+                    .set_storage_class(self.objects.storage_class(kind))
+                    .set_server_side_encryption(self.server_side_encryption())
+                    .set_ssekms_key_id(self.objects.sse_kms_key_id.clone())
+                    .set_tagging(self.tagging.clone())
+                    .send()
+            })
+            .await?;
+
+        let end_time = Utc::now();
+        self.record().add_put(start_time, end_time, 0);
+
+        response
+            .upload_id
+            .ok_or_else(|| Error::MissingUploadId(key.to_owned()))
+    }
+
+    /// Completes a multipart upload from its parts, returning the `ETag` of the object.
+    async fn complete_multipart(
+        &self,
+        s3: &S3Repo,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+    ) -> Result<Option<String>> {
+        let upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(parts))
+            .build();
+
+        let start_time = Utc::now();
+        let response = self
+            .send_with_retry(|| {
+                s3.client
+                    .complete_multipart_upload()
+                    .bucket(&s3.repo.bucket)
+                    .key(s3.repo.object_key(key))
+                    .upload_id(upload_id)
+                    .multipart_upload(upload.clone())
+                    .send()
+            })
+            .await?;
+
+        let end_time = Utc::now();
+        self.record().add_put(start_time, end_time, 0);
+        Ok(response.e_tag)
+    }
+
+    /// Aborts a multipart upload after a failure. Failing to do so only leaves parts behind until
+    /// a lifecycle rule removes them, so the error is logged instead of returned.
+    async fn abort_multipart(&self, s3: &S3Repo, key: &str, upload_id: &str) {
+        let start_time = Utc::now();
+        let result = self
+            .send_with_retry(|| {
+                s3.client
+                    .abort_multipart_upload()
+                    .bucket(&s3.repo.bucket)
+                    .key(s3.repo.object_key(key))
+                    .upload_id(upload_id)
+                    .send()
+            })
+            .await;
+
+        match result {
+            Ok(_) => {
+                let end_time = Utc::now();
+                self.record().add_delete(start_time, end_time);
+            }
+            Err(err) => {
+                warn!(
+                    "failed to abort upload of `{key}` ({})",
+                    DisplayErrorContext(&err)
+                );
+            }
+        }
+    }
+
+    /// Deletes the object with `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after any retries.
+    #[allow(dead_code)]
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        let start_time = Utc::now();
+        let s3 = match &self.backend {
+            Backend::S3(s3) => s3,
+            Backend::Local(dir) => {
+                dir.delete(key).await?;
+                let end_time = Utc::now();
+                self.record().add_delete(start_time, end_time);
+                return Ok(());
+            }
+        };
+        self.send_with_retry(|| {
+            s3.client
+                .delete_object()
+                .bucket(&s3.repo.bucket)
+                .key(s3.repo.object_key(key))
+                .send()
+        })
+        .await?;
+
+        let end_time = Utc::now();
+        self.record().add_delete(start_time, end_time);
+        Ok(())
+    }
+
+    /// Deletes the objects with `keys`, in as many requests as needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the requests fails. The objects in earlier requests are deleted
+    /// anyway.
+    pub async fn delete_many<S, I>(&self, keys: I) -> Result<()>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+    {
+        for keys in &keys.into_iter().chunks(MAX_KEYS_PER_REQUEST) {
+            self.delete_chunk(keys).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the objects with `keys` in a single request, which S3 limits to
+    /// `MAX_KEYS_PER_REQUEST` keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request fails after any retries.
+    pub async fn delete_chunk<S, I>(&self, keys: I) -> Result<()>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = S>,
+    {
+        let s3 = match &self.backend {
+            Backend::S3(s3) => s3,
+            Backend::Local(dir) => {
+                let start_time = Utc::now();
+                for key in keys {
+                    dir.delete(&key.into()).await?;
+                }
+                let end_time = Utc::now();
+                self.record().add_delete(start_time, end_time);
+                return Ok(());
+            }
+        };
+
+        let mut delete_builder = Delete::builder().quiet(true);
+        for key in keys {
+            let object = ObjectIdentifier::builder()
+                .key(s3.repo.object_key(&key.into()))
+                .build()?;
+            delete_builder = delete_builder.objects(object);
+        }
+
+        let delete = delete_builder.build()?;
+
+        let start_time = Utc::now();
+        self.send_with_retry(|| {
+            s3.client
+                .delete_objects()
+                .bucket(&s3.repo.bucket)
+                .delete(delete.clone())
+                .send()
+        })
+        .await?;
+
+        let end_time = Utc::now();
+        self.record().add_delete(start_time, end_time);
+        Ok(())
+    }
+
+    /// Sends the request built by `send`, building and sending it again if it fails with an error
+    /// that the retry policy allows retrying.
+    async fn send_with_retry<T, E, F, Fut>(
+        &self,
+        mut send: F,
+    ) -> std::result::Result<T, SdkError<E, HttpResponse>>
+    where
+        E: ProvideErrorMetadata + std::error::Error + 'static,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, SdkError<E, HttpResponse>>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            if let Some(limit) = &self.request_limit {
+                limit.acquire(1).await;
+            }
+
+            // the SDK's request futures are large, so they're kept off the callers' futures
+            match Box::pin(send()).await {
+                Err(err) if self.retry_policy.should_retry(&err, attempt) => {
+                    debug!(
+                        "retrying request after attempt {attempt} failed ({})",
+                        DisplayErrorContext(&err)
+                    );
+                    self.record().add_retry();
+                    sleep(self.retry_policy.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads the body of a response, waiting on the download limit as each chunk arrives so that
+    /// the limit also slows down the connection.
+    async fn read_body(&self, mut body: ByteStream) -> Result<Vec<u8>> {
+        let Some(limit) = &self.download_limit else {
+            return Ok(body.collect().await?.to_vec());
+        };
+
+        let mut bytes = vec![];
+        while let Some(chunk) = body.try_next().await? {
+            limit.acquire(chunk.len() as u64).await;
+            bytes.extend_from_slice(&chunk);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Returns the stats to record a request in. A panic while they were locked can't leave them
+    /// inconsistent, since each request is recorded with a single call.
+    fn record(&self) -> MutexGuard<'_, StorageStats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the stats of every request sent by this storage.
+    pub fn stats(self) -> StorageStats {
+        unarc(self.stats)
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+async fn create_client(options: ClientOptions) -> Client {
+    // requests are retried by `send_with_retry` instead, so that retries follow the policy and
+    // are counted in the stats
+    let mut loader = aws_config::from_env().retry_config(RetryConfig::disabled());
+    if let Some(profile_name) = options.profile_name {
+        loader = loader.profile_name(profile_name);
+    }
+    if let Some(region) = options.region {
+        loader = loader.region(Region::new(region));
+    }
+    if let Some(endpoint_url) = options.endpoint_url {
+        loader = loader.endpoint_url(endpoint_url);
+    }
+
+    let s3_config = loader.load().await;
+    Client::new(&s3_config)
+}
+
+/// Converts an error from copying an object, distinguishing the destination's credentials not
+/// being allowed to read the source, so that the object can be copied some other way.
+fn copy_error<E>(err: SdkError<E, HttpResponse>, key: &str) -> Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let is_forbidden = err
+        .raw_response()
+        .is_some_and(|response| response.status().as_u16() == STATUS_FORBIDDEN);
+
+    if is_forbidden {
+        Error::CopyDenied(key.to_owned())
+    } else {
+        Error::other(err)
+    }
+}
+
+fn md5_base64(bytes: &[u8]) -> String {
+    let digest = md5::compute(bytes);
+    BASE64_STANDARD.encode(*digest)
+}
+
+/// Returns the byte ranges of the parts that an object of `size` bytes is uploaded or copied in.
+fn part_ranges(size: u64) -> impl Iterator<Item = Range<u64>> {
+    let part_size = MULTIPART_PART_SIZE as u64;
+    (0..size)
+        .step_by(MULTIPART_PART_SIZE)
+        .map(move |start| start..size.min(start + part_size))
+}
+
+fn usize_range(range: Range<u64>) -> Range<usize> {
+    usize::try_from(range.start).unwrap()..usize::try_from(range.end).unwrap()
+}
@@ -0,0 +1,86 @@
+use std::fmt::Write;
+
+use aws_sdk_s3::types::StorageClass;
+
+use crate::{archive::Archive, entity::Entity, pack::Pack, tree::Tree};
+
+/// Kinds of objects that can be stored with different settings, told apart by their keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    /// Packs, which hold the blocks of backed up files
+    Block,
+    /// Archives and the trees that they reference
+    Archive,
+    /// Metadata of archives, blocks, packs, and trees
+    Metadata,
+}
+
+impl ObjectKind {
+    pub fn from_key(key: &str) -> Self {
+        if key.starts_with(Pack::KEY_PREFIX) {
+            ObjectKind::Block
+        } else if key.starts_with(Archive::KEY_PREFIX) || key.starts_with(Tree::KEY_PREFIX) {
+            ObjectKind::Archive
+        } else {
+            ObjectKind::Metadata
+        }
+    }
+}
+
+/// Settings applied to every object that's uploaded.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectOptions {
+    pub block_storage_class: Option<StorageClass>,
+    pub archive_storage_class: Option<StorageClass>,
+    pub metadata_storage_class: Option<StorageClass>,
+    /// KMS key to encrypt objects with on the server, instead of the bucket's default encryption.
+    pub sse_kms_key_id: Option<String>,
+    pub tags: Vec<(String, String)>,
+}
+
+impl ObjectOptions {
+    pub fn storage_class(&self, kind: ObjectKind) -> Option<StorageClass> {
+        let maybe_class = match kind {
+            ObjectKind::Block => &self.block_storage_class,
+            ObjectKind::Archive => &self.archive_storage_class,
+            ObjectKind::Metadata => &self.metadata_storage_class,
+        };
+        maybe_class.clone()
+    }
+
+    /// Returns the tags in the URL query format that S3 expects, if there are any.
+    pub fn tagging(&self) -> Option<String> {
+        if self.tags.is_empty() {
+            return None;
+        }
+
+        let pairs = self
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", url_encode(key), url_encode(value)))
+            .collect::<Vec<_>>();
+        Some(pairs.join("&"))
+    }
+}
+
+/// Returns the source of a copy request for the object at `object_key` in `bucket`, in which
+/// every segment of the key is encoded but the slashes between them are kept.
+pub fn copy_source(bucket: &str, object_key: &str) -> String {
+    let segments = object_key.split('/').map(url_encode).collect::<Vec<_>>();
+    format!("{bucket}/{}", segments.join("/"))
+}
+
+/// Percent-encodes everything except unreserved characters.
+pub fn url_encode(s: &str) -> String {
+    let mut encoded = String::new();
+
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            write!(encoded, "%{byte:02X}").unwrap();
+        }
+    }
+
+    encoded
+}
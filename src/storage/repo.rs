@@ -0,0 +1,116 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use crate::error::{Error, Result};
+
+const SCHEME: &str = "s3://";
+const LOCAL_SCHEME: &str = "file://";
+
+/// Location of a repository, given as `s3://BUCKET[/PREFIX]`. Every key in the repository is
+/// stored under the prefix, so that a bucket can hold more than one repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoUrl {
+    pub bucket: String,
+    /// Empty, or ending with `/`
+    pub prefix: String,
+}
+
+impl RepoUrl {
+    /// Returns the repository at the root of `bucket`.
+    #[must_use]
+    pub fn from_bucket(bucket: String) -> Self {
+        RepoUrl {
+            bucket,
+            prefix: String::new(),
+        }
+    }
+
+    /// Returns the key in the bucket of the object with `key` in the repository.
+    #[must_use]
+    pub fn object_key(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    /// Returns the key in the repository of the object with `object_key` in the bucket.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidKey`] if the object isn't under the repository's prefix.
+    pub fn repo_key(&self, object_key: String) -> Result<String> {
+        match object_key.strip_prefix(&self.prefix) {
+            Some(key) => Ok(key.to_owned()),
+            None => Err(Error::InvalidKey(object_key)),
+        }
+    }
+}
+
+impl FromStr for RepoUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidRepoUrl(s.to_owned());
+
+        let rest = s.strip_prefix(SCHEME).ok_or_else(invalid)?;
+        let (bucket, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(invalid());
+        }
+
+        let path = path.trim_matches('/');
+        // the prefix is also used as a path for the metadata cache, so it can't step outside it
+        if !path.is_empty() && path.split('/').any(|part| matches!(part, "" | "." | "..")) {
+            return Err(invalid());
+        }
+
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{path}/")
+        };
+
+        Ok(RepoUrl {
+            bucket: bucket.to_owned(),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for RepoUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{SCHEME}{}/{}", self.bucket, self.prefix)
+    }
+}
+
+/// Location of a repository that can be written to, either in S3 or in a local directory given as
+/// a path or a `file://` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoLocation {
+    S3(RepoUrl),
+    Local(PathBuf),
+}
+
+impl FromStr for RepoLocation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.starts_with(SCHEME) {
+            return s.parse().map(RepoLocation::S3);
+        }
+
+        let path = s.strip_prefix(LOCAL_SCHEME).unwrap_or(s);
+        // anything else with a scheme is more likely a mistyped URL than a directory
+        if path.is_empty() || path.contains("://") {
+            return Err(Error::InvalidRepoUrl(s.to_owned()));
+        }
+
+        Ok(RepoLocation::Local(PathBuf::from(path)))
+    }
+}
+
+impl fmt::Display for RepoLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoLocation::S3(repo) => repo.fmt(f),
+            RepoLocation::Local(path) => path.display().fmt(f),
+        }
+    }
+}
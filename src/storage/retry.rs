@@ -0,0 +1,158 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::{ProvideErrorMetadata, SdkError},
+};
+use clap::ValueEnum;
+
+const STATUS_REQUEST_TIMEOUT: u16 = 408;
+const STATUS_TOO_MANY_REQUESTS: u16 = 429;
+
+const THROTTLING_CODES: [&str; 5] = [
+    "SlowDown",
+    "Throttling",
+    "ThrottlingException",
+    "RequestLimitExceeded",
+    "TooManyRequests",
+];
+
+const TIMEOUT_CODES: [&str; 1] = ["RequestTimeout"];
+
+/// Kinds of failed requests that may succeed if they're sent again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RetryableError {
+    /// Requests rejected for being sent too quickly
+    Throttling,
+    /// Internal errors of the storage service
+    Server,
+    /// Requests that took too long
+    Timeout,
+    /// Connections that failed or were closed
+    Connection,
+}
+
+impl RetryableError {
+    pub const ALL: [RetryableError; 4] = [
+        RetryableError::Throttling,
+        RetryableError::Server,
+        RetryableError::Timeout,
+        RetryableError::Connection,
+    ];
+
+    /// Classifies a failed request, returning `None` if it isn't worth retrying.
+    pub fn classify<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> Option<Self> {
+        match err {
+            SdkError::TimeoutError(_) => Some(RetryableError::Timeout),
+            SdkError::DispatchFailure(failure) if failure.is_timeout() => {
+                Some(RetryableError::Timeout)
+            }
+            SdkError::DispatchFailure(failure) if failure.is_io() => {
+                Some(RetryableError::Connection)
+            }
+            SdkError::ResponseError(_) => Some(RetryableError::Connection),
+            SdkError::ServiceError(service_err) => {
+                let status = service_err.raw().status().as_u16();
+                RetryableError::from_response(status, service_err.err().code())
+            }
+            _ => None,
+        }
+    }
+
+    /// Classifies an error response from the storage service by its status and error code.
+    pub fn from_response(status: u16, maybe_code: Option<&str>) -> Option<Self> {
+        let code = maybe_code.unwrap_or_default();
+
+        if status == STATUS_TOO_MANY_REQUESTS || THROTTLING_CODES.contains(&code) {
+            Some(RetryableError::Throttling)
+        } else if status == STATUS_REQUEST_TIMEOUT || TIMEOUT_CODES.contains(&code) {
+            Some(RetryableError::Timeout)
+        } else if (500..600).contains(&status) {
+            Some(RetryableError::Server)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for RetryableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryableError::Throttling => write!(f, "throttling"),
+            RetryableError::Server => write!(f, "server"),
+            RetryableError::Timeout => write!(f, "timeout"),
+            RetryableError::Connection => write!(f, "connection"),
+        }
+    }
+}
+
+/// How failed requests are retried: up to `max_attempts` in total, waiting `base_delay` before the
+/// first retry and doubling the delay for each one after it, up to `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Whether to pick a random delay up to the computed one, so that requests that failed together
+    /// aren't all retried at the same time.
+    pub jitter: bool,
+    pub retryable_errors: Vec<RetryableError>,
+}
+
+impl RetryPolicy {
+    /// Returns whether a request that failed after `attempt` attempts should be sent again.
+    pub fn should_retry<E: ProvideErrorMetadata>(
+        &self,
+        err: &SdkError<E, HttpResponse>,
+        attempt: u32,
+    ) -> bool {
+        attempt < self.max_attempts
+            && RetryableError::classify(err)
+                .is_some_and(|kind| self.retryable_errors.contains(&kind))
+    }
+
+    /// Returns the delay before retrying a request that failed after `attempt` attempts, without
+    /// jitter.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Returns the delay before retrying a request that failed after `attempt` attempts.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        if self.jitter {
+            backoff.mul_f64(random_fraction())
+        } else {
+            backoff
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Returns the policy used by the CLI when no retry options are given.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(20),
+            jitter: true,
+            retryable_errors: RetryableError::ALL.to_vec(),
+        }
+    }
+}
+
+/// Returns a random number in `[0, 1)`, which doesn't need to be unpredictable to spread out
+/// retries.
+#[allow(clippy::cast_precision_loss)]
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+
+    // the top 53 bits fit exactly in the mantissa of an f64
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
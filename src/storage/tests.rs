@@ -0,0 +1,357 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::{ErrorMetadata, SdkError},
+    operation::copy_object::CopyObjectError,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    error::Error,
+    storage::{
+        ClientOptions, MULTIPART_PART_SIZE, ObjectOptions, RateLimits, RepoLocation, RepoUrl,
+        RetryPolicy, RetryableError, Storage, copy_error,
+        limit::TokenBucket,
+        object::{ObjectKind, copy_source, url_encode},
+        part_ranges,
+    },
+};
+
+fn policy(jitter: bool) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 10,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+        jitter,
+        retryable_errors: RetryableError::ALL.to_vec(),
+    }
+}
+
+#[test]
+fn backoff_doubles_up_to_max_delay() {
+    let policy = policy(false);
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(4), Duration::from_millis(800));
+    assert_eq!(policy.backoff(5), Duration::from_secs(1));
+    assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+}
+
+#[test]
+fn delay_without_jitter() {
+    let policy = policy(false);
+    for attempt in 1..10 {
+        assert_eq!(policy.delay(attempt), policy.backoff(attempt));
+    }
+}
+
+#[test]
+fn delay_with_jitter() {
+    let policy = policy(true);
+    for attempt in 1..10 {
+        assert!(policy.delay(attempt) <= policy.backoff(attempt));
+    }
+}
+
+#[test]
+fn classify_responses() {
+    let cases = [
+        (503, Some("SlowDown"), Some(RetryableError::Throttling)),
+        (429, None, Some(RetryableError::Throttling)),
+        (400, Some("RequestTimeout"), Some(RetryableError::Timeout)),
+        (408, None, Some(RetryableError::Timeout)),
+        (500, Some("InternalError"), Some(RetryableError::Server)),
+        (502, None, Some(RetryableError::Server)),
+        (304, None, None),
+        (403, Some("AccessDenied"), None),
+        (404, Some("NoSuchKey"), None),
+    ];
+
+    for (status, code, expected) in cases {
+        assert_eq!(RetryableError::from_response(status, code), expected);
+    }
+}
+
+#[test]
+fn token_bucket_starts_full() {
+    let bucket = TokenBucket::new(100);
+    let now = Instant::now();
+    assert_eq!(bucket.take(60, now), Duration::ZERO);
+    assert_eq!(bucket.take(40, now), Duration::ZERO);
+}
+
+#[test]
+fn token_bucket_waits_off_debt() {
+    let bucket = TokenBucket::new(100);
+    let now = Instant::now();
+    assert_eq!(bucket.take(150, now), Duration::from_millis(500));
+    assert_eq!(bucket.take(50, now), Duration::from_secs(1));
+}
+
+#[test]
+fn token_bucket_refills_up_to_rate() {
+    let bucket = TokenBucket::new(100);
+    let now = Instant::now();
+    assert_eq!(bucket.take(100, now), Duration::ZERO);
+    assert_eq!(
+        bucket.take(50, now + Duration::from_millis(500)),
+        Duration::ZERO
+    );
+    assert_eq!(
+        bucket.take(150, now + Duration::from_secs(10)),
+        Duration::from_millis(500)
+    );
+}
+
+#[test]
+fn object_kinds_from_keys() {
+    let cases = [
+        ("packs/0123abcd", ObjectKind::Block),
+        ("archives/0123abcd", ObjectKind::Archive),
+        ("trees/0123abcd", ObjectKind::Archive),
+        ("metadata/packs", ObjectKind::Metadata),
+        ("metadata/blocks/ab", ObjectKind::Metadata),
+    ];
+
+    for (key, expected) in cases {
+        assert_eq!(ObjectKind::from_key(key), expected);
+    }
+}
+
+#[test]
+fn url_encode_reserved_characters() {
+    assert_eq!(url_encode("backup-tool_1.0~"), "backup-tool_1.0~");
+    assert_eq!(url_encode("team=ops&env"), "team%3Dops%26env");
+    assert_eq!(url_encode("a b/c"), "a%20b%2Fc");
+    assert_eq!(url_encode("é"), "%C3%A9");
+}
+
+#[test]
+fn tagging_joins_encoded_tags() {
+    let mut options = ObjectOptions::default();
+    assert_eq!(options.tagging(), None);
+
+    options.tags = vec![
+        ("app".to_owned(), "cubist".to_owned()),
+        ("cost center".to_owned(), "a&b".to_owned()),
+    ];
+    assert_eq!(
+        options.tagging().as_deref(),
+        Some("app=cubist&cost%20center=a%26b")
+    );
+}
+
+#[test]
+fn parse_repo_urls() {
+    let cases = [
+        ("s3://bucket", "bucket", ""),
+        ("s3://bucket/", "bucket", ""),
+        ("s3://bucket/team-a", "bucket", "team-a/"),
+        ("s3://bucket/team-a/", "bucket", "team-a/"),
+        ("s3://bucket/teams/a/", "bucket", "teams/a/"),
+    ];
+
+    for (url, bucket, prefix) in cases {
+        let repo = url.parse::<RepoUrl>().unwrap();
+        assert_eq!(repo.bucket, bucket);
+        assert_eq!(repo.prefix, prefix);
+    }
+}
+
+#[test]
+fn parse_invalid_repo_urls() {
+    let urls = [
+        "bucket",
+        "https://bucket/team-a",
+        "s3://",
+        "s3:///team-a",
+        "s3://bucket/teams//a",
+        "s3://bucket/../team-a",
+    ];
+
+    for url in urls {
+        assert_eq!(
+            url.parse::<RepoUrl>(),
+            Err(Error::InvalidRepoUrl(url.to_owned()))
+        );
+    }
+}
+
+#[test]
+fn parse_repo_locations() {
+    let cases = [
+        (
+            "s3://bucket/team-a",
+            RepoLocation::S3("s3://bucket/team-a".parse().unwrap()),
+        ),
+        (
+            "/mnt/backup",
+            RepoLocation::Local(PathBuf::from("/mnt/backup")),
+        ),
+        ("backup", RepoLocation::Local(PathBuf::from("backup"))),
+        (
+            "file:///mnt/backup",
+            RepoLocation::Local(PathBuf::from("/mnt/backup")),
+        ),
+    ];
+
+    for (location, expected) in cases {
+        assert_eq!(location.parse::<RepoLocation>(), Ok(expected));
+    }
+
+    for location in ["", "file://", "https://bucket/team-a", "s3://"] {
+        assert_eq!(
+            location.parse::<RepoLocation>(),
+            Err(Error::InvalidRepoUrl(location.to_owned()))
+        );
+    }
+}
+
+#[test]
+fn same_endpoint_needs_same_region() {
+    let options = |region: &str| ClientOptions {
+        region: Some(region.to_owned()),
+        ..ClientOptions::default()
+    };
+    assert!(options("eu-west-1").same_endpoint(&options("eu-west-1")));
+    assert!(!options("eu-west-1").same_endpoint(&options("us-east-1")));
+}
+
+/// Returns storage for a repository in an empty local directory.
+async fn local_storage(name: &str) -> Storage {
+    let dir = std::env::temp_dir().join(format!("cubist-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    Storage::new(
+        RepoLocation::Local(dir),
+        ClientOptions::default(),
+        None,
+        RetryPolicy::default(),
+        RateLimits::default(),
+        ObjectOptions::default(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn local_objects_roundtrip() {
+    let storage = local_storage("roundtrip").await;
+    assert_eq!(storage.try_get("metadata/version").await, Ok(None));
+
+    storage
+        .put("packs/ab", b"0123456789".to_vec())
+        .await
+        .unwrap();
+    storage
+        .put_cached("metadata/version", b"7".to_vec())
+        .await
+        .unwrap();
+    assert!(storage.exists("packs/ab").await.unwrap());
+    assert_eq!(storage.get("packs/ab").await.unwrap(), b"0123456789");
+    assert_eq!(storage.get_range("packs/ab", 2..5).await.unwrap(), b"234");
+    assert_eq!(storage.get_range("packs/ab", 0..0).await, Ok(vec![]));
+    assert_eq!(storage.get_range("packs/none", 3..3).await, Ok(vec![]));
+    assert_eq!(
+        storage.try_get_cached("metadata/version").await,
+        Ok(Some(b"7".to_vec()))
+    );
+
+    storage.delete("packs/ab").await.unwrap();
+    assert_eq!(
+        storage.get("packs/ab").await,
+        Err(Error::ItemNotFound("packs/ab".to_owned()))
+    );
+    // deleting a missing object succeeds, as in S3
+    storage
+        .delete_many(["packs/ab", "metadata/version"])
+        .await
+        .unwrap();
+    assert_eq!(storage.keys_vec(None).await, Ok(vec![]));
+}
+
+#[tokio::test]
+async fn local_objects_listed_by_prefix() {
+    let storage = local_storage("list").await;
+    for key in [
+        "packs/ab",
+        "packs/ac",
+        "packs/b",
+        "metadata/blocks",
+        "metadata/block-shards/0/0",
+    ] {
+        storage.put(key, vec![0; 3]).await.unwrap();
+    }
+
+    assert_eq!(
+        storage.keys_vec(Some("packs/a")).await.unwrap(),
+        ["packs/ab", "packs/ac"]
+    );
+    assert_eq!(
+        storage.keys_vec(Some("metadata/")).await.unwrap(),
+        ["metadata/block-shards/0/0", "metadata/blocks"]
+    );
+    assert_eq!(storage.keys_vec(Some("trees/")).await, Ok(vec![]));
+
+    let objects = storage.objects_paginated(None).collect::<Vec<_>>().await;
+    let sizes = objects
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|object| object.size);
+    assert_eq!(sizes.collect::<Vec<_>>(), [3; 5]);
+}
+
+#[test]
+fn copy_source_keeps_slashes() {
+    assert_eq!(
+        copy_source("bucket", "team a/packs/0123"),
+        "bucket/team%20a/packs/0123"
+    );
+    assert_eq!(
+        copy_source("bucket", "archives/x+y"),
+        "bucket/archives/x%2By"
+    );
+}
+
+#[test]
+fn part_ranges_cover_object() {
+    let part_size = MULTIPART_PART_SIZE as u64;
+    let size = 2 * part_size + 5;
+    let ranges = part_ranges(size).collect::<Vec<_>>();
+    assert_eq!(
+        ranges,
+        [0..part_size, part_size..2 * part_size, 2 * part_size..size]
+    );
+}
+
+#[test]
+fn part_ranges_of_exact_multiple() {
+    let part_size = MULTIPART_PART_SIZE as u64;
+    let ranges = part_ranges(2 * part_size).collect::<Vec<_>>();
+    assert_eq!(ranges, [0..part_size, part_size..2 * part_size]);
+}
+
+#[test]
+fn part_ranges_of_small_object() {
+    assert_eq!(part_ranges(1).collect::<Vec<_>>(), vec![0..1]);
+    assert_eq!(part_ranges(0).count(), 0);
+}
+
+fn copy_object_error(status: u16, code: &str) -> SdkError<CopyObjectError, HttpResponse> {
+    let response = HttpResponse::new(status.try_into().unwrap(), "".into());
+    let metadata = ErrorMetadata::builder().code(code).build();
+    SdkError::service_error(CopyObjectError::generic(metadata), response)
+}
+
+#[test]
+fn copy_denied() {
+    let err = copy_error(copy_object_error(403, "AccessDenied"), "packs/abc");
+    assert_eq!(err, Error::CopyDenied("packs/abc".to_owned()));
+
+    let err = copy_error(copy_object_error(404, "NoSuchKey"), "packs/abc");
+    assert!(matches!(err, Error::Other(_)));
+}
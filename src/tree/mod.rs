@@ -0,0 +1,164 @@
+#[cfg(test)]
+mod tests;
+
+mod records;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block::BlockRefs,
+    compress::compress,
+    entity::Entity,
+    error::{Error, Result},
+    file::{FileData, Metadata, Node, NodeChildren},
+    hash::Hash,
+    serde::serialize,
+};
+
+pub use self::records::{TreeRecord, TreeRecords};
+
+pub const COMPRESSION_LEVEL: u8 = 3;
+
+pub type TreeEntries = BTreeMap<OsString, TreeEntry>;
+
+/// Block refs of the files in each directory, keyed by the directory's path in the archive.
+pub type DirRefs = HashMap<PathBuf, BlockRefs>;
+
+/// A single directory of an archive, which references its subdirectories by hash so that
+/// unchanged subtrees are shared between archives.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tree {
+    pub entries: TreeEntries,
+    /// References to the blocks of the files directly in this directory.
+    pub block_refs: BlockRefs,
+}
+
+impl Entity for Tree {
+    const NAME: &'static str = "tree";
+    const KEY_PREFIX: &'static str = "trees/";
+}
+
+impl Tree {
+    pub fn subtrees(&self) -> impl Iterator<Item = &Hash<Tree>> {
+        self.entries.values().filter_map(|entry| match entry {
+            TreeEntry::Directory { tree, .. } => Some(tree),
+            TreeEntry::File { .. } | TreeEntry::Symlink { .. } => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TreeEntry {
+    File {
+        metadata: Metadata,
+        data: FileData,
+    },
+    Symlink {
+        metadata: Metadata,
+        path: PathBuf,
+    },
+    Directory {
+        metadata: Metadata,
+        tree: Hash<Tree>,
+    },
+}
+
+#[derive(Debug)]
+pub struct EncodedTree {
+    pub tree: Tree,
+    pub compressed_bytes: Vec<u8>,
+}
+
+/// Trees built from a file tree, keyed by hash, along with the hash of the root tree.
+#[derive(Debug)]
+pub struct TreeSet {
+    pub root: Hash<Tree>,
+    pub trees: HashMap<Hash<Tree>, EncodedTree>,
+}
+
+impl TreeSet {
+    /// Builds a tree for every directory in `children`, taking the block refs of each directory's
+    /// files from `dir_refs`, which is keyed by the directory's path.
+    pub fn build(children: NodeChildren, dir_refs: &mut DirRefs) -> Result<Self> {
+        let mut trees = HashMap::new();
+        let root = build_tree(children, Path::new(""), dir_refs, &mut trees)?;
+        Ok(TreeSet { root, trees })
+    }
+}
+
+fn build_tree(
+    children: NodeChildren,
+    path: &Path,
+    dir_refs: &mut DirRefs,
+    trees: &mut HashMap<Hash<Tree>, EncodedTree>,
+) -> Result<Hash<Tree>> {
+    let mut entries = TreeEntries::new();
+
+    for (name, node) in children {
+        let entry = match node {
+            Node::File { metadata, data } => TreeEntry::File { metadata, data },
+            Node::Symlink { metadata, path } => TreeEntry::Symlink { metadata, path },
+            Node::Directory { metadata, children } => {
+                let tree = build_tree(children, &path.join(&name), dir_refs, trees)?;
+                TreeEntry::Directory { metadata, tree }
+            }
+        };
+        entries.insert(name, entry);
+    }
+
+    let block_refs = dir_refs.remove(path).unwrap_or_default();
+    let tree = Tree {
+        entries,
+        block_refs,
+    };
+    let bytes = serialize(&tree)?;
+    let hash = Hash::tree(&bytes);
+    let compressed_bytes = compress(&bytes, COMPRESSION_LEVEL)?;
+    trees.insert(
+        hash,
+        EncodedTree {
+            tree,
+            compressed_bytes,
+        },
+    );
+    Ok(hash)
+}
+
+/// Converts the tree with the given hash and all of its subtrees back into file nodes.
+pub fn node_children(hash: &Hash<Tree>, trees: &HashMap<Hash<Tree>, Tree>) -> Result<NodeChildren> {
+    let tree = trees
+        .get(hash)
+        .ok_or_else(|| Error::ItemNotFound(hash.key()))?;
+    let mut children = NodeChildren::new();
+
+    for (name, entry) in &tree.entries {
+        let node = entry_node(entry, trees)?;
+        children.insert(name.clone(), node);
+    }
+
+    Ok(children)
+}
+
+pub fn entry_node(entry: &TreeEntry, trees: &HashMap<Hash<Tree>, Tree>) -> Result<Node> {
+    let node = match entry {
+        TreeEntry::File { metadata, data } => Node::File {
+            metadata: metadata.clone(),
+            data: data.clone(),
+        },
+        TreeEntry::Symlink { metadata, path } => Node::Symlink {
+            metadata: metadata.clone(),
+            path: path.clone(),
+        },
+        TreeEntry::Directory { metadata, tree } => Node::Directory {
+            metadata: metadata.clone(),
+            children: node_children(tree, trees)?,
+        },
+    };
+    Ok(node)
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entity::{EntityIndex, EntityRecord},
+    error::{Error, Result},
+    hash::Hash,
+};
+
+use super::Tree;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TreeRecord {
+    /// Number of archives and distinct parent trees that reference this tree.
+    pub ref_count: u64,
+    pub size: u64,
+}
+
+impl EntityRecord<Tree> for TreeRecord {
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TreeRecords {
+    records: HashMap<Hash<Tree>, TreeRecord>,
+}
+
+impl TreeRecords {
+    pub fn new() -> Self {
+        TreeRecords {
+            records: HashMap::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Hash<Tree>, &TreeRecord)> {
+        self.records.iter()
+    }
+
+    /// Adds a reference to an existing tree, returning whether the tree was found.
+    pub fn add_ref(&mut self, hash: &Hash<Tree>) -> bool {
+        if let Some(record) = self.records.get_mut(hash) {
+            record.ref_count += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes a reference to a tree, returning its record if it's no longer referenced.
+    pub fn remove_ref(&mut self, hash: &Hash<Tree>) -> Result<Option<TreeRecord>> {
+        let record = self
+            .records
+            .get_mut(hash)
+            .ok_or_else(|| Error::TreeRecordNotFound(*hash))?;
+
+        if record.ref_count > 1 {
+            record.ref_count -= 1;
+            Ok(None)
+        } else {
+            self.remove(hash).map(Some)
+        }
+    }
+}
+
+impl Default for TreeRecords {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EntityIndex<Tree> for TreeRecords {
+    type Record = TreeRecord;
+
+    const KEY: &'static str = "metadata/trees";
+
+    fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    fn contains(&self, hash: &Hash<Tree>) -> bool {
+        self.records.contains_key(hash)
+    }
+
+    fn get(&self, hash: &Hash<Tree>) -> Option<&TreeRecord> {
+        self.records.get(hash)
+    }
+
+    fn get_mut(&mut self, hash: &Hash<Tree>) -> Option<&mut TreeRecord> {
+        self.records.get_mut(hash)
+    }
+
+    fn insert(&mut self, hash: Hash<Tree>, record: TreeRecord) {
+        self.records.insert(hash, record);
+    }
+
+    fn remove(&mut self, hash: &Hash<Tree>) -> Result<TreeRecord> {
+        self.records
+            .remove(hash)
+            .ok_or_else(|| Error::TreeRecordNotFound(*hash))
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::HashMap, ffi::OsString, path::PathBuf};
+
+use crate::{
+    block::{Block, BlockRefs},
+    entity::EntityIndex,
+    error::Error,
+    file::{FileData, Metadata, Node, NodeChildren},
+    hash::Hash,
+    tree::{DirRefs, Tree, TreeRecord, TreeRecords, TreeSet, node_children},
+};
+
+fn metadata() -> Metadata {
+    Metadata {
+        inode: 1,
+        mode: 0o644,
+        group: 0,
+        owner: 0,
+        size: 0,
+        accessed: None,
+        created: None,
+        modified: None,
+    }
+}
+
+fn block_hash(n: u8) -> Hash<Block> {
+    Hash::leaf_block(&[n])
+}
+
+fn file(n: u8) -> Node {
+    Node::File {
+        metadata: metadata(),
+        data: FileData::Blocks(block_hash(n)),
+    }
+}
+
+fn dir(children: impl IntoIterator<Item = (&'static str, Node)>) -> Node {
+    Node::Directory {
+        metadata: metadata(),
+        children: children
+            .into_iter()
+            .map(|(name, node)| (OsString::from(name), node))
+            .collect(),
+    }
+}
+
+fn children(node: Node) -> NodeChildren {
+    match node {
+        Node::Directory { children, .. } => children,
+        _ => unreachable!(),
+    }
+}
+
+fn decoded_trees(tree_set: &TreeSet) -> HashMap<Hash<Tree>, Tree> {
+    tree_set
+        .trees
+        .iter()
+        .map(|(hash, encoded_tree)| (*hash, encoded_tree.tree.clone()))
+        .collect()
+}
+
+#[test]
+fn build_shares_identical_dirs() {
+    let root = dir([
+        ("a", dir([("file", file(0))])),
+        ("b", dir([("file", file(0))])),
+        ("c", dir([("file", file(1))])),
+    ]);
+    let tree_set = TreeSet::build(children(root), &mut DirRefs::new()).unwrap();
+
+    assert_eq!(tree_set.trees.len(), 3);
+    let subtrees = tree_set.trees[&tree_set.root]
+        .tree
+        .subtrees()
+        .collect::<Vec<_>>();
+    assert_eq!(subtrees.len(), 3);
+    assert_eq!(subtrees[0], subtrees[1]);
+    assert_ne!(subtrees[0], subtrees[2]);
+}
+
+#[test]
+fn build_takes_dir_refs() {
+    let root = dir([("file", file(0)), ("dir", dir([("file", file(1))]))]);
+    let mut root_refs = BlockRefs::new();
+    root_refs.add_count(&block_hash(0), 1);
+    let mut dir_refs = BlockRefs::new();
+    dir_refs.add_count(&block_hash(1), 2);
+
+    let mut all_refs = DirRefs::new();
+    all_refs.insert(PathBuf::new(), root_refs.clone());
+    all_refs.insert(PathBuf::from("dir"), dir_refs.clone());
+    let tree_set = TreeSet::build(children(root), &mut all_refs).unwrap();
+    assert!(all_refs.is_empty());
+
+    let root_tree = &tree_set.trees[&tree_set.root].tree;
+    assert_eq!(root_tree.block_refs, root_refs);
+
+    let subtree = root_tree.subtrees().next().unwrap();
+    assert_eq!(tree_set.trees[subtree].tree.block_refs, dir_refs);
+}
+
+#[test]
+fn build_is_deterministic() {
+    let build = || {
+        let root = dir([("a", file(0)), ("b", dir([("c", file(1))]))]);
+        TreeSet::build(children(root), &mut DirRefs::new()).unwrap()
+    };
+
+    assert_eq!(build().root, build().root);
+}
+
+#[test]
+fn node_children_roundtrip() {
+    let root = dir([("a", file(0)), ("b", dir([("c", file(1)), ("d", dir([]))]))]);
+    let tree_set = TreeSet::build(children(root), &mut DirRefs::new()).unwrap();
+    let trees = decoded_trees(&tree_set);
+
+    let expected = dir([("a", file(0)), ("b", dir([("c", file(1)), ("d", dir([]))]))]);
+    assert_eq!(
+        node_children(&tree_set.root, &trees).unwrap(),
+        children(expected)
+    );
+}
+
+#[test]
+fn node_children_missing_tree() {
+    let root = dir([("dir", dir([("file", file(0))]))]);
+    let tree_set = TreeSet::build(children(root), &mut DirRefs::new()).unwrap();
+    let mut trees = decoded_trees(&tree_set);
+
+    let subtree = *trees[&tree_set.root].subtrees().next().unwrap();
+    trees.remove(&subtree);
+
+    assert_eq!(
+        node_children(&tree_set.root, &trees),
+        Err(Error::ItemNotFound(subtree.key()))
+    );
+}
+
+#[test]
+fn tree_records_refs() {
+    let hash = Hash::tree(&[]);
+    let mut records = TreeRecords::new();
+    assert!(!records.add_ref(&hash));
+    assert_eq!(
+        records.remove_ref(&hash).unwrap_err(),
+        Error::TreeRecordNotFound(hash)
+    );
+
+    records.insert(
+        hash,
+        TreeRecord {
+            ref_count: 1,
+            size: 10,
+        },
+    );
+    assert!(records.add_ref(&hash));
+    assert_eq!(records.get(&hash).unwrap().ref_count, 2);
+
+    assert!(records.remove_ref(&hash).unwrap().is_none());
+    assert!(records.remove_ref(&hash).unwrap().is_some());
+    assert!(!records.contains(&hash));
+}